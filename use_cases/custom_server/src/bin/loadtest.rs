@@ -0,0 +1,349 @@
+//! Compare the blocking server with the epoll event loop under many idle
+//! keep-alive connections.
+//!
+//! For each backend this starts the `custom_server` binary next to this
+//! one (so build both: `cargo build --release`), parks `--idle`
+//! connections on it that each make one request and then sit quietly, and
+//! then has `--clients` threads send `GET /health` over keep-alive
+//! connections as fast as they can for `--seconds`. The blocking server
+//! gives every open connection a worker, so once its workers and queue
+//! are taken by idle clients, the busy ones wait; the event loop doesn't
+//! care how many connections are open.
+//!
+//! The server runs in its own process because each side of every
+//! connection is a file descriptor, and ten thousand connections with
+//! both ends in one process would hit the usual hard limit.
+//!
+//! Concepts: `std::process::Command` with a piped stdout, threads joined
+//! through `JoinHandle` results, `TcpStream::connect_timeout`, latency
+//! percentiles from sorted samples.
+
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::process::{self, Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const USAGE: &str = "usage: loadtest [--idle N] [--clients N] [--seconds S] \
+                     [--backend blocking|epoll|both] [--addr HOST:PORT]";
+
+/// Threads that open the idle connections.
+const OPENERS: usize = 100;
+
+/// How long a connection attempt may take. On loopback a server that is
+/// accepting answers in well under a millisecond; one that isn't leaves
+/// the attempt hanging until this runs out.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long a response may take before the request counts as failed.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+const REQUEST: &[u8] = b"GET /health HTTP/1.1\r\nHost: loadtest\r\n\r\n";
+
+struct Options {
+    idle: usize,
+    clients: usize,
+    duration: Duration,
+    backends: Vec<Backend>,
+    /// Test a server that is already running instead of starting one.
+    addr: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Backend {
+    Blocking,
+    Epoll,
+}
+
+impl Backend {
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Blocking => "blocking",
+            Backend::Epoll => "epoll",
+        }
+    }
+}
+
+/// What the busy clients saw.
+#[derive(Default)]
+struct Report {
+    latencies: Vec<Duration>,
+    errors: usize,
+}
+
+impl Report {
+    fn merge(&mut self, other: Report) {
+        self.latencies.extend(other.latencies);
+        self.errors += other.errors;
+    }
+
+    fn percentile(&self, p: usize) -> Duration {
+        // `latencies` is sorted by then; see `print`.
+        match self.latencies.len() {
+            0 => Duration::ZERO,
+            n => self.latencies[(n - 1) * p / 100],
+        }
+    }
+
+    fn print(&mut self, label: &str, elapsed: Duration) {
+        self.latencies.sort_unstable();
+        println!(
+            "{label}: {} requests in {:.1}s = {:.0} req/s, p50 {:?}, p99 {:?}, {} errors",
+            self.latencies.len(),
+            elapsed.as_secs_f64(),
+            self.latencies.len() as f64 / elapsed.as_secs_f64(),
+            self.percentile(50),
+            self.percentile(99),
+            self.errors,
+        );
+    }
+}
+
+fn main() {
+    let options = parse_args(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("loadtest: {err}\n{USAGE}");
+        process::exit(2);
+    });
+    #[cfg(target_os = "linux")]
+    if let Err(err) = custom_server::event_loop::epoll::raise_fd_limit() {
+        eprintln!("loadtest: can't raise the open file limit: {err}");
+    }
+
+    if let Some(addr) = options.addr {
+        run(&options, addr, &addr.to_string());
+        return;
+    }
+    for &backend in &options.backends {
+        let (mut child, addr) = start_server(backend).unwrap_or_else(|err| {
+            eprintln!("loadtest: can't start the {} server: {err}", backend.name());
+            process::exit(1);
+        });
+        run(&options, addr, backend.name());
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Park the idle connections on the server at `addr`, run the busy
+/// clients, and print what happened.
+fn run(options: &Options, addr: SocketAddr, label: &str) {
+    let started = Instant::now();
+    let idle = open_idle(addr, options.idle);
+    println!(
+        "{label}: {}/{} idle connections answered in {:.1}s",
+        idle.len(),
+        options.idle,
+        started.elapsed().as_secs_f64()
+    );
+
+    let started = Instant::now();
+    let deadline = started + options.duration;
+    let clients: Vec<_> = (0..options.clients)
+        .map(|_| thread::spawn(move || hammer(addr, deadline)))
+        .collect();
+    let mut report = Report::default();
+    for client in clients {
+        report.merge(client.join().expect("client thread panicked"));
+    }
+    report.print(label, started.elapsed());
+    drop(idle);
+}
+
+/// Start `custom_server` on a free port and return it with the address
+/// it's listening on.
+fn start_server(backend: Backend) -> io::Result<(Child, SocketAddr)> {
+    let program = env::current_exe()?.with_file_name("custom_server");
+    if !program.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found; build it first", program.display()),
+        ));
+    }
+    let mut command = Command::new(&program);
+    // Long enough that no idle connection times out during the test.
+    command.args(["--addr", "127.0.0.1:0", "--idle-timeout", "600"]);
+    if backend == Backend::Epoll {
+        command.arg("--event-loop");
+    }
+    let mut child = command.stdout(Stdio::piped()).spawn()?;
+
+    let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+    let mut line = String::new();
+    stdout.read_line(&mut line)?;
+    let addr = line
+        .strip_prefix("listening on ")
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|addr| addr.parse().ok());
+    let Some(addr) = addr else {
+        let _ = child.kill();
+        return Err(io::Error::other(format!(
+            "unexpected first line from the server: {line:?}"
+        )));
+    };
+    // The server logs every request; keep reading so it never blocks on
+    // a full pipe.
+    thread::spawn(move || io::copy(&mut stdout, &mut io::sink()));
+    Ok((child, addr))
+}
+
+/// Open up to `count` connections to `addr`, each with one request sent,
+/// and return those that got an answer. A thread stops opening more once
+/// the server stops accepting, rather than waiting out a timeout for
+/// every connection it was given.
+fn open_idle(addr: SocketAddr, count: usize) -> Vec<TcpStream> {
+    let openers: Vec<_> = (0..OPENERS.min(count))
+        .map(|i| {
+            let share = count / OPENERS + usize::from(i < count % OPENERS);
+            thread::spawn(move || {
+                let mut opened = Vec::with_capacity(share);
+                for _ in 0..share {
+                    match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+                        .and_then(|mut stream| stream.write_all(REQUEST).map(|()| stream))
+                    {
+                        Ok(stream) => opened.push(stream),
+                        Err(_) => break,
+                    }
+                }
+                opened
+            })
+        })
+        .collect();
+    let opened: Vec<TcpStream> = openers
+        .into_iter()
+        .flat_map(|opener| opener.join().expect("opener thread panicked"))
+        .collect();
+
+    // Every request went out before any answer is awaited, so this waits
+    // about one `RESPONSE_TIMEOUT` in total, not one per connection.
+    let deadline = Instant::now() + RESPONSE_TIMEOUT;
+    opened
+        .into_iter()
+        .filter_map(|stream| {
+            let left = deadline.saturating_duration_since(Instant::now());
+            stream
+                .set_read_timeout(Some(left.max(Duration::from_millis(1))))
+                .ok()?;
+            let mut reader = BufReader::new(stream);
+            read_response(&mut reader).ok()?;
+            Some(reader.into_inner())
+        })
+        .collect()
+}
+
+/// Send requests over one keep-alive connection until `deadline`,
+/// reconnecting after a failure.
+fn hammer(addr: SocketAddr, deadline: Instant) -> Report {
+    let mut report = Report::default();
+    let mut connection: Option<BufReader<TcpStream>> = None;
+    while Instant::now() < deadline {
+        let reader = match &mut connection {
+            Some(reader) => reader,
+            None => match connect(addr) {
+                Ok(stream) => connection.insert(BufReader::new(stream)),
+                Err(_) => {
+                    report.errors += 1;
+                    continue;
+                }
+            },
+        };
+        let sent = Instant::now();
+        let answered = reader
+            .get_mut()
+            .write_all(REQUEST)
+            .and_then(|()| read_response(reader));
+        match answered {
+            Ok(keep_alive) => {
+                report.latencies.push(sent.elapsed());
+                if !keep_alive {
+                    connection = None;
+                }
+            }
+            Err(_) => {
+                report.errors += 1;
+                connection = None;
+            }
+        }
+    }
+    report
+}
+
+fn connect(addr: SocketAddr) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Read one response with a `Content-Length` body, returning whether the
+/// connection stays open after it.
+fn read_response(reader: &mut BufReader<TcpStream>) -> io::Result<bool> {
+    let mut line = String::new();
+    let mut length = 0;
+    let mut keep_alive = true;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = value.parse().map_err(io::Error::other)?;
+            } else if name.eq_ignore_ascii_case("Connection") {
+                keep_alive = !value.eq_ignore_ascii_case("close");
+            }
+        }
+    }
+    io::copy(&mut reader.take(length), &mut io::sink())?;
+    Ok(keep_alive)
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        idle: 10_000,
+        clients: 8,
+        duration: Duration::from_secs(5),
+        backends: vec![Backend::Blocking, Backend::Epoll],
+        addr: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+        match arg.as_str() {
+            "--idle" => options.idle = number(&arg, &value()?)?,
+            "--clients" => options.clients = number(&arg, &value()?)?,
+            "--seconds" => options.duration = Duration::from_secs(number(&arg, &value()?)?),
+            "--backend" => {
+                options.backends = match value()?.as_str() {
+                    "blocking" => vec![Backend::Blocking],
+                    "epoll" => vec![Backend::Epoll],
+                    "both" => vec![Backend::Blocking, Backend::Epoll],
+                    other => return Err(format!("unknown backend {other:?}")),
+                }
+            }
+            "--addr" => {
+                let addr = value()?;
+                options.addr = addr
+                    .to_socket_addrs()
+                    .map_err(|err| format!("--addr {addr}: {err}"))?
+                    .next();
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                process::exit(0);
+            }
+            _ => return Err(format!("unknown argument {arg:?}")),
+        }
+    }
+    Ok(options)
+}
+
+fn number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{flag}: {value:?} is not a number"))
+}
@@ -0,0 +1,548 @@
+//! A blocking HTTP/1.1 client, the server's counterpart: the same
+//! `Request` goes out and the same `Response` comes back.
+//!
+//! ```no_run
+//! use custom_server::client::Client;
+//!
+//! let client = Client::new();
+//! let response = client.get("http://127.0.0.1:7878/users/7").send()?;
+//! println!("{} {}", response.status, String::from_utf8_lossy(&response.body));
+//! # Ok::<(), custom_server::client::Error>(())
+//! ```
+//!
+//! A `Client` keeps connections open between requests, per origin (see
+//! `pool`), so a burst of requests to one server costs one connection
+//! setup rather than one each. Clones share the pool, and a client can be
+//! used from several threads at once: each request takes a connection of
+//! its own. A pooled connection may turn out to have been closed by the
+//! server just as it was reused; a request that fails that way and can
+//! safely be repeated (`GET`, `PUT`, `DELETE`, ..., but not `POST`) is
+//! retried once on a new connection.
+//!
+//! Responses are read whole, chunked ones decoded. Redirects (`301`,
+//! `302`, `303`, `307`, `308`) are followed up to `max_redirects`; a `303`,
+//! or a `301`/`302` answering a `POST`, turns the request into a bodiless
+//! `GET`, as browsers do, while `307`/`308` repeat it exactly.
+//! Credentials (`Authorization`, `Cookie`) aren't carried to a different
+//! origin. `https` works with a rustls `ClientConfig` given to `tls`; this
+//! crate has no list of trusted roots to default to.
+//!
+//! Concepts: a builder that borrows its client (`RequestBuilder<'a>`),
+//! deferring an error (a bad URL) until `send` so calls chain,
+//! `Arc` to share the pool between clones.
+
+mod pool;
+mod url;
+
+pub use url::Url;
+
+use std::error;
+use std::fmt;
+use std::io::{self, BufReader, BufWriter};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, StreamOwned};
+
+use crate::http::{Headers, Limits, Method, ParseError, Request, Response, StatusCode};
+use pool::{Connection, Key, Pool, Stream};
+
+/// An HTTP client. Cheap to clone; clones share their idle connections.
+#[derive(Clone)]
+pub struct Client {
+    config: Config,
+    pool: Arc<Pool>,
+}
+
+#[derive(Clone)]
+struct Config {
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_redirects: usize,
+    idle_timeout: Duration,
+    max_idle_per_host: usize,
+    limits: Limits,
+    tls: Option<Arc<ClientConfig>>,
+}
+
+impl Client {
+    /// A client with a 10 s connect timeout, a 30 s timeout on each read
+    /// and write, up to 5 redirects followed, and idle connections kept
+    /// for 4 s (under the server's default 5 s idle timeout), 8 per
+    /// origin.
+    pub fn new() -> Client {
+        Client {
+            config: Config {
+                connect_timeout: Duration::from_secs(10),
+                timeout: Duration::from_secs(30),
+                max_redirects: 5,
+                idle_timeout: Duration::from_secs(4),
+                max_idle_per_host: 8,
+                limits: Limits {
+                    max_head: 64 * 1024,
+                    max_body: 64 * 1024 * 1024,
+                    ..Limits::default()
+                },
+                tls: None,
+            },
+            pool: Arc::default(),
+        }
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Client {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    /// How long any one read or write may take; a request can override
+    /// it.
+    pub fn timeout(mut self, timeout: Duration) -> Client {
+        self.config.timeout = timeout;
+        self
+    }
+
+    /// Redirects followed before giving up; 0 returns them as responses.
+    pub fn max_redirects(mut self, max_redirects: usize) -> Client {
+        self.config.max_redirects = max_redirects;
+        self
+    }
+
+    /// How long a connection may wait in the pool to be reused.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Client {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Idle connections kept per origin; 0 closes each after its request.
+    pub fn max_idle_per_host(mut self, max: usize) -> Client {
+        self.config.max_idle_per_host = max;
+        self
+    }
+
+    /// Size limits for responses.
+    pub fn limits(mut self, limits: Limits) -> Client {
+        self.config.limits = limits;
+        self
+    }
+
+    /// Speak TLS to `https` URLs with `config`, whose roots decide which
+    /// servers are trusted.
+    pub fn tls(mut self, config: ClientConfig) -> Client {
+        self.config.tls = Some(Arc::new(config));
+        self
+    }
+
+    pub fn request(&self, method: Method, url: &str) -> RequestBuilder<'_> {
+        RequestBuilder {
+            client: self,
+            method,
+            url: Url::parse(url).map_err(Error::Url),
+            headers: Headers::new(),
+            body: Vec::new(),
+            timeout: self.config.timeout,
+        }
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Get, url)
+    }
+
+    pub fn head(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Head, url)
+    }
+
+    pub fn post(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Post, url)
+    }
+
+    pub fn put(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Put, url)
+    }
+
+    pub fn delete(&self, url: &str) -> RequestBuilder<'_> {
+        self.request(Method::Delete, url)
+    }
+
+    /// Open connections waiting to be reused, over all origins.
+    pub fn idle_connections(&self) -> usize {
+        self.pool.len()
+    }
+
+    /// One request and its response, on a pooled connection if there is
+    /// one that works, else a new one.
+    fn exchange(&self, url: &Url, request: &Request, timeout: Duration) -> Result<Response, Error> {
+        let key: Key = (url.https, url.host.clone(), url.port);
+        if let Some(connection) = self.pool.take(&key, self.config.idle_timeout) {
+            match self.exchange_on(connection, &key, request, timeout) {
+                Err(err) if is_idempotent(request.method) && is_stale(&err) => {}
+                result => return result,
+            }
+        }
+        let connection = self.connect(url, timeout)?;
+        self.exchange_on(connection, &key, request, timeout)
+    }
+
+    fn exchange_on(
+        &self,
+        mut connection: Connection,
+        key: &Key,
+        request: &Request,
+        timeout: Duration,
+    ) -> Result<Response, Error> {
+        let socket = connection.get_ref().socket();
+        socket.set_read_timeout(Some(timeout))?;
+        socket.set_write_timeout(Some(timeout))?;
+        // One write for head and body, so Nagle's algorithm doesn't hold
+        // the body back.
+        request.write_to(&mut BufWriter::new(connection.get_mut()))?;
+        let (response, persistent) =
+            Response::read_persistent(&mut connection, request.method, &self.config.limits)?;
+        if persistent {
+            let max = self.config.max_idle_per_host;
+            self.pool.put(key.clone(), connection, max);
+        }
+        Ok(response)
+    }
+
+    fn connect(&self, url: &Url, timeout: Duration) -> Result<Connection, Error> {
+        let mut last_err = None;
+        let mut socket = None;
+        for addr in (url.host.as_str(), url.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.config.connect_timeout) {
+                Ok(connected) => {
+                    socket = Some(connected);
+                    break;
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        let socket = match (socket, last_err) {
+            (Some(socket), _) => socket,
+            (None, Some(err)) => return Err(err.into()),
+            (None, None) => return Err(Error::Url(format!("{}: no address", url.host))),
+        };
+        socket.set_nodelay(true)?;
+        if !url.https {
+            return Ok(BufReader::new(Stream::Plain(socket)));
+        }
+
+        let Some(tls) = &self.config.tls else {
+            return Err(Error::Url(format!("{url}: https needs Client::tls")));
+        };
+        let name = ServerName::try_from(url.host.clone())
+            .map_err(|err| Error::Url(format!("{}: {err}", url.host)))?;
+        let connection = ClientConnection::new(Arc::clone(tls), name).map_err(io::Error::other)?;
+        let mut stream = StreamOwned::new(connection, socket);
+        // Finish the handshake now, so a certificate problem shows up as
+        // such rather than as a failed write.
+        stream.sock.set_read_timeout(Some(timeout))?;
+        stream.sock.set_write_timeout(Some(timeout))?;
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        Ok(BufReader::new(Stream::Tls(Box::new(stream))))
+    }
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("timeout", &self.config.timeout)
+            .field("idle_connections", &self.idle_connections())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A request being put together; `send` it to get the response.
+#[must_use = "a request does nothing until it's sent"]
+pub struct RequestBuilder<'a> {
+    client: &'a Client,
+    method: Method,
+    url: Result<Url, Error>,
+    headers: Headers,
+    body: Vec<u8>,
+    timeout: Duration,
+}
+
+impl RequestBuilder<'_> {
+    /// Add a header. `Content-Length` is filled in anyway, and `Host`
+    /// too unless given here, for as long as redirects stay on the
+    /// request's origin.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.append(name, value);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    /// The client's `timeout`, for this request only.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Send the request, following redirects, and read the response.
+    pub fn send(self) -> Result<Response, Error> {
+        let client = self.client;
+        let mut url = self.url?;
+        let mut request = Request::new(self.method, "");
+        request.headers = self.headers;
+        request.body = self.body;
+        if !request.headers.contains("User-Agent") {
+            request.headers.set("User-Agent", "custom_server-client");
+        }
+
+        let mut own_host = !request.headers.contains("Host");
+        let mut redirects = 0;
+        loop {
+            request.target = url.target.clone();
+            if own_host {
+                request.headers.set("Host", url.authority());
+            }
+            let response = client.exchange(&url, &request, self.timeout)?;
+            let location = match response.status.0 {
+                301 | 302 | 303 | 307 | 308 => response.headers.get("Location"),
+                _ => None,
+            };
+            let Some(location) = location.filter(|_| client.config.max_redirects > 0) else {
+                return Ok(response);
+            };
+            if redirects == client.config.max_redirects {
+                return Err(Error::TooManyRedirects(
+                    url.join(location).map_err(Error::Url)?,
+                ));
+            }
+            redirects += 1;
+
+            let next = url.join(location).map_err(Error::Url)?;
+            let status = response.status;
+            let becomes_get = status == StatusCode(303)
+                || (matches!(status.0, 301 | 302) && request.method == Method::Post);
+            if becomes_get && request.method != Method::Head {
+                request.method = Method::Get;
+                request.body.clear();
+                request.headers.remove("Content-Type");
+            }
+            if (next.https, &next.host, next.port) != (url.https, &url.host, url.port) {
+                request.headers.remove("Authorization");
+                request.headers.remove("Cookie");
+                own_host = true;
+            }
+            url = next;
+        }
+    }
+}
+
+/// Why a request got no response.
+#[derive(Debug)]
+pub enum Error {
+    /// The URL (or a redirect's `Location`) is malformed or not `http(s)`.
+    Url(String),
+    /// Connecting, or a read or write, took longer than allowed.
+    Timeout,
+    /// The connection failed.
+    Io(io::Error),
+    /// The server's response couldn't be parsed.
+    Parse(ParseError),
+    /// Redirected `max_redirects` times, the last time to this URL.
+    TooManyRedirects(Url),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Url(why) => write!(f, "bad URL {why}"),
+            Error::Timeout => write!(f, "timed out"),
+            Error::Io(err) => write!(f, "{err}"),
+            Error::Parse(err) => write!(f, "bad response: {err}"),
+            Error::TooManyRedirects(url) => write!(f, "too many redirects, last to {url}"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Error {
+        match err {
+            ParseError::Io(err) => err.into(),
+            err => Error::Parse(err),
+        }
+    }
+}
+
+/// Whether sending `method` twice has the same effect as once.
+fn is_idempotent(method: Method) -> bool {
+    !matches!(method, Method::Post | Method::Patch | Method::Connect)
+}
+
+/// Whether `err` is what a reused connection the server had already
+/// closed looks like: the write or the first read finds it gone.
+fn is_stale(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::Io(err) if matches!(
+            err.kind(),
+            io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::BrokenPipe
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+
+    /// A server that answers each connection's requests with the next of
+    /// `answers` (one per request), and reports the requests it read.
+    fn scripted(answers: Vec<Vec<&'static str>>) -> (SocketAddr, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let mut seen = Vec::new();
+            for answers in answers {
+                let (socket, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(&socket);
+                for answer in answers {
+                    let request = Request::read_from(&mut reader, &Limits::default())
+                        .unwrap()
+                        .unwrap();
+                    seen.push(format!("{} {}", request.method, request.target));
+                    (&socket).write_all(answer.as_bytes()).unwrap();
+                }
+            }
+            seen
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn decodes_chunked_bodies_and_reuses_the_connection() {
+        let (addr, server) = scripted(vec![vec![
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+            "HTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok",
+        ]]);
+        let client = Client::new();
+        let response = client.get(&format!("http://{addr}/a")).send().unwrap();
+        assert_eq!(response.body, b"hello world");
+        assert!(!response.headers.contains("Transfer-Encoding"));
+        assert_eq!(client.idle_connections(), 1);
+
+        let response = client
+            .put(&format!("http://{addr}/b"))
+            .body("x")
+            .send()
+            .unwrap();
+        assert_eq!(
+            (response.status, response.body),
+            (StatusCode::CREATED, b"ok".to_vec())
+        );
+        assert_eq!(server.join().unwrap(), ["GET /a", "PUT /b"]);
+    }
+
+    #[test]
+    fn follows_redirects_and_changes_method_where_http_says_to() {
+        let (addr, server) = scripted(vec![vec![
+            "HTTP/1.1 303 See Other\r\nLocation: /next?x=1\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 307 Temporary Redirect\r\nLocation: last\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\ndone",
+        ]]);
+        let client = Client::new();
+        let response = client
+            .post(&format!("http://{addr}/form"))
+            .body("a=1")
+            .send()
+            .unwrap();
+        assert_eq!(response.body, b"done");
+        assert_eq!(
+            server.join().unwrap(),
+            ["POST /form", "GET /next?x=1", "GET /last"]
+        );
+
+        let (addr, server) = scripted(vec![vec![
+            "HTTP/1.1 302 Found\r\nLocation: /a\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 302 Found\r\nLocation: /b\r\nContent-Length: 0\r\n\r\n",
+        ]]);
+        let client = Client::new().max_redirects(1);
+        let err = client.get(&format!("http://{addr}/")).send().unwrap_err();
+        assert!(
+            matches!(&err, Error::TooManyRedirects(url) if url.target == "/b"),
+            "{err}"
+        );
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn retries_an_idempotent_request_when_the_pooled_connection_is_gone() {
+        // The first connection closes right after its one answer, which
+        // says nothing about closing; the second takes the retry.
+        let (addr, server) = scripted(vec![
+            vec!["HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n1"],
+            vec!["HTTP/1.1 200 OK\r\nContent-Length: 1\r\n\r\n2"],
+        ]);
+        let client = Client::new();
+        let url = format!("http://{addr}/");
+        assert_eq!(client.get(&url).send().unwrap().body, b"1");
+        assert_eq!(client.get(&url).send().unwrap().body, b"2");
+        assert_eq!(server.join().unwrap(), ["GET /", "GET /"]);
+    }
+
+    #[test]
+    fn times_out_on_a_server_that_never_answers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            // Read the request, then say nothing until the client gives up.
+            let mut reader = BufReader::new(&socket);
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let _ = reader.read(&mut [0; 1]);
+        });
+        let err = Client::new()
+            .get(&format!("http://{addr}/slow"))
+            .timeout(Duration::from_millis(100))
+            .send()
+            .unwrap_err();
+        assert!(matches!(err, Error::Timeout), "{err}");
+        server.join().unwrap();
+
+        let err = Client::new().get("ftp://example.com/").send().unwrap_err();
+        assert!(matches!(err, Error::Url(_)), "{err}");
+    }
+}
@@ -0,0 +1,160 @@
+//! Open connections kept for reuse, keyed by scheme, host and port.
+//!
+//! Setting up a connection costs a round trip, and a TLS handshake one or
+//! two more, so after an exchange that leaves the connection usable it
+//! goes back here instead of being closed. The next request for the same
+//! origin takes the most recently used one. A connection that has sat
+//! idle for `idle_timeout` is dropped rather than reused, and so is one
+//! the server has closed meanwhile: an idle connection should have
+//! nothing to read, so if a non-blocking peek finds the end of the stream
+//! or stray bytes, it's no good.
+//!
+//! Concepts: `HashMap<K, Vec<V>>` as a multimap, `TcpStream::peek` with
+//! `set_nonblocking`, an enum over two stream types implementing `Read`
+//! and `Write`.
+
+use std::collections::HashMap;
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rustls::{ClientConnection, StreamOwned};
+
+/// Scheme (`true` for https), host and port.
+pub type Key = (bool, String, u16);
+
+/// A connection to a server, plain or TLS.
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    pub fn socket(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(socket) => socket,
+            Stream::Tls(stream) => &stream.sock,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(socket) => socket.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(socket) => socket.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A connection with its read buffer, which travels with it into the
+/// pool and out again.
+pub type Connection = BufReader<Stream>;
+
+#[derive(Default)]
+pub struct Pool {
+    idle: Mutex<HashMap<Key, Vec<(Connection, Instant)>>>,
+}
+
+impl Pool {
+    /// An idle connection to `key` that still looks usable.
+    pub fn take(&self, key: &Key, idle_timeout: Duration) -> Option<Connection> {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.get_mut(key)?;
+        while let Some((connection, since)) = connections.pop() {
+            if since.elapsed() < idle_timeout && is_quiet(&connection) {
+                return Some(connection);
+            }
+        }
+        None
+    }
+
+    /// Keep `connection` for the next request to `key`, unless there are
+    /// already `max` waiting.
+    pub fn put(&self, key: Key, connection: Connection, max: usize) {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.entry(key).or_default();
+        if connections.len() < max {
+            connections.push((connection, Instant::now()));
+        }
+    }
+
+    /// How many connections are waiting, over all origins.
+    pub fn len(&self) -> usize {
+        self.idle.lock().unwrap().values().map(Vec::len).sum()
+    }
+}
+
+/// Whether an idle connection has nothing to read: no buffered bytes, and
+/// a peek that would block rather than find data or the end.
+fn is_quiet(connection: &Connection) -> bool {
+    if !connection.buffer().is_empty() {
+        return false;
+    }
+    let socket = connection.get_ref().socket();
+    if socket.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let quiet = matches!(
+        socket.peek(&mut [0; 1]),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock
+    );
+    socket.set_nonblocking(false).is_ok() && quiet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn keeps_live_connections_and_drops_closed_or_stale_ones() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Each client end, with the server end to keep it open or close.
+        let connect = || {
+            let client = TcpStream::connect(addr).unwrap();
+            let (server, _) = listener.accept().unwrap();
+            (BufReader::new(Stream::Plain(client)), server)
+        };
+        let key: Key = (false, "127.0.0.1".into(), addr.port());
+        let minute = Duration::from_secs(60);
+        let pool = Pool::default();
+
+        let (first, _open) = connect();
+        let (second, _also_open) = connect();
+        pool.put(key.clone(), first, 1);
+        pool.put(key.clone(), second, 1);
+        assert_eq!(pool.len(), 1);
+        // Idle for longer than allowed.
+        assert!(pool.take(&key, Duration::ZERO).is_none());
+
+        let (client, server) = connect();
+        pool.put(key.clone(), client, 1);
+        drop(server);
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(pool.take(&key, minute).is_none());
+
+        let (client, _open) = connect();
+        pool.put(key.clone(), client, 1);
+        assert!(pool.take(&key, minute).is_some());
+        assert_eq!(pool.len(), 0);
+    }
+}
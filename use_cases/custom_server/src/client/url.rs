@@ -0,0 +1,181 @@
+//! The slice of URL syntax an HTTP client needs: `http` or `https`, a
+//! host (a name, an IPv4 address or a bracketed IPv6 one), an optional
+//! port, and the path and query that become the request target. No user
+//! info, and any `#fragment` is dropped, as browsers do; it's never sent.
+//!
+//! Concepts: `split_once` and `strip_prefix` for hand parsing, `Display`
+//! to turn a value back into text.
+
+use std::fmt;
+
+/// An absolute `http` or `https` URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub https: bool,
+    /// Without brackets, so an IPv6 host is `::1`.
+    pub host: String,
+    pub port: u16,
+    /// Path and query, always starting with `/`.
+    pub target: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Url, String> {
+        let bad = |why: &str| Err(format!("{url:?}: {why}"));
+        let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+        let https = match scheme.to_ascii_lowercase().as_str() {
+            "http" => false,
+            "https" => true,
+            _ => return bad("not an http:// or https:// URL"),
+        };
+        let rest = rest.split('#').next().unwrap_or("");
+        let split = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, target) = rest.split_at(split);
+        let target = match target {
+            "" => "/".to_string(),
+            t if t.starts_with('?') => format!("/{t}"),
+            t => t.to_string(),
+        };
+        if target
+            .bytes()
+            .any(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+        {
+            return bad("spaces in the path must be percent-encoded");
+        }
+        if authority.contains('@') {
+            return bad("user info is not supported");
+        }
+
+        let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let Some((host, after)) = bracketed.split_once(']') else {
+                return bad("unclosed [");
+            };
+            (host, after.strip_prefix(':'))
+        } else {
+            match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            }
+        };
+        if host.is_empty() {
+            return bad("no host");
+        }
+        let port = match port {
+            None => {
+                if https {
+                    443
+                } else {
+                    80
+                }
+            }
+            Some(port) => match port.parse() {
+                Ok(port) if port != 0 => port,
+                _ => return bad("bad port"),
+            },
+        };
+        Ok(Url {
+            https,
+            host: host.to_ascii_lowercase(),
+            port,
+            target,
+        })
+    }
+
+    /// Where `location` (from a redirect) points, seen from this URL: an
+    /// absolute URL, a path from the root, or a path relative to this
+    /// one's directory.
+    pub fn join(&self, location: &str) -> Result<Url, String> {
+        if location.contains("://") {
+            return Url::parse(location);
+        }
+        let scheme = if self.https { "https" } else { "http" };
+        if let Some(rest) = location.strip_prefix("//") {
+            return Url::parse(&format!("{scheme}://{rest}"));
+        }
+        let target = if location.starts_with('/') {
+            location.to_string()
+        } else {
+            let path = self.target.split('?').next().unwrap_or("/");
+            let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+            format!("{dir}{location}")
+        };
+        Ok(Url {
+            target: if target.is_empty() {
+                "/".into()
+            } else {
+                target
+            },
+            ..self.clone()
+        })
+    }
+
+    /// The `Host` header: the port only when it isn't the scheme's
+    /// default.
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        let default = if self.https { 443 } else { 80 };
+        if self.port == default {
+            host
+        } else {
+            format!("{host}:{}", self.port)
+        }
+    }
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.https { "https" } else { "http" };
+        write!(f, "{scheme}://{}{}", self.authority(), self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hosts_ports_and_targets() {
+        let url = Url::parse("HTTP://Example.com:8080/a/b?c=d#frag").unwrap();
+        assert_eq!(
+            url,
+            Url {
+                https: false,
+                host: "example.com".into(),
+                port: 8080,
+                target: "/a/b?c=d".into(),
+            }
+        );
+        assert_eq!(url.to_string(), "http://example.com:8080/a/b?c=d");
+
+        let v6 = Url::parse("https://[::1]?x").unwrap();
+        assert_eq!((v6.host.as_str(), v6.port), ("::1", 443));
+        assert_eq!(v6.target, "/?x");
+        assert_eq!(v6.authority(), "[::1]");
+
+        for bad in [
+            "ftp://x/",
+            "example.com/",
+            "http:///path",
+            "http://x:0/",
+            "http://x:port/",
+            "http://u:p@x/",
+            "http://x/a b",
+        ] {
+            assert!(Url::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn joins_redirect_locations() {
+        let base = Url::parse("http://a.test:81/docs/guide?page=2").unwrap();
+        let join = |location| base.join(location).unwrap().to_string();
+        assert_eq!(join("https://b.test/x"), "https://b.test/x");
+        assert_eq!(join("//c.test/y"), "http://c.test/y");
+        assert_eq!(join("/root"), "http://a.test:81/root");
+        assert_eq!(join("intro?x=1"), "http://a.test:81/docs/intro?x=1");
+    }
+}
@@ -0,0 +1,477 @@
+//! One connection of the event loop, as a state machine over its buffers.
+//!
+//! A blocking server keeps a connection's progress on its thread's stack:
+//! "halfway through the headers" is simply where `read` is blocked. Here
+//! one thread juggles every connection, so that progress has to live in
+//! the connection itself: the bytes received but not yet parsed (`input`)
+//! and the bytes of responses not yet sent (`output`). The loop calls
+//! `on_readable`/`on_writable` when epoll says the socket is ready, each
+//! does as much as it can without blocking, and the returned `Next` says
+//! what to wait for now.
+//!
+//! A request is parsed from `input` with the same parser the blocking
+//! server uses, reading from a byte slice — but only once it has fully
+//! arrived. Parsing it again from the start on every read would be
+//! quadratic in the number of reads, and a client trickling a large
+//! chunked body a few bytes at a time could keep the loop's one thread
+//! busy re-parsing it. So `Pending` remembers how far the request has been
+//! looked at: the bytes searched for the blank line that ends the head,
+//! then the parsed head and where its body ends (or, for a chunked body,
+//! how far its chunks have been walked). Each read only looks at the new
+//! bytes, and each part is parsed once.
+//!
+//! A response whose body is a file (a large static file) isn't read into
+//! `output` whole: its head goes first, then the file a chunk at a time,
+//! each chunk read only once the previous one has been sent. Requests
+//! pipelined behind it wait until it's done, so answers stay in order.
+//!
+//! Handlers run on the loop's one thread, so a handler that panics would
+//! take every connection down with it. As the thread pool does for its
+//! jobs, the call is wrapped in `catch_unwind`: the client gets a `500`,
+//! its connection closes, and the loop carries on.
+//!
+//! Concepts: explicit state instead of a call stack, `WouldBlock` as "try
+//! again when epoll says so", `Vec::drain` to consume a buffer's front,
+//! `catch_unwind`.
+
+use std::fs::File;
+use std::io::{self, Read, Take, Write};
+use std::mem;
+use std::net::{SocketAddr, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::time::Instant;
+
+use super::epoll::Interest;
+use crate::http::request::{Framing, MAX_CHUNK_LINE};
+use crate::http::{Limits, ParseError, Request, Response, StatusCode};
+use crate::router::Router;
+use crate::{ConnectionConfig, READ_TIMEOUT, answer, reject};
+
+/// Bytes read from a socket at a time.
+const READ_CHUNK: usize = 16 * 1024;
+
+/// Bytes of a file body read into `output` at a time.
+const FILE_CHUNK: u64 = 64 * 1024;
+
+/// Most bytes `input` may hold. The worst case for a request the limits
+/// allow is a maximal chunked body with every byte in its own chunk; a
+/// request still incomplete past that never will be.
+fn input_cap(config: &ConnectionConfig) -> usize {
+    config.limits.max_head * 2 + config.limits.max_body * 8
+}
+
+/// What a connection waits for next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Next {
+    /// The next bytes of a request.
+    Read,
+    /// Room to send the rest of `output`. Nothing more is read meanwhile,
+    /// so a client that pipelines requests but never reads the answers
+    /// can't make the server buffer without bound.
+    Write,
+    /// Nothing: close it.
+    Close,
+}
+
+pub struct Connection {
+    pub stream: TcpStream,
+    peer: SocketAddr,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    /// How much of `output` has been sent.
+    written: usize,
+    /// The rest of a file body, to follow `output`.
+    file: Option<Take<File>>,
+    /// How far the request at the front of `input` has been parsed.
+    pending: Pending,
+    /// Requests answered so far.
+    served: usize,
+    /// Close once `output` has been sent.
+    closing: bool,
+    /// The server is shutting down: answer the request in progress, if
+    /// any, and then close.
+    draining: bool,
+    /// When the connection times out if nothing happens first.
+    pub deadline: Instant,
+    /// What it's registered with epoll for.
+    pub interest: Interest,
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream, peer: SocketAddr, config: &ConnectionConfig) -> Connection {
+        Connection {
+            stream,
+            peer,
+            input: Vec::new(),
+            output: Vec::new(),
+            written: 0,
+            file: None,
+            pending: Pending::Head { scanned: 0 },
+            served: 0,
+            closing: false,
+            draining: false,
+            deadline: Instant::now() + config.idle_timeout,
+            interest: Interest::Read,
+        }
+    }
+
+    /// Between requests, with nothing to send: closing it loses nothing.
+    pub fn is_idle(&self) -> bool {
+        self.input.is_empty() && self.output.is_empty() && self.file.is_none()
+    }
+
+    /// Answer the request in progress, if any, and then close.
+    pub fn drain(&mut self) {
+        self.draining = true;
+    }
+
+    /// Read what has arrived and answer every complete request in it.
+    pub fn on_readable(&mut self, router: &Router, config: &ConnectionConfig) -> Next {
+        let was_empty = self.input.is_empty();
+        loop {
+            let len = self.input.len();
+            self.input.resize(len + READ_CHUNK, 0);
+            match self.stream.read(&mut self.input[len..]) {
+                Ok(0) => {
+                    // The client closed its side; anything it left
+                    // unfinished can't be completed.
+                    self.input.truncate(len);
+                    return Next::Close;
+                }
+                Ok(n) => self.input.truncate(len + n),
+                Err(err) => {
+                    self.input.truncate(len);
+                    match err.kind() {
+                        io::ErrorKind::WouldBlock => break,
+                        io::ErrorKind::Interrupted => continue,
+                        _ => return Next::Close,
+                    }
+                }
+            }
+            if self.input.len() > input_cap(config) {
+                break;
+            }
+        }
+        if was_empty && !self.input.is_empty() {
+            self.deadline = Instant::now() + READ_TIMEOUT;
+        }
+        self.process(router, config)
+    }
+
+    /// Send more of `output`; once it's all gone, carry on with any
+    /// requests that arrived meanwhile.
+    pub fn on_writable(&mut self, router: &Router, config: &ConnectionConfig) -> Next {
+        loop {
+            while self.written < self.output.len() {
+                match self.stream.write(&self.output[self.written..]) {
+                    Ok(0) => return Next::Close,
+                    Ok(n) => {
+                        self.written += n;
+                        self.deadline = Instant::now() + READ_TIMEOUT;
+                    }
+                    Err(err) => match err.kind() {
+                        io::ErrorKind::WouldBlock => return Next::Write,
+                        io::ErrorKind::Interrupted => continue,
+                        _ => return Next::Close,
+                    },
+                }
+            }
+            self.output.clear();
+            self.written = 0;
+            match self.next_file_chunk() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => {
+                    eprintln!("{} {err}", self.peer);
+                    return Next::Close;
+                }
+            }
+        }
+        if self.closing || (self.draining && self.input.is_empty()) {
+            return Next::Close;
+        }
+        self.deadline = Instant::now()
+            + if self.input.is_empty() {
+                config.idle_timeout
+            } else {
+                READ_TIMEOUT
+            };
+        self.process(router, config)
+    }
+
+    /// Answer the complete requests at the front of `input`, then start
+    /// sending the answers.
+    fn process(&mut self, router: &Router, config: &ConnectionConfig) -> Next {
+        while !self.closing && self.file.is_none() {
+            let request = match self.parse(config) {
+                Ok(Some(request)) => request,
+                Ok(None) => break,
+                Err(err) => {
+                    if let Some(response) = reject(self.peer, &err) {
+                        let _ = response.write_to(&mut self.output);
+                    }
+                    self.closing = true;
+                    break;
+                }
+            };
+            self.served += 1;
+            let may_keep_alive = self.served < config.max_requests && !self.draining;
+            let peer = self.peer;
+            let answered = panic::catch_unwind(AssertUnwindSafe(|| {
+                answer(peer, router, request, may_keep_alive)
+            }));
+            let Ok(answer) = answered else {
+                // The panic hook has printed the message and location.
+                eprintln!("{peer} handler panicked; closing the connection");
+                let _ = Response::error(StatusCode::INTERNAL_SERVER_ERROR)
+                    .with_header("Connection", "close")
+                    .write_to(&mut self.output);
+                self.closing = true;
+                break;
+            };
+            self.closing = !answer.keep_alive;
+            let Some(file) = answer.file() else {
+                let _ = answer.write_to(&mut self.output);
+                continue;
+            };
+            let _ = answer.write_head_to(&mut self.output);
+            match file.open() {
+                Ok(file) => self.file = Some(file),
+                Err(err) => {
+                    // The head promised a body that can't be sent.
+                    eprintln!("{}: {err}", file.path.display());
+                    self.closing = true;
+                }
+            }
+        }
+        if self.input.len() > input_cap(config) {
+            self.closing = true;
+        }
+        if self.output.is_empty() {
+            return if self.closing {
+                Next::Close
+            } else {
+                Next::Read
+            };
+        }
+        self.on_writable(router, config)
+    }
+
+    /// Move the file body's next chunk into the empty `output`: `false`
+    /// once there's none left. A file that ends early is an error, since
+    /// its length has been promised.
+    fn next_file_chunk(&mut self) -> io::Result<bool> {
+        let Some(file) = &mut self.file else {
+            return Ok(false);
+        };
+        let read = file
+            .by_ref()
+            .take(FILE_CHUNK)
+            .read_to_end(&mut self.output)?;
+        if read > 0 {
+            return Ok(true);
+        }
+        let short = file.limit() > 0;
+        self.file = None;
+        if short {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file shorter than when it was sized",
+            ));
+        }
+        Ok(false)
+    }
+
+    /// The request at the front of `input`, removing it from there, or
+    /// `None` if it hasn't fully arrived.
+    fn parse(&mut self, config: &ConnectionConfig) -> Result<Option<Request>, ParseError> {
+        let limits = &config.limits;
+        if let Pending::Head { scanned } = &mut self.pending {
+            // Empty lines before a request are ignored; dropping them here
+            // means the first blank line found is the end of the head.
+            let blank = self
+                .input
+                .iter()
+                .take_while(|&&b| b == b'\r' || b == b'\n')
+                .count();
+            self.input.drain(..blank);
+            let from = scanned.saturating_sub(blank + 2);
+            let ended = ends_head(&self.input[from..]);
+            if !ended && self.input.len() < limits.max_head {
+                *scanned = self.input.len();
+                return Ok(None);
+            }
+            let mut rest = &self.input[..];
+            let request = match Request::read_head(&mut rest, limits) {
+                Ok(Some(request)) => request,
+                Ok(None) => return Ok(None),
+                Err(err) if err.is_incomplete() => {
+                    *scanned = self.input.len();
+                    return Ok(None);
+                }
+                Err(err) => return Err(err),
+            };
+            let start = self.input.len() - rest.len();
+            let body = match request.framing()? {
+                Framing::None => BodyEnd::At(start),
+                // `read_head` has checked it against `max_body`.
+                Framing::Length(len) => BodyEnd::At(start + len as usize),
+                Framing::Chunked => BodyEnd::Chunked(Chunks::new(start)),
+            };
+            if request.expects_continue() {
+                self.output
+                    .extend_from_slice(b"HTTP/1.1 100 Continue\r\n\r\n");
+            }
+            self.pending = Pending::Body {
+                request,
+                start,
+                body,
+            };
+        }
+
+        let Pending::Body {
+            request,
+            start,
+            body,
+        } = &mut self.pending
+        else {
+            unreachable!("the head was parsed above");
+        };
+        let arrived = match body {
+            BodyEnd::At(end) => self.input.len() >= *end,
+            BodyEnd::Chunked(chunks) => chunks.scan(&self.input, limits),
+        };
+        if !arrived {
+            return Ok(None);
+        }
+        let mut rest = &self.input[*start..];
+        match request.read_body(&mut rest, limits) {
+            Ok(()) => {}
+            Err(err) if err.is_incomplete() => return Ok(None),
+            Err(err) => return Err(err),
+        }
+        let used = self.input.len() - rest.len();
+        let Pending::Body { request, .. } =
+            mem::replace(&mut self.pending, Pending::Head { scanned: 0 })
+        else {
+            unreachable!("matched above");
+        };
+        self.input.drain(..used);
+        Ok(Some(request))
+    }
+}
+
+/// How far the request at the front of `input` has been looked at.
+enum Pending {
+    /// Its head hasn't ended within the first `scanned` bytes.
+    Head { scanned: usize },
+    /// The head is parsed; the body starts at `start`.
+    Body {
+        request: Request,
+        start: usize,
+        body: BodyEnd,
+    },
+}
+
+/// Where a body ends.
+enum BodyEnd {
+    /// At this offset in `input` (`Content-Length`, or no body).
+    At(usize),
+    /// After the last chunk, which hasn't been seen yet.
+    Chunked(Chunks),
+}
+
+/// Whether `bytes` holds a blank line: `\n\n` or `\n\r\n`.
+fn ends_head(bytes: &[u8]) -> bool {
+    bytes
+        .iter()
+        .enumerate()
+        .any(|(i, &b)| b == b'\n' && matches!(&bytes[i + 1..], [b'\n', ..] | [b'\r', b'\n', ..]))
+}
+
+/// A walk over a chunked body as it arrives, just far enough to tell when
+/// it has all arrived; decoding it is left to the parser. Anything
+/// malformed or over the limits counts as arrived too, so the parser can
+/// say what's wrong with it.
+struct Chunks {
+    /// Offset in `input` of the next line to look at.
+    pos: usize,
+    /// Body bytes announced so far.
+    body: usize,
+    /// Trailer bytes so far.
+    trailers: usize,
+    stage: Stage,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    /// A chunk-size line.
+    Size,
+    /// Chunk data up to `pos`, then the empty line after it.
+    Data,
+    /// Trailer lines, up to an empty one.
+    Trailers,
+}
+
+impl Chunks {
+    fn new(start: usize) -> Chunks {
+        Chunks {
+            pos: start,
+            body: 0,
+            trailers: 0,
+            stage: Stage::Size,
+        }
+    }
+
+    /// Walk what has arrived since the last call: `true` once the body
+    /// is complete (or can't be).
+    fn scan(&mut self, input: &[u8], limits: &Limits) -> bool {
+        loop {
+            let Some(rest) = input.get(self.pos..) else {
+                return false;
+            };
+            // Line lengths count their `\n`, as the parser's budgets do.
+            let budget = match self.stage {
+                Stage::Size => MAX_CHUNK_LINE,
+                Stage::Data => 2,
+                Stage::Trailers => limits.max_head - self.trailers,
+            };
+            let Some(newline) = rest.iter().take(budget).position(|&b| b == b'\n') else {
+                return rest.len() >= budget;
+            };
+            let line = &rest[..newline];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            self.pos += newline + 1;
+            match self.stage {
+                Stage::Size => {
+                    let size = line.split(|&b| b == b';').next().unwrap_or(b"");
+                    let size = size.trim_ascii_end();
+                    let Some(size) = std::str::from_utf8(size)
+                        .ok()
+                        .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit()))
+                        .and_then(|s| usize::from_str_radix(s, 16).ok())
+                    else {
+                        return true;
+                    };
+                    if size == 0 {
+                        self.stage = Stage::Trailers;
+                    } else if size > limits.max_body - self.body {
+                        return true;
+                    } else {
+                        self.body += size;
+                        self.pos += size;
+                        self.stage = Stage::Data;
+                    }
+                }
+                Stage::Data if line.is_empty() => self.stage = Stage::Size,
+                Stage::Data => return true,
+                Stage::Trailers => {
+                    if line.is_empty() {
+                        return true;
+                    }
+                    self.trailers += newline + 1;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! A thin safe wrapper over the three epoll system calls.
+//!
+//! The kernel keeps an *interest list* of file descriptors per epoll
+//! instance, each tagged with a number of our choosing (the token);
+//! `wait` blocks until some of them are ready and reports their tokens.
+//! Registrations are level-triggered: a socket with unread bytes keeps
+//! being reported until they're read, so a handler that stops early
+//! loses nothing, it just gets called again.
+//!
+//! Concepts: FFI through `libc`, `unsafe` blocks kept small and each
+//! justified, `OwnedFd` closing the descriptor on drop,
+//! `io::Error::last_os_error`.
+
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::time::Duration;
+
+/// What readiness to report for a descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interest {
+    /// Data (or end of stream) to read.
+    Read,
+    /// Room in the send buffer.
+    Write,
+}
+
+impl Interest {
+    fn bits(self) -> u32 {
+        match self {
+            Interest::Read => (libc::EPOLLIN | libc::EPOLLRDHUP) as u32,
+            Interest::Write => libc::EPOLLOUT as u32,
+        }
+    }
+}
+
+/// One readiness report from `wait`.
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub token: u64,
+    /// The peer hung up or the socket failed; reading will say which.
+    pub closed: bool,
+}
+
+pub struct Epoll {
+    fd: OwnedFd,
+    events: Vec<libc::epoll_event>,
+}
+
+impl Epoll {
+    pub fn new() -> io::Result<Epoll> {
+        // SAFETY: no pointers involved; the result is checked below.
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Epoll {
+            // SAFETY: `fd` was just created and nothing else owns it.
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            events: Vec::with_capacity(1024),
+        })
+    }
+
+    pub fn add(&self, fd: &impl AsRawFd, token: u64, interest: Interest) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_ADD, fd.as_raw_fd(), token, interest)
+    }
+
+    /// Change what `fd` is watched for.
+    pub fn modify(&self, fd: &impl AsRawFd, token: u64, interest: Interest) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_MOD, fd.as_raw_fd(), token, interest)
+    }
+
+    /// Stop watching `fd`. Closing it does this too, as long as no other
+    /// descriptor refers to the same socket.
+    pub fn delete(&self, fd: &impl AsRawFd) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_DEL, fd.as_raw_fd(), 0, Interest::Read)
+    }
+
+    fn control(
+        &self,
+        op: libc::c_int,
+        fd: RawFd,
+        token: u64,
+        interest: Interest,
+    ) -> io::Result<()> {
+        let mut event = libc::epoll_event {
+            events: interest.bits(),
+            u64: token,
+        };
+        // SAFETY: `event` outlives the call, which only reads it.
+        let result = unsafe { libc::epoll_ctl(self.fd.as_raw_fd(), op, fd, &mut event) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout` (forever if `None`) for descriptors to become
+    /// ready, and return what's ready. An interrupted wait returns no
+    /// events rather than an error.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> io::Result<Vec<Event>> {
+        let timeout = match timeout {
+            // Round up, so a timer due in 0.3 ms isn't polled for in a
+            // busy loop of zero-length waits.
+            Some(timeout) => timeout
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .min(libc::c_int::MAX as u128) as libc::c_int,
+            None => -1,
+        };
+        // SAFETY: the kernel writes at most `capacity` events into the
+        // buffer.
+        let ready = unsafe {
+            libc::epoll_wait(
+                self.fd.as_raw_fd(),
+                self.events.as_mut_ptr(),
+                self.events.capacity() as libc::c_int,
+                timeout,
+            )
+        };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            return match err.kind() {
+                io::ErrorKind::Interrupted => Ok(Vec::new()),
+                _ => Err(err),
+            };
+        }
+        // SAFETY: the kernel initialized the first `ready` events.
+        unsafe { self.events.set_len(ready as usize) };
+        let hangup = (libc::EPOLLHUP | libc::EPOLLRDHUP | libc::EPOLLERR) as u32;
+        Ok(self
+            .events
+            .iter()
+            .map(|event| Event {
+                token: event.u64,
+                closed: event.events & hangup != 0,
+            })
+            .collect())
+    }
+}
+
+/// Raise this process's open-file limit to the most it's allowed, since
+/// every connection is a descriptor and the usual soft limit is 1024.
+/// Returns the new limit.
+pub fn raise_fd_limit() -> io::Result<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: `limit` is a valid `rlimit` for the kernel to fill in and
+    // then read back.
+    unsafe {
+        if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if limit.rlim_cur < limit.rlim_max {
+            limit.rlim_cur = limit.rlim_max;
+            if libc::setrlimit(libc::RLIMIT_NOFILE, &limit) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(limit.rlim_cur)
+}
+
+/// Let up to `backlog` connections wait for `accept` on `listener`
+/// (the kernel caps it at `net.core.somaxconn`). The standard library
+/// listens with a backlog of 128, and a burst beyond that is dropped: the
+/// clients only retry a second later.
+pub fn set_backlog(listener: &TcpListener, backlog: i32) -> io::Result<()> {
+    // SAFETY: no pointers involved; calling `listen` again on a listening
+    // socket only changes its backlog.
+    if unsafe { libc::listen(listener.as_raw_fd(), backlog) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpStream;
+
+    #[test]
+    fn reports_readiness_by_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut epoll = Epoll::new().unwrap();
+        epoll.add(&listener, 7, Interest::Read).unwrap();
+        assert!(epoll.wait(Some(Duration::ZERO)).unwrap().is_empty());
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let events = epoll.wait(Some(Duration::from_secs(1))).unwrap();
+        assert_eq!(events.iter().map(|e| e.token).collect::<Vec<_>>(), [7]);
+
+        let (server, _) = listener.accept().unwrap();
+        epoll.add(&server, 8, Interest::Write).unwrap();
+        assert_eq!(epoll.wait(Some(Duration::ZERO)).unwrap()[0].token, 8);
+        epoll.modify(&server, 8, Interest::Read).unwrap();
+        assert!(epoll.wait(Some(Duration::ZERO)).unwrap().is_empty());
+        client.write_all(b"x").unwrap();
+        assert_eq!(
+            epoll.wait(Some(Duration::from_secs(1))).unwrap()[0].token,
+            8
+        );
+
+        drop(client);
+        let event = epoll.wait(Some(Duration::from_secs(1))).unwrap()[0];
+        assert!(event.closed);
+        epoll.delete(&server).unwrap();
+        assert!(epoll.wait(Some(Duration::ZERO)).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,522 @@
+//! A single-threaded server core on Linux epoll, as an alternative to the
+//! blocking `server::Server`.
+//!
+//! The blocking server spends a thread per connection, and an idle
+//! keep-alive connection holds one for as long as it stays open: with
+//! sixteen workers, sixteen idle browsers are enough to make everyone
+//! else wait. Here every socket is non-blocking and registered with one
+//! epoll instance. The loop sleeps in `epoll_wait` until some of them can
+//! make progress, then lets each `Connection` read, parse, answer and
+//! write as far as it can without blocking. An idle connection costs a
+//! file descriptor and a few buffers, so one thread holds ten thousand
+//! of them.
+//!
+//! Timeouts are a min-heap of deadlines, and the earliest one bounds how
+//! long `epoll_wait` may sleep. A connection's deadline moves whenever it
+//! makes progress; rather than finding and updating its old heap entry,
+//! the new deadline is pushed, and an entry that no longer matches its
+//! connection's deadline is skipped when it comes due. Unlike the blocking
+//! server's per-read timeout, a request gets `READ_TIMEOUT` from its first
+//! byte to its last, which also defeats clients that trickle in a byte at
+//! a time.
+//!
+//! Handlers run on the loop thread, so a slow one stalls every connection:
+//! this core suits many mostly-idle connections with quick handlers, the
+//! blocking one handlers that take their time.
+//!
+//! Concepts: readiness-based I/O, non-blocking sockets, a slab of
+//! connections addressed by index, `BinaryHeap` with `Reverse` as a timer
+//! queue.
+
+mod connection;
+pub mod epoll;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use crate::ConnectionConfig;
+use crate::http::Limits;
+use crate::router::Router;
+use crate::server::Shutdown;
+use connection::{Connection, Next};
+use epoll::{Epoll, Interest};
+
+/// The listener's token; connections use their index in the slab.
+const LISTENER: u64 = u64::MAX;
+
+/// Connections the kernel may queue for `accept`. The loop accepts
+/// between handling events, so a burst of new clients arriving while it's
+/// busy needs somewhere to wait.
+const BACKLOG: i32 = 4096;
+
+/// How long to stop accepting after `accept` fails, say because the
+/// process is out of file descriptors. The listener stays readable, so
+/// retrying straight away would spin.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A bound listener served by an epoll loop on the calling thread.
+pub struct EventLoop {
+    listener: TcpListener,
+    router: Router,
+    config: ConnectionConfig,
+    shutdown: Shutdown,
+}
+
+impl EventLoop {
+    /// Bind `addr` (port 0 picks a free one; see `local_addr`).
+    pub fn bind(addr: impl ToSocketAddrs, router: Router) -> io::Result<EventLoop> {
+        let listener = TcpListener::bind(addr)?;
+        epoll::set_backlog(&listener, BACKLOG)?;
+        let shutdown = Shutdown::new(listener.local_addr()?);
+        Ok(EventLoop {
+            listener,
+            router,
+            config: ConnectionConfig::default(),
+            shutdown,
+        })
+    }
+
+    pub fn limits(mut self, limits: Limits) -> EventLoop {
+        self.config.limits = limits;
+        self
+    }
+
+    /// How long an open connection may wait for its next request.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> EventLoop {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Requests answered on one connection before it's closed.
+    pub fn max_requests(mut self, max_requests: usize) -> EventLoop {
+        self.config.max_requests = max_requests;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// A handle that stops `run` from another thread.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Serve connections until shutdown is requested, then finish the
+    /// requests in progress and close everything.
+    pub fn run(self) -> io::Result<()> {
+        let EventLoop {
+            listener,
+            router,
+            config,
+            shutdown,
+        } = self;
+        listener.set_nonblocking(true)?;
+        let mut epoll = Epoll::new()?;
+        epoll.add(&listener, LISTENER, Interest::Read)?;
+        let mut listener = Some(listener);
+        let mut accept_paused_until = None;
+
+        let mut connections = Slab::default();
+        let mut timers: BinaryHeap<Reverse<(Instant, usize)>> = BinaryHeap::new();
+
+        loop {
+            if shutdown.is_requested()
+                && let Some(listener) = listener.take()
+            {
+                // Closing the listener refuses new connections; idle ones
+                // go now, busy ones once they've answered.
+                drop(listener);
+                for id in connections.ids() {
+                    let connection = connections.get_mut(id).expect("listed");
+                    if connection.is_idle() {
+                        connections.remove(id);
+                    } else {
+                        connection.drain();
+                    }
+                }
+            }
+            if listener.is_none() && connections.is_empty() {
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            let next_timer = timers.peek().map(|Reverse((when, _))| *when);
+            let wake = next_timer.into_iter().chain(accept_paused_until).min();
+            let events = epoll.wait(wake.map(|when| when.saturating_duration_since(now)))?;
+
+            for event in events {
+                if event.token == LISTENER {
+                    if let Some(listener) = &listener
+                        && !accept(listener, &epoll, &mut connections, &mut timers, &config)
+                    {
+                        epoll.delete(listener)?;
+                        accept_paused_until = Some(Instant::now() + ACCEPT_BACKOFF);
+                    }
+                    continue;
+                }
+
+                // An event for a connection closed earlier in this batch
+                // finds its slot empty or reused; a spurious call to
+                // `on_readable` just finds nothing to read.
+                let id = event.token as usize;
+                let Some(connection) = connections.get_mut(id) else {
+                    continue;
+                };
+                let before = connection.deadline;
+                let next = match connection.interest {
+                    Interest::Read => connection.on_readable(&router, &config),
+                    Interest::Write => connection.on_writable(&router, &config),
+                };
+                let interest = match next {
+                    Next::Read => Interest::Read,
+                    Next::Write => Interest::Write,
+                    Next::Close => {
+                        connections.remove(id);
+                        continue;
+                    }
+                };
+                if interest != connection.interest {
+                    epoll.modify(&connection.stream, id as u64, interest)?;
+                    connection.interest = interest;
+                }
+                if connection.deadline != before {
+                    timers.push(Reverse((connection.deadline, id)));
+                }
+            }
+
+            let now = Instant::now();
+            if let Some(until) = accept_paused_until
+                && until <= now
+            {
+                accept_paused_until = None;
+                if let Some(listener) = &listener {
+                    epoll.add(listener, LISTENER, Interest::Read)?;
+                }
+            }
+            while let Some(&Reverse((when, id))) = timers.peek() {
+                if when > now {
+                    break;
+                }
+                timers.pop();
+                // Skip entries left behind when the deadline moved.
+                if connections.get_mut(id).is_some_and(|c| c.deadline == when) {
+                    connections.remove(id);
+                }
+            }
+        }
+    }
+}
+
+/// Accept every connection waiting on `listener` and register it. `false`
+/// if accepting failed and should pause for a while.
+fn accept(
+    listener: &TcpListener,
+    epoll: &Epoll,
+    connections: &mut Slab,
+    timers: &mut BinaryHeap<Reverse<(Instant, usize)>>,
+    config: &ConnectionConfig,
+) -> bool {
+    loop {
+        let (stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(err) => match err.kind() {
+                io::ErrorKind::WouldBlock => return true,
+                io::ErrorKind::Interrupted | io::ErrorKind::ConnectionAborted => continue,
+                _ => {
+                    eprintln!("failed to accept connection: {err}");
+                    return false;
+                }
+            },
+        };
+        if let Err(err) = stream.set_nonblocking(true) {
+            eprintln!("{peer}: {err}");
+            continue;
+        }
+        let id = connections.insert(Connection::new(stream, peer, config));
+        let connection = connections.get_mut(id).expect("just inserted");
+        if let Err(err) = epoll.add(&connection.stream, id as u64, Interest::Read) {
+            eprintln!("{peer}: {err}");
+            connections.remove(id);
+            continue;
+        }
+        timers.push(Reverse((connection.deadline, id)));
+    }
+}
+
+/// Open connections by id, reusing the ids of closed ones so the table
+/// stays as small as the peak number of connections.
+#[derive(Default)]
+struct Slab {
+    slots: Vec<Option<Connection>>,
+    free: Vec<usize>,
+    len: usize,
+}
+
+impl Slab {
+    fn insert(&mut self, connection: Connection) -> usize {
+        self.len += 1;
+        match self.free.pop() {
+            Some(id) => {
+                self.slots[id] = Some(connection);
+                id
+            }
+            None => {
+                self.slots.push(Some(connection));
+                self.slots.len() - 1
+            }
+        }
+    }
+
+    /// Drop the connection, which closes its socket and with that takes it
+    /// off the epoll interest list.
+    fn remove(&mut self, id: usize) {
+        if let Some(slot) = self.slots.get_mut(id)
+            && slot.take().is_some()
+        {
+            self.len -= 1;
+            self.free.push(id);
+        }
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Connection> {
+        self.slots.get_mut(id)?.as_mut()
+    }
+
+    fn ids(&self) -> Vec<usize> {
+        (0..self.slots.len())
+            .filter(|&id| self.slots[id].is_some())
+            .collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Request, Response, StatusCode};
+    use crate::test_util::TempDir;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |_| Response::text(StatusCode::OK, "hi"))
+            .get("/n/:n", |r: &Request| {
+                Response::text(StatusCode::OK, r.param("n").unwrap().to_string())
+            })
+            .get("/big", |_| {
+                Response::new(StatusCode::OK).with_body(vec![b'x'; 4 << 20])
+            })
+            .route(crate::http::Method::Post, "/echo", |r: &Request| {
+                Response::new(StatusCode::OK).with_body(r.body.clone())
+            })
+            .get("/panic", |_| panic!("handler bug"))
+    }
+
+    fn start(event_loop: EventLoop) -> (SocketAddr, Shutdown, thread::JoinHandle<io::Result<()>>) {
+        let addr = event_loop.local_addr().unwrap();
+        let shutdown = event_loop.shutdown_handle();
+        (addr, shutdown, thread::spawn(move || event_loop.run()))
+    }
+
+    fn read_all(stream: &mut TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn answers_requests_that_arrive_piecemeal_or_pipelined() {
+        let (addr, shutdown, server) = start(EventLoop::bind("127.0.0.1:0", router()).unwrap());
+
+        // A byte at a time, body included.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let raw = "POST /echo HTTP/1.1\r\nHost: t\r\nConnection: close\r\n\
+                   Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        for b in raw.bytes() {
+            stream.write_all(&[b]).unwrap();
+            thread::sleep(Duration::from_micros(200));
+        }
+        assert!(read_all(&mut stream).ends_with("\r\n\r\nhello"));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let get = |n: u32, last: bool| {
+            let close = if last { "Connection: close\r\n" } else { "" };
+            format!("GET /n/{n} HTTP/1.1\r\nHost: t\r\n{close}\r\n")
+        };
+        let pipelined: String = (1..=20).map(|n| get(n, n == 20)).collect();
+        stream.write_all(pipelined.as_bytes()).unwrap();
+        let responses = read_all(&mut stream);
+        let bodies: Vec<&str> = responses
+            .split("HTTP/1.1 200 OK\r\n")
+            .skip(1)
+            .map(|r| r.rsplit("\r\n\r\n").next().unwrap())
+            .collect();
+        let expected: Vec<String> = (1..=20).map(|n| n.to_string()).collect();
+        assert_eq!(bodies, expected);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"BREW / HTTP/1.1\r\n\r\n").unwrap();
+        assert!(read_all(&mut stream).starts_with("HTTP/1.1 501 "));
+
+        shutdown.request();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn a_body_trickling_in_does_not_hold_up_other_connections() {
+        let (addr, shutdown, server) = start(EventLoop::bind("127.0.0.1:0", router()).unwrap());
+
+        // 100k one-byte chunks, then more of them a few at a time.
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.set_nodelay(true).unwrap();
+        slow.write_all(b"POST /echo HTTP/1.1\r\nHost: t\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n")
+            .unwrap();
+        slow.write_all(&b"1\r\na\r\n".repeat(100_000)).unwrap();
+        let trickle = thread::spawn(move || {
+            for _ in 0..200 {
+                slow.write_all(b"1\r\na\r\n").unwrap();
+                thread::sleep(Duration::from_millis(2));
+            }
+            slow.write_all(b"0\r\n\r\n").unwrap();
+            read_all(&mut slow)
+        });
+
+        // Past the one-off scan of the bulk, while the trickle goes on.
+        thread::sleep(Duration::from_millis(100));
+        let mut fast = TcpStream::connect(addr).unwrap();
+        let mut slowest = Duration::ZERO;
+        for _ in 0..20 {
+            let started = Instant::now();
+            fast.write_all(b"GET / HTTP/1.1\r\nHost: t\r\n\r\n")
+                .unwrap();
+            let mut response = [0; 256];
+            let n = fast.read(&mut response).unwrap();
+            assert!(response[..n].ends_with(b"hi"));
+            slowest = slowest.max(started.elapsed());
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(slowest < Duration::from_millis(50), "{slowest:?}");
+        assert!(trickle.join().unwrap().ends_with(&"a".repeat(100_200)));
+
+        shutdown.request();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn a_panicking_handler_gets_a_500_and_the_loop_carries_on() {
+        let (addr, shutdown, server) = start(EventLoop::bind("127.0.0.1:0", router()).unwrap());
+
+        // Requests pipelined after the panicking one go unanswered.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /panic HTTP/1.1\r\nHost: t\r\n\r\nGET / HTTP/1.1\r\nHost: t\r\n\r\n")
+            .unwrap();
+        let response = read_all(&mut stream);
+        assert!(response.starts_with("HTTP/1.1 500 "), "{response}");
+        assert!(response.contains("Connection: close\r\n"));
+        assert_eq!(response.matches("HTTP/1.1").count(), 1);
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: t\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        assert!(read_all(&mut stream).ends_with("hi"));
+
+        shutdown.request();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn file_bodies_are_sent_in_chunks_ahead_of_pipelined_answers() {
+        let dir = TempDir::new("loop");
+        let content: Vec<u8> = (0..(1 << 20)).map(|i| (i % 251) as u8).collect();
+        let file = dir.file("file", &content);
+        let router = router().get("/file", move |_| {
+            Response::new(StatusCode::OK).with_file(&file, 5..(1 << 20))
+        });
+        let (addr, shutdown, server) = start(EventLoop::bind("127.0.0.1:0", router).unwrap());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /file HTTP/1.1\r\nHost: t\r\n\r\nGET /n/2 HTTP/1.1\r\nHost: t\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        let body = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert_eq!(&received[body..body + content.len() - 5], &content[5..]);
+        assert!(received.ends_with(b"\r\n\r\n2"));
+
+        shutdown.request();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn large_responses_wait_for_a_slow_reader() {
+        let (addr, shutdown, server) = start(EventLoop::bind("127.0.0.1:0", router()).unwrap());
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(b"GET /big HTTP/1.1\r\nHost: t\r\n\r\nGET / HTTP/1.1\r\nHost: t\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        // The loop keeps serving others while `slow` isn't reading.
+        thread::sleep(Duration::from_millis(100));
+        let mut other = TcpStream::connect(addr).unwrap();
+        other
+            .write_all(b"GET / HTTP/1.1\r\nHost: t\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        assert!(read_all(&mut other).ends_with("hi"));
+
+        let mut received = Vec::new();
+        slow.read_to_end(&mut received).unwrap();
+        let body = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        assert!(received[body..body + (4 << 20)].iter().all(|&b| b == b'x'));
+        assert!(received.ends_with(b"\r\n\r\nhi"));
+
+        shutdown.request();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn many_idle_connections_then_timeouts_and_shutdown() {
+        let event_loop = EventLoop::bind("127.0.0.1:0", router())
+            .unwrap()
+            .idle_timeout(Duration::from_millis(300));
+        let (addr, shutdown, server) = start(event_loop);
+
+        let idle: Vec<TcpStream> = (0..500)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: t\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        assert!(read_all(&mut stream).ends_with("hi"));
+
+        // The idle ones time out and are closed by the server.
+        thread::sleep(Duration::from_millis(500));
+        for mut stream in idle {
+            assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+        }
+
+        // A request in progress at shutdown is still answered.
+        let mut busy = TcpStream::connect(addr).unwrap();
+        busy.write_all(b"GET / HTTP/1.1\r\nHost: t\r\n").unwrap();
+        thread::sleep(Duration::from_millis(50));
+        shutdown.request();
+        thread::sleep(Duration::from_millis(50));
+        busy.write_all(b"\r\n").unwrap();
+        let answered = read_all(&mut busy);
+        assert!(answered.contains("Connection: close\r\n"));
+        assert!(answered.ends_with("hi"));
+        server.join().unwrap().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+//! HTTP dates, as in `Last-Modified` and `If-Modified-Since`.
+//!
+//! HTTP uses one fixed format, `Sun, 06 Nov 1994 08:49:37 GMT`, always in
+//! GMT and to the second. Converting between that and a `SystemTime` is
+//! calendar arithmetic on the days since 1970-01-01: the conversions below
+//! are Howard Hinnant's `civil_from_days`/`days_from_civil`, which shift
+//! the year to start in March so the leap day falls at the end.
+//!
+//! Only this format is parsed. RFC 9110 also lists two obsolete ones, but
+//! the only dates this server reads are ones it sent out itself and a
+//! client echoes back; one it can't read just means a conditional request
+//! is answered in full, which is always allowed.
+//!
+//! Concepts: `SystemTime` and `UNIX_EPOCH`, `div_euclid`/`rem_euclid`
+//! for floor division.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// `time` as an HTTP date; anything before 1970 is written as 1970.
+pub fn format(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    let days = secs.div_euclid(86_400);
+    let of_day = secs.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    // 1970-01-01 was a Thursday.
+    let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+    format!(
+        "{weekday}, {day:02} {} {year:04} {:02}:{:02}:{:02} GMT",
+        MONTHS[month as usize - 1],
+        of_day / 3600,
+        of_day / 60 % 60,
+        of_day % 60
+    )
+}
+
+/// Parse an HTTP date, or `None` if it isn't one.
+pub fn parse(s: &str) -> Option<SystemTime> {
+    let fields: Vec<&str> = s.split(' ').collect();
+    let [weekday, day, month, year, time, "GMT"] = fields[..] else {
+        return None;
+    };
+    if !WEEKDAYS.contains(&weekday.strip_suffix(',')?) || day.len() != 2 || year.len() != 4 {
+        return None;
+    }
+    let day: u32 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|&m| m == month)? as u32 + 1;
+    let year: i64 = year.parse().ok()?;
+    let days = days_from_civil(year, month, day);
+    // Rejects the 31st of a 30-day month and the like.
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+
+    let [h, m, s] = time.split(':').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let two_digits = |s: &str| -> Option<i64> { (s.len() == 2).then(|| s.parse().ok()).flatten() };
+    let (h, m, s) = (two_digits(h)?, two_digits(m)?, two_digits(s)?);
+    if h > 23 || m > 59 || s > 60 {
+        return None;
+    }
+    let secs = days * 86_400 + h * 3600 + m * 60 + s;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// `(year, month, day)` of the day `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Days from 1970-01-01 to `year-month-day`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let mp = i64::from((month + 9) % 12);
+    let day_of_year = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_and_parses_the_rfc_example() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        assert_eq!(format(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(
+            format(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "Tue, 29 Feb 2000 00:00:00 GMT"
+        );
+
+        // Every day for a few centuries survives the round trip.
+        for days in (0..150_000).step_by(7) {
+            let time = UNIX_EPOCH + Duration::from_secs(days * 86_400 + 45_296);
+            assert_eq!(parse(&format(time)), Some(time));
+        }
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for bad in [
+            "",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 31 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 6 Nov 1994 08:49:37 GMT",
+            "Xyz, 06 Nov 1994 08:49:37 GMT",
+        ] {
+            assert_eq!(parse(bad), None, "{bad:?}");
+        }
+    }
+}
@@ -0,0 +1,109 @@
+/// Header fields, in the order they arrived.
+///
+/// Names compare case-insensitively (`Content-Length` and `content-length`
+/// are the same header) but keep the spelling they were given, so a message
+/// is written back the way it was built. A `Vec` rather than a `HashMap`:
+/// a header may legally appear more than once (`Set-Cookie`), and messages
+/// have few enough of them that a linear scan is the fast option anyway.
+///
+/// Concepts: `eq_ignore_ascii_case`, iterators over tuples, `impl Trait`
+/// in argument position.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers::default()
+    }
+
+    /// The first value of header `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of header `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Whether a comma-separated header (`Connection`, `Accept-Encoding`)
+    /// lists `token`, ignoring case and any `;` parameters.
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name).flat_map(|v| v.split(',')).any(|item| {
+            let item = item.split(';').next().unwrap_or("").trim();
+            item.eq_ignore_ascii_case(token)
+        })
+    }
+
+    /// Add a field, keeping any others with the same name.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.fields.push((name.into(), value.into()));
+    }
+
+    /// Replace every field named `name` with this one.
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.fields.push((name, value.into()));
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.fields.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_case_insensitive_and_repeats_are_kept() {
+        let mut headers = Headers::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("set-cookie", "b=2");
+        headers.append("Content-Type", "text/plain");
+        assert_eq!(headers.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(
+            headers.get_all("Set-Cookie").collect::<Vec<_>>(),
+            vec!["a=1", "b=2"]
+        );
+
+        headers.set("content-type", "text/html");
+        assert_eq!(headers.get("Content-Type"), Some("text/html"));
+        assert_eq!(headers.len(), 3);
+    }
+
+    #[test]
+    fn has_token_reads_comma_separated_lists() {
+        let mut headers = Headers::new();
+        headers.append("Connection", "keep-alive, Upgrade");
+        headers.append("Accept-Encoding", "br;q=1.0, gzip;q=0.5");
+        assert!(headers.has_token("connection", "upgrade"));
+        assert!(headers.has_token("Accept-Encoding", "gzip"));
+        assert!(!headers.has_token("Connection", "close"));
+    }
+}
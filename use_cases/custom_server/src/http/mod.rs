@@ -0,0 +1,21 @@
+//! HTTP/1.1 messages: parsing requests off a socket and writing responses
+//! back.
+//!
+//! Everything here works on `BufRead`/`Write` rather than `TcpStream`, so
+//! the parser can be tested by feeding it byte slices, and the same types
+//! can later be used on the client side of a connection.
+//!
+//! Concepts: modules and `pub use` re-exports, newtypes.
+
+pub mod date;
+pub mod headers;
+pub mod method;
+pub mod request;
+pub mod response;
+pub mod status;
+
+pub use headers::Headers;
+pub use method::Method;
+pub use request::{Limits, ParseError, Request, Version};
+pub use response::{FileBody, Response};
+pub use status::StatusCode;
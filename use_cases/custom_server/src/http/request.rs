@@ -0,0 +1,737 @@
+//! Reading an HTTP/1.1 request off a connection.
+//!
+//! A request is a *head* — the request line and header fields, each ending
+//! in CRLF, then an empty line — followed by a body whose length the head
+//! announces in one of two ways:
+//!
+//! - `Content-Length: N`: exactly `N` bytes follow.
+//! - `Transfer-Encoding: chunked`: a series of chunks, each a hex size
+//!   line and that many bytes, ending with a zero-size chunk and optional
+//!   trailer fields.
+//!
+//! With neither, there is no body. A request with both is rejected rather
+//! than guessing: two parties reading it different ways is how request
+//! smuggling works.
+//!
+//! Every read is bounded by `Limits` *while* parsing, so a client can't
+//! make the server buffer an endless header line or a 10 GB body before
+//! noticing. Each way a request can be wrong maps to the status code the
+//! client gets back (`ParseError::status`).
+//!
+//! `write_to` goes the other way, for when this side is the client: the
+//! reverse proxy forwarding a request upstream.
+//!
+//! Concepts: `BufRead::read_until` on a `Take` adapter, `u64::from_str_radix`,
+//! `From<io::Error>` so `?` mixes I/O and parse errors.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::net::SocketAddr;
+
+use super::{Headers, Method, StatusCode};
+
+/// Longest chunk-size line accepted, extensions included.
+pub(crate) const MAX_CHUNK_LINE: usize = 1024;
+
+/// The protocol version from the request line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+/// Size limits enforced while reading a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Request line plus all header lines, in bytes.
+    pub max_head: usize,
+    /// Number of header fields.
+    pub max_headers: usize,
+    /// Body bytes, after removing any chunked framing.
+    pub max_body: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_head: 8 * 1024,
+            max_headers: 100,
+            max_body: 1024 * 1024,
+        }
+    }
+}
+
+/// A parsed request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: Method,
+    /// The request target as sent, in origin form: the path plus any
+    /// `?query`, still percent-encoded.
+    pub target: String,
+    pub version: Version,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// Path parameters (`:id` in a route pattern), filled in by the router
+    /// and already percent-decoded.
+    pub params: HashMap<String, String>,
+    /// The client's address, filled in by the connection that read the
+    /// request.
+    pub peer: Option<SocketAddr>,
+}
+
+/// How the body of a message is delimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    None,
+    Length(u64),
+    Chunked,
+}
+
+impl Request {
+    /// A bodiless HTTP/1.1 request, for building one by hand.
+    pub fn new(method: Method, target: impl Into<String>) -> Request {
+        Request {
+            method,
+            target: target.into(),
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: HashMap::new(),
+            peer: None,
+        }
+    }
+
+    /// Read a whole request: `read_head` then `read_body`. `Ok(None)` if
+    /// the connection closed before a request started.
+    pub fn read_from(
+        reader: &mut impl BufRead,
+        limits: &Limits,
+    ) -> Result<Option<Request>, ParseError> {
+        let Some(mut request) = Request::read_head(reader, limits)? else {
+            return Ok(None);
+        };
+        request.read_body(reader, limits)?;
+        Ok(Some(request))
+    }
+
+    /// Read the request line and headers, and check that the body they
+    /// announce is acceptable, without reading it. Split from `read_body`
+    /// so a server can answer `Expect: 100-continue` in between.
+    pub fn read_head(
+        reader: &mut impl BufRead,
+        limits: &Limits,
+    ) -> Result<Option<Request>, ParseError> {
+        let mut budget = limits.max_head;
+        // A server should ignore empty lines before the request line.
+        let line = loop {
+            match read_line(reader, &mut budget)? {
+                Line::Eof if budget == limits.max_head => return Ok(None),
+                Line::Eof => return Err(ParseError::Io(io::ErrorKind::UnexpectedEof.into())),
+                Line::TooLong => return Err(ParseError::UriTooLong),
+                Line::Text(line) if line.is_empty() => continue,
+                Line::Text(line) => break line,
+            }
+        };
+        let (method, target, version) = parse_request_line(&line)?;
+
+        let mut headers = Headers::new();
+        loop {
+            let line = match read_line(reader, &mut budget)? {
+                Line::Eof => return Err(ParseError::Io(io::ErrorKind::UnexpectedEof.into())),
+                Line::TooLong => return Err(ParseError::HeadersTooLarge),
+                Line::Text(line) => line,
+            };
+            if line.is_empty() {
+                break;
+            }
+            if headers.len() == limits.max_headers {
+                return Err(ParseError::HeadersTooLarge);
+            }
+            let (name, value) = parse_header(&line)?;
+            headers.append(name, value);
+        }
+
+        if version == Version::Http11 && headers.get_all("Host").count() != 1 {
+            return Err(ParseError::BadRequest(
+                "an HTTP/1.1 request needs one Host header",
+            ));
+        }
+        if let Framing::Length(len) = framing(&headers)?
+            && len > limits.max_body as u64
+        {
+            return Err(ParseError::BodyTooLarge);
+        }
+        Ok(Some(Request {
+            method,
+            target,
+            version,
+            headers,
+            body: Vec::new(),
+            params: HashMap::new(),
+            peer: None,
+        }))
+    }
+
+    /// Read the body announced by the headers into `self.body`.
+    pub fn read_body(
+        &mut self,
+        reader: &mut impl BufRead,
+        limits: &Limits,
+    ) -> Result<(), ParseError> {
+        self.body = match framing(&self.headers)? {
+            Framing::None => Vec::new(),
+            Framing::Length(len) => read_exact(reader, len)?,
+            Framing::Chunked => read_chunked(reader, limits)?,
+        };
+        Ok(())
+    }
+
+    /// How the body announced by the headers is delimited.
+    pub(crate) fn framing(&self) -> Result<Framing, ParseError> {
+        framing(&self.headers)
+    }
+
+    /// Whether the client waits for `100 Continue` before sending its body.
+    pub fn expects_continue(&self) -> bool {
+        self.version == Version::Http11 && self.headers.has_token("Expect", "100-continue")
+    }
+
+    /// Whether the client wants the connection kept open for another
+    /// request: the default in HTTP/1.1 unless it sent `Connection: close`,
+    /// and in HTTP/1.0 only if it sent `Connection: keep-alive`.
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 => !self.headers.has_token("Connection", "close"),
+            Version::Http10 => self.headers.has_token("Connection", "keep-alive"),
+        }
+    }
+
+    /// The target's path, without the query.
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or("")
+    }
+
+    /// The target's query string, without the `?`.
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    /// Path parameter `name` from the matched route.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// Write the request line, headers and body to `out`, as a client
+    /// sends them. As with `Response::write_to`, `Content-Length` comes
+    /// from the body; it's left out for a bodiless `GET` and the like, and
+    /// any `Transfer-Encoding` is dropped since the body goes out whole.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut head = format!(
+            "{} {} {}\r\n",
+            self.method,
+            self.target,
+            self.version.as_str()
+        );
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length")
+                && !name.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        let expects_body = matches!(self.method, Method::Post | Method::Put | Method::Patch);
+        if expects_body || !self.body.is_empty() {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        out.write_all(head.as_bytes())?;
+        out.write_all(&self.body)?;
+        out.flush()
+    }
+}
+
+/// Why a request couldn't be read.
+#[derive(Debug)]
+pub enum ParseError {
+    /// Reading from the connection failed, or it closed mid-request.
+    Io(io::Error),
+    /// The request doesn't follow HTTP/1.1 syntax. The message says where.
+    BadRequest(&'static str),
+    /// A well-formed method this server doesn't know.
+    UnknownMethod(String),
+    /// `HTTP/2.0` and the like on a request line.
+    UnsupportedVersion(String),
+    /// The request line is longer than the head limit allows.
+    UriTooLong,
+    /// Too many header fields, or too many bytes of them.
+    HeadersTooLarge,
+    /// The body is, or would be, larger than `Limits::max_body`.
+    BodyTooLarge,
+    /// A `Transfer-Encoding` other than plain `chunked`.
+    UnsupportedTransferEncoding(String),
+}
+
+impl ParseError {
+    /// The status to answer with, or `None` when the connection is beyond
+    /// answering and should just be closed.
+    pub fn status(&self) -> Option<StatusCode> {
+        Some(match self {
+            ParseError::Io(_) => return None,
+            ParseError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ParseError::UnknownMethod(_) | ParseError::UnsupportedTransferEncoding(_) => {
+                StatusCode::NOT_IMPLEMENTED
+            }
+            ParseError::UnsupportedVersion(_) => StatusCode::VERSION_NOT_SUPPORTED,
+            ParseError::UriTooLong => StatusCode::URI_TOO_LONG,
+            ParseError::HeadersTooLarge => StatusCode::HEADER_FIELDS_TOO_LARGE,
+            ParseError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        })
+    }
+
+    /// Whether the input just ended too soon. Parsing a buffer that holds
+    /// only part of a request fails this way, and may succeed once more
+    /// bytes have arrived.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, ParseError::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(_) => write!(f, "cannot read request"),
+            ParseError::BadRequest(reason) => write!(f, "bad request: {reason}"),
+            ParseError::UnknownMethod(method) => write!(f, "unknown method '{method}'"),
+            ParseError::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version '{version}'")
+            }
+            ParseError::UriTooLong => write!(f, "request line too long"),
+            ParseError::HeadersTooLarge => write!(f, "request headers too large"),
+            ParseError::BodyTooLarge => write!(f, "request body too large"),
+            ParseError::UnsupportedTransferEncoding(coding) => {
+                write!(f, "unsupported transfer coding '{coding}'")
+            }
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> ParseError {
+        ParseError::Io(err)
+    }
+}
+
+/// `s` with `%XX` escapes decoded, or `None` if an escape is malformed or
+/// the result isn't UTF-8. `+` stays a `+`: that shorthand is for form
+/// data, not paths.
+pub fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// One line read by `read_line`.
+pub(super) enum Line {
+    Text(String),
+    /// The connection ended before a complete line.
+    Eof,
+    /// No `\n` within the budget.
+    TooLong,
+}
+
+/// Read one `\n`-terminated line, charging its length to `budget`. The
+/// terminator (`\r\n`, or a bare `\n`) is not part of the text.
+pub(super) fn read_line(reader: &mut impl BufRead, budget: &mut usize) -> Result<Line, ParseError> {
+    let mut buf = Vec::new();
+    reader.take(*budget as u64).read_until(b'\n', &mut buf)?;
+    *budget -= buf.len();
+    if buf.last() != Some(&b'\n') {
+        return Ok(if *budget == 0 {
+            Line::TooLong
+        } else {
+            Line::Eof
+        });
+    }
+    buf.pop();
+    if buf.last() == Some(&b'\r') {
+        buf.pop();
+    }
+    String::from_utf8(buf)
+        .map(Line::Text)
+        .map_err(|_| ParseError::BadRequest("request head is not valid UTF-8"))
+}
+
+fn parse_request_line(line: &str) -> Result<(Method, String, Version), ParseError> {
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ParseError::BadRequest("malformed request line"));
+    };
+    if method.is_empty() || !method.bytes().all(is_token_byte) {
+        return Err(ParseError::BadRequest("malformed method"));
+    }
+    let method: Method = method
+        .parse()
+        .map_err(|()| ParseError::UnknownMethod(method.to_string()))?;
+
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        v if v.len() == 8
+            && v.starts_with("HTTP/")
+            && v.as_bytes()[5].is_ascii_digit()
+            && v.as_bytes()[6] == b'.'
+            && v.as_bytes()[7].is_ascii_digit() =>
+        {
+            return Err(ParseError::UnsupportedVersion(v.to_string()));
+        }
+        _ => return Err(ParseError::BadRequest("malformed protocol version")),
+    };
+
+    let target = if target.starts_with('/') || (target == "*" && method == Method::Options) {
+        target.to_string()
+    } else if let Some((_, rest)) = target.split_once("://").filter(|(scheme, _)| {
+        scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+    }) {
+        // Absolute form, as sent to proxies: keep just the path and query.
+        match rest.find(['/', '?']) {
+            Some(i) if rest.as_bytes()[i] == b'/' => rest[i..].to_string(),
+            Some(i) => format!("/{}", &rest[i..]),
+            None => "/".to_string(),
+        }
+    } else {
+        return Err(ParseError::BadRequest("malformed request target"));
+    };
+    Ok((method, target, version))
+}
+
+pub(super) fn parse_header(line: &str) -> Result<(String, String), ParseError> {
+    if line.starts_with([' ', '\t']) {
+        return Err(ParseError::BadRequest("obsolete header line folding"));
+    }
+    let Some((name, value)) = line.split_once(':') else {
+        return Err(ParseError::BadRequest("header line without a colon"));
+    };
+    // No whitespace is allowed between the name and the colon.
+    if name.is_empty() || !name.bytes().all(is_token_byte) {
+        return Err(ParseError::BadRequest("malformed header name"));
+    }
+    Ok((
+        name.to_string(),
+        value.trim_matches([' ', '\t']).to_string(),
+    ))
+}
+
+/// Characters allowed in methods and header names (RFC 9110 `tchar`).
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+pub(super) fn framing(headers: &Headers) -> Result<Framing, ParseError> {
+    let has_length = headers.contains("Content-Length");
+    if headers.contains("Transfer-Encoding") {
+        if has_length {
+            return Err(ParseError::BadRequest(
+                "both Content-Length and Transfer-Encoding",
+            ));
+        }
+        let codings: Vec<&str> = headers
+            .get_all("Transfer-Encoding")
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|c| !c.is_empty())
+            .collect();
+        return match codings.as_slice() {
+            [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(Framing::Chunked),
+            _ => Err(ParseError::UnsupportedTransferEncoding(codings.join(", "))),
+        };
+    }
+    if !has_length {
+        return Ok(Framing::None);
+    }
+    // Repeats (`Content-Length: 5, 5`) are fine as long as they agree.
+    let mut length = None;
+    for value in headers.get_all("Content-Length").flat_map(|v| v.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::BadRequest("malformed Content-Length"));
+        }
+        let n: u64 = value.parse().map_err(|_| ParseError::BodyTooLarge)?;
+        if length.is_some_and(|l| l != n) {
+            return Err(ParseError::BadRequest("conflicting Content-Length values"));
+        }
+        length = Some(n);
+    }
+    Ok(length.map_or(Framing::None, Framing::Length))
+}
+
+pub(super) fn read_exact(reader: &mut impl BufRead, len: u64) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::with_capacity(len.min(64 * 1024) as usize);
+    reader.take(len).read_to_end(&mut body)?;
+    if (body.len() as u64) < len {
+        return Err(ParseError::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(body)
+}
+
+pub(super) fn read_chunked(
+    reader: &mut impl BufRead,
+    limits: &Limits,
+) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let mut budget = MAX_CHUNK_LINE;
+        let line = match read_line(reader, &mut budget)? {
+            Line::Text(line) => line,
+            Line::Eof => return Err(ParseError::Io(io::ErrorKind::UnexpectedEof.into())),
+            Line::TooLong => return Err(ParseError::BadRequest("chunk size line too long")),
+        };
+        // Chunk extensions (`;name=value`) carry nothing we use.
+        let size = line
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim_end_matches([' ', '\t']);
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(ParseError::BadRequest("malformed chunk size"));
+        }
+        let size = u64::from_str_radix(size, 16).map_err(|_| ParseError::BodyTooLarge)?;
+        if size == 0 {
+            break;
+        }
+        // `size` comes from the client, so subtract rather than add: a
+        // chunk of `ffffffffffffffff` would overflow the sum.
+        if size > limits.max_body.saturating_sub(body.len()) as u64 {
+            return Err(ParseError::BodyTooLarge);
+        }
+        body.extend(read_exact(reader, size)?);
+        let mut budget = 2;
+        match read_line(reader, &mut budget)? {
+            Line::Text(line) if line.is_empty() => {}
+            Line::Eof => return Err(ParseError::Io(io::ErrorKind::UnexpectedEof.into())),
+            _ => return Err(ParseError::BadRequest("chunk data longer than its size")),
+        }
+    }
+    // Trailer fields are read and dropped.
+    let mut budget = limits.max_head;
+    loop {
+        match read_line(reader, &mut budget)? {
+            Line::Text(line) if line.is_empty() => return Ok(body),
+            Line::Text(line) => {
+                parse_header(&line)?;
+            }
+            Line::Eof => return Err(ParseError::Io(io::ErrorKind::UnexpectedEof.into())),
+            Line::TooLong => return Err(ParseError::HeadersTooLarge),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Option<Request>, ParseError> {
+        Request::read_from(&mut raw.as_bytes(), &Limits::default())
+    }
+
+    fn status(raw: &str) -> Option<u16> {
+        parse(raw).unwrap_err().status().map(|s| s.0)
+    }
+
+    #[test]
+    fn parses_request_line_headers_and_length_delimited_body() {
+        let request = parse(
+            "\r\nPOST /users/42?verbose=1 HTTP/1.1\r\nHost: localhost\r\nX-Foo:  bar \r\n\
+             Content-Length: 5\r\n\r\nhello",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.path(), "/users/42");
+        assert_eq!(request.query(), Some("verbose=1"));
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.headers.get("x-foo"), Some("bar"));
+        assert_eq!(request.body, b"hello");
+
+        // Bare `\n` line endings, HTTP/1.0 without a Host.
+        let request = parse("GET / HTTP/1.0\n\n").unwrap().unwrap();
+        assert_eq!(request.version, Version::Http10);
+        assert!(parse("").unwrap().is_none());
+    }
+
+    #[test]
+    fn decodes_chunked_bodies_and_drops_trailers() {
+        let request = parse(
+            "PUT /upload HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+             5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nX-Checksum: abc\r\n\r\n",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(request.body, b"hello, world");
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n"),
+            Some(400)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            Some(501)
+        );
+    }
+
+    #[test]
+    fn malformed_requests_map_to_status_codes() {
+        assert_eq!(status("GARBAGE\r\n\r\n"), Some(400));
+        assert_eq!(status("GET  / HTTP/1.1\r\nHost: x\r\n\r\n"), Some(400));
+        assert_eq!(status("GET / HTTP/1.1\r\n\r\n"), Some(400));
+        assert_eq!(
+            status("GET / HTTP/1.1\r\nHost: x\r\nBad Name: y\r\n\r\n"),
+            Some(400)
+        );
+        assert_eq!(
+            status("GET / HTTP/1.1\r\nHost: x\r\n folded\r\n\r\n"),
+            Some(400)
+        );
+        assert_eq!(status("BREW /pot HTTP/1.1\r\nHost: x\r\n\r\n"), Some(501));
+        assert_eq!(status("GET / HTTP/2.0\r\nHost: x\r\n\r\n"), Some(505));
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\n"),
+            Some(400)
+        );
+        assert_eq!(
+            status(
+                "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n"
+            ),
+            Some(400)
+        );
+        // Cut off mid-body: nothing to answer.
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 9\r\n\r\nshort"),
+            None
+        );
+    }
+
+    #[test]
+    fn limits_are_enforced_while_reading() {
+        let limits = Limits {
+            max_head: 64,
+            max_headers: 2,
+            max_body: 8,
+        };
+        let read = |raw: String| Request::read_from(&mut raw.as_bytes(), &limits).unwrap_err();
+        let long_target = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(100));
+        assert!(matches!(read(long_target), ParseError::UriTooLong));
+        let many = "GET / HTTP/1.1\r\nHost: x\r\nA: 1\r\nB: 2\r\n\r\n".to_string();
+        assert!(matches!(read(many), ParseError::HeadersTooLarge));
+        // Rejected from the header alone, before any body is read.
+        let big = "POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 9\r\n\r\n".to_string();
+        assert!(matches!(read(big), ParseError::BodyTooLarge));
+        let chunked = "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n";
+        assert!(matches!(
+            read(chunked.to_string()),
+            ParseError::BodyTooLarge
+        ));
+        // A huge chunk after a small one, whose size plus the body so far
+        // doesn't fit in a u64.
+        let overflow = "POST / HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+                        1\r\na\r\nffffffffffffffff\r\n";
+        assert!(matches!(
+            read(overflow.to_string()),
+            ParseError::BodyTooLarge
+        ));
+    }
+
+    #[test]
+    fn absolute_targets_and_percent_decoding() {
+        let request =
+            parse("GET http://example.com/a%20b?x=1 HTTP/1.1\r\nHost: example.com\r\n\r\n")
+                .unwrap()
+                .unwrap();
+        assert_eq!(request.target, "/a%20b?x=1");
+        assert_eq!(percent_decode("/a%20b%2Fc+d").as_deref(), Some("/a b/c+d"));
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%ff"), None);
+    }
+
+    #[test]
+    fn every_prefix_of_a_request_is_incomplete_rather_than_wrong() {
+        let raw = "POST /a HTTP/1.1\r\nHost: x\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   3\r\nabc\r\n0\r\nTrailer: t\r\n\r\n";
+        for end in 1..raw.len() {
+            let err = parse(&raw[..end]).unwrap_err();
+            assert!(err.is_incomplete(), "{:?}: {err}", &raw[..end]);
+        }
+        assert_eq!(parse(raw).unwrap().unwrap().body, b"abc");
+    }
+
+    #[test]
+    fn keep_alive_defaults_by_version() {
+        let keep_alive = |raw: &str| parse(raw).unwrap().unwrap().keep_alive();
+        assert!(keep_alive("GET / HTTP/1.1\r\nHost: x\r\n\r\n"));
+        assert!(!keep_alive(
+            "GET / HTTP/1.1\r\nHost: x\r\nConnection: Close\r\n\r\n"
+        ));
+        assert!(!keep_alive("GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(
+            "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn written_requests_parse_back_the_same() {
+        let mut request = Request::new(Method::Put, "/items/1?x=y");
+        request.headers.append("Host", "example.com");
+        request.headers.append("Transfer-Encoding", "chunked");
+        request.body = b"data".to_vec();
+        let mut out = Vec::new();
+        request.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out.clone()).unwrap(),
+            "PUT /items/1?x=y HTTP/1.1\r\nHost: example.com\r\nContent-Length: 4\r\n\r\ndata"
+        );
+        let parsed = parse(std::str::from_utf8(&out).unwrap()).unwrap().unwrap();
+        assert_eq!(
+            (parsed.method, parsed.body),
+            (Method::Put, b"data".to_vec())
+        );
+
+        let mut out = Vec::new();
+        Request::new(Method::Get, "/").write_to(&mut out).unwrap();
+        assert_eq!(out, b"GET / HTTP/1.1\r\n\r\n");
+    }
+}
@@ -0,0 +1,381 @@
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use super::request::{Framing, Line, framing, parse_header, read_chunked, read_exact, read_line};
+use super::{Headers, Limits, Method, ParseError, StatusCode, Version};
+
+/// A response to send back: status, headers and a body held in memory,
+/// or left on disk until it's sent (`with_file`).
+///
+/// `write_to` fills in `Content-Length` from the body, so handlers never
+/// set it themselves and can't get it wrong. The body is bytes, not a
+/// `String`, since not everything served is text; the head and body go out
+/// in separate writes so the body never has to be valid UTF-8. A file body
+/// is copied to the connection a buffer at a time, so serving a large file
+/// costs a buffer, not the file's size in memory.
+///
+/// Concepts: builder-style methods taking and returning `self`,
+/// `impl Into<String>`, `write!` into any `io::Write`, `Seek` +
+/// `Read::take` to read a slice of a file.
+///
+/// `read_from` parses a response, for the client side of a connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+    /// Sent instead of `body` when set.
+    pub file: Option<FileBody>,
+}
+
+/// A body read from a file only as it's sent: `len` bytes of the file at
+/// `path`, starting at `offset`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileBody {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl FileBody {
+    /// The file, positioned at `offset` and cut off after `len` bytes.
+    pub fn open(&self) -> io::Result<io::Take<File>> {
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(self.offset))?;
+        Ok(file.take(self.len))
+    }
+
+    /// Copy the bytes to `out`. A file that has shrunk since its length was
+    /// taken is an error: the `Content-Length` already sent can't be met.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let copied = io::copy(&mut self.open()?, out)?;
+        if copied < self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{}: shorter than when it was sized", self.path.display()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Response {
+    /// An empty response with `status`.
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Vec::new(),
+            file: None,
+        }
+    }
+
+    /// A `text/plain` response.
+    pub fn text(status: StatusCode, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    /// A `text/html` response.
+    pub fn html(status: StatusCode, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    /// An `application/json` response; `body` must already be JSON.
+    pub fn json(status: StatusCode, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(body.into())
+    }
+
+    /// A plain-text error page for `status`: its code and reason phrase.
+    pub fn error(status: StatusCode) -> Response {
+        Response::text(status, format!("{status}\n"))
+    }
+
+    /// Set header `name`, replacing any earlier value.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.set(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = body.into();
+        self.file = None;
+        self
+    }
+
+    /// Send bytes `range` of the file at `path` as the body, read when
+    /// the response is written. The caller has checked the file's size.
+    pub fn with_file(mut self, path: impl Into<PathBuf>, range: Range<u64>) -> Response {
+        self.body.clear();
+        self.file = Some(FileBody {
+            path: path.into(),
+            offset: range.start,
+            len: range.end - range.start,
+        });
+        self
+    }
+
+    /// The body's length, wherever it is.
+    pub fn body_len(&self) -> u64 {
+        match &self.file {
+            Some(file) => file.len,
+            None => self.body.len() as u64,
+        }
+    }
+
+    /// Write the status line, headers and body to `out`.
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        self.write_head_to(out)?;
+        if self.status.allows_body() {
+            match &self.file {
+                Some(file) => file.write_to(out)?,
+                None => out.write_all(&self.body)?,
+            }
+        }
+        out.flush()
+    }
+
+    /// Write only the status line and headers, as the answer to a `HEAD`
+    /// request: `Content-Length` still gives the size of the body that a
+    /// `GET` would have received.
+    pub fn write_head_to(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length") {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        if self.status.allows_body() {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body_len()));
+        }
+        head.push_str("\r\n");
+        out.write_all(head.as_bytes())
+    }
+
+    /// Read the final response to a `method` request from `reader`,
+    /// skipping any `1xx` interim ones. The body is decoded: a chunked one
+    /// is joined up and loses its `Transfer-Encoding`, and one with no
+    /// length runs until the server closes the connection. `limits` bound
+    /// the head and body as for requests.
+    pub fn read_from(
+        reader: &mut impl BufRead,
+        method: Method,
+        limits: &Limits,
+    ) -> Result<Response, ParseError> {
+        Response::read_persistent(reader, method, limits).map(|(response, _)| response)
+    }
+
+    /// `read_from`, and whether the connection can carry another request
+    /// afterwards: the server didn't ask to close it, and the body didn't
+    /// run to the end of the connection.
+    pub fn read_persistent(
+        reader: &mut impl BufRead,
+        method: Method,
+        limits: &Limits,
+    ) -> Result<(Response, bool), ParseError> {
+        let (mut response, version) = loop {
+            let (response, version) = read_head(reader, limits)?;
+            if !(100..200).contains(&response.status.0) {
+                break (response, version);
+            }
+        };
+        let mut persistent = match version {
+            Version::Http11 => !response.headers.has_token("Connection", "close"),
+            Version::Http10 => response.headers.has_token("Connection", "keep-alive"),
+        };
+        let has_body = method != Method::Head && response.status.allows_body();
+        response.body = match framing(&response.headers)? {
+            _ if !has_body => Vec::new(),
+            Framing::Length(len) if len > limits.max_body as u64 => {
+                return Err(ParseError::BodyTooLarge);
+            }
+            Framing::Length(len) => read_exact(reader, len)?,
+            Framing::Chunked => {
+                response.headers.remove("Transfer-Encoding");
+                read_chunked(reader, limits)?
+            }
+            Framing::None => {
+                persistent = false;
+                let mut body = Vec::new();
+                reader
+                    .take(limits.max_body as u64 + 1)
+                    .read_to_end(&mut body)?;
+                if body.len() > limits.max_body {
+                    return Err(ParseError::BodyTooLarge);
+                }
+                body
+            }
+        };
+        Ok((response, persistent))
+    }
+}
+
+/// The status line and headers of a response.
+fn read_head(
+    reader: &mut impl BufRead,
+    limits: &Limits,
+) -> Result<(Response, Version), ParseError> {
+    let mut budget = limits.max_head;
+    let line = match read_line(reader, &mut budget)? {
+        Line::Text(line) => line,
+        Line::Eof => return Err(ParseError::Io(io::ErrorKind::UnexpectedEof.into())),
+        Line::TooLong => return Err(ParseError::HeadersTooLarge),
+    };
+    // `HTTP/1.1 200 OK`; the reason phrase may be empty or missing.
+    let mut parts = line.splitn(3, ' ');
+    let (Some(version), Some(code)) = (parts.next(), parts.next()) else {
+        return Err(ParseError::BadRequest("malformed status line"));
+    };
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        _ => return Err(ParseError::UnsupportedVersion(version.to_string())),
+    };
+    let status = match code.parse::<u16>() {
+        Ok(code) if code.to_string().len() == 3 && code >= 100 => StatusCode(code),
+        _ => return Err(ParseError::BadRequest("malformed status code")),
+    };
+
+    let mut response = Response::new(status);
+    loop {
+        let line = match read_line(reader, &mut budget)? {
+            Line::Eof => return Err(ParseError::Io(io::ErrorKind::UnexpectedEof.into())),
+            Line::TooLong => return Err(ParseError::HeadersTooLarge),
+            Line::Text(line) => line,
+        };
+        if line.is_empty() {
+            return Ok((response, version));
+        }
+        if response.headers.len() == limits.max_headers {
+            return Err(ParseError::HeadersTooLarge);
+        }
+        let (name, value) = parse_header(&line)?;
+        response.headers.append(name, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn serializes_head_and_body_with_content_length() {
+        let response = Response::text(StatusCode::OK, "hi\n").with_header("X-Id", "7");
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nX-Id: 7\r\n\
+             Content-Length: 3\r\n\r\nhi\n"
+        );
+
+        let mut head = Vec::new();
+        response.write_head_to(&mut head).unwrap();
+        assert!(
+            String::from_utf8(head)
+                .unwrap()
+                .ends_with("Content-Length: 3\r\n\r\n")
+        );
+    }
+
+    fn read(raw: &str, method: Method) -> Result<Response, ParseError> {
+        Response::read_from(&mut raw.as_bytes(), method, &Limits::default())
+    }
+
+    #[test]
+    fn reads_length_chunked_and_close_delimited_bodies() {
+        let sized = read(
+            "HTTP/1.1 100 Continue\r\n\r\n\
+             HTTP/1.1 201 Created\r\nContent-Length: 2\r\nX-A: 1\r\n\r\nhiEXTRA",
+            Method::Post,
+        )
+        .unwrap();
+        assert_eq!(sized.status, StatusCode::CREATED);
+        assert_eq!(sized.headers.get("X-A"), Some("1"));
+        assert_eq!(sized.body, b"hi");
+
+        let chunked = read(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+            Method::Get,
+        )
+        .unwrap();
+        assert_eq!(chunked.body, b"abcde");
+        assert!(!chunked.headers.contains("Transfer-Encoding"));
+
+        let until_close = read("HTTP/1.0 200\r\n\r\nto the end", Method::Get).unwrap();
+        assert_eq!(until_close.body, b"to the end");
+        let persistent = |raw: &str| {
+            let limits = Limits::default();
+            Response::read_persistent(&mut raw.as_bytes(), Method::Get, &limits)
+                .unwrap()
+                .1
+        };
+        assert!(persistent("HTTP/1.1 204 No Content\r\n\r\n"));
+        assert!(!persistent("HTTP/1.1 200 OK\r\n\r\nuntil close"));
+        assert!(!persistent(
+            "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+        ));
+        assert!(persistent(
+            "HTTP/1.0 200 OK\r\nConnection: keep-alive\r\nContent-Length: 0\r\n\r\n"
+        ));
+        // The length describes the body a GET would have had.
+        let head = read("HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n", Method::Head).unwrap();
+        assert!(head.body.is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_or_truncated_responses() {
+        for raw in [
+            "HTTP/1.1 2000 OK\r\n\r\n",
+            "HTTP/1.1\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nbad header\r\n\r\n",
+        ] {
+            assert!(
+                matches!(read(raw, Method::Get), Err(ParseError::BadRequest(_))),
+                "{raw}"
+            );
+        }
+        assert!(matches!(
+            read("HTTP/2 200\r\n\r\n", Method::Get),
+            Err(ParseError::UnsupportedVersion(_))
+        ));
+        let err = read(
+            "HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort",
+            Method::Get,
+        );
+        assert!(err.unwrap_err().is_incomplete());
+    }
+
+    #[test]
+    fn file_bodies_are_read_as_they_are_written() {
+        let dir = TempDir::new("body");
+        let path = dir.file("body", "0123456789");
+        let response = Response::new(StatusCode::OK).with_file(&path, 2..5);
+        let mut out = Vec::new();
+        response.write_to(&mut out).unwrap();
+        assert!(out.ends_with(b"Content-Length: 3\r\n\r\n234"));
+
+        // Shrunk in between: the promised length can't be sent.
+        std::fs::write(&path, "01").unwrap();
+        assert!(response.write_to(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn bodiless_statuses_send_no_body_or_length() {
+        let mut out = Vec::new();
+        Response::new(StatusCode::NO_CONTENT)
+            .with_body("ignored")
+            .write_to(&mut out)
+            .unwrap();
+        assert_eq!(out, b"HTTP/1.1 204 No Content\r\n\r\n");
+    }
+}
@@ -0,0 +1,401 @@
+//! A small HTTP/1.1 server built directly on `std::net`.
+//!
+//! `http` turns bytes into `Request`s and `Response`s into bytes; `router`
+//! decides which handler answers a request, through any `middleware`
+//! wrapped around it (logging, compression, CORS); `handle_connection`
+//! ties the two together for one accepted connection. `server` runs the
+//! accept loop and hands each connection to a worker from `threadpool`,
+//! optionally on a second, HTTPS port whose connections `tls` decrypts;
+//! on Linux, `event_loop` is an alternative core that serves every
+//! connection from one thread with epoll. `websocket` takes over
+//! connections that ask to upgrade, and `proxy` passes requests on to
+//! other servers instead of answering them. `client` is the other end: a
+//! blocking HTTP client built on the same types, which the integration
+//! tests use to talk to the server. `main.rs` only builds the route
+//! table, starts one of the two cores and wires up Ctrl+C, and
+//! `bin/loadtest.rs` compares them.
+//!
+//! A connection stays open for further requests, as HTTP/1.1 intends, and
+//! a client may *pipeline*: send several requests without waiting for the
+//! answers. The same `BufReader` reads every request on the connection, so
+//! bytes of the next request that arrived with the previous one are simply
+//! still in its buffer, and the answers go out in order because requests
+//! are handled one after another. A connection closes when either side
+//! asks (`Connection: close`), after `max_requests`, when it has been idle
+//! for `idle_timeout`, when the server is shutting down, or after a request
+//! that couldn't be parsed, since there's no telling where the next one
+//! would start.
+//!
+//! Concepts: library + binary crates (the binary uses this crate like any
+//! other dependency), `BufReader` over a `&TcpStream` (a shared reference
+//! to a socket is itself `Read` and `Write`), socket timeouts, `BufWriter`
+//! to send each response in one write.
+
+pub mod client;
+#[cfg(target_os = "linux")]
+pub mod event_loop;
+pub mod http;
+pub mod middleware;
+pub mod proxy;
+pub mod router;
+pub mod server;
+pub mod static_files;
+pub mod threadpool;
+pub mod tls;
+pub mod websocket;
+
+#[cfg(test)]
+mod test_util;
+
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rustls::{ServerConnection, StreamOwned};
+
+use http::{FileBody, Limits, Method, ParseError, Request, Response, StatusCode, Version};
+use router::Router;
+use server::Shutdown;
+use tls::TlsConfig;
+use websocket::Upgrade;
+
+/// How long a client may take to send a request once it has started.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often an idle connection checks whether the server is shutting
+/// down.
+const IDLE_POLL: Duration = Duration::from_millis(250);
+
+/// How connections are served, apart from the routes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionConfig {
+    pub limits: Limits,
+    /// How long an open connection may wait for its next request.
+    pub idle_timeout: Duration,
+    /// How long one write may wait for the client to make room. A client
+    /// that stops reading, maybe with requests still pipelined behind,
+    /// would otherwise hold its worker, and a shutdown, forever.
+    pub write_timeout: Duration,
+    /// Requests answered on one connection before it's closed, so a single
+    /// client can't hold a worker forever.
+    pub max_requests: usize,
+    /// WebSocket connections open at once. Each has a thread of its own
+    /// rather than a worker, so this, not the pool, is what bounds them;
+    /// an upgrade past it gets `503`.
+    pub max_websockets: usize,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> ConnectionConfig {
+        ConnectionConfig {
+            limits: Limits::default(),
+            idle_timeout: Duration::from_secs(5),
+            write_timeout: Duration::from_secs(10),
+            max_requests: 100,
+            max_websockets: 256,
+        }
+    }
+}
+
+/// Answer requests from `stream` through `router` until the connection
+/// should close. A request that can't be parsed gets the matching error
+/// status (`400`, `413`, ...) and ends the connection; `Err` means the
+/// connection itself failed.
+pub fn handle_connection(
+    stream: TcpStream,
+    router: &Router,
+    config: &ConnectionConfig,
+    shutdown: &Shutdown,
+) -> io::Result<()> {
+    stream.set_write_timeout(Some(config.write_timeout))?;
+    serve(stream, router, config, shutdown)
+}
+
+/// `handle_connection` for a client that speaks TLS: the handshake, then
+/// the same requests and answers, encrypted.
+pub fn handle_tls_connection(
+    stream: TcpStream,
+    tls: &TlsConfig,
+    router: &Router,
+    config: &ConnectionConfig,
+    shutdown: &Shutdown,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(config.write_timeout))?;
+    let encrypted = tls
+        .accept(stream)
+        .map_err(|err| io::Error::new(err.kind(), format!("TLS handshake failed: {err}")))?;
+    serve(encrypted, router, config, shutdown)
+}
+
+/// A connection's byte stream, plain or TLS, and the socket beneath it
+/// for timeouts. Owned, so an upgraded connection can move to a thread
+/// of its own.
+pub(crate) trait Transport: Read + Write + Send + 'static {
+    fn socket(&self) -> &TcpStream;
+
+    /// End the stream cleanly before the socket closes.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+}
+
+impl Transport for StreamOwned<ServerConnection, TcpStream> {
+    fn socket(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.conn.send_close_notify();
+        self.flush()
+    }
+}
+
+/// The request loop behind both: requests are read from and answers
+/// written to `io`, the socket itself or a TLS session over it.
+fn serve(
+    io: impl Transport,
+    router: &Router,
+    config: &ConnectionConfig,
+    shutdown: &Shutdown,
+) -> io::Result<()> {
+    let peer = io.socket().peer_addr()?;
+    let mut reader = BufReader::new(io);
+
+    let mut served = 0;
+    loop {
+        served += 1;
+        if !wait_for_request(&mut reader, config.idle_timeout, shutdown)? {
+            break;
+        }
+        reader
+            .get_ref()
+            .socket()
+            .set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut request = match read_request(&mut reader, &config.limits) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(err) => {
+                if let Some(response) = reject(peer, &err) {
+                    send(&mut reader, |out| response.write_to(out))?;
+                }
+                break;
+            }
+        };
+        request.peer = Some(peer);
+        if let Some(upgrade) = websocket::upgrade(router, &mut request) {
+            return upgrade_connection(peer, reader, router, request, upgrade, config, shutdown);
+        }
+        let may_keep_alive = served < config.max_requests && !shutdown.is_requested();
+        let answered = panic::catch_unwind(AssertUnwindSafe(|| {
+            answer(peer, router, request, may_keep_alive)
+        }));
+        let Ok(answer) = answered else {
+            // The panic hook has printed the message and location.
+            eprintln!("{peer} handler panicked; closing the connection");
+            let response = Response::error(StatusCode::INTERNAL_SERVER_ERROR)
+                .with_header("Connection", "close");
+            send(&mut reader, |out| response.write_to(out))?;
+            break;
+        };
+        send(&mut reader, |out| answer.write_to(out))?;
+        if !answer.keep_alive {
+            break;
+        }
+    }
+    reader.get_mut().finish()
+}
+
+/// Answer a WebSocket upgrade. The handshake's response goes through the
+/// router's middleware, so it's logged (and can be refused) like any other
+/// request. If it's accepted, the connection moves to a thread of its own,
+/// where the route's handler has it until it closes, and the worker is
+/// free for other requests.
+fn upgrade_connection<S: Transport>(
+    peer: SocketAddr,
+    mut reader: BufReader<S>,
+    router: &Router,
+    request: Request,
+    upgrade: Upgrade,
+    config: &ConnectionConfig,
+    shutdown: &Shutdown,
+) -> io::Result<()> {
+    let target = request.target.clone();
+    let (response, accepted) = match upgrade {
+        Upgrade::Reject(response) => (response, None),
+        Upgrade::Accept(response, handler) => {
+            match shutdown.sessions.enter(config.max_websockets) {
+                Some(session) => (response, Some((handler, session))),
+                None => (Response::error(StatusCode::SERVICE_UNAVAILABLE), None),
+            }
+        }
+    };
+    let response = router.handle_with(request, response);
+    let Some((handler, session)) =
+        accepted.filter(|_| response.status == StatusCode::SWITCHING_PROTOCOLS)
+    else {
+        let response = response.with_header("Connection", "close");
+        send(&mut reader, |out| response.write_to(out))?;
+        return reader.get_mut().finish();
+    };
+
+    let max_message = config.limits.max_body;
+    let shutdown = shutdown.clone();
+    let spawned = thread::Builder::new()
+        .name(format!("websocket {peer}"))
+        .spawn(move || {
+            let mut reader = reader;
+            // Counted until the session ends, however it ends.
+            let _session = session;
+            let served = send(&mut reader, |out| response.write_to(out))
+                .and_then(|()| websocket::serve(reader, handler, max_message, &shutdown));
+            match served {
+                Ok(code) => eprintln!("{peer} {target} WebSocket closed ({code})"),
+                Err(err) => eprintln!("connection error: {err}"),
+            }
+        });
+    spawned
+        .map(drop)
+        .map_err(|err| io::Error::new(err.kind(), format!("no thread for a WebSocket: {err}")))
+}
+
+/// Write through a `BufWriter`, so a response goes out in one write rather
+/// than one per header, and flush.
+fn send<W: Write>(
+    reader: &mut BufReader<W>,
+    write: impl FnOnce(&mut BufWriter<&mut W>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(reader.get_mut());
+    write(&mut writer)?;
+    writer.flush()
+}
+
+/// A response ready to send, and what happens to the connection after.
+pub(crate) struct Answer {
+    response: Response,
+    /// Only the head goes out: the request was `HEAD`.
+    head_only: bool,
+    /// The connection stays open for another request.
+    pub keep_alive: bool,
+}
+
+impl Answer {
+    pub fn write_to(&self, out: &mut impl Write) -> io::Result<()> {
+        if self.head_only {
+            self.response.write_head_to(out)
+        } else {
+            self.response.write_to(out)
+        }
+    }
+
+    /// The body to send from a file, if there is one; what comes before
+    /// it is `write_head_to`.
+    pub fn file(&self) -> Option<&FileBody> {
+        let sends_body = !self.head_only && self.response.status.allows_body();
+        self.response.file.as_ref().filter(|_| sends_body)
+    }
+
+    pub fn write_head_to(&self, out: &mut impl Write) -> io::Result<()> {
+        self.response.write_head_to(out)
+    }
+}
+
+/// Run `request` from `peer` through `router` and decide whether the
+/// connection stays open: the client wants it, `may_keep_alive` allows it,
+/// and the handler didn't say `Connection: close`. Logging is up to the
+/// router's middleware.
+pub(crate) fn answer(
+    peer: SocketAddr,
+    router: &Router,
+    mut request: Request,
+    may_keep_alive: bool,
+) -> Answer {
+    request.peer = Some(peer);
+    let method = request.method;
+    let version = request.version;
+    let keep_alive = may_keep_alive && request.keep_alive();
+    let mut response = router.handle(request);
+    let keep_alive = keep_alive && !response.headers.has_token("Connection", "close");
+    if !keep_alive {
+        response = response.with_header("Connection", "close");
+    } else if version == Version::Http10 {
+        response = response.with_header("Connection", "keep-alive");
+    }
+    Answer {
+        response,
+        head_only: method == Method::Head,
+        keep_alive,
+    }
+}
+
+/// The response to a request that couldn't be read, if the connection
+/// can still take one; the connection closes after it either way. The
+/// error goes to stderr with the other diagnostics, since stdout is
+/// `AccessLog`'s.
+pub(crate) fn reject(peer: SocketAddr, err: &ParseError) -> Option<Response> {
+    eprintln!("{peer} invalid request: {err}");
+    Some(Response::error(err.status()?).with_header("Connection", "close"))
+}
+
+/// Wait until the next request starts arriving: `false` if the client
+/// closed the connection, stayed idle for `idle_timeout`, or the server
+/// is shutting down first. A pipelined request is already in the buffer
+/// and needs no waiting.
+fn wait_for_request(
+    reader: &mut BufReader<impl Transport>,
+    idle_timeout: Duration,
+    shutdown: &Shutdown,
+) -> io::Result<bool> {
+    if !reader.buffer().is_empty() {
+        return Ok(true);
+    }
+    let deadline = Instant::now() + idle_timeout;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() || shutdown.is_requested() {
+            return Ok(false);
+        }
+        reader
+            .get_ref()
+            .socket()
+            .set_read_timeout(Some(left.min(IDLE_POLL)))?;
+        match reader.fill_buf() {
+            Ok(buffer) => return Ok(!buffer.is_empty()),
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            // A TLS client that hangs up without saying goodbye first.
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// `Request::read_from`, plus the `100 Continue` a client that sent
+/// `Expect: 100-continue` waits for before its body.
+fn read_request(
+    reader: &mut BufReader<impl Read + Write>,
+    limits: &Limits,
+) -> Result<Option<Request>, ParseError> {
+    let Some(mut request) = Request::read_head(reader, limits)? else {
+        return Ok(None);
+    };
+    if request.expects_continue() {
+        let writer = reader.get_mut();
+        writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        writer.flush()?;
+    }
+    request.read_body(reader, limits)?;
+    Ok(Some(request))
+}
@@ -1,41 +1,297 @@
-use std::io::{Read, Write};
-use std::net::TcpListener;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+use custom_server::http::{Method, Request, Response, StatusCode};
+use custom_server::middleware::{AccessLog, Cors, Gzip, RequestId};
+use custom_server::proxy::{Balance, HealthCheck, Proxy, ProxyConfig};
+use custom_server::router::Router;
+use custom_server::server::{Server, Shutdown};
+use custom_server::static_files::StaticFiles;
+use custom_server::tls::{SelfSigned, TlsConfig};
+use custom_server::websocket::chat::{self, Room};
+
+const USAGE: &str = "usage: custom_server [--addr HOST:PORT] [--root DIR] [--listings] \
+                     [--event-loop] [--idle-timeout SECS] \
+                     [--https HOST:PORT [--cert PEM --key PEM]] \
+                     [--upstream HOST:PORT ... [--balance round-robin|least-connections] \
+                     [--health-check PATH]]";
+
+/// How often `--health-check` asks each upstream.
+const HEALTH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Command-line options.
+struct Options {
+    addr: String,
+    /// Serve the files under this directory for any `GET` no other route
+    /// takes.
+    root: Option<PathBuf>,
+    /// List directories that have no `index.html`.
+    listings: bool,
+    /// Serve with the single-threaded epoll core instead of the thread
+    /// pool.
+    event_loop: bool,
+    idle_timeout: Option<Duration>,
+    /// Also serve HTTPS here.
+    https: Option<String>,
+    /// The certificate chain and key for HTTPS; without them a
+    /// self-signed certificate is made up at startup.
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    /// Proxy every request to these instead of serving the routes.
+    upstreams: Vec<String>,
+    balance: Option<Balance>,
+    health_check: Option<String>,
+}
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878")
-        .expect("failed to bind to 127.0.0.1:7878");
+    let options = parse_args(env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("custom_server: {err}\n{USAGE}");
+        process::exit(2);
+    });
+    let files = options.root.as_ref().map(|root| {
+        StaticFiles::new(root)
+            .unwrap_or_else(|err| {
+                eprintln!("custom_server: {}: {err}", root.display());
+                process::exit(2);
+            })
+            .listings(options.listings)
+    });
+    let router = if options.upstreams.is_empty() {
+        routes(files)
+    } else {
+        proxy(&options).unwrap_or_else(|err| {
+            eprintln!("custom_server: {err}");
+            process::exit(2);
+        })
+    };
 
-    println!("listening on 127.0.0.1:7878");
+    let served = if options.event_loop {
+        serve_event_loop(&options, router)
+    } else {
+        serve_threads(&options, router)
+    };
+    if let Err(err) = served {
+        eprintln!("custom_server: {err}");
+        process::exit(1);
+    }
+}
 
-    loop {
-        handle_connection(&listener);
+fn serve_threads(options: &Options, router: Router) -> io::Result<()> {
+    let mut server = Server::bind(&options.addr, router)?;
+    if let Some(idle_timeout) = options.idle_timeout {
+        server = server.idle_timeout(idle_timeout);
+    }
+    if let Some(addr) = &options.https {
+        server = server.https(addr, tls_config(options)?)?;
     }
+    on_ctrl_c(server.shutdown_handle());
+    println!("listening on {}", server.local_addr()?);
+    if let Some(addr) = server.https_addr() {
+        println!("listening on https://{}", addr?);
+    }
+    server.run()
 }
 
-fn handle_connection(listener: &TcpListener) {
-    let (mut stream, addr) = listener
-        .accept()
-        .expect("failed to accept connection");
+/// The certificate from `--cert`/`--key`, or a new self-signed one for
+/// this machine, whose certificate is saved for clients to trust.
+fn tls_config(options: &Options) -> io::Result<TlsConfig> {
+    if let (Some(cert), Some(key)) = (&options.cert, &options.key) {
+        return TlsConfig::load(cert, key);
+    }
+    let generated = SelfSigned::generate(&["localhost", "127.0.0.1", "::1"])?;
+    let path = env::temp_dir().join("custom_server-self-signed.pem");
+    fs::write(&path, &generated.cert_pem)?;
+    println!(
+        "using a self-signed certificate for localhost; trust it with \
+         `curl --cacert {}`",
+        path.display()
+    );
+    generated.config()
+}
 
-    println!("accepted connection from {addr}");
+#[cfg(target_os = "linux")]
+fn serve_event_loop(options: &Options, router: Router) -> io::Result<()> {
+    use custom_server::event_loop::{EventLoop, epoll};
 
-    let mut buffer = [0u8; 1024];
-    let bytes_read = stream
-        .read(&mut buffer)
-        .expect("failed to read from stream");
+    // Each connection is a file descriptor, and the point of this core is
+    // holding many of them.
+    if let Err(err) = epoll::raise_fd_limit() {
+        eprintln!("custom_server: can't raise the open file limit: {err}");
+    }
+    let mut event_loop = EventLoop::bind(&options.addr, router)?;
+    if let Some(idle_timeout) = options.idle_timeout {
+        event_loop = event_loop.idle_timeout(idle_timeout);
+    }
+    on_ctrl_c(event_loop.shutdown_handle());
+    println!("listening on {} (event loop)", event_loop.local_addr()?);
+    event_loop.run()
+}
 
-    println!("--- received {bytes_read} bytes ---");
-    println!("{}", String::from_utf8_lossy(&buffer[..bytes_read]));
-    println!("--- end ---");
+#[cfg(not(target_os = "linux"))]
+fn serve_event_loop(_: &Options, _: Router) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--event-loop needs Linux (epoll)",
+    ))
+}
 
-    let body = "<b>hello from server</b>\n";
-    let response = format!(
-        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nContent-Type: text/html\r\n\r\n{}",
-        body.len(),
-        body
-    );
+/// The first Ctrl+C stops accepting and lets requests in flight finish;
+/// a second one gives up on them.
+fn on_ctrl_c(shutdown: Shutdown) {
+    ctrlc::set_handler(move || {
+        if shutdown.request() {
+            process::exit(130);
+        }
+        println!("shutting down; finishing requests in flight (Ctrl+C again to quit now)");
+    })
+    .expect("failed to install the Ctrl+C handler");
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        addr: "127.0.0.1:7878".to_string(),
+        root: None,
+        listings: false,
+        event_loop: false,
+        idle_timeout: None,
+        https: None,
+        cert: None,
+        key: None,
+        upstreams: Vec::new(),
+        balance: None,
+        health_check: None,
+    };
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--addr" => options.addr = args.next().ok_or("--addr needs a value")?,
+            "--root" => options.root = Some(args.next().ok_or("--root needs a value")?.into()),
+            "--listings" => options.listings = true,
+            "--event-loop" => options.event_loop = true,
+            "--idle-timeout" => {
+                let secs = args.next().ok_or("--idle-timeout needs a value")?;
+                let secs: u64 = secs
+                    .parse()
+                    .map_err(|_| format!("--idle-timeout: {secs:?} is not a number of seconds"))?;
+                options.idle_timeout = Some(Duration::from_secs(secs));
+            }
+            "--https" => options.https = Some(args.next().ok_or("--https needs a value")?),
+            "--cert" => options.cert = Some(args.next().ok_or("--cert needs a value")?.into()),
+            "--key" => options.key = Some(args.next().ok_or("--key needs a value")?.into()),
+            "--upstream" => options
+                .upstreams
+                .push(args.next().ok_or("--upstream needs a value")?),
+            "--balance" => {
+                let balance = args.next().ok_or("--balance needs a value")?;
+                options.balance = Some(balance.parse()?);
+            }
+            "--health-check" => {
+                let path = args.next().ok_or("--health-check needs a value")?;
+                if !path.starts_with('/') {
+                    return Err(format!("--health-check: {path:?} is not a path"));
+                }
+                options.health_check = Some(path);
+            }
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                process::exit(0);
+            }
+            _ => return Err(format!("unknown argument {arg:?}")),
+        }
+    }
+    if options.listings && options.root.is_none() {
+        return Err("--listings needs --root".to_string());
+    }
+    if options.cert.is_some() != options.key.is_some() {
+        return Err("--cert and --key go together".to_string());
+    }
+    if options.cert.is_some() && options.https.is_none() {
+        return Err("--cert and --key need --https".to_string());
+    }
+    if options.https.is_some() && options.event_loop {
+        return Err("--https is not supported with --event-loop".to_string());
+    }
+    if options.upstreams.is_empty() {
+        if options.balance.is_some() || options.health_check.is_some() {
+            return Err("--balance and --health-check need --upstream".to_string());
+        }
+    } else if options.root.is_some() {
+        return Err("--root and --upstream don't go together".to_string());
+    } else if options.event_loop {
+        // One slow upstream would hold up every connection.
+        return Err("--upstream is not supported with --event-loop".to_string());
+    }
+    Ok(options)
+}
 
-    stream
-        .write_all(response.as_bytes())
-        .expect("failed to write to stream");
+/// Every request proxied to the upstreams, with the same IDs, logging and
+/// compression as the routes get.
+fn proxy(options: &Options) -> io::Result<Router> {
+    let config = ProxyConfig {
+        balance: options.balance.unwrap_or(Balance::RoundRobin),
+        health_check: options.health_check.as_ref().map(|path| HealthCheck {
+            path: path.clone(),
+            interval: HEALTH_INTERVAL,
+        }),
+        ..ProxyConfig::default()
+    };
+    let proxy = Proxy::new(&options.upstreams, config)?;
+    println!("proxying to {}", options.upstreams.join(", "));
+    Ok(Router::new()
+        .wrap(RequestId::new())
+        .wrap(AccessLog::new())
+        .wrap(Gzip::new())
+        .wrap(proxy))
+}
+
+/// The demo routes, then the static files (if any) for every other path.
+/// Files are registered last because the first matching route wins. Every
+/// request gets an ID and a log line; responses are readable from any
+/// origin and compressed when the client accepts gzip.
+fn routes(files: Option<StaticFiles>) -> Router {
+    let room = Room::new();
+    let mut router = Router::new()
+        .get("/health", |_| {
+            Response::json(StatusCode::OK, "{\"status\":\"ok\"}\n")
+        })
+        .get("/users/:id", |request: &Request| {
+            let id = request.param("id").unwrap_or_default();
+            Response::text(StatusCode::OK, format!("user {id}\n"))
+        })
+        .route(Method::Post, "/echo", |request: &Request| {
+            let content_type = request
+                .headers
+                .get("Content-Type")
+                .unwrap_or("application/octet-stream")
+                .to_string();
+            Response::new(StatusCode::OK)
+                .with_header("Content-Type", content_type)
+                .with_body(request.body.clone())
+        })
+        .get("/chat", |_| Response::html(StatusCode::OK, chat::PAGE))
+        .websocket("/chat/ws", move |request| room.join(request));
+    match files {
+        Some(files) => {
+            router = router.get("/*path", move |request: &Request| {
+                files.serve(request, request.param("path").unwrap_or_default())
+            });
+        }
+        None => {
+            router = router.get("/", |_| {
+                Response::html(StatusCode::OK, "<b>hello from server</b>\n")
+            });
+        }
+    }
+    router
+        .wrap(RequestId::new())
+        .wrap(AccessLog::new())
+        .wrap(
+            Cors::new()
+                .headers(["Content-Type"])
+                .expose(["X-Request-Id"]),
+        )
+        .wrap(Gzip::new())
 }
@@ -0,0 +1,164 @@
+//! One line per request, in logfmt: `key=value` pairs separated by
+//! spaces, with a value in double quotes when it has a space, quote or
+//! `=` of its own. Plain enough to read in a terminal, and structured
+//! enough for a log collector to split into fields without a regex.
+//!
+//! ```text
+//! peer=127.0.0.1:50212 method=GET path=/users/7 status=200 bytes=7 latency_ms=0.084 request_id=5f3a9c1e-000012
+//! ```
+//!
+//! `bytes` is the body actually sent, so `0` for `HEAD` and `304`, and the
+//! compressed size if `Gzip` runs inside this middleware. `latency_ms`
+//! covers everything inside it too, but not the time spent writing the
+//! response to the socket.
+//!
+//! Concepts: `Instant` for elapsed time, `Box<dyn Fn>` for a pluggable
+//! sink, `fmt::Write` to build a `String` with `write!`.
+
+use std::fmt::Write;
+use std::time::Instant;
+
+use super::request_id::HEADER as REQUEST_ID;
+use super::{Middleware, Next};
+use crate::http::{Method, Request, Response};
+
+/// Logs every request and its response.
+pub struct AccessLog {
+    sink: Box<dyn Fn(&str) + Send + Sync>,
+}
+
+impl AccessLog {
+    /// Log to standard output.
+    pub fn new() -> AccessLog {
+        AccessLog::to(|line| println!("{line}"))
+    }
+
+    /// Hand each line to `sink` instead.
+    pub fn to(sink: impl Fn(&str) + Send + Sync + 'static) -> AccessLog {
+        AccessLog {
+            sink: Box::new(sink),
+        }
+    }
+}
+
+impl Default for AccessLog {
+    fn default() -> AccessLog {
+        AccessLog::new()
+    }
+}
+
+impl Middleware for AccessLog {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let started = Instant::now();
+        let peer = request
+            .peer
+            .map_or("-".to_string(), |peer| peer.to_string());
+        let method = request.method;
+        let path = request.path().to_string();
+        let request_id = request.headers.get(REQUEST_ID).unwrap_or("-").to_string();
+
+        let response = next.run(request);
+        let latency = started.elapsed();
+        let bytes = if method == Method::Head || !response.status.allows_body() {
+            0
+        } else {
+            response.body_len()
+        };
+
+        let mut line = String::new();
+        for (key, value) in [
+            ("peer", peer.as_str()),
+            ("method", method.as_str()),
+            ("path", &path),
+            ("status", &response.status.0.to_string()),
+            ("bytes", &bytes.to_string()),
+            (
+                "latency_ms",
+                &format!("{:.3}", latency.as_secs_f64() * 1000.0),
+            ),
+            ("request_id", &request_id),
+        ] {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            let _ = write!(line, "{key}={}", quote(value));
+        }
+        (self.sink)(&line);
+        response
+    }
+}
+
+/// `value` as a logfmt value: bare if it can be, otherwise quoted with
+/// `"` and `\` escaped.
+fn quote(value: &str) -> String {
+    let bare = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_graphic() && c != '"' && c != '=' && c != '\\');
+    if bare {
+        return value.to_string();
+    }
+    let escaped: String = value
+        .chars()
+        .flat_map(|c| match c {
+            '"' | '\\' => vec!['\\', c],
+            c if c.is_control() => c.escape_default().collect(),
+            c => vec![c],
+        })
+        .collect();
+    format!("\"{escaped}\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::StatusCode;
+    use crate::router::Router;
+    use std::sync::{Arc, Mutex};
+
+    fn logged(router: Router, request: Request) -> String {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&lines);
+        let router = router.wrap(AccessLog::to(move |line| {
+            sink.lock().unwrap().push(line.to_string());
+        }));
+        router.handle(request);
+        let mut lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 1);
+        lines.pop().unwrap()
+    }
+
+    #[test]
+    fn logs_one_line_of_fields_per_request() {
+        let router =
+            || Router::new().get("/users/:id", |_| Response::text(StatusCode::OK, "hello"));
+        let mut request = Request::new(Method::Get, "/users/7?verbose=1");
+        request.peer = Some("127.0.0.1:5000".parse().unwrap());
+        request.headers.append(REQUEST_ID, "abc-1");
+        let line = logged(router(), request);
+        let (start, end) = line.split_once(" latency_ms=").unwrap();
+        assert_eq!(
+            start,
+            "peer=127.0.0.1:5000 method=GET path=/users/7 status=200 bytes=5"
+        );
+        let (latency, end) = end.split_once(' ').unwrap();
+        assert!(latency.parse::<f64>().unwrap() >= 0.0);
+        assert_eq!(end, "request_id=abc-1");
+
+        // No body goes out for HEAD, and nothing here knows the peer.
+        let line = logged(router(), Request::new(Method::Head, "/users/7"));
+        assert!(line.starts_with("peer=- method=HEAD"), "{line}");
+        assert!(line.contains(" status=200 bytes=0 "), "{line}");
+        let line = logged(router(), Request::new(Method::Get, "/nope"));
+        assert!(line.contains(" status=404 "), "{line}");
+    }
+
+    #[test]
+    fn quotes_values_that_would_break_the_format() {
+        assert_eq!(quote("/a/b"), "/a/b");
+        assert_eq!(quote(""), "\"\"");
+        assert_eq!(quote("a b"), "\"a b\"");
+        assert_eq!(quote("x=\"1\""), "\"x=\\\"1\\\"\"");
+        assert_eq!(quote("a\nb"), "\"a\\nb\"");
+    }
+}
@@ -0,0 +1,269 @@
+//! Cross-origin resource sharing: letting scripts on other sites call
+//! this server from a browser.
+//!
+//! A browser sends the page's `Origin` with a cross-origin request, and
+//! lets the script see the response only if it says
+//! `Access-Control-Allow-Origin` with that origin (or `*`). A request
+//! that could do more than a plain form submission (a `PUT`, a JSON
+//! `Content-Type`, a custom header) is first checked with a *preflight*:
+//! an `OPTIONS` request carrying `Access-Control-Request-Method` and
+//! `Access-Control-Request-Headers`, answered with what's allowed and for
+//! how long the answer may be cached. Preflights are answered here and
+//! never reach the routes, which would mostly say `405` to `OPTIONS`.
+//!
+//! This is a browser-enforced policy, not access control: other clients
+//! ignore it, and a refused preflight only means the browser won't send
+//! the real request.
+//!
+//! Concepts: enums for "any or these", `Iterator::all` over a header
+//! list, `IntoIterator` arguments for builder methods.
+
+use std::time::Duration;
+
+use super::{Middleware, Next, vary};
+use crate::http::{Method, Request, Response, StatusCode};
+
+#[derive(Debug, Clone)]
+enum Origins {
+    Any,
+    List(Vec<String>),
+}
+
+/// Answers preflights and adds `Access-Control-*` headers to responses
+/// for allowed origins.
+#[derive(Debug, Clone)]
+pub struct Cors {
+    origins: Origins,
+    methods: Vec<Method>,
+    /// Request headers a script may set, lowercase.
+    headers: Vec<String>,
+    /// Response headers a script may read, beyond the basic few.
+    expose: Vec<String>,
+    max_age: Duration,
+}
+
+impl Cors {
+    /// Any origin may make `GET`, `HEAD` and `POST` requests with no
+    /// extra headers; preflights are cached for ten minutes.
+    pub fn new() -> Cors {
+        Cors {
+            origins: Origins::Any,
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            headers: Vec::new(),
+            expose: Vec::new(),
+            max_age: Duration::from_secs(600),
+        }
+    }
+
+    /// Only these origins (`https://example.com`, scheme, host and any
+    /// port, no path).
+    pub fn origins<S: Into<String>>(mut self, origins: impl IntoIterator<Item = S>) -> Cors {
+        self.origins = Origins::List(origins.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn methods(mut self, methods: impl IntoIterator<Item = Method>) -> Cors {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    /// Request headers scripts may send, such as `Content-Type` or
+    /// `Authorization`.
+    pub fn headers<S: Into<String>>(mut self, headers: impl IntoIterator<Item = S>) -> Cors {
+        self.headers = headers
+            .into_iter()
+            .map(|h| h.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Response headers scripts may read, such as `X-Request-Id`.
+    pub fn expose<S: Into<String>>(mut self, headers: impl IntoIterator<Item = S>) -> Cors {
+        self.expose = headers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// How long a browser may reuse a preflight answer.
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = max_age;
+        self
+    }
+
+    /// The `Access-Control-Allow-Origin` value for `origin`, if allowed.
+    fn allow_origin(&self, origin: &str) -> Option<String> {
+        match &self.origins {
+            Origins::Any => Some("*".to_string()),
+            Origins::List(list) => list
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(origin))
+                .then(|| origin.to_string()),
+        }
+    }
+
+    fn preflight(&self, request: &Request, origin: &str, method: &str) -> Response {
+        let method_ok = self.methods.iter().any(|m| m.as_str() == method);
+        let requested: Vec<String> = request
+            .headers
+            .get_all("Access-Control-Request-Headers")
+            .flat_map(|v| v.split(','))
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect();
+        let headers_ok = requested.iter().all(|h| self.headers.contains(h));
+        let mut response = match self.allow_origin(origin) {
+            Some(allowed) if method_ok && headers_ok => {
+                let methods: Vec<&str> = self.methods.iter().map(|m| m.as_str()).collect();
+                let mut response = Response::new(StatusCode::NO_CONTENT)
+                    .with_header("Access-Control-Allow-Origin", allowed)
+                    .with_header("Access-Control-Allow-Methods", methods.join(", "))
+                    .with_header("Access-Control-Max-Age", self.max_age.as_secs().to_string());
+                if !requested.is_empty() {
+                    response =
+                        response.with_header("Access-Control-Allow-Headers", requested.join(", "));
+                }
+                response
+            }
+            _ => Response::error(StatusCode::FORBIDDEN),
+        };
+        for field in [
+            "Origin",
+            "Access-Control-Request-Method",
+            "Access-Control-Request-Headers",
+        ] {
+            vary(&mut response, field);
+        }
+        response
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let origin = request.headers.get("Origin").map(str::to_string);
+        if request.method == Method::Options
+            && let Some(origin) = &origin
+            && let Some(method) = request.headers.get("Access-Control-Request-Method")
+        {
+            return self.preflight(&request, origin, method);
+        }
+
+        let mut response = next.run(request);
+        if matches!(self.origins, Origins::List(_)) {
+            // The answer depends on who's asking.
+            vary(&mut response, "Origin");
+        }
+        if let Some(allowed) = origin.and_then(|origin| self.allow_origin(&origin)) {
+            response.headers.set("Access-Control-Allow-Origin", allowed);
+            if !self.expose.is_empty() {
+                let expose = self.expose.join(", ");
+                response
+                    .headers
+                    .set("Access-Control-Expose-Headers", expose);
+            }
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::router::Router;
+
+    fn router(cors: Cors) -> Router {
+        Router::new()
+            .get("/data", |_| Response::json(StatusCode::OK, "[]"))
+            .wrap(cors)
+    }
+
+    fn request(method: Method, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new(method, "/data");
+        for (name, value) in headers {
+            request.headers.append(*name, *value);
+        }
+        request
+    }
+
+    #[test]
+    fn answers_preflights_without_reaching_the_routes() {
+        let router = router(
+            Cors::new()
+                .origins(["https://app.example"])
+                .methods([Method::Get, Method::Put])
+                .headers(["Content-Type", "X-Token"])
+                .max_age(Duration::from_secs(60)),
+        );
+        let preflight = |origin: &str, method: &str, headers: &str| {
+            router.handle(request(
+                Method::Options,
+                &[
+                    ("Origin", origin),
+                    ("Access-Control-Request-Method", method),
+                    ("Access-Control-Request-Headers", headers),
+                ],
+            ))
+        };
+
+        let response = preflight("https://app.example", "PUT", "x-token, Content-Type");
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let header = |name| response.headers.get(name).unwrap();
+        assert_eq!(header("Access-Control-Allow-Origin"), "https://app.example");
+        assert_eq!(header("Access-Control-Allow-Methods"), "GET, PUT");
+        assert_eq!(
+            header("Access-Control-Allow-Headers"),
+            "x-token, content-type"
+        );
+        assert_eq!(header("Access-Control-Max-Age"), "60");
+        assert!(response.headers.has_token("Vary", "Origin"));
+
+        for (origin, method, headers) in [
+            ("https://evil.example", "PUT", ""),
+            ("https://app.example", "DELETE", ""),
+            ("https://app.example", "GET", "Authorization"),
+        ] {
+            let response = preflight(origin, method, headers);
+            assert_eq!(response.status, StatusCode::FORBIDDEN, "{origin} {method}");
+            assert!(!response.headers.contains("Access-Control-Allow-Origin"));
+        }
+
+        // A plain OPTIONS isn't a preflight; the routes get it.
+        let plain = router.handle(request(
+            Method::Options,
+            &[("Origin", "https://app.example")],
+        ));
+        assert_eq!(plain.status, StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn marks_responses_for_allowed_origins() {
+        let any = router(Cors::new().expose(["X-Request-Id"]));
+        let response = any.handle(request(Method::Get, &[("Origin", "https://a.example")]));
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert_eq!(
+            response.headers.get("Access-Control-Expose-Headers"),
+            Some("X-Request-Id")
+        );
+        assert_eq!(response.headers.get("Vary"), None);
+        let same_origin = any.handle(request(Method::Get, &[]));
+        assert!(!same_origin.headers.contains("Access-Control-Allow-Origin"));
+
+        let listed = router(Cors::new().origins(["https://a.example"]));
+        let response = listed.handle(request(Method::Get, &[("Origin", "https://a.example")]));
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("https://a.example")
+        );
+        assert_eq!(response.headers.get("Vary"), Some("Origin"));
+        let response = listed.handle(request(Method::Get, &[("Origin", "https://b.example")]));
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(!response.headers.contains("Access-Control-Allow-Origin"));
+    }
+}
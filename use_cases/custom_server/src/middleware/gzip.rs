@@ -0,0 +1,232 @@
+//! Response compression, negotiated with `Accept-Encoding`.
+//!
+//! A client lists the codings it can decode, each with an optional
+//! preference `q` from 0 to 1, where `q=0` means "not this one":
+//! `Accept-Encoding: gzip, br;q=0.8`. `*` stands for any coding not named.
+//! Only gzip is offered here; when the client accepts it, a response is
+//! compressed if it's worth it: text-like, not already encoded, not a
+//! byte range (`Content-Range` counts bytes of the uncompressed file), and
+//! at least `min_size` bytes, since below that the gzip header and the
+//! round trip through the compressor cost more than they save. A result no
+//! smaller than the original is thrown away.
+//!
+//! Every response that *could* have been compressed gets `Vary:
+//! Accept-Encoding`, whatever this client asked for, so a shared cache
+//! won't hand the gzip version to a client that can't read it. A strong
+//! `ETag` becomes weak (`W/"..."`) on the compressed version: the bytes
+//! differ from the uncompressed ones, so they mustn't share a strong
+//! validator.
+//!
+//! Concepts: `flate2::write::GzEncoder` as an `io::Write` adapter,
+//! `Option` chains for parsing header parameters.
+
+use std::io::Write;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use super::{Middleware, Next, vary};
+use crate::http::{Headers, Request, Response, StatusCode};
+
+/// Compresses responses for clients that accept gzip.
+#[derive(Debug, Clone, Copy)]
+pub struct Gzip {
+    min_size: usize,
+    level: u32,
+}
+
+impl Gzip {
+    /// Compress bodies of 256 bytes or more, at the default level (6).
+    pub fn new() -> Gzip {
+        Gzip {
+            min_size: 256,
+            level: Compression::default().level(),
+        }
+    }
+
+    /// Leave bodies smaller than `bytes` as they are.
+    pub fn min_size(mut self, bytes: usize) -> Gzip {
+        self.min_size = bytes;
+        self
+    }
+
+    /// From 0 (store only) to 9 (smallest, slowest); clamped to that
+    /// range.
+    pub fn level(mut self, level: u32) -> Gzip {
+        self.level = level.min(9);
+        self
+    }
+
+    fn compress(&self, body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(self.level));
+        encoder
+            .write_all(body)
+            .and_then(|()| encoder.finish())
+            .expect("writing to a Vec can't fail")
+    }
+}
+
+impl Default for Gzip {
+    fn default() -> Gzip {
+        Gzip::new()
+    }
+}
+
+impl Middleware for Gzip {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        let accepts = accepts_gzip(&request.headers);
+        let mut response = next.run(request);
+        if !compressible(&response) || response.body.len() < self.min_size {
+            return response;
+        }
+        vary(&mut response, "Accept-Encoding");
+        if !accepts {
+            return response;
+        }
+        let compressed = self.compress(&response.body);
+        if compressed.len() >= response.body.len() {
+            return response;
+        }
+        if let Some(etag) = response.headers.get("ETag")
+            && !etag.starts_with("W/")
+        {
+            let weak = format!("W/{etag}");
+            response.headers.set("ETag", weak);
+        }
+        response
+            .with_header("Content-Encoding", "gzip")
+            .with_body(compressed)
+    }
+}
+
+/// Whether `Accept-Encoding` allows gzip: named with a `q` above zero, or
+/// not named and covered by `*`.
+fn accepts_gzip(headers: &Headers) -> bool {
+    let mut gzip = None;
+    let mut any = None;
+    for item in headers
+        .get_all("Accept-Encoding")
+        .flat_map(|v| v.split(','))
+    {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or("").trim();
+        let q = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip") {
+            gzip = Some(q);
+        } else if coding == "*" {
+            any = Some(q);
+        }
+    }
+    gzip.or(any).is_some_and(|q| q > 0.0)
+}
+
+/// Whether `response` is the kind worth compressing, regardless of size.
+/// A body still on disk is left alone: compressing it would mean reading
+/// all of it into memory, which is what keeping it on disk avoids.
+fn compressible(response: &Response) -> bool {
+    let headers = &response.headers;
+    if !response.status.allows_body()
+        || response.file.is_some()
+        || response.status == StatusCode::PARTIAL_CONTENT
+        || headers.contains("Content-Encoding")
+        || headers.contains("Content-Range")
+        || headers.has_token("Cache-Control", "no-transform")
+    {
+        return false;
+    }
+    let Some(content_type) = headers.get("Content-Type") else {
+        return false;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Method;
+    use crate::router::Router;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn router() -> Router {
+        let text = "all work and no play makes jack a dull boy\n".repeat(20);
+        let page = text.clone();
+        Router::new()
+            .get("/text", move |_| {
+                Response::text(StatusCode::OK, page.as_str()).with_header("ETag", "\"v1\"")
+            })
+            .get("/small", |_| Response::text(StatusCode::OK, "tiny"))
+            .get("/png", move |_| {
+                Response::new(StatusCode::OK)
+                    .with_header("Content-Type", "image/png")
+                    .with_body(text.as_str())
+            })
+            .wrap(Gzip::new())
+    }
+
+    fn get(path: &str, accept: Option<&str>) -> Response {
+        let mut request = Request::new(Method::Get, path);
+        if let Some(accept) = accept {
+            request.headers.append("Accept-Encoding", accept);
+        }
+        router().handle(request)
+    }
+
+    #[test]
+    fn compresses_text_for_clients_that_accept_gzip() {
+        let plain = get("/text", None);
+        let response = get("/text", Some("br;q=1.0, gzip;q=0.5"));
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.headers.get("ETag"), Some("W/\"v1\""));
+        assert!(response.body.len() < plain.body.len() / 4);
+        let mut decoded = Vec::new();
+        GzDecoder::new(response.body.as_slice())
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, plain.body);
+
+        // The uncompressed version still says it could vary.
+        assert_eq!(plain.headers.get("Content-Encoding"), None);
+        assert_eq!(plain.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(plain.headers.get("ETag"), Some("\"v1\""));
+    }
+
+    #[test]
+    fn leaves_small_binary_and_refused_responses_alone() {
+        for (path, accept) in [
+            ("/small", "gzip"),
+            ("/png", "gzip"),
+            ("/text", "gzip;q=0, *"),
+            ("/text", "identity"),
+        ] {
+            let response = get(path, Some(accept));
+            assert_eq!(
+                response.headers.get("Content-Encoding"),
+                None,
+                "{path} {accept}"
+            );
+        }
+        let response = get("/text", Some("*;q=0.1"));
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+    }
+}
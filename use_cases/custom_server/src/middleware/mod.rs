@@ -0,0 +1,173 @@
+//! Code that runs around every handler: it sees each request before the
+//! router does and each response after, and may change either, or answer
+//! without calling the handler at all.
+//!
+//! A middleware gets the request and a `Next`, the rest of the chain.
+//! Calling `next.run(request)` passes the request inward, through the
+//! middleware wrapped after this one and finally to the route's handler,
+//! and returns the response on its way back out. Not calling it
+//! short-circuits: the CORS preflight answers by itself. Because `Next` is
+//! consumed by `run`, a middleware can call the rest of the chain at most
+//! once.
+//!
+//! The built-ins:
+//!
+//! - `RequestId` gives every request an `X-Request-Id` and echoes it in
+//!   the response.
+//! - `AccessLog` writes one structured line per request.
+//! - `Cors` answers preflights and marks responses readable cross-origin.
+//! - `Gzip` compresses responses for clients that accept it.
+//!
+//! Order matters. Wrap `RequestId` first so the log line has the ID, and
+//! `Gzip` last so the log records the bytes actually sent.
+//!
+//! Concepts: trait objects in a slice, `split_first` to walk a chain
+//! recursively, a blanket `impl` so plain closures are middleware too.
+
+mod access_log;
+mod cors;
+mod gzip;
+mod request_id;
+
+pub use access_log::AccessLog;
+pub use cors::Cors;
+pub use gzip::Gzip;
+pub use request_id::RequestId;
+
+use crate::http::{Request, Response};
+use crate::router::Router;
+
+/// Something that wraps request handling.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: Request, next: Next<'_>) -> Response;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Request, Next<'_>) -> Response + Send + Sync,
+{
+    fn handle(&self, request: Request, next: Next<'_>) -> Response {
+        self(request, next)
+    }
+}
+
+/// The rest of the chain: the middleware inside this one, then the
+/// router.
+pub struct Next<'a> {
+    chain: &'a [Box<dyn Middleware>],
+    router: &'a Router,
+    /// What the end of the chain answers instead of the router, when the
+    /// response was decided before the middleware ran.
+    answer: Option<Response>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(chain: &'a [Box<dyn Middleware>], router: &'a Router) -> Next<'a> {
+        Next {
+            chain,
+            router,
+            answer: None,
+        }
+    }
+
+    /// A chain that ends in `answer` rather than the router.
+    pub(crate) fn answering(
+        chain: &'a [Box<dyn Middleware>],
+        router: &'a Router,
+        answer: Response,
+    ) -> Next<'a> {
+        Next {
+            chain,
+            router,
+            answer: Some(answer),
+        }
+    }
+
+    /// Pass `request` on and return what comes back.
+    pub fn run(self, request: Request) -> Response {
+        match self.chain.split_first() {
+            Some((first, rest)) => {
+                let next = Next {
+                    chain: rest,
+                    ..self
+                };
+                first.handle(request, next)
+            }
+            None => match self.answer {
+                Some(answer) => answer,
+                None => self.router.dispatch(request),
+            },
+        }
+    }
+}
+
+/// Add `field` to the response's `Vary`, telling caches the response
+/// depends on that request header.
+fn vary(response: &mut Response, field: &str) {
+    if response.headers.has_token("Vary", field) || response.headers.has_token("Vary", "*") {
+        return;
+    }
+    let value = match response.headers.get("Vary") {
+        Some(existing) => format!("{existing}, {field}"),
+        None => field.to_string(),
+    };
+    response.headers.set("Vary", value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Method, StatusCode};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn middleware_runs_outermost_first_and_may_short_circuit() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let trace = |name: &'static str| {
+            let seen = Arc::clone(&seen);
+            move |request: Request, next: Next<'_>| {
+                seen.lock().unwrap().push(format!("{name} in"));
+                let response = next.run(request);
+                seen.lock().unwrap().push(format!("{name} out"));
+                response
+            }
+        };
+        let router = Router::new()
+            .get("/", |_| Response::text(StatusCode::OK, "handler"))
+            .wrap(trace("outer"))
+            .wrap(trace("inner"))
+            .wrap(|request: Request, next: Next<'_>| {
+                if request.headers.contains("X-Block") {
+                    return Response::error(StatusCode::FORBIDDEN);
+                }
+                next.run(request)
+            });
+
+        let response = router.handle(Request::new(Method::Get, "/"));
+        assert_eq!(response.body, b"handler");
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["outer in", "inner in", "inner out", "outer out"]
+        );
+
+        let mut blocked = Request::new(Method::Get, "/");
+        blocked.headers.append("X-Block", "1");
+        assert_eq!(router.handle(blocked).status, StatusCode::FORBIDDEN);
+        // Unmatched paths go through the chain too.
+        let response = router.handle(Request::new(Method::Get, "/nope"));
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(seen.lock().unwrap().len(), 12);
+    }
+
+    #[test]
+    fn vary_lists_each_field_once() {
+        let mut response = Response::new(StatusCode::OK);
+        vary(&mut response, "Origin");
+        vary(&mut response, "Accept-Encoding");
+        vary(&mut response, "origin");
+        assert_eq!(
+            response.headers.get("Vary"),
+            Some("Origin, Accept-Encoding")
+        );
+    }
+}
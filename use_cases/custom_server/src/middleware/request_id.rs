@@ -0,0 +1,125 @@
+//! A request ID: a short string that names one request everywhere it's
+//! mentioned, in this server's log, in the response the client gets, and
+//! in whatever the handler calls on its behalf.
+//!
+//! A client or proxy in front that already assigned an ID sends it in
+//! `X-Request-Id`, and that one is kept so the trail spans both. Otherwise
+//! the server makes one up: a prefix that differs between runs, and a
+//! counter. IDs end up in log lines, so a client's is only trusted if it's
+//! short and plain.
+//!
+//! Concepts: `AtomicU64::fetch_add` for a lock-free counter, hashing with
+//! `RandomState` as a cheap source of per-process randomness.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{Middleware, Next};
+use crate::http::{Request, Response};
+
+/// The header the ID travels in, both ways.
+pub const HEADER: &str = "X-Request-Id";
+
+/// Longest ID accepted from a client.
+const MAX_LEN: usize = 128;
+
+/// Sets `X-Request-Id` on every request that lacks a usable one, so
+/// handlers and later middleware can read it, and copies it to the
+/// response.
+#[derive(Debug)]
+pub struct RequestId {
+    prefix: String,
+    next: AtomicU64,
+}
+
+impl RequestId {
+    pub fn new() -> RequestId {
+        let random = RandomState::new().hash_one(std::process::id());
+        RequestId {
+            prefix: format!("{:08x}", random as u32),
+            next: AtomicU64::new(1),
+        }
+    }
+
+    fn generate(&self) -> String {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        format!("{}-{n:06}", self.prefix)
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> RequestId {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, mut request: Request, next: Next<'_>) -> Response {
+        let id = match request.headers.get(HEADER) {
+            Some(id) if is_valid(id) => id.to_string(),
+            _ => self.generate(),
+        };
+        request.headers.set(HEADER, id.as_str());
+        next.run(request).with_header(HEADER, id)
+    }
+}
+
+/// Whether `id` is safe to log and echo: not too long, and only letters,
+/// digits and `-_.:`.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Method, StatusCode};
+    use crate::router::Router;
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |request: &Request| {
+                let id = request.headers.get(HEADER).unwrap_or("none");
+                Response::text(StatusCode::OK, id)
+            })
+            .wrap(RequestId::new())
+    }
+
+    fn call(router: &Router, incoming: Option<&str>) -> (String, String) {
+        let mut request = Request::new(Method::Get, "/");
+        if let Some(id) = incoming {
+            request.headers.append(HEADER, id);
+        }
+        let response = router.handle(request);
+        let echoed = response.headers.get(HEADER).unwrap().to_string();
+        (String::from_utf8(response.body).unwrap(), echoed)
+    }
+
+    #[test]
+    fn keeps_a_plain_incoming_id() {
+        let router = router();
+        let (seen, echoed) = call(&router, Some("edge-42.a:b_c"));
+        assert_eq!(
+            (seen.as_str(), echoed.as_str()),
+            ("edge-42.a:b_c", "edge-42.a:b_c")
+        );
+    }
+
+    #[test]
+    fn replaces_a_missing_or_suspicious_id_with_a_fresh_one() {
+        let router = router();
+        let (first, echoed) = call(&router, None);
+        assert_eq!(first, echoed);
+        let (second, _) = call(&router, Some("evil\" status=500"));
+        let (third, _) = call(&router, Some(&"x".repeat(MAX_LEN + 1)));
+        assert!(first.ends_with("-000001"), "{first}");
+        assert!(second.ends_with("-000002"), "{second}");
+        assert!(third.ends_with("-000003"), "{third}");
+        assert_eq!(first[..8], third[..8]);
+    }
+}
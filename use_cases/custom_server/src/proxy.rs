@@ -0,0 +1,560 @@
+//! Reverse proxy mode: instead of answering requests itself, the server
+//! passes each one to one of several upstream HTTP servers and relays the
+//! answer back, the role nginx plays in front of application servers.
+//!
+//! Each request is rebuilt rather than replayed byte for byte. Hop-by-hop
+//! headers (`Connection` and the fields it names, `Keep-Alive`,
+//! `Transfer-Encoding`, `Upgrade`, ...) describe the client's connection
+//! to this server, not the one to the upstream, so they're dropped both
+//! ways. `Host` becomes the upstream's, and the original goes along in
+//! `X-Forwarded-Host`, with the client's address appended to
+//! `X-Forwarded-For`, since the upstream otherwise only ever sees the
+//! proxy's. Bodies are buffered whole in both directions, and each
+//! request gets its own upstream connection.
+//!
+//! Which upstream gets a request is up to `Balance`: the next one in turn,
+//! or the one with the fewest requests in flight, which steers work away
+//! from an upstream that has slowed down.
+//!
+//! An upstream that fails `max_fails` times in a row (refuses the
+//! connection, times out, sends garbage) is *ejected*: it gets no requests
+//! for `eject_for`. A request whose upstream refused the connection is
+//! tried on the next one, since nothing was sent yet; once the request is
+//! out, it isn't retried, as it may not be safe to repeat. With a
+//! `HealthCheck`, a background thread also asks every upstream for a
+//! path at an interval: failing checks eject an upstream and keep it out,
+//! and the first passing one brings it back early. The thread holds only a
+//! `Weak` reference, and ends once the last clone of the `Proxy` is gone.
+//!
+//! Concepts: `Weak` so a background thread doesn't keep its owner alive,
+//! `AtomicUsize` counters, a guard type whose `Drop` undoes what its
+//! constructor did, `TcpStream::connect_timeout`.
+
+use std::fmt;
+use std::io::{self, BufReader, BufWriter};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::http::{Headers, Limits, Method, ParseError, Request, Response, StatusCode};
+use crate::middleware::{Middleware, Next};
+
+/// Headers that only ever apply to one connection.
+const HOP_BY_HOP: &[&str] = &[
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+    // The body has already been read, so there's nothing to continue.
+    "Expect",
+];
+
+/// How an upstream is chosen for each request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    /// Each in turn.
+    RoundRobin,
+    /// The one with the fewest requests in flight; ties go in turn.
+    LeastConnections,
+}
+
+impl FromStr for Balance {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Balance, String> {
+        match s {
+            "round-robin" => Ok(Balance::RoundRobin),
+            "least-connections" => Ok(Balance::LeastConnections),
+            _ => Err(format!(
+                "unknown balancing {s:?} (round-robin or least-connections)"
+            )),
+        }
+    }
+}
+
+/// Ask every upstream for `path` each `interval`; a `2xx` or `3xx` means
+/// it's healthy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    pub path: String,
+    pub interval: Duration,
+}
+
+/// How the proxy treats its upstreams.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    pub balance: Balance,
+    /// How long to wait for an upstream to accept a connection.
+    pub connect_timeout: Duration,
+    /// How long an upstream may take over each read or write once
+    /// connected; past it the client gets `504 Gateway Timeout`.
+    pub timeout: Duration,
+    /// Failures in a row that eject an upstream.
+    pub max_fails: u32,
+    /// How long an ejected upstream gets no requests.
+    pub eject_for: Duration,
+    pub health_check: Option<HealthCheck>,
+    /// Size limits for upstream responses.
+    pub limits: Limits,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> ProxyConfig {
+        ProxyConfig {
+            balance: Balance::RoundRobin,
+            connect_timeout: Duration::from_secs(2),
+            timeout: Duration::from_secs(30),
+            max_fails: 2,
+            eject_for: Duration::from_secs(10),
+            health_check: None,
+            limits: Limits {
+                max_head: 64 * 1024,
+                max_body: 64 * 1024 * 1024,
+                ..Limits::default()
+            },
+        }
+    }
+}
+
+/// Forwards every request to an upstream. Use it as the innermost
+/// middleware: it never calls the rest of the chain, so the router's own
+/// routes don't run. Cheap to clone; clones share the upstreams and their
+/// health.
+#[derive(Clone)]
+pub struct Proxy {
+    pool: Arc<Pool>,
+}
+
+struct Pool {
+    upstreams: Vec<Upstream>,
+    config: ProxyConfig,
+    /// Where the next round-robin turn starts.
+    next: AtomicUsize,
+}
+
+struct Upstream {
+    /// `host:port` as given, which is also the `Host` it's sent.
+    name: String,
+    addr: SocketAddr,
+    /// Requests in flight.
+    active: AtomicUsize,
+    health: Mutex<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    /// Failures in a row.
+    failures: u32,
+    /// Ejected until then.
+    down_until: Option<Instant>,
+}
+
+impl Proxy {
+    /// A proxy to `upstreams`, each `host:port`, resolved now. Starts the
+    /// health-check thread if `config` asks for one.
+    pub fn new<S: AsRef<str>>(
+        upstreams: impl IntoIterator<Item = S>,
+        config: ProxyConfig,
+    ) -> io::Result<Proxy> {
+        let upstreams = upstreams
+            .into_iter()
+            .map(|name| {
+                let name = name.as_ref();
+                let addr = name.to_socket_addrs()?.next().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("{name}: no address"))
+                })?;
+                Ok(Upstream {
+                    name: name.to_string(),
+                    addr,
+                    active: AtomicUsize::new(0),
+                    health: Mutex::default(),
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        if upstreams.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a proxy needs at least one upstream",
+            ));
+        }
+        let pool = Arc::new(Pool {
+            upstreams,
+            config,
+            next: AtomicUsize::new(0),
+        });
+        if let Some(check) = pool.config.health_check.clone() {
+            let pool = Arc::downgrade(&pool);
+            thread::spawn(move || check_health(pool, check));
+        }
+        Ok(Proxy { pool })
+    }
+
+    /// The upstreams currently taking requests, by name.
+    pub fn available(&self) -> Vec<String> {
+        let now = Instant::now();
+        self.pool
+            .upstreams
+            .iter()
+            .filter(|upstream| upstream.is_available(now))
+            .map(|upstream| upstream.name.clone())
+            .collect()
+    }
+
+    /// Send `request` to an upstream and return its answer, or the `502`,
+    /// `503` or `504` that explains why there isn't one.
+    pub fn forward(&self, request: &Request) -> Response {
+        let config = &self.pool.config;
+        let mut tried = vec![false; self.pool.upstreams.len()];
+        while let Some(lease) = self.pool.pick(&tried) {
+            let upstream = lease.upstream;
+            tried[lease.index] = true;
+            let stream = match TcpStream::connect_timeout(&upstream.addr, config.connect_timeout) {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("upstream {}: {err}", upstream.name);
+                    upstream.failed(config);
+                    continue;
+                }
+            };
+            let outbound = outbound(request, &upstream.name);
+            return match exchange(stream, &outbound, config) {
+                Ok(response) => {
+                    upstream.succeeded();
+                    inbound(response)
+                }
+                Err(err) => {
+                    eprintln!("upstream {}: {err}", upstream.name);
+                    upstream.failed(config);
+                    let timed_out = matches!(
+                        &err,
+                        ParseError::Io(err) if matches!(
+                            err.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        )
+                    );
+                    Response::error(if timed_out {
+                        StatusCode::GATEWAY_TIMEOUT
+                    } else {
+                        StatusCode::BAD_GATEWAY
+                    })
+                }
+            };
+        }
+        // Every upstream was either ejected or refused just now.
+        if tried.contains(&true) {
+            Response::error(StatusCode::BAD_GATEWAY)
+        } else {
+            Response::error(StatusCode::SERVICE_UNAVAILABLE)
+        }
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self
+            .pool
+            .upstreams
+            .iter()
+            .map(|u| u.name.as_str())
+            .collect();
+        f.debug_struct("Proxy")
+            .field("upstreams", &names)
+            .field("config", &self.pool.config)
+            .finish()
+    }
+}
+
+impl Middleware for Proxy {
+    fn handle(&self, request: Request, _: Next<'_>) -> Response {
+        self.forward(&request)
+    }
+}
+
+impl Pool {
+    /// The upstream for the next request, skipping ejected ones and those
+    /// marked in `tried`.
+    fn pick(&self, tried: &[bool]) -> Option<Lease<'_>> {
+        let now = Instant::now();
+        let n = self.upstreams.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut candidates = (0..n)
+            .map(|i| (start + i) % n)
+            .filter(|&i| !tried[i] && self.upstreams[i].is_available(now));
+        let index = match self.config.balance {
+            Balance::RoundRobin => candidates.next(),
+            // `min_by_key` keeps the first of equals, so ties go in turn.
+            Balance::LeastConnections => {
+                candidates.min_by_key(|&i| self.upstreams[i].active.load(Ordering::Relaxed))
+            }
+        }?;
+        Some(Lease::new(index, &self.upstreams[index]))
+    }
+}
+
+/// An upstream chosen for one request, counted as in flight until this is
+/// dropped.
+struct Lease<'a> {
+    index: usize,
+    upstream: &'a Upstream,
+}
+
+impl<'a> Lease<'a> {
+    fn new(index: usize, upstream: &'a Upstream) -> Lease<'a> {
+        upstream.active.fetch_add(1, Ordering::Relaxed);
+        Lease { index, upstream }
+    }
+}
+
+impl Drop for Lease<'_> {
+    fn drop(&mut self) {
+        self.upstream.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Upstream {
+    fn is_available(&self, now: Instant) -> bool {
+        let health = self.health.lock().unwrap();
+        health.down_until.is_none_or(|until| now >= until)
+    }
+
+    fn succeeded(&self) {
+        self.health.lock().unwrap().failures = 0;
+    }
+
+    /// Count a failure, ejecting the upstream if it's one too many.
+    fn failed(&self, config: &ProxyConfig) {
+        let mut health = self.health.lock().unwrap();
+        health.failures += 1;
+        if health.failures >= config.max_fails {
+            health.failures = 0;
+            health.down_until = Some(Instant::now() + config.eject_for);
+            eprintln!(
+                "upstream {} ejected for {}s",
+                self.name,
+                config.eject_for.as_secs_f64()
+            );
+        }
+    }
+
+    /// Record a health check: a pass brings an ejected upstream back, a
+    /// failure keeps one out or counts toward ejecting a healthy one.
+    fn checked(&self, healthy: bool, config: &ProxyConfig) {
+        let now = Instant::now();
+        let mut health = self.health.lock().unwrap();
+        let down = health.down_until.is_some_and(|until| now < until);
+        match (healthy, down) {
+            (true, true) => {
+                *health = Health::default();
+                eprintln!("upstream {} is back", self.name);
+            }
+            (true, false) => health.failures = 0,
+            (false, true) => health.down_until = Some(now + config.eject_for),
+            (false, false) => {
+                drop(health);
+                self.failed(config);
+            }
+        }
+    }
+}
+
+/// The request to send upstream for `request`.
+fn outbound(request: &Request, upstream: &str) -> Request {
+    // `HEAD` goes up as `GET`, like the router serves it, so the length
+    // of the body sent back is the length the client is told.
+    let method = match request.method {
+        Method::Head => Method::Get,
+        method => method,
+    };
+    let mut out = Request::new(method, request.target.clone());
+    out.headers = without_hop_by_hop(&request.headers);
+    out.headers.set("Host", upstream);
+    if let Some(host) = request.headers.get("Host")
+        && !out.headers.contains("X-Forwarded-Host")
+    {
+        out.headers.set("X-Forwarded-Host", host);
+    }
+    if let Some(peer) = request.peer {
+        let forwarded: Vec<String> = request
+            .headers
+            .get_all("X-Forwarded-For")
+            .map(str::to_string)
+            .chain([peer.ip().to_string()])
+            .collect();
+        out.headers.set("X-Forwarded-For", forwarded.join(", "));
+    }
+    out.headers.set("Connection", "close");
+    out.body = request.body.clone();
+    out
+}
+
+/// The response to relay for `response` from upstream.
+fn inbound(mut response: Response) -> Response {
+    response.headers = without_hop_by_hop(&response.headers);
+    response
+}
+
+/// `headers` less the hop-by-hop ones, including any that `Connection`
+/// names.
+fn without_hop_by_hop(headers: &Headers) -> Headers {
+    let named: Vec<String> = headers
+        .get_all("Connection")
+        .flat_map(|v| v.split(','))
+        .map(|name| name.trim().to_string())
+        .collect();
+    let mut out = Headers::new();
+    for (name, value) in headers.iter() {
+        let hop = HOP_BY_HOP
+            .iter()
+            .copied()
+            .chain(named.iter().map(String::as_str))
+            .any(|h| h.eq_ignore_ascii_case(name));
+        if !hop {
+            out.append(name, value);
+        }
+    }
+    out
+}
+
+/// Send `request` over `stream` and read the answer.
+fn exchange(
+    stream: TcpStream,
+    request: &Request,
+    config: &ProxyConfig,
+) -> Result<Response, ParseError> {
+    stream.set_read_timeout(Some(config.timeout))?;
+    stream.set_write_timeout(Some(config.timeout))?;
+    // One write for head and body, or Nagle's algorithm holds the body
+    // back until the upstream acknowledges the head.
+    request.write_to(&mut BufWriter::new(&stream))?;
+    Response::read_from(&mut BufReader::new(&stream), request.method, &config.limits)
+}
+
+/// The health-check thread: check every upstream, sleep, repeat, until
+/// the proxy is dropped.
+fn check_health(pool: Weak<Pool>, check: HealthCheck) {
+    while let Some(pool) = pool.upgrade() {
+        for upstream in &pool.upstreams {
+            let healthy = probe(upstream, &check.path, &pool.config);
+            upstream.checked(healthy, &pool.config);
+        }
+        drop(pool);
+        thread::sleep(check.interval);
+    }
+}
+
+/// Whether `upstream` answers `GET path` with a `2xx` or `3xx` in time.
+fn probe(upstream: &Upstream, path: &str, config: &ProxyConfig) -> bool {
+    let Ok(stream) = TcpStream::connect_timeout(&upstream.addr, config.connect_timeout) else {
+        return false;
+    };
+    let mut request = Request::new(Method::Get, path);
+    request.headers.set("Host", upstream.name.as_str());
+    request.headers.set("Connection", "close");
+    let config = ProxyConfig {
+        timeout: config.connect_timeout,
+        ..config.clone()
+    };
+    exchange(stream, &request, &config)
+        .is_ok_and(|response| (200..400).contains(&response.status.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxy(balance: Balance) -> Proxy {
+        let config = ProxyConfig {
+            balance,
+            max_fails: 1,
+            ..ProxyConfig::default()
+        };
+        Proxy::new(["127.0.0.1:1", "127.0.0.1:2", "127.0.0.1:3"], config).unwrap()
+    }
+
+    fn pick(proxy: &Proxy) -> usize {
+        proxy.pool.pick(&[false; 3]).unwrap().index
+    }
+
+    #[test]
+    fn rewrites_headers_for_the_upstream() {
+        let mut request = Request::new(Method::Head, "/a?b=c");
+        request.peer = Some("10.0.0.9:4000".parse().unwrap());
+        for (name, value) in [
+            ("Host", "example.com"),
+            ("Connection", "keep-alive, X-Hop"),
+            ("X-Hop", "secret"),
+            ("Keep-Alive", "timeout=5"),
+            ("X-Forwarded-For", "203.0.113.7"),
+            ("Accept", "*/*"),
+        ] {
+            request.headers.append(name, value);
+        }
+        let out = outbound(&request, "127.0.0.1:8000");
+        assert_eq!((out.method, out.target.as_str()), (Method::Get, "/a?b=c"));
+        let headers: Vec<_> = out.headers.iter().collect();
+        assert_eq!(
+            headers,
+            [
+                ("Accept", "*/*"),
+                ("Host", "127.0.0.1:8000"),
+                ("X-Forwarded-Host", "example.com"),
+                ("X-Forwarded-For", "203.0.113.7, 10.0.0.9"),
+                ("Connection", "close"),
+            ]
+        );
+
+        let response = Response::text(StatusCode::OK, "hi")
+            .with_header("Connection", "close")
+            .with_header("Transfer-Encoding", "chunked");
+        let relayed = inbound(response);
+        assert_eq!(relayed.headers.iter().count(), 1);
+    }
+
+    #[test]
+    fn round_robin_takes_turns_and_skips_ejected_upstreams() {
+        let proxy = proxy(Balance::RoundRobin);
+        assert_eq!(
+            (0..4).map(|_| pick(&proxy)).collect::<Vec<_>>(),
+            [0, 1, 2, 0]
+        );
+        proxy.pool.upstreams[2].failed(&proxy.pool.config);
+        assert_eq!(
+            (0..4).map(|_| pick(&proxy)).collect::<Vec<_>>(),
+            [1, 0, 0, 1]
+        );
+        assert_eq!(proxy.available(), ["127.0.0.1:1", "127.0.0.1:2"]);
+
+        // A passing health check brings it straight back.
+        proxy.pool.upstreams[2].checked(true, &proxy.pool.config);
+        assert_eq!(proxy.available().len(), 3);
+        // Once every upstream is out, there's nothing to try.
+        for upstream in &proxy.pool.upstreams {
+            upstream.failed(&proxy.pool.config);
+        }
+        assert!(proxy.pool.pick(&[false; 3]).is_none());
+        let status = proxy.forward(&Request::new(Method::Get, "/")).status;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn least_connections_prefers_idle_upstreams() {
+        let proxy = proxy(Balance::LeastConnections);
+        let busy = proxy.pool.pick(&[false; 3]).unwrap();
+        let also_busy = proxy.pool.pick(&[false; 3]).unwrap();
+        assert_eq!((busy.index, also_busy.index), (0, 1));
+        assert_eq!(pick(&proxy), 2);
+        assert_eq!(pick(&proxy), 2);
+        // With the first one free again, only the second is avoided.
+        drop(busy);
+        let picks: Vec<usize> = (0..6).map(|_| pick(&proxy)).collect();
+        assert!(picks.contains(&0) && !picks.contains(&1), "{picks:?}");
+    }
+}
@@ -0,0 +1,286 @@
+//! Dispatching requests to handlers by method and path.
+//!
+//! A route pattern is split on `/` into segments, and so is the request
+//! path. A literal segment must match exactly; `:name` matches any one
+//! segment and captures it as a parameter; `*name`, only as the last
+//! segment, captures the whole rest of the path (possibly empty). Routes
+//! are tried in the order they were added, and the first match wins, so
+//! register `/users/me` before `/users/:id`.
+//!
+//! When no route matches the path, the answer is `404`. When some route
+//! matches the path but none for this method, it's `405` with an `Allow`
+//! header listing the methods that would have worked. A `HEAD` request is
+//! served by the `GET` route for its path; the connection then sends only
+//! the headers.
+//!
+//! Middleware added with `wrap` runs around all of this, 404s included.
+//!
+//! Concepts: `Box<dyn Fn>` trait objects with `Send + Sync` so the table
+//! can be shared across threads, slice patterns, `BTreeSet` for a sorted,
+//! deduplicated `Allow` list.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::http::request::percent_decode;
+use crate::http::{Method, Request, Response, StatusCode};
+use crate::middleware::{Middleware, Next};
+use crate::websocket::{self, NewHandler};
+
+/// What a route runs. Any closure of the right shape works, including one
+/// that captures configuration.
+pub type Handler = Box<dyn Fn(&Request) -> Response + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String),
+}
+
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+/// A route table.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    /// Paths that upgrade to WebSocket, each also in `routes` as a `GET`
+    /// that explains as much.
+    websockets: Vec<(Vec<Segment>, NewHandler)>,
+    /// Outermost first.
+    middleware: Vec<Box<dyn Middleware>>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Add a route for `method` requests whose path matches `pattern`.
+    ///
+    /// # Panics
+    ///
+    /// If `pattern` has a `*rest` segment anywhere but last; that's a bug
+    /// in the route table, not something a request can cause.
+    pub fn route(
+        mut self,
+        method: Method,
+        pattern: &str,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Router {
+        self.routes.push(Route {
+            method,
+            segments: parse_pattern(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get(
+        self,
+        pattern: &str,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Router {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post(
+        self,
+        pattern: &str,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Router {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    /// Upgrade requests for `pattern` to WebSocket, with a handler from
+    /// `new_handler` for each connection. Other requests for the path get
+    /// `426 Upgrade Required`.
+    ///
+    /// # Panics
+    ///
+    /// As for `route`.
+    pub fn websocket<H: websocket::Handler + 'static>(
+        mut self,
+        pattern: &str,
+        new_handler: impl Fn(&Request) -> H + Send + Sync + 'static,
+    ) -> Router {
+        self.websockets.push((
+            parse_pattern(pattern),
+            Box::new(move |request| Box::new(new_handler(request))),
+        ));
+        self.get(pattern, |_| {
+            Response::error(StatusCode::UPGRADE_REQUIRED)
+                .with_header("Upgrade", "websocket")
+                .with_header("Sec-WebSocket-Version", "13")
+        })
+    }
+
+    /// Run `middleware` around every request. The first one wrapped is
+    /// the outermost: it sees the request first and the response last.
+    pub fn wrap(mut self, middleware: impl Middleware + 'static) -> Router {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// The WebSocket route for `request`'s path, if any, with its path
+    /// parameters filled in.
+    pub(crate) fn websocket_route(&self, request: &mut Request) -> Option<&NewHandler> {
+        let path = request.path().to_string();
+        let path: Vec<&str> = split(&path).collect();
+        self.websockets.iter().find_map(|(segments, new_handler)| {
+            request.params = match_segments(segments, &path)?;
+            Some(new_handler)
+        })
+    }
+
+    /// Answer `request`: the middleware, then the route's handler.
+    pub fn handle(&self, request: Request) -> Response {
+        Next::new(&self.middleware, self).run(request)
+    }
+
+    /// Run the middleware around `response`, decided without a handler —
+    /// a WebSocket handshake — so it's logged like any other answer.
+    pub(crate) fn handle_with(&self, request: Request, response: Response) -> Response {
+        Next::answering(&self.middleware, self, response).run(request)
+    }
+
+    /// Run the handler for `request`, or produce the `404`/`405` for it.
+    pub(crate) fn dispatch(&self, mut request: Request) -> Response {
+        let path: Vec<&str> = split(request.path()).collect();
+        let mut allowed = BTreeSet::new();
+        for route in &self.routes {
+            let Some(params) = match_segments(&route.segments, &path) else {
+                continue;
+            };
+            let serves = route.method == request.method
+                || (route.method == Method::Get && request.method == Method::Head);
+            if serves {
+                request.params = params;
+                return (route.handler)(&request);
+            }
+            allowed.insert(route.method.as_str());
+            if route.method == Method::Get {
+                allowed.insert(Method::Head.as_str());
+            }
+        }
+        if allowed.is_empty() {
+            return Response::error(StatusCode::NOT_FOUND);
+        }
+        Response::error(StatusCode::METHOD_NOT_ALLOWED)
+            .with_header("Allow", allowed.into_iter().collect::<Vec<_>>().join(", "))
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let segments: Vec<Segment> = split(pattern)
+        .map(|s| {
+            if let Some(name) = s.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = s.strip_prefix('*') {
+                Segment::Rest(name.to_string())
+            } else {
+                Segment::Literal(s.to_string())
+            }
+        })
+        .collect();
+    if let Some(i) = segments.iter().position(|s| matches!(s, Segment::Rest(_))) {
+        assert!(
+            i == segments.len() - 1,
+            "`*` segment must be last in {pattern:?}"
+        );
+    }
+    segments
+}
+
+/// The non-empty segments of a path, so `/a//b/` is `["a", "b"]`.
+fn split(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+/// The parameters captured if `path` matches `pattern`.
+fn match_segments(pattern: &[Segment], path: &[&str]) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    for (i, segment) in pattern.iter().enumerate() {
+        match segment {
+            Segment::Rest(name) => {
+                let rest: Vec<String> = path[i.min(path.len())..]
+                    .iter()
+                    .map(|s| percent_decode(s))
+                    .collect::<Option<_>>()?;
+                params.insert(name.clone(), rest.join("/"));
+                return Some(params);
+            }
+            Segment::Literal(literal) => {
+                if path.get(i).and_then(|s| percent_decode(s)).as_ref() != Some(literal) {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                params.insert(name.clone(), percent_decode(path.get(i)?)?);
+            }
+        }
+    }
+    (pattern.len() == path.len()).then_some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router() -> Router {
+        Router::new()
+            .get("/health", |_| Response::text(StatusCode::OK, "ok"))
+            .get("/users/me", |_| Response::text(StatusCode::OK, "me"))
+            .get("/users/:id", |r| {
+                Response::text(StatusCode::OK, format!("user {}", r.param("id").unwrap()))
+            })
+            .route(Method::Delete, "/users/:id", |_| {
+                Response::new(StatusCode::NO_CONTENT)
+            })
+            .get("/files/*path", |r| {
+                Response::text(
+                    StatusCode::OK,
+                    format!("file [{}]", r.param("path").unwrap()),
+                )
+            })
+    }
+
+    fn call(method: Method, target: &str) -> (u16, String) {
+        let response = router().handle(Request::new(method, target));
+        (response.status.0, String::from_utf8(response.body).unwrap())
+    }
+
+    #[test]
+    fn matches_literals_params_and_rest_in_order() {
+        assert_eq!(call(Method::Get, "/health"), (200, "ok".into()));
+        assert_eq!(call(Method::Get, "/users/me"), (200, "me".into()));
+        assert_eq!(call(Method::Get, "/users/42?x=1"), (200, "user 42".into()));
+        assert_eq!(call(Method::Get, "/users/a%20b/"), (200, "user a b".into()));
+        assert_eq!(
+            call(Method::Get, "/files/css/site.css"),
+            (200, "file [css/site.css]".into())
+        );
+        assert_eq!(call(Method::Get, "/files"), (200, "file []".into()));
+        assert_eq!(call(Method::Head, "/health").0, 200);
+    }
+
+    #[test]
+    fn unknown_paths_are_404_and_wrong_methods_405_with_allow() {
+        assert_eq!(call(Method::Get, "/nope").0, 404);
+        assert_eq!(call(Method::Get, "/users/1/extra").0, 404);
+        assert_eq!(call(Method::Get, "/users/%zz").0, 404);
+
+        let response = router().handle(Request::new(Method::Post, "/users/7"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers.get("Allow"), Some("DELETE, GET, HEAD"));
+    }
+
+    #[test]
+    #[should_panic(expected = "must be last")]
+    fn rest_segment_must_come_last() {
+        let _ = Router::new().get("/a/*rest/b", |_| Response::new(StatusCode::OK));
+    }
+}
@@ -0,0 +1,407 @@
+//! The accept loop: a listener, a route table and a pool of workers.
+//!
+//! Every accepted connection is handed to the `ThreadPool` and the loop
+//! goes straight back to `accept`, so a client that is slow to send its
+//! request holds up one worker rather than the whole server. With `https`,
+//! a second listener speaks TLS: its own accept loop runs on another
+//! thread and feeds the same pool, so both ports share the workers and the
+//! route table.
+//!
+//! Stopping is cooperative. A `Shutdown` handle, cloned into a Ctrl+C
+//! handler or a test, sets a flag and then opens a connection to the
+//! listener itself (each of them): `accept` has no timeout, and that
+//! throwaway connection is what wakes it up to see the flag. `run` then closes the listener and
+//! drops the pool, which waits for the requests already accepted, queued
+//! ones included, and then waits for the WebSocket sessions, which run on
+//! threads of their own and close themselves when they see the flag. A
+//! connection stuck writing to a client that has stopped reading gives up
+//! after `write_timeout`, so it can't keep the wait from ending.
+//!
+//! Concepts: `Arc` to share the route table with every job, `AtomicBool`
+//! as a stop flag, a self-connection to unblock `accept`, `thread::scope`
+//! to borrow the pool from a second accept loop.
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::http::Limits;
+use crate::router::Router;
+use crate::threadpool::ThreadPool;
+use crate::tls::TlsConfig;
+use crate::websocket::Sessions;
+use crate::{ConnectionConfig, handle_connection, handle_tls_connection};
+
+/// Worker threads by default. Workers mostly wait on sockets, not the
+/// CPU, so there can be more of them than cores.
+pub const DEFAULT_WORKERS: usize = 16;
+
+/// Accepted connections that may wait for a worker by default.
+pub const DEFAULT_QUEUE: usize = 64;
+
+/// A bound listener and everything needed to serve it.
+pub struct Server {
+    listener: TcpListener,
+    /// The HTTPS listener, if any.
+    https: Option<(TcpListener, TlsConfig)>,
+    router: Arc<Router>,
+    config: ConnectionConfig,
+    workers: usize,
+    queue: usize,
+    shutdown: Shutdown,
+}
+
+impl Server {
+    /// Bind `addr` (port 0 picks a free one; see `local_addr`).
+    pub fn bind(addr: impl ToSocketAddrs, router: Router) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        let shutdown = Shutdown::new(listener.local_addr()?);
+        Ok(Server {
+            listener,
+            https: None,
+            router: Arc::new(router),
+            config: ConnectionConfig::default(),
+            workers: DEFAULT_WORKERS,
+            queue: DEFAULT_QUEUE,
+            shutdown,
+        })
+    }
+
+    pub fn workers(mut self, workers: usize) -> Server {
+        self.workers = workers;
+        self
+    }
+
+    pub fn queue(mut self, queue: usize) -> Server {
+        self.queue = queue;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Server {
+        self.config.limits = limits;
+        self
+    }
+
+    /// How long an open connection may wait for its next request.
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Server {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// How long one write may wait for a client that isn't reading.
+    pub fn write_timeout(mut self, write_timeout: Duration) -> Server {
+        self.config.write_timeout = write_timeout;
+        self
+    }
+
+    /// Requests answered on one connection before it's closed.
+    pub fn max_requests(mut self, max_requests: usize) -> Server {
+        self.config.max_requests = max_requests;
+        self
+    }
+
+    /// WebSocket connections open at once; they don't use the workers.
+    pub fn max_websockets(mut self, max_websockets: usize) -> Server {
+        self.config.max_websockets = max_websockets;
+        self
+    }
+
+    /// Also serve HTTPS on `addr`, with the certificate in `tls`.
+    pub fn https(mut self, addr: impl ToSocketAddrs, tls: TlsConfig) -> io::Result<Server> {
+        let listener = TcpListener::bind(addr)?;
+        self.shutdown.also_wake(listener.local_addr()?);
+        self.https = Some((listener, tls));
+        Ok(self)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// The HTTPS listener's address, if there is one.
+    pub fn https_addr(&self) -> Option<io::Result<SocketAddr>> {
+        self.https
+            .as_ref()
+            .map(|(listener, _)| listener.local_addr())
+    }
+
+    /// A handle that stops `run` from another thread.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Serve connections until shutdown is requested, then wait for the
+    /// ones already accepted to finish.
+    pub fn run(self) -> io::Result<()> {
+        let pool = ThreadPool::new(self.workers, self.queue)?;
+        let accept = Accept {
+            pool: &pool,
+            router: &self.router,
+            config: self.config,
+            shutdown: &self.shutdown,
+        };
+        thread::scope(|scope| {
+            if let Some((listener, tls)) = self.https {
+                scope.spawn(move || accept.run(listener, Some(&tls)));
+            }
+            accept.run(self.listener, None);
+        });
+        drop(pool);
+        self.shutdown.sessions.wait();
+        Ok(())
+    }
+}
+
+/// What an accept loop needs to hand connections to the pool.
+#[derive(Clone, Copy)]
+struct Accept<'a> {
+    pool: &'a ThreadPool,
+    router: &'a Arc<Router>,
+    config: ConnectionConfig,
+    shutdown: &'a Shutdown,
+}
+
+impl Accept<'_> {
+    /// Accept from `listener` until shutdown is requested, speaking TLS
+    /// if `tls` is given.
+    fn run(self, listener: TcpListener, tls: Option<&TlsConfig>) {
+        for stream in listener.incoming() {
+            if self.shutdown.is_requested() {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("failed to accept connection: {err}");
+                    continue;
+                }
+            };
+            let router = Arc::clone(self.router);
+            let config = self.config;
+            let shutdown = self.shutdown.clone();
+            let tls = tls.cloned();
+            self.pool.execute(move || {
+                let served = match &tls {
+                    Some(tls) => handle_tls_connection(stream, tls, &router, &config, &shutdown),
+                    None => handle_connection(stream, &router, &config, &shutdown),
+                };
+                if let Err(err) = served {
+                    eprintln!("connection error: {err}");
+                }
+            });
+        }
+        // Refuse new connections right away rather than leaving them in the
+        // backlog while the pool drains.
+        drop(listener);
+    }
+}
+
+/// Asks a running `Server` to stop. Cheap to clone.
+#[derive(Debug, Clone)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    /// The listeners to connect to.
+    wake: Arc<Mutex<Vec<SocketAddr>>>,
+    /// WebSocket sessions, which outlive the pool's jobs and so are
+    /// waited for separately.
+    pub(crate) sessions: Arc<Sessions>,
+}
+
+impl Shutdown {
+    pub(crate) fn new(listening: SocketAddr) -> Shutdown {
+        let shutdown = Shutdown {
+            requested: Arc::new(AtomicBool::new(false)),
+            wake: Arc::default(),
+            sessions: Arc::default(),
+        };
+        shutdown.also_wake(listening);
+        shutdown
+    }
+
+    /// Wake the listener on `listening` too.
+    fn also_wake(&self, listening: SocketAddr) {
+        // A listener on every interface is reachable on loopback.
+        let mut wake = listening;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(match wake {
+                SocketAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                SocketAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        self.wake.lock().unwrap().push(wake);
+    }
+
+    /// Stop accepting connections and let the server wind down. Returns
+    /// whether shutdown had already been requested, so a second Ctrl+C
+    /// can mean "don't wait".
+    pub fn request(&self) -> bool {
+        let already = self.requested.swap(true, Ordering::SeqCst);
+        // If the connection fails, the listener is gone and the accept
+        // loop with it.
+        for &wake in self.wake.lock().unwrap().iter() {
+            let _ = TcpStream::connect(wake);
+        }
+        already
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{Request, Response, StatusCode};
+    use std::io::{Read, Write};
+    use std::thread;
+    use std::time::Instant;
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |_| Response::text(StatusCode::OK, "hi"))
+            .get("/big", |_| {
+                Response::text(StatusCode::OK, "x".repeat(1 << 20))
+            })
+            .get("/n/:n", |r: &Request| {
+                Response::text(StatusCode::OK, r.param("n").unwrap().to_string())
+            })
+            .get("/slow", |_| {
+                thread::sleep(Duration::from_millis(300));
+                Response::text(StatusCode::OK, "done")
+            })
+    }
+
+    fn server() -> Server {
+        Server::bind("127.0.0.1:0", router()).unwrap().workers(2)
+    }
+
+    fn start(server: Server) -> (SocketAddr, Shutdown, thread::JoinHandle<io::Result<()>>) {
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        (addr, shutdown, thread::spawn(move || server.run()))
+    }
+
+    /// Send `raw` and read until the server closes the connection.
+    fn exchange(addr: SocketAddr, raw: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(raw.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> String {
+        exchange(
+            addr,
+            &format!("GET {path} HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n"),
+        )
+    }
+
+    #[test]
+    fn a_stalled_client_does_not_block_others() {
+        let (addr, shutdown, server) = start(server());
+
+        // Connected but never sends anything: its worker sits in `read`.
+        let stalled = TcpStream::connect(addr).unwrap();
+        let started = Instant::now();
+        assert!(get(addr, "/").ends_with("\r\n\r\nhi"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        drop(stalled);
+        shutdown.request();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn shutdown_finishes_requests_in_flight() {
+        let (addr, shutdown, server) = start(server());
+
+        // An idle keep-alive connection doesn't hold up the shutdown.
+        let mut idle = TcpStream::connect(addr).unwrap();
+        idle.write_all(b"GET / HTTP/1.1\r\nHost: test\r\n\r\n")
+            .unwrap();
+
+        let client = thread::spawn(move || get(addr, "/slow"));
+        thread::sleep(Duration::from_millis(100));
+        let started = Instant::now();
+        assert!(!shutdown.request());
+        assert!(shutdown.request());
+        server.join().unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(client.join().unwrap().ends_with("\r\n\r\ndone"));
+        assert!(TcpStream::connect(addr).is_err());
+
+        let mut answered = String::new();
+        idle.read_to_string(&mut answered).unwrap();
+        assert!(answered.ends_with("\r\n\r\nhi"));
+    }
+
+    #[test]
+    fn pipelined_requests_are_answered_in_order_on_one_connection() {
+        let (addr, shutdown, server) = start(server());
+        let responses = exchange(
+            addr,
+            "GET /n/1 HTTP/1.1\r\nHost: t\r\n\r\nGET /n/2 HTTP/1.1\r\nHost: t\r\n\r\n\
+             GET /n/3 HTTP/1.1\r\nHost: t\r\nConnection: close\r\n\r\n",
+        );
+        let bodies: Vec<&str> = responses
+            .split("HTTP/1.1 200 OK\r\n")
+            .skip(1)
+            .map(|r| r.rsplit("\r\n\r\n").next().unwrap())
+            .collect();
+        assert_eq!(bodies, ["1", "2", "3"]);
+        assert_eq!(responses.matches("Connection: close").count(), 1);
+
+        shutdown.request();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn connections_close_after_max_requests_or_idle_timeout() {
+        let server = server()
+            .max_requests(2)
+            .idle_timeout(Duration::from_millis(200));
+        let (addr, shutdown, server) = start(server);
+
+        let request = "GET / HTTP/1.1\r\nHost: t\r\n\r\n";
+        let responses = exchange(addr, &request.repeat(3));
+        assert_eq!(responses.matches("200 OK").count(), 2);
+        assert!(responses.ends_with("Connection: close\r\nContent-Length: 2\r\n\r\nhi"));
+
+        let started = Instant::now();
+        assert_eq!(exchange(addr, request).matches("200 OK").count(), 1);
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // HTTP/1.0 closes unless asked not to, and is told when it's kept.
+        let kept = "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n";
+        assert!(exchange(addr, kept).contains("Connection: keep-alive\r\n"));
+        assert!(exchange(addr, "GET / HTTP/1.0\r\n\r\n").contains("Connection: close\r\n"));
+
+        shutdown.request();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn a_client_that_stops_reading_cannot_stall_shutdown() {
+        let server = server()
+            .workers(1)
+            .write_timeout(Duration::from_millis(200));
+        let (addr, shutdown, server) = start(server);
+
+        // Far more response bytes than the socket buffers hold, none read.
+        let mut greedy = TcpStream::connect(addr).unwrap();
+        greedy
+            .write_all(&b"GET /big HTTP/1.1\r\nHost: t\r\n\r\n".repeat(64))
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let started = Instant::now();
+        shutdown.request();
+        server.join().unwrap().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(greedy);
+    }
+}
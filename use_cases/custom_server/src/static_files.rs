@@ -0,0 +1,619 @@
+//! Serving a directory tree over HTTP.
+//!
+//! A request path maps onto a file under the root directory, and it has to
+//! stay under it. `..` segments are refused outright, and the joined path
+//! is canonicalized (resolving symlinks) and must still start with the
+//! canonical root. Anything outside gets the same `404` as a missing
+//! file, so probing reveals nothing.
+//!
+//! Files go out with a `Content-Type` chosen from the extension, plus the
+//! validators caches need: an `ETag` built from size and modification
+//! time, and `Last-Modified`. A client that already has the current
+//! version sends one back (`If-None-Match`, `If-Modified-Since`) and gets
+//! an empty `304`. `Range: bytes=...` asks for part of a file (resuming a
+//! download, a video player seeking) and gets a `206` with only those
+//! bytes read from disk. `If-Range` makes that conditional on the file
+//! not having changed in between.
+//!
+//! Only small files (up to `IN_MEMORY`) are read into the response, which
+//! lets the gzip middleware compress them. Anything bigger, and every
+//! range, becomes a `FileBody` that's read a buffer at a time as it's
+//! sent, so a few requests for a huge file can't use up memory; a `HEAD`
+//! request never reads the file at all, since only its size is needed.
+//!
+//! A directory is served as its `index.html`, or, when listings are
+//! turned on, as an HTML page of its entries.
+//!
+//! Concepts: `Path::canonicalize` and `starts_with`, `fs::Metadata`,
+//! escaping untrusted names into HTML.
+
+use std::fmt::Write as _;
+use std::fs::{self, Metadata};
+use std::io;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::http::request::percent_decode;
+use crate::http::{Method, Request, Response, StatusCode, date};
+
+/// Files up to this size are read into memory to be served; bigger ones
+/// are streamed from disk.
+pub const IN_MEMORY: u64 = 64 * 1024;
+
+/// Files under one directory.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    listings: bool,
+}
+
+impl StaticFiles {
+    /// Serve the files under `root`, which must be an existing directory.
+    pub fn new(root: impl AsRef<Path>) -> io::Result<StaticFiles> {
+        let root = fs::canonicalize(root)?;
+        if !root.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotADirectory,
+                format!("{} is not a directory", root.display()),
+            ));
+        }
+        Ok(StaticFiles {
+            root,
+            listings: false,
+        })
+    }
+
+    /// Answer requests for a directory without an `index.html` with a
+    /// list of its entries rather than `404`.
+    pub fn listings(mut self, enabled: bool) -> StaticFiles {
+        self.listings = enabled;
+        self
+    }
+
+    /// Answer `request` with the file at `relative`, the part of the path
+    /// below where these files are mounted (a route's `*rest`).
+    pub fn serve(&self, request: &Request, relative: &str) -> Response {
+        let Some(path) = self.resolve(relative) else {
+            return Response::error(StatusCode::NOT_FOUND);
+        };
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(err) => return io_error(&path, &err),
+        };
+        if !metadata.is_dir() {
+            return serve_file(request, &path, &metadata);
+        }
+
+        // Links in `index.html` or a listing are relative to the directory,
+        // which only works if the URL ends in `/`.
+        if !request.path().ends_with('/') {
+            let mut location = format!("{}/", request.path());
+            if let Some(query) = request.query() {
+                location = format!("{location}?{query}");
+            }
+            return Response::error(StatusCode::MOVED_PERMANENTLY)
+                .with_header("Location", location);
+        }
+        if let Some(index) = self.contain(path.join("index.html"))
+            && let Ok(metadata) = fs::metadata(&index)
+            && metadata.is_file()
+        {
+            return serve_file(request, &index, &metadata);
+        }
+        if self.listings {
+            return listing(request.path(), &path);
+        }
+        Response::error(StatusCode::NOT_FOUND)
+    }
+
+    /// The root joined with the `/`-separated `relative`, if the result
+    /// exists and is inside the root.
+    fn resolve(&self, relative: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for part in relative.split('/').filter(|p| !p.is_empty() && *p != ".") {
+            // A single ordinary name: not `..`, and nothing like `C:` that
+            // `push` would treat as a new root.
+            let mut components = Path::new(part).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(name)), None) => path.push(name),
+                _ => return None,
+            }
+        }
+        self.contain(path)
+    }
+
+    /// `path` with symlinks resolved, if it exists and is inside the root.
+    fn contain(&self, path: PathBuf) -> Option<PathBuf> {
+        let path = fs::canonicalize(path).ok()?;
+        path.starts_with(&self.root).then_some(path)
+    }
+}
+
+fn serve_file(request: &Request, path: &Path, metadata: &Metadata) -> Response {
+    let len = metadata.len();
+    let modified = metadata.modified().ok().map(whole_seconds);
+    let etag = match modified.and_then(|m| m.duration_since(UNIX_EPOCH).ok()) {
+        Some(since) => format!("\"{len:x}-{:x}\"", since.as_secs()),
+        None => format!("\"{len:x}\""),
+    };
+    let last_modified = modified.map(date::format);
+
+    let mut response = Response::new(StatusCode::OK).with_header("ETag", &etag);
+    if let Some(last_modified) = &last_modified {
+        response = response.with_header("Last-Modified", last_modified);
+    }
+    if is_fresh(request, &etag, modified) {
+        response.status = StatusCode::NOT_MODIFIED;
+        return response;
+    }
+    response = response
+        .with_header("Content-Type", content_type(path))
+        .with_header("Accept-Ranges", "bytes");
+
+    let mut range = Ranged::Whole;
+    if request.method == Method::Get
+        && let Some(value) = request.headers.get("Range")
+    {
+        let unchanged = request.headers.get("If-Range").is_none_or(|validator| {
+            validator == etag || Some(validator) == last_modified.as_deref()
+        });
+        if unchanged {
+            range = parse_range(value, len);
+        }
+    }
+    match range {
+        Ranged::Whole if len <= IN_MEMORY && request.method != Method::Head => {
+            match fs::read(path) {
+                Ok(body) => response.with_body(body),
+                Err(err) => io_error(path, &err),
+            }
+        }
+        Ranged::Whole => response.with_file(path, 0..len),
+        Ranged::Part(part) => {
+            response.status = StatusCode::PARTIAL_CONTENT;
+            response
+                .with_header(
+                    "Content-Range",
+                    format!("bytes {}-{}/{len}", part.start, part.end - 1),
+                )
+                .with_file(path, part)
+        }
+        Ranged::Unsatisfiable => Response::error(StatusCode::RANGE_NOT_SATISFIABLE)
+            .with_header("Content-Range", format!("bytes */{len}")),
+    }
+}
+
+/// Whether the client's cached copy is current, going by `If-None-Match`
+/// or, only without it, `If-Modified-Since`.
+fn is_fresh(request: &Request, etag: &str, modified: Option<SystemTime>) -> bool {
+    if !matches!(request.method, Method::Get | Method::Head) {
+        return false;
+    }
+    if let Some(tags) = request.headers.get("If-None-Match") {
+        // The weak comparison: `W/"x"` matches `"x"`.
+        let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        return tags
+            .split(',')
+            .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag));
+    }
+    match (request.headers.get("If-Modified-Since"), modified) {
+        (Some(since), Some(modified)) => date::parse(since).is_some_and(|since| modified <= since),
+        _ => false,
+    }
+}
+
+/// What a `Range` header asks of a file of a given length.
+#[derive(Debug, PartialEq, Eq)]
+enum Ranged {
+    /// No usable range: send the whole file. That includes syntax this
+    /// server doesn't handle, such as several ranges at once; answering
+    /// those with the full file is always allowed.
+    Whole,
+    Part(Range<u64>),
+    /// A valid range that lies entirely past the end of the file.
+    Unsatisfiable,
+}
+
+fn parse_range(value: &str, len: u64) -> Ranged {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ranged::Whole;
+    };
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return Ranged::Whole;
+    };
+    let number = |s: &str| -> Option<u64> {
+        if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        s.parse().ok()
+    };
+
+    if first.is_empty() {
+        // `bytes=-N`: the last `N` bytes.
+        return match number(last) {
+            None => Ranged::Whole,
+            Some(0) => Ranged::Unsatisfiable,
+            Some(_) if len == 0 => Ranged::Unsatisfiable,
+            Some(n) => Ranged::Part(len.saturating_sub(n)..len),
+        };
+    }
+    let Some(first) = number(first) else {
+        return Ranged::Whole;
+    };
+    let end = if last.is_empty() {
+        len
+    } else {
+        match number(last) {
+            Some(last) if last >= first => len.min(last.saturating_add(1)),
+            _ => return Ranged::Whole,
+        }
+    };
+    if first >= len {
+        return Ranged::Unsatisfiable;
+    }
+    Ranged::Part(first..end)
+}
+
+/// An HTML page listing the directory `dir`, served at `url_path`.
+fn listing(url_path: &str, dir: &Path) -> Response {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => return io_error(dir, &err),
+    };
+    // Names that aren't UTF-8 can't be asked for through a URL the router
+    // will decode, so they're left out.
+    let mut rows: Vec<(bool, String, Option<Metadata>)> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let metadata = fs::metadata(entry.path()).ok();
+            let is_dir = metadata.as_ref().is_some_and(Metadata::is_dir);
+            Some((is_dir, name, metadata))
+        })
+        .collect();
+    // Directories first, then by name.
+    rows.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    let title = escape_html(&percent_decode(url_path).unwrap_or_else(|| url_path.to_string()));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n\
+         <body>\n<h1>Index of {title}</h1>\n<table>\n"
+    );
+    if url_path != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+    for (is_dir, name, metadata) in &rows {
+        let slash = if *is_dir { "/" } else { "" };
+        let size = match metadata {
+            Some(metadata) if !is_dir => metadata.len().to_string(),
+            _ => "-".to_string(),
+        };
+        let modified = metadata
+            .as_ref()
+            .and_then(|m| m.modified().ok())
+            .map(date::format)
+            .unwrap_or_default();
+        let _ = writeln!(
+            html,
+            "<tr><td><a href=\"{}{slash}\">{}{slash}</a></td><td>{size}</td><td>{modified}</td></tr>",
+            percent_encode(name),
+            escape_html(name),
+        );
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    Response::html(StatusCode::OK, html)
+}
+
+/// The response for a failed read of `path`.
+fn io_error(path: &Path, err: &io::Error) -> Response {
+    match err.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => {
+            Response::error(StatusCode::NOT_FOUND)
+        }
+        io::ErrorKind::PermissionDenied => Response::error(StatusCode::FORBIDDEN),
+        _ => {
+            eprintln!("{}: {err}", path.display());
+            Response::error(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// `Content-Type` for a file, going by its extension.
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "txt" | "log" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "json" | "map" => "application/json",
+        "xml" => "application/xml",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+/// `time` without its fraction of a second, since that's all an HTTP date
+/// can express: a file modified at 12:00:00.5 is not newer than
+/// `If-Modified-Since: ... 12:00:00 GMT`.
+fn whole_seconds(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => UNIX_EPOCH + std::time::Duration::from_secs(since.as_secs()),
+        Err(_) => time,
+    }
+}
+
+/// A file name as a URL path segment: everything but the unreserved
+/// characters is `%XX`-encoded.
+fn percent_encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(char::from(b));
+        } else {
+            let _ = write!(encoded, "%{b:02X}");
+        }
+    }
+    encoded
+}
+
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// A scratch directory with a `www` root to serve and a file beside
+    /// it that must stay out of reach.
+    fn site(name: &str) -> TempDir {
+        let dir = TempDir::new(&format!("static-{name}"));
+        dir.file("secret.txt", "outside");
+        dir.file("www/index.html", "<h1>home</h1>");
+        dir.file("www/css/site.css", "body {}");
+        dir.file("www/digits.txt", "0123456789");
+        dir.file("www/blob", [0u8, 1, 2]);
+        dir
+    }
+
+    fn files(dir: &TempDir) -> StaticFiles {
+        StaticFiles::new(dir.0.join("www")).unwrap()
+    }
+
+    fn request(method: Method, path: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new(method, path);
+        for (name, value) in headers {
+            request.headers.append(*name, *value);
+        }
+        request
+    }
+
+    /// The response to a `GET`, with any body still on disk read in.
+    fn get(files: &StaticFiles, path: &str, headers: &[(&str, &str)]) -> Response {
+        let request = request(Method::Get, path, headers);
+        let mut response = files.serve(&request, path.trim_start_matches('/'));
+        if let Some(file) = response.file.take() {
+            file.write_to(&mut response.body).unwrap();
+        }
+        response
+    }
+
+    #[test]
+    fn serves_files_with_their_type_and_directories_by_index() {
+        let dir = site("types");
+        let files = files(&dir);
+
+        let css = get(&files, "/css/site.css", &[]);
+        assert_eq!(css.status, StatusCode::OK);
+        assert_eq!(
+            css.headers.get("Content-Type"),
+            Some("text/css; charset=utf-8")
+        );
+        assert_eq!(css.body, b"body {}");
+        assert_eq!(
+            get(&files, "/blob", &[]).headers.get("Content-Type"),
+            Some("application/octet-stream")
+        );
+
+        assert_eq!(get(&files, "/", &[]).body, b"<h1>home</h1>");
+        let redirect = get(&files, "/css", &[]);
+        assert_eq!(redirect.status, StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(redirect.headers.get("Location"), Some("/css/"));
+        assert_eq!(get(&files, "/css/", &[]).status, StatusCode::NOT_FOUND);
+        assert_eq!(get(&files, "/nope.txt", &[]).status, StatusCode::NOT_FOUND);
+        assert_eq!(get(&files, "/blob/x", &[]).status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn big_files_and_ranges_stay_on_disk_until_sent() {
+        let dir = site("big");
+        let big = dir.0.join("www/big.bin");
+        fs::write(&big, vec![7u8; IN_MEMORY as usize + 1]).unwrap();
+        let files = files(&dir);
+        let serve = |method, path, headers: &[(&str, &str)]| {
+            files.serve(&request(method, path, headers), &path[1..])
+        };
+
+        let whole = serve(Method::Get, "/big.bin", &[]);
+        assert!(whole.body.is_empty());
+        assert_eq!(whole.body_len(), IN_MEMORY + 1);
+        let part = serve(Method::Get, "/big.bin", &[("Range", "bytes=10-")]);
+        assert_eq!(part.file.unwrap().offset, 10);
+        // Small files are read, so they can be compressed, except for HEAD.
+        assert_eq!(serve(Method::Get, "/digits.txt", &[]).file, None);
+        let head = serve(Method::Head, "/digits.txt", &[]);
+        assert_eq!((head.file.is_some(), head.body_len()), (true, 10));
+    }
+
+    #[test]
+    fn nothing_outside_the_root_is_reachable() {
+        let dir = site("outside");
+        let files = files(&dir);
+        for path in [
+            "/../secret.txt",
+            "/css/../../secret.txt",
+            "/./../secret.txt",
+        ] {
+            assert_eq!(
+                get(&files, path, &[]).status,
+                StatusCode::NOT_FOUND,
+                "{path}"
+            );
+        }
+        assert_eq!(get(&files, "/css/./site.css", &[]).status, StatusCode::OK);
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.0.join("secret.txt"), dir.0.join("www/link")).unwrap();
+            assert_eq!(get(&files, "/link", &[]).status, StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[test]
+    fn conditional_requests_get_304_while_the_file_is_unchanged() {
+        let dir = site("conditional");
+        let files = files(&dir);
+        let first = get(&files, "/digits.txt", &[]);
+        let etag = first.headers.get("ETag").unwrap().to_string();
+        let last_modified = first.headers.get("Last-Modified").unwrap().to_string();
+
+        let cached = get(&files, "/digits.txt", &[("If-None-Match", &etag)]);
+        assert_eq!(cached.status, StatusCode::NOT_MODIFIED);
+        assert!(cached.body.is_empty());
+        assert_eq!(cached.headers.get("ETag"), Some(etag.as_str()));
+
+        let weak = format!("\"other\", W/{etag}");
+        let status = |headers: &[(&str, &str)]| get(&files, "/digits.txt", headers).status;
+        assert_eq!(
+            status(&[("If-None-Match", &weak)]),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(status(&[("If-None-Match", "\"other\"")]), StatusCode::OK);
+        assert_eq!(
+            status(&[("If-Modified-Since", &last_modified)]),
+            StatusCode::NOT_MODIFIED
+        );
+        assert_eq!(
+            status(&[("If-Modified-Since", "Thu, 01 Jan 1970 00:00:00 GMT")]),
+            StatusCode::OK
+        );
+        // If-None-Match wins over If-Modified-Since.
+        assert_eq!(
+            status(&[
+                ("If-None-Match", "\"other\""),
+                ("If-Modified-Since", &last_modified)
+            ]),
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn range_requests_get_just_those_bytes() {
+        let dir = site("ranges");
+        let files = files(&dir);
+        let part = get(&files, "/digits.txt", &[("Range", "bytes=2-4")]);
+        assert_eq!(part.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(part.body, b"234");
+        assert_eq!(part.headers.get("Content-Range"), Some("bytes 2-4/10"));
+
+        let body = |range: &str| get(&files, "/digits.txt", &[("Range", range)]).body;
+        assert_eq!(body("bytes=7-"), b"789");
+        assert_eq!(body("bytes=-3"), b"789");
+        assert_eq!(body("bytes=8-100"), b"89");
+        assert_eq!(body("bytes=0-1,5-6"), b"0123456789");
+
+        let past_end = get(&files, "/digits.txt", &[("Range", "bytes=10-")]);
+        assert_eq!(past_end.status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(past_end.headers.get("Content-Range"), Some("bytes */10"));
+
+        let etag = part.headers.get("ETag").unwrap();
+        let status = |if_range: &str| {
+            get(
+                &files,
+                "/digits.txt",
+                &[("Range", "bytes=0-0"), ("If-Range", if_range)],
+            )
+            .status
+        };
+        assert_eq!(status(etag), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(status("\"stale\""), StatusCode::OK);
+    }
+
+    #[test]
+    fn parse_range_follows_the_byte_range_grammar() {
+        assert_eq!(parse_range("bytes=0-0", 5), Ranged::Part(0..1));
+        assert_eq!(parse_range("bytes=-10", 5), Ranged::Part(0..5));
+        assert_eq!(parse_range("bytes=-0", 5), Ranged::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-1", 0), Ranged::Unsatisfiable);
+        assert_eq!(parse_range("bytes=5-", 5), Ranged::Unsatisfiable);
+        for ignored in [
+            "items=0-1",
+            "bytes=3-1",
+            "bytes=a-b",
+            "bytes=-",
+            "bytes=+1-2",
+        ] {
+            assert_eq!(parse_range(ignored, 5), Ranged::Whole, "{ignored}");
+        }
+    }
+
+    #[test]
+    fn listings_escape_names_and_put_directories_first() {
+        let dir = site("listings");
+        fs::remove_file(dir.0.join("www/index.html")).unwrap();
+        fs::write(dir.0.join("www/a <b>&c.txt"), "").unwrap();
+        assert_eq!(get(&files(&dir), "/", &[]).status, StatusCode::NOT_FOUND);
+
+        let page = get(&files(&dir).listings(true), "/", &[]);
+        let html = String::from_utf8(page.body).unwrap();
+        assert!(html.contains("<a href=\"a%20%3Cb%3E%26c.txt\">a &lt;b&gt;&amp;c.txt</a>"));
+        assert!(html.find("css/").unwrap() < html.find("blob").unwrap());
+        assert!(!html.contains("../"));
+        let nested = get(&files(&dir).listings(true), "/css/", &[]);
+        assert!(
+            String::from_utf8(nested.body)
+                .unwrap()
+                .contains("href=\"../\"")
+        );
+    }
+}
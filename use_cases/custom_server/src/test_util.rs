@@ -0,0 +1,32 @@
+//! Helpers shared by the unit tests of several modules.
+
+use std::fs;
+use std::path::PathBuf;
+
+/// A scratch directory under the system temp dir, removed on drop. `name`
+/// keeps tests that run at the same time out of each other's way.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let root =
+            std::env::temp_dir().join(format!("custom_server-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        TempDir(root)
+    }
+
+    /// Write `contents` to `relative`, creating directories on the way.
+    pub fn file(&self, relative: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
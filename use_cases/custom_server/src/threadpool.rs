@@ -0,0 +1,169 @@
+//! A fixed set of worker threads running jobs from a bounded queue.
+//!
+//! The accept loop hands each connection to `execute` and goes straight
+//! back to accepting, so one slow client only ties up one worker. The
+//! queue in front of the workers is bounded: when every worker is busy and
+//! the queue is full, `execute` blocks, which stops the accept loop and
+//! leaves further connections waiting in the kernel's backlog instead of
+//! piling up in memory.
+//!
+//! A job that panics takes down neither its worker nor the server: the
+//! worker catches the unwind, reports it and takes the next job. Dropping
+//! the pool is the graceful shutdown: no new jobs can arrive, the workers
+//! finish the one they're running and everything still queued, then exit,
+//! and `drop` returns once they have.
+//!
+//! Concepts: `mpsc::sync_channel` as a bounded queue, a `Receiver` shared
+//! through `Arc<Mutex<_>>`, `catch_unwind`, `Drop` joining threads.
+
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+pub struct ThreadPool {
+    /// `None` once shutdown has begun; dropping it is what tells the
+    /// workers to stop.
+    sender: Option<SyncSender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    /// Start `workers` threads behind a queue holding up to `queue` jobs
+    /// that no worker has picked up yet.
+    ///
+    /// # Panics
+    ///
+    /// If `workers` is zero: nothing would ever run.
+    pub fn new(workers: usize, queue: usize) -> io::Result<ThreadPool> {
+        assert!(workers > 0, "a thread pool needs at least one worker");
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..workers)
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                thread::Builder::new()
+                    .name(format!("worker-{id}"))
+                    .spawn(move || work(id, &receiver))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(ThreadPool {
+            sender: Some(sender),
+            workers,
+        })
+    }
+
+    /// Queue `job` for the next free worker, waiting for room in the queue
+    /// if it's full.
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) {
+        self.sender
+            .as_ref()
+            .expect("the sender lives until drop")
+            .send(Box::new(job))
+            .expect("workers outlive the sender");
+    }
+
+    /// Number of worker threads.
+    pub fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.workers.is_empty()
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Let the workers drain the queue, then wait for them.
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A worker's loop: take a job, run it, repeat until the queue is closed
+/// and empty.
+fn work(id: usize, receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // The guard is a temporary, so the lock is released before the job
+        // runs and other workers can pick up jobs meanwhile. No job runs
+        // under the lock, so it's never poisoned in practice; if it were,
+        // the receiver inside would still be fine to use.
+        let job = receiver
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .recv();
+        let Ok(job) = job else {
+            return;
+        };
+        // The default panic hook has already printed the message and
+        // location by the time `catch_unwind` returns.
+        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            eprintln!("worker-{id}: job panicked; worker continues");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn jobs_run_concurrently_on_every_worker() {
+        // Each job waits for all the others, so this only finishes if four
+        // of them are running at the same time.
+        let pool = ThreadPool::new(4, 0).unwrap();
+        assert_eq!(pool.len(), 4);
+        let barrier = Arc::new(Barrier::new(4));
+        let names = Arc::new(Mutex::new(Vec::new()));
+        for _ in 0..4 {
+            let (barrier, names) = (Arc::clone(&barrier), Arc::clone(&names));
+            pool.execute(move || {
+                barrier.wait();
+                let name = thread::current().name().unwrap().to_string();
+                names.lock().unwrap().push(name);
+            });
+        }
+        drop(pool);
+        let mut names = names.lock().unwrap().clone();
+        names.sort();
+        assert_eq!(names, ["worker-0", "worker-1", "worker-2", "worker-3"]);
+    }
+
+    #[test]
+    fn a_panicking_job_leaves_its_worker_running() {
+        let pool = ThreadPool::new(1, 4).unwrap();
+        let done = Arc::new(AtomicUsize::new(0));
+        pool.execute(|| panic!("handler bug"));
+        for _ in 0..3 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+        assert_eq!(done.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn dropping_the_pool_drains_queued_jobs() {
+        let pool = ThreadPool::new(2, 100).unwrap();
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let done = Arc::clone(&done);
+            pool.execute(move || {
+                thread::sleep(std::time::Duration::from_millis(1));
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        drop(pool);
+        assert_eq!(done.load(Ordering::SeqCst), 100);
+    }
+}
@@ -0,0 +1,147 @@
+//! TLS for the HTTPS listener, on top of rustls.
+//!
+//! TLS sits between the socket and HTTP: once the handshake is done, a
+//! `rustls::StreamOwned` is `Read` and `Write` like the socket, except the
+//! bytes it reads have been decrypted and the bytes written to it get
+//! encrypted. So `handle_tls_connection` does the handshake and then runs
+//! the very same request loop as a plain connection.
+//!
+//! A certificate comes from PEM files (`TlsConfig::load`), or for local
+//! development from `SelfSigned::generate`, which makes a throwaway one
+//! with rcgen. Nothing trusts a self-signed certificate by default: give
+//! its PEM to the client (`curl --cacert cert.pem`) or tell the client not
+//! to check (`curl -k`).
+//!
+//! Concepts: wrapping one `Read + Write` in another, `Arc` to share an
+//! immutable config with every connection, converting foreign error types
+//! into `io::Error`.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+/// A server certificate and key, ready to accept TLS connections with.
+/// Cheap to clone.
+#[derive(Clone)]
+pub struct TlsConfig {
+    config: Arc<ServerConfig>,
+}
+
+impl TlsConfig {
+    /// Use the certificate chain (leaf first) and private key in the PEM
+    /// text `cert_chain` and `key`.
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> io::Result<TlsConfig> {
+        let certs = CertificateDer::pem_slice_iter(cert_chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| invalid(format!("bad certificate PEM: {err}")))?;
+        if certs.is_empty() {
+            return Err(invalid("no certificate in the PEM"));
+        }
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|err| invalid(format!("bad private key PEM: {err}")))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13, &rustls::version::TLS12])
+            .map_err(invalid)?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(invalid)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(TlsConfig {
+            config: Arc::new(config),
+        })
+    }
+
+    /// Read the certificate chain and private key from PEM files.
+    pub fn load(cert_path: impl AsRef<Path>, key_path: impl AsRef<Path>) -> io::Result<TlsConfig> {
+        let read = |path: &Path| {
+            fs::read(path)
+                .map_err(|err| io::Error::new(err.kind(), format!("{}: {err}", path.display())))
+        };
+        TlsConfig::from_pem(&read(cert_path.as_ref())?, &read(key_path.as_ref())?)
+    }
+
+    /// Run the TLS handshake on a freshly accepted `socket`, which should
+    /// have a read timeout so a silent client can't stall it forever.
+    pub(crate) fn accept(
+        &self,
+        socket: TcpStream,
+    ) -> io::Result<StreamOwned<ServerConnection, TcpStream>> {
+        let connection = ServerConnection::new(Arc::clone(&self.config)).map_err(invalid)?;
+        let mut stream = StreamOwned::new(connection, socket);
+        while stream.conn.is_handshaking() {
+            stream.conn.complete_io(&mut stream.sock)?;
+        }
+        Ok(stream)
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig").finish_non_exhaustive()
+    }
+}
+
+/// A freshly generated self-signed certificate and its key, as PEM.
+pub struct SelfSigned {
+    pub cert_pem: String,
+    pub key_pem: String,
+}
+
+impl SelfSigned {
+    /// A certificate valid for each of `names`, which may be DNS names or
+    /// IP addresses (`"localhost"`, `"127.0.0.1"`).
+    pub fn generate(names: &[&str]) -> io::Result<SelfSigned> {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let certified = rcgen::generate_simple_self_signed(names).map_err(invalid)?;
+        Ok(SelfSigned {
+            cert_pem: certified.cert.pem(),
+            key_pem: certified.signing_key.serialize_pem(),
+        })
+    }
+
+    pub fn config(&self) -> io::Result<TlsConfig> {
+        TlsConfig::from_pem(self.cert_pem.as_bytes(), self.key_pem.as_bytes())
+    }
+}
+
+fn invalid(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_a_generated_certificate_and_rejects_garbage() {
+        let generated = SelfSigned::generate(&["localhost", "127.0.0.1"]).unwrap();
+        assert!(
+            generated
+                .cert_pem
+                .starts_with("-----BEGIN CERTIFICATE-----")
+        );
+        generated.config().unwrap();
+
+        let err = TlsConfig::from_pem(b"not pem", generated.key_pem.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("no certificate"), "{err}");
+        // A key that doesn't match the certificate.
+        let other = SelfSigned::generate(&["localhost"]).unwrap();
+        let err = TlsConfig::from_pem(generated.cert_pem.as_bytes(), other.key_pem.as_bytes());
+        assert_eq!(err.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            TlsConfig::load("/nonexistent/cert.pem", "/nonexistent/key.pem")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! The demo chat room: every text message from a member goes to all the
+//! members, the sender included, so each client sees one shared history.
+//!
+//! The room is a table of outboxes behind a mutex, shared by every
+//! member's handler. A member's messages are broadcast from its own
+//! connection's thread; each outbox queues them for its connection to
+//! send when it next wakes up, so a slow client delays nobody else.
+//!
+//! Concepts: `Arc<Mutex<..>>` shared between connections,
+//! `HashMap::retain` to drop members whose connection is gone.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::{CloseCode, Handler, Message, Outbox};
+use crate::http::Request;
+use crate::http::request::percent_decode;
+
+/// Longest name a member may pick, in characters.
+const MAX_NAME: usize = 32;
+
+/// A chat room. Cheap to clone; clones are the same room.
+#[derive(Debug, Clone, Default)]
+pub struct Room {
+    members: Arc<Mutex<Members>>,
+}
+
+#[derive(Debug, Default)]
+struct Members {
+    next_id: u64,
+    outboxes: HashMap<u64, Outbox>,
+}
+
+impl Room {
+    pub fn new() -> Room {
+        Room::default()
+    }
+
+    /// The handler for a new member, named by the request's `?name=`, or
+    /// `guest<n>` without one.
+    pub fn join(&self, request: &Request) -> Member {
+        let id = {
+            let mut members = self.members.lock().unwrap();
+            members.next_id += 1;
+            members.next_id
+        };
+        let name = request
+            .query()
+            .into_iter()
+            .flat_map(|query| query.split('&'))
+            .find_map(|pair| pair.strip_prefix("name="))
+            .and_then(|name| percent_decode(&name.replace('+', " ")))
+            .map(|name| name.trim().chars().take(MAX_NAME).collect::<String>())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| format!("guest{id}"));
+        Member {
+            room: self.clone(),
+            id,
+            name,
+        }
+    }
+
+    /// How many members are connected.
+    pub fn len(&self) -> usize {
+        self.members.lock().unwrap().outboxes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn broadcast(&self, text: &str) {
+        let mut members = self.members.lock().unwrap();
+        members
+            .outboxes
+            .retain(|_, out| out.send(Message::Text(text.to_string())));
+    }
+}
+
+/// One member's connection to a `Room`.
+pub struct Member {
+    room: Room,
+    id: u64,
+    name: String,
+}
+
+impl Handler for Member {
+    fn on_open(&mut self, out: &Outbox) {
+        let mut members = self.room.members.lock().unwrap();
+        members.outboxes.insert(self.id, out.clone());
+        drop(members);
+        self.room.broadcast(&format!("* {} joined", self.name));
+    }
+
+    fn on_message(&mut self, message: Message, out: &Outbox) {
+        match message {
+            Message::Text(text) => self.room.broadcast(&format!("{}: {text}", self.name)),
+            Message::Binary(_) => {
+                out.close(CloseCode::UNSUPPORTED_DATA, "text messages only");
+            }
+        }
+    }
+
+    fn on_close(&mut self, _: CloseCode) {
+        self.room.members.lock().unwrap().outboxes.remove(&self.id);
+        self.room.broadcast(&format!("* {} left", self.name));
+    }
+}
+
+/// A page that talks to a room at `/chat/ws`.
+pub const PAGE: &str = r#"<!doctype html>
+<meta charset="utf-8">
+<title>chat</title>
+<style>
+  body { font: 15px sans-serif; max-width: 40em; margin: 2em auto; }
+  #log { height: 20em; overflow-y: auto; border: 1px solid #ccc; padding: .5em; white-space: pre-wrap; }
+  form { display: flex; gap: .5em; margin-top: .5em; }
+  #text { flex: 1; }
+</style>
+<div id="log"></div>
+<form><input id="text" autocomplete="off" autofocus><button>send</button></form>
+<script>
+  const log = document.getElementById("log");
+  const text = document.getElementById("text");
+  const show = line => { log.textContent += line + "\n"; log.scrollTop = log.scrollHeight; };
+  const name = prompt("Your name?") || "";
+  const scheme = location.protocol === "https:" ? "wss:" : "ws:";
+  const socket = new WebSocket(`${scheme}//${location.host}/chat/ws?name=${encodeURIComponent(name)}`);
+  socket.onmessage = event => show(event.data);
+  socket.onclose = event => show(`(disconnected: ${event.code})`);
+  document.querySelector("form").onsubmit = event => {
+    event.preventDefault();
+    if (text.value) socket.send(text.value);
+    text.value = "";
+  };
+</script>
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::Method;
+    use crate::websocket::Command;
+    use std::sync::mpsc;
+
+    /// An outbox whose messages land in a channel instead of a socket.
+    fn outbox() -> (Outbox, mpsc::Receiver<Command>) {
+        let (sender, receiver) = mpsc::channel();
+        (Outbox { sender }, receiver)
+    }
+
+    fn texts(receiver: &mpsc::Receiver<Command>) -> Vec<String> {
+        receiver
+            .try_iter()
+            .map(|command| match command {
+                Command::Send(Message::Text(text)) => text,
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn messages_reach_every_member_until_they_leave() {
+        let room = Room::new();
+        let mut ann = room.join(&Request::new(Method::Get, "/chat/ws?name=Ann+Lee"));
+        let mut guest = room.join(&Request::new(Method::Get, "/chat/ws"));
+        let (ann_out, ann_in) = outbox();
+        let (guest_out, guest_in) = outbox();
+
+        ann.on_open(&ann_out);
+        guest.on_open(&guest_out);
+        ann.on_message(Message::Text("hi".into()), &ann_out);
+        assert_eq!(room.len(), 2);
+        assert_eq!(
+            texts(&ann_in),
+            ["* Ann Lee joined", "* guest2 joined", "Ann Lee: hi"]
+        );
+        assert_eq!(texts(&guest_in), ["* guest2 joined", "Ann Lee: hi"]);
+
+        // A member whose connection is gone is dropped on the next send.
+        drop(guest_in);
+        ann.on_message(Message::Text("anyone?".into()), &ann_out);
+        assert_eq!(room.len(), 1);
+        guest.on_close(CloseCode::ABNORMAL);
+        assert_eq!(texts(&ann_in), ["Ann Lee: anyone?", "* guest2 left"]);
+    }
+}
@@ -0,0 +1,355 @@
+//! WebSocket frames (RFC 6455 section 5): the unit a message travels in.
+//!
+//! ```text
+//!  0                   1                   2                   3
+//!  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+//! +-+-+-+-+-------+-+-------------+-------------------------------+
+//! |F|R|R|R| opcode|M| Payload len |    Extended payload length    |
+//! |I|S|S|S|  (4)  |A|     (7)     |             (16/64)           |
+//! |N|V|V|V|       |S|             |   (if payload len==126/127)   |
+//! | |1|2|3|       |K|             |                               |
+//! +-+-+-+-+-------+-+-------------+ - - - - - - - - - - - - - - - +
+//! |     Extended payload length continued, if payload len == 127  |
+//! + - - - - - - - - - - - - - - - +-------------------------------+
+//! |                               | Masking-key, if MASK set to 1 |
+//! +-------------------------------+-------------------------------+
+//! | Masking-key (continued)       |          Payload Data         |
+//! +-------------------------------- - - - - - - - - - - - - - - - +
+//! ```
+//!
+//! A client masks every frame it sends by XOR-ing the payload with a key
+//! it picks per frame, so that a proxy that doesn't understand WebSocket
+//! can't be tricked into caching attacker-chosen bytes. The server never
+//! masks. A message may be split over several frames: the first carries
+//! the opcode, the rest are `Continuation`, and the last has `fin` set.
+//! Control frames (`Close`, `Ping`, `Pong`) are never split and may come
+//! between the pieces of a message.
+//!
+//! `parse` works on a byte slice that may hold only part of a frame, like
+//! the event loop's request parsing, so the caller can read whatever has
+//! arrived and try again when more does.
+//!
+//! Concepts: bit twiddling with masks and shifts, `u16::from_be_bytes`
+//! and friends for network byte order, a newtype for close codes.
+
+use std::fmt;
+use std::io::{self, Write};
+
+/// What a frame carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation,
+    Text,
+    Binary,
+    Close,
+    Ping,
+    Pong,
+}
+
+impl Opcode {
+    fn from_bits(bits: u8) -> Option<Opcode> {
+        Some(match bits {
+            0x0 => Opcode::Continuation,
+            0x1 => Opcode::Text,
+            0x2 => Opcode::Binary,
+            0x8 => Opcode::Close,
+            0x9 => Opcode::Ping,
+            0xA => Opcode::Pong,
+            _ => return None,
+        })
+    }
+
+    fn bits(self) -> u8 {
+        match self {
+            Opcode::Continuation => 0x0,
+            Opcode::Text => 0x1,
+            Opcode::Binary => 0x2,
+            Opcode::Close => 0x8,
+            Opcode::Ping => 0x9,
+            Opcode::Pong => 0xA,
+        }
+    }
+
+    pub fn is_control(self) -> bool {
+        matches!(self, Opcode::Close | Opcode::Ping | Opcode::Pong)
+    }
+}
+
+/// Why a connection is closed, as sent in a `Close` frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CloseCode(pub u16);
+
+impl CloseCode {
+    pub const NORMAL: CloseCode = CloseCode(1000);
+    /// The server is shutting down, or the browser leaving the page.
+    pub const GOING_AWAY: CloseCode = CloseCode(1001);
+    pub const PROTOCOL_ERROR: CloseCode = CloseCode(1002);
+    /// A kind of message the endpoint can't take, say binary for a text
+    /// chat.
+    pub const UNSUPPORTED_DATA: CloseCode = CloseCode(1003);
+    /// The peer's `Close` frame had no code. Never sent itself.
+    pub const NO_STATUS: CloseCode = CloseCode(1005);
+    /// The connection dropped without a `Close` frame. Never sent itself.
+    pub const ABNORMAL: CloseCode = CloseCode(1006);
+    /// A text message that isn't UTF-8.
+    pub const INVALID_DATA: CloseCode = CloseCode(1007);
+    pub const POLICY_VIOLATION: CloseCode = CloseCode(1008);
+    pub const MESSAGE_TOO_BIG: CloseCode = CloseCode(1009);
+    pub const INTERNAL_ERROR: CloseCode = CloseCode(1011);
+
+    /// Whether a peer may send this code in a `Close` frame: the ones RFC
+    /// 6455 and its registry define for the wire, plus the ranges left to
+    /// libraries (3000-3999) and applications (4000-4999).
+    pub fn is_sendable(self) -> bool {
+        matches!(self.0, 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
+impl fmt::Display for CloseCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// One frame, unmasked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The last frame of its message.
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// A single-frame message or control frame.
+    pub fn new(opcode: Opcode, payload: impl Into<Vec<u8>>) -> Frame {
+        Frame {
+            fin: true,
+            opcode,
+            payload: payload.into(),
+        }
+    }
+
+    /// A `Close` frame with `code` and a short `reason`.
+    pub fn close(code: CloseCode, reason: &str) -> Frame {
+        let mut payload = code.0.to_be_bytes().to_vec();
+        // Control frames hold at most 125 bytes; two are the code.
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        Frame::new(Opcode::Close, payload)
+    }
+
+    /// Parse the frame at the start of `buf`, returning it with the number
+    /// of bytes it took, or `None` if it hasn't fully arrived. A server
+    /// passes `masked: true`, since clients must mask; a client, `false`.
+    /// A payload over `max_payload` bytes is refused before it arrives.
+    /// An error is the code to close the connection with.
+    pub fn parse(
+        buf: &[u8],
+        masked: bool,
+        max_payload: usize,
+    ) -> Result<Option<(Frame, usize)>, CloseCode> {
+        let [first, second, ..] = *buf else {
+            return Ok(None);
+        };
+        let fin = first & 0x80 != 0;
+        // No extensions are negotiated, so the reserved bits must be 0.
+        if first & 0x70 != 0 {
+            return Err(CloseCode::PROTOCOL_ERROR);
+        }
+        let opcode = Opcode::from_bits(first & 0x0F).ok_or(CloseCode::PROTOCOL_ERROR)?;
+        if (second & 0x80 != 0) != masked {
+            return Err(CloseCode::PROTOCOL_ERROR);
+        }
+
+        let mut at = 2;
+        let len = match second & 0x7F {
+            126 => {
+                let Some(bytes) = buf.get(at..at + 2) else {
+                    return Ok(None);
+                };
+                at += 2;
+                u16::from_be_bytes([bytes[0], bytes[1]]) as u64
+            }
+            127 => {
+                let Some(bytes) = buf.get(at..at + 8) else {
+                    return Ok(None);
+                };
+                at += 8;
+                u64::from_be_bytes(bytes.try_into().unwrap())
+            }
+            len => len as u64,
+        };
+        if opcode.is_control() && (len > 125 || !fin) {
+            return Err(CloseCode::PROTOCOL_ERROR);
+        }
+        if len > max_payload as u64 {
+            return Err(CloseCode::MESSAGE_TOO_BIG);
+        }
+        let len = len as usize;
+
+        let mut key = None;
+        if masked {
+            let Some(bytes) = buf.get(at..at + 4) else {
+                return Ok(None);
+            };
+            key = Some([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            at += 4;
+        }
+        let Some(payload) = buf.get(at..at + len) else {
+            return Ok(None);
+        };
+        let mut payload = payload.to_vec();
+        if let Some(key) = key {
+            apply_mask(&mut payload, key);
+        }
+        Ok(Some((
+            Frame {
+                fin,
+                opcode,
+                payload,
+            },
+            at + len,
+        )))
+    }
+
+    /// Write the frame to `out`, masked with `mask` if given (a client
+    /// must; a server must not).
+    pub fn write_to(&self, out: &mut impl Write, mask: Option<[u8; 4]>) -> io::Result<()> {
+        let mut head = Vec::with_capacity(14);
+        head.push(u8::from(self.fin) << 7 | self.opcode.bits());
+        let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+        match self.payload.len() {
+            len @ 0..=125 => head.push(mask_bit | len as u8),
+            len @ 126..=0xFFFF => {
+                head.push(mask_bit | 126);
+                head.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                head.push(mask_bit | 127);
+                head.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        match mask {
+            Some(key) => {
+                head.extend_from_slice(&key);
+                let mut payload = self.payload.clone();
+                apply_mask(&mut payload, key);
+                head.extend_from_slice(&payload);
+                out.write_all(&head)
+            }
+            None => {
+                out.write_all(&head)?;
+                out.write_all(&self.payload)
+            }
+        }
+    }
+
+    /// The code and reason of a `Close` frame's payload. An empty payload
+    /// is `NO_STATUS`; a malformed one is an error.
+    pub fn close_reason(&self) -> Result<(CloseCode, &str), CloseCode> {
+        match self.payload.as_slice() {
+            [] => Ok((CloseCode::NO_STATUS, "")),
+            [high, low, reason @ ..] => {
+                let code = CloseCode(u16::from_be_bytes([*high, *low]));
+                if !code.is_sendable() {
+                    return Err(CloseCode::PROTOCOL_ERROR);
+                }
+                let reason = std::str::from_utf8(reason).map_err(|_| CloseCode::INVALID_DATA)?;
+                Ok((code, reason))
+            }
+            [_] => Err(CloseCode::PROTOCOL_ERROR),
+        }
+    }
+}
+
+/// Mask or unmask `payload` in place: masking twice is the identity.
+fn apply_mask(payload: &mut [u8], key: [u8; 4]) {
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= key[i % 4];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(frame: &Frame, mask: Option<[u8; 4]>) -> Vec<u8> {
+        let mut out = Vec::new();
+        frame.write_to(&mut out, mask).unwrap();
+        out
+    }
+
+    #[test]
+    fn round_trips_every_length_form_masked_and_not() {
+        for len in [0, 5, 125, 126, 0xFFFF, 0x10000] {
+            let frame = Frame::new(Opcode::Binary, vec![7u8; len]);
+            for mask in [None, Some([1, 2, 3, 4])] {
+                let bytes = encode(&frame, mask);
+                let parsed = Frame::parse(&bytes, mask.is_some(), 1 << 20).unwrap();
+                assert_eq!(parsed, Some((frame.clone(), bytes.len())));
+                // A frame cut short is merely incomplete.
+                for cut in [0, 1, bytes.len() / 2, bytes.len() - 1] {
+                    let partial = Frame::parse(&bytes[..cut], mask.is_some(), 1 << 20);
+                    assert_eq!(partial, Ok(None), "len {len} cut {cut}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn matches_the_rfc_examples() {
+        // Section 5.7: a single-frame unmasked and masked "Hello".
+        let hello = Frame::new(Opcode::Text, "Hello");
+        assert_eq!(encode(&hello, None), b"\x81\x05\x48\x65\x6c\x6c\x6f");
+        let masked = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+        assert_eq!(encode(&hello, Some([0x37, 0xfa, 0x21, 0x3d])), masked);
+        assert_eq!(Frame::parse(masked, true, 125), Ok(Some((hello, 11))));
+
+        // A fragmented "Hel" + "lo".
+        let (first, used) = Frame::parse(b"\x01\x03\x48\x65\x6c\x80\x02\x6c\x6f", false, 125)
+            .unwrap()
+            .unwrap();
+        assert_eq!((first.fin, first.opcode, used), (false, Opcode::Text, 5));
+    }
+
+    #[test]
+    fn rejects_what_the_protocol_forbids() {
+        let parse = |bytes: &[u8]| Frame::parse(bytes, true, 1000);
+        // Unmasked from a client, reserved bit, unknown opcode.
+        assert_eq!(parse(b"\x81\x00"), Err(CloseCode::PROTOCOL_ERROR));
+        assert_eq!(parse(b"\xC1\x80\0\0\0\0"), Err(CloseCode::PROTOCOL_ERROR));
+        assert_eq!(parse(b"\x83\x80\0\0\0\0"), Err(CloseCode::PROTOCOL_ERROR));
+        // A fragmented or oversized control frame.
+        assert_eq!(parse(b"\x09\x80\0\0\0\0"), Err(CloseCode::PROTOCOL_ERROR));
+        assert_eq!(parse(b"\x89\xFE\x00\x7E"), Err(CloseCode::PROTOCOL_ERROR));
+        // Too big, known from the length alone.
+        assert_eq!(
+            parse(b"\x82\xFF\0\0\0\0\0\0\x10\0"),
+            Err(CloseCode::MESSAGE_TOO_BIG)
+        );
+    }
+
+    #[test]
+    fn close_frames_carry_a_code_and_a_reason() {
+        let frame = Frame::close(CloseCode::GOING_AWAY, "bye");
+        assert_eq!(frame.close_reason(), Ok((CloseCode::GOING_AWAY, "bye")));
+        assert_eq!(
+            Frame::new(Opcode::Close, "").close_reason(),
+            Ok((CloseCode::NO_STATUS, ""))
+        );
+        for bad in [&b"\x03"[..], b"\x03\xED", b"\x00\x00", b"\x0B\xB8\xFF"] {
+            assert!(
+                Frame::new(Opcode::Close, bad).close_reason().is_err(),
+                "{bad:?}"
+            );
+        }
+        // A long reason is cut to fit, on a character boundary.
+        let long = Frame::close(CloseCode::NORMAL, &"é".repeat(100));
+        assert_eq!(long.payload.len(), 124);
+        assert!(long.close_reason().is_ok());
+    }
+}
@@ -0,0 +1,183 @@
+//! The opening handshake (RFC 6455 section 4): an HTTP request asking to
+//! switch protocols, and the `101` that agrees to.
+//!
+//! The client sends a random `Sec-WebSocket-Key`; the server proves it
+//! understood the request by answering with the base64 SHA-1 of that key
+//! joined to a fixed GUID. It's not security, just a way to make sure
+//! neither side is an HTTP server or cache that happened to pass the
+//! headers along. SHA-1 and base64 are small enough to write out here
+//! rather than pull in crates for.
+//!
+//! Concepts: `u32::rotate_left` and wrapping arithmetic, `chunks_exact`,
+//! lookup tables as byte strings.
+
+use crate::http::{Method, Request, Response, StatusCode, Version};
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Check that `request` is a valid upgrade to WebSocket, and return the
+/// `101` to answer it with, or the error response if it isn't.
+pub fn accept(request: &Request) -> Result<Response, Response> {
+    let bad = |why: &str| Err(Response::text(StatusCode::BAD_REQUEST, format!("{why}\n")));
+    if request.method != Method::Get || request.version != Version::Http11 {
+        return bad("a WebSocket upgrade must be an HTTP/1.1 GET");
+    }
+    if !request.headers.has_token("Upgrade", "websocket")
+        || !request.headers.has_token("Connection", "upgrade")
+    {
+        return bad("missing Upgrade: websocket or Connection: Upgrade");
+    }
+    if request.headers.get("Sec-WebSocket-Version") != Some("13") {
+        return Err(Response::error(StatusCode::UPGRADE_REQUIRED)
+            .with_header("Sec-WebSocket-Version", "13"));
+    }
+    // The key is 16 random bytes in base64: 22 characters and "==".
+    let key = request.headers.get("Sec-WebSocket-Key").unwrap_or("");
+    let well_formed =
+        key.len() == 24 && key.ends_with("==") && key[..22].bytes().all(|b| BASE64.contains(&b));
+    if !well_formed {
+        return bad("bad Sec-WebSocket-Key");
+    }
+    Ok(Response::new(StatusCode::SWITCHING_PROTOCOLS)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", accept_key(key)))
+}
+
+/// The `Sec-WebSocket-Accept` value for `key`.
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with `=` padding.
+pub fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// SHA-1 (FIPS 180-4). Broken for signatures, but it's what the handshake
+/// specifies.
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    // Pad to a multiple of 64 bytes: a 1 bit, zeros, and the length in
+    // bits as a big-endian u64.
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (out, word) in digest.chunks_exact_mut(4).zip(h) {
+        out.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn sha1_and_base64_match_known_answers() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // Two blocks once padded.
+        assert_eq!(
+            hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        for (input, encoded) in [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v")] {
+            assert_eq!(base64(input.as_bytes()), encoded);
+        }
+        // The example from RFC 6455 section 1.3.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn accepts_only_well_formed_upgrades() {
+        let upgrade = |version: &str, key: &str| {
+            let mut request = Request::new(Method::Get, "/ws");
+            request.headers.append("Upgrade", "websocket");
+            request.headers.append("Connection", "keep-alive, Upgrade");
+            request.headers.append("Sec-WebSocket-Version", version);
+            request.headers.append("Sec-WebSocket-Key", key);
+            request
+        };
+        let response = accept(&upgrade("13", "dGhlIHNhbXBsZSBub25jZQ==")).unwrap();
+        assert_eq!(response.status, StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(
+            response.headers.get("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+
+        let refused = accept(&upgrade("8", "dGhlIHNhbXBsZSBub25jZQ==")).unwrap_err();
+        assert_eq!(refused.status, StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(refused.headers.get("Sec-WebSocket-Version"), Some("13"));
+        let bad = accept(&upgrade("13", "short==")).unwrap_err();
+        assert_eq!(bad.status, StatusCode::BAD_REQUEST);
+        assert!(accept(&Request::new(Method::Get, "/ws")).is_err());
+    }
+}
@@ -0,0 +1,676 @@
+//! WebSocket (RFC 6455): a connection that starts as an HTTP request and
+//! then carries messages both ways for as long as either side likes.
+//!
+//! A route added with `Router::websocket` names a function that makes a
+//! `Handler` for each connection. When a request for that path asks to
+//! upgrade, `handshake::accept` checks it and the server answers `101`;
+//! from then on the connection belongs to the handler, on a thread of its
+//! own, until it closes. A session can last for hours, so it doesn't keep
+//! the worker that read the upgrade: a pool's worth of chat clients would
+//! otherwise leave no worker for plain requests. `Sessions` counts these
+//! threads, so the server can cap them (`max_websockets`, past which an
+//! upgrade gets `503`) and wait for them when it stops. A plain `GET` for
+//! the path gets `426 Upgrade Required`, and so does an upgrade sent to
+//! the epoll event loop, which doesn't take over connections this way.
+//!
+//! A handler reacts to messages from its client, but it can also be told
+//! things by other connections: a chat relays one member's message to all
+//! the others. So a handler sends through an `Outbox`, which can be cloned
+//! and used from any thread. The session loop reads the socket with a
+//! short timeout and, between reads, sends whatever is waiting in the
+//! outbox. Polling costs a wake-up every `POLL` per connection, but it
+//! works the same over TLS, where a connection can't be split into a
+//! reading half and a writing half the way a `TcpStream` can.
+//!
+//! Pings are answered with pongs, fragmented messages are put back
+//! together before the handler sees them, and anything the protocol
+//! forbids closes the connection with the matching `CloseCode`.
+//!
+//! Concepts: trait objects for per-connection state, `mpsc` channels as
+//! mailboxes, `Option` as a small state machine, `Condvar` to wait for a
+//! count to reach zero.
+
+pub mod chat;
+pub mod frame;
+pub mod handshake;
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::Transport;
+use crate::http::{Request, Response};
+use crate::router::Router;
+use crate::server::Shutdown;
+pub use frame::{CloseCode, Frame, Opcode};
+
+/// How often a session checks its outbox and the shutdown flag while
+/// waiting for its client.
+const POLL: Duration = Duration::from_millis(20);
+
+/// How long to wait for the client's `Close` after sending ours.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A complete message, however many frames it came in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// What a WebSocket route runs for each connection, on the connection's
+/// own thread.
+pub trait Handler: Send {
+    /// The connection is open. `out` reaches the client from now on; keep
+    /// a clone to send to it later, from anywhere.
+    fn on_open(&mut self, out: &Outbox) {
+        let _ = out;
+    }
+
+    fn on_message(&mut self, message: Message, out: &Outbox);
+
+    /// The connection has closed, with `code` from whichever side closed
+    /// it (`ABNORMAL` if it just dropped).
+    fn on_close(&mut self, code: CloseCode) {
+        let _ = code;
+    }
+}
+
+/// Makes the handler for a connection, from the upgrade request.
+pub type NewHandler = Box<dyn Fn(&Request) -> Box<dyn Handler> + Send + Sync>;
+
+#[derive(Debug)]
+enum Command {
+    Send(Message),
+    Close(CloseCode, String),
+}
+
+/// Sends to one connection's client. Cheap to clone, and usable from any
+/// thread.
+#[derive(Debug, Clone)]
+pub struct Outbox {
+    sender: Sender<Command>,
+}
+
+impl Outbox {
+    /// Queue `message` for the client. `false` if the connection has
+    /// closed, so a broadcaster knows to forget it.
+    pub fn send(&self, message: Message) -> bool {
+        self.sender.send(Command::Send(message)).is_ok()
+    }
+
+    /// Close the connection with `code` once what's queued has been sent.
+    pub fn close(&self, code: CloseCode, reason: impl Into<String>) -> bool {
+        self.sender
+            .send(Command::Close(code, reason.into()))
+            .is_ok()
+    }
+}
+
+/// The outcome of an upgrade request for a WebSocket route.
+pub(crate) enum Upgrade {
+    /// Send the `101`, then hand the connection to the handler.
+    Accept(Response, Box<dyn Handler>),
+    /// Send the error response and close.
+    Reject(Response),
+}
+
+/// If `request` asks to upgrade to WebSocket on one of `router`'s
+/// WebSocket routes, the handshake's outcome. `None` leaves the request
+/// to the ordinary routes.
+pub(crate) fn upgrade(router: &Router, request: &mut Request) -> Option<Upgrade> {
+    if !request.headers.has_token("Upgrade", "websocket") {
+        return None;
+    }
+    let new_handler = router.websocket_route(request)?;
+    Some(match handshake::accept(request) {
+        Ok(response) => Upgrade::Accept(response, new_handler(request)),
+        Err(response) => Upgrade::Reject(response),
+    })
+}
+
+/// Run `handler` on an upgraded connection until it closes, and return
+/// the close code. Messages over `max_message` bytes close it with
+/// `MESSAGE_TOO_BIG`.
+pub(crate) fn serve(
+    mut reader: BufReader<impl Transport>,
+    handler: Box<dyn Handler>,
+    max_message: usize,
+    shutdown: &Shutdown,
+) -> io::Result<CloseCode> {
+    // Frames the client sent right after the handshake may already be in
+    // the buffer.
+    let input = reader.buffer().to_vec();
+    reader.consume(input.len());
+    let io = reader.into_inner();
+    io.socket().set_read_timeout(Some(POLL))?;
+    let (sender, commands) = mpsc::channel();
+    let mut session = Session {
+        io,
+        handler,
+        outbox: Outbox { sender },
+        commands,
+        input,
+        message: None,
+        max_message,
+        closed_at: None,
+    };
+    session.handler.on_open(&session.outbox);
+    let code = session.run(shutdown);
+    session
+        .handler
+        .on_close(*code.as_ref().unwrap_or(&CloseCode::ABNORMAL));
+    // The client may be gone already; the close code says how it went.
+    let _ = session.io.finish();
+    code
+}
+
+/// The WebSocket sessions running, each on its own thread.
+#[derive(Debug, Default)]
+pub(crate) struct Sessions {
+    live: Mutex<usize>,
+    ended: Condvar,
+}
+
+impl Sessions {
+    /// Count a new session, unless `max` are running already. The session
+    /// counts until the returned guard is dropped.
+    pub fn enter(self: &Arc<Self>, max: usize) -> Option<SessionGuard> {
+        let mut live = self.live.lock().unwrap();
+        if *live >= max {
+            return None;
+        }
+        *live += 1;
+        Some(SessionGuard(Arc::clone(self)))
+    }
+
+    /// Block until every session has ended.
+    pub fn wait(&self) {
+        let live = self.live.lock().unwrap();
+        drop(self.ended.wait_while(live, |live| *live > 0).unwrap());
+    }
+}
+
+pub(crate) struct SessionGuard(Arc<Sessions>);
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        *self.0.live.lock().unwrap() -= 1;
+        self.0.ended.notify_all();
+    }
+}
+
+struct Session<S> {
+    io: S,
+    handler: Box<dyn Handler>,
+    outbox: Outbox,
+    commands: Receiver<Command>,
+    /// Bytes received but not yet parsed into frames.
+    input: Vec<u8>,
+    /// The opcode and payload so far of a message arriving in fragments.
+    message: Option<(Opcode, Vec<u8>)>,
+    max_message: usize,
+    /// When we sent our `Close`, and with what code.
+    closed_at: Option<(Instant, CloseCode)>,
+}
+
+impl<S: Read + Write> Session<S> {
+    fn run(&mut self, shutdown: &Shutdown) -> io::Result<CloseCode> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            while self.closed_at.is_none() {
+                match self.commands.try_recv() {
+                    Ok(Command::Send(message)) => self.send_message(message)?,
+                    Ok(Command::Close(code, reason)) => self.close(code, &reason)?,
+                    Err(_) => break,
+                }
+            }
+            if self.closed_at.is_none() && shutdown.is_requested() {
+                self.close(CloseCode::GOING_AWAY, "server shutting down")?;
+            }
+            if let Some((at, code)) = self.closed_at
+                && at.elapsed() > CLOSE_TIMEOUT
+            {
+                return Ok(code);
+            }
+
+            loop {
+                match Frame::parse(&self.input, true, self.max_message) {
+                    Ok(Some((frame, used))) => {
+                        self.input.drain(..used);
+                        if let Some(code) = self.on_frame(frame)? {
+                            return Ok(code);
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(code) => return self.fail(code),
+                }
+            }
+
+            match self.io.read(&mut chunk) {
+                Ok(0) => return Ok(CloseCode::ABNORMAL),
+                Ok(n) => self.input.extend_from_slice(&chunk[..n]),
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                            | io::ErrorKind::Interrupted
+                    ) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(CloseCode::ABNORMAL);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Act on one frame from the client; `Some` once the connection is
+    /// done, with its close code.
+    fn on_frame(&mut self, frame: Frame) -> io::Result<Option<CloseCode>> {
+        match frame.opcode {
+            Opcode::Ping => {
+                if self.closed_at.is_none() {
+                    self.write(&Frame::new(Opcode::Pong, frame.payload))?;
+                }
+                Ok(None)
+            }
+            Opcode::Pong => Ok(None),
+            Opcode::Close => {
+                let (code, reason) = match frame.close_reason() {
+                    Ok(close) => close,
+                    Err(code) => return self.fail(code).map(Some),
+                };
+                if let Some((_, ours)) = self.closed_at {
+                    // The answer to our `Close`.
+                    return Ok(Some(ours));
+                }
+                // Echo it, as the protocol asks.
+                let echo = if code == CloseCode::NO_STATUS {
+                    Frame::new(Opcode::Close, Vec::new())
+                } else {
+                    Frame::close(code, reason)
+                };
+                self.write(&echo)?;
+                Ok(Some(code))
+            }
+            Opcode::Text | Opcode::Binary if self.message.is_some() => {
+                self.fail(CloseCode::PROTOCOL_ERROR).map(Some)
+            }
+            Opcode::Continuation if self.message.is_none() => {
+                self.fail(CloseCode::PROTOCOL_ERROR).map(Some)
+            }
+            Opcode::Text | Opcode::Binary | Opcode::Continuation => {
+                let (opcode, mut payload) =
+                    self.message.take().unwrap_or((frame.opcode, Vec::new()));
+                payload.extend_from_slice(&frame.payload);
+                if payload.len() > self.max_message {
+                    return self.fail(CloseCode::MESSAGE_TOO_BIG).map(Some);
+                }
+                if !frame.fin {
+                    self.message = Some((opcode, payload));
+                    return Ok(None);
+                }
+                let message = match opcode {
+                    Opcode::Text => match String::from_utf8(payload) {
+                        Ok(text) => Message::Text(text),
+                        Err(_) => return self.fail(CloseCode::INVALID_DATA).map(Some),
+                    },
+                    _ => Message::Binary(payload),
+                };
+                // After our `Close`, the client's messages are discarded.
+                if self.closed_at.is_none() {
+                    self.handler.on_message(message, &self.outbox);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn send_message(&mut self, message: Message) -> io::Result<()> {
+        let frame = match message {
+            Message::Text(text) => Frame::new(Opcode::Text, text),
+            Message::Binary(bytes) => Frame::new(Opcode::Binary, bytes),
+        };
+        self.write(&frame)
+    }
+
+    /// Start the closing handshake: send our `Close` and wait for theirs.
+    fn close(&mut self, code: CloseCode, reason: &str) -> io::Result<()> {
+        self.write(&Frame::close(code, reason))?;
+        self.closed_at = Some((Instant::now(), code));
+        Ok(())
+    }
+
+    /// The client broke the protocol: say why and hang up without waiting
+    /// for an answer.
+    fn fail(&mut self, code: CloseCode) -> io::Result<CloseCode> {
+        if self.closed_at.is_none() {
+            self.write(&Frame::close(code, ""))?;
+        }
+        Ok(code)
+    }
+
+    fn write(&mut self, frame: &Frame) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(frame.payload.len() + 10);
+        frame.write_to(&mut bytes, None)?;
+        self.io.write_all(&bytes)?;
+        self.io.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::StatusCode;
+    use crate::middleware::AccessLog;
+    use crate::server::Server;
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Echoes every message back, and records how the connection ended.
+    struct Echo(Arc<Mutex<Vec<CloseCode>>>);
+
+    impl Handler for Echo {
+        fn on_message(&mut self, message: Message, out: &Outbox) {
+            if message == Message::Text("bye".into()) {
+                out.close(CloseCode::NORMAL, "as you wish");
+            } else {
+                out.send(message);
+            }
+        }
+
+        fn on_close(&mut self, code: CloseCode) {
+            self.0.lock().unwrap().push(code);
+        }
+    }
+
+    struct Client {
+        stream: TcpStream,
+        input: Vec<u8>,
+    }
+
+    impl Client {
+        fn connect(addr: SocketAddr) -> Client {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            stream
+                .write_all(
+                    b"GET /echo HTTP/1.1\r\nHost: t\r\nUpgrade: websocket\r\n\
+                      Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+                      Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+                )
+                .unwrap();
+            let mut client = Client {
+                stream,
+                input: Vec::new(),
+            };
+            while !client.input.windows(4).any(|w| w == b"\r\n\r\n") {
+                client.fill();
+            }
+            let end = client
+                .input
+                .windows(4)
+                .position(|w| w == b"\r\n\r\n")
+                .unwrap()
+                + 4;
+            let head = String::from_utf8(client.input.drain(..end).collect()).unwrap();
+            assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+            assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+            client
+        }
+
+        fn fill(&mut self) -> usize {
+            let mut chunk = [0u8; 4096];
+            let n = self.stream.read(&mut chunk).unwrap();
+            self.input.extend_from_slice(&chunk[..n]);
+            n
+        }
+
+        fn send(&mut self, frame: &Frame) {
+            frame
+                .write_to(&mut self.stream, Some([9, 8, 7, 6]))
+                .unwrap();
+        }
+
+        fn send_raw(&mut self, bytes: &[u8]) {
+            self.stream.write_all(bytes).unwrap();
+        }
+
+        /// The next frame from the server, or `None` once it has hung up.
+        fn recv(&mut self) -> Option<Frame> {
+            loop {
+                if let Some((frame, used)) = Frame::parse(&self.input, false, 1 << 20).unwrap() {
+                    self.input.drain(..used);
+                    return Some(frame);
+                }
+                if self.fill() == 0 {
+                    return None;
+                }
+            }
+        }
+    }
+
+    fn start() -> (
+        SocketAddr,
+        Shutdown,
+        Arc<Mutex<Vec<CloseCode>>>,
+        thread::JoinHandle<()>,
+    ) {
+        start_with(|server| server)
+    }
+
+    fn start_with(
+        configure: impl FnOnce(Server) -> Server,
+    ) -> (
+        SocketAddr,
+        Shutdown,
+        Arc<Mutex<Vec<CloseCode>>>,
+        thread::JoinHandle<()>,
+    ) {
+        let closed = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&closed);
+        let router = Router::new()
+            .websocket("/echo", move |_| Echo(Arc::clone(&recorded)))
+            .get("/plain", |_| Response::text(StatusCode::OK, "plain"));
+        let server = configure(Server::bind("127.0.0.1:0", router).unwrap().workers(2));
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let server = thread::spawn(move || server.run().unwrap());
+        (addr, shutdown, closed, server)
+    }
+
+    #[test]
+    fn echoes_messages_reassembles_fragments_and_answers_pings() {
+        let (addr, shutdown, closed, server) = start();
+        let mut client = Client::connect(addr);
+
+        client.send(&Frame::new(Opcode::Text, "hello"));
+        assert_eq!(client.recv(), Some(Frame::new(Opcode::Text, "hello")));
+
+        // A binary message in three pieces, with a ping in the middle.
+        let piece = |opcode, fin, payload: &[u8]| Frame {
+            fin,
+            opcode,
+            payload: payload.to_vec(),
+        };
+        client.send(&piece(Opcode::Binary, false, b"ab"));
+        client.send(&piece(Opcode::Continuation, false, b"cd"));
+        client.send(&Frame::new(Opcode::Ping, "are you there"));
+        client.send(&piece(Opcode::Continuation, true, b"ef"));
+        assert_eq!(
+            client.recv(),
+            Some(Frame::new(Opcode::Pong, "are you there"))
+        );
+        assert_eq!(client.recv(), Some(Frame::new(Opcode::Binary, "abcdef")));
+
+        // A big message, sent one byte at a time.
+        let big = Frame::new(Opcode::Text, "x".repeat(70_000));
+        let mut bytes = Vec::new();
+        big.write_to(&mut bytes, Some([1, 2, 3, 4])).unwrap();
+        for byte in &bytes[..20] {
+            client.send_raw(&[*byte]);
+        }
+        client.send_raw(&bytes[20..]);
+        assert_eq!(client.recv(), Some(big));
+
+        // The client closes; the server echoes the code and hangs up.
+        client.send(&Frame::close(CloseCode::NORMAL, "done"));
+        assert_eq!(client.recv(), Some(Frame::close(CloseCode::NORMAL, "done")));
+        assert_eq!(client.recv(), None);
+
+        shutdown.request();
+        server.join().unwrap();
+        assert_eq!(*closed.lock().unwrap(), [CloseCode::NORMAL]);
+    }
+
+    #[test]
+    fn protocol_violations_close_with_the_matching_code() {
+        let (addr, shutdown, closed, server) = start();
+        let masked = |frame: Frame| {
+            let mut bytes = Vec::new();
+            frame.write_to(&mut bytes, Some([1, 2, 3, 4])).unwrap();
+            bytes
+        };
+        let cases = [
+            // Unmasked.
+            (b"\x81\x02hi".to_vec(), CloseCode::PROTOCOL_ERROR),
+            // Text that isn't UTF-8.
+            (
+                masked(Frame::new(Opcode::Text, vec![0xFF])),
+                CloseCode::INVALID_DATA,
+            ),
+            // A continuation with nothing to continue.
+            (
+                masked(Frame::new(Opcode::Continuation, "x")),
+                CloseCode::PROTOCOL_ERROR,
+            ),
+            // Over the message limit, which is the request body limit,
+            // is refused from the length alone.
+            (
+                b"\x82\xFF\0\0\0\0\0\x10\0\x01\0\0\0\0".to_vec(),
+                CloseCode::MESSAGE_TOO_BIG,
+            ),
+        ];
+        for (bytes, code) in cases {
+            let mut client = Client::connect(addr);
+            client.send_raw(&bytes);
+            let close = client.recv().unwrap();
+            assert_eq!(close.close_reason().unwrap().0, code);
+            assert_eq!(client.recv(), None);
+        }
+        shutdown.request();
+        server.join().unwrap();
+        assert_eq!(closed.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn handlers_and_shutdown_can_close_too() {
+        let (addr, shutdown, closed, server) = start();
+
+        let mut client = Client::connect(addr);
+        client.send(&Frame::new(Opcode::Text, "bye"));
+        assert_eq!(
+            client.recv(),
+            Some(Frame::close(CloseCode::NORMAL, "as you wish"))
+        );
+        client.send(&Frame::close(CloseCode::NORMAL, ""));
+        assert_eq!(client.recv(), None);
+
+        let mut client = Client::connect(addr);
+        shutdown.request();
+        let close = client.recv().unwrap();
+        assert_eq!(close.close_reason().unwrap().0, CloseCode::GOING_AWAY);
+        client.send(&Frame::close(CloseCode::GOING_AWAY, ""));
+        assert_eq!(client.recv(), None);
+        server.join().unwrap();
+        assert_eq!(
+            *closed.lock().unwrap(),
+            [CloseCode::NORMAL, CloseCode::GOING_AWAY]
+        );
+
+        // Without an upgrade, the route says what it wants.
+        let router = Router::new().websocket("/echo", |_| Echo(Arc::default()));
+        let plain = router.handle(Request::new(crate::http::Method::Get, "/echo"));
+        assert_eq!(plain.status, StatusCode::UPGRADE_REQUIRED);
+    }
+
+    #[test]
+    fn the_handshake_goes_through_the_middleware() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&lines);
+        let router = Router::new()
+            .websocket("/echo", |_| Echo(Arc::default()))
+            .wrap(AccessLog::to(move |line| {
+                sink.lock().unwrap().push(line.to_string());
+            }));
+        let server = Server::bind("127.0.0.1:0", router).unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let server = thread::spawn(move || server.run().unwrap());
+
+        let mut client = Client::connect(addr);
+        client.send(&Frame::close(CloseCode::NORMAL, ""));
+        assert_eq!(client.recv(), Some(Frame::close(CloseCode::NORMAL, "")));
+        shutdown.request();
+        server.join().unwrap();
+
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        assert!(
+            lines[0].contains(" method=GET path=/echo status=101 "),
+            "{}",
+            lines[0]
+        );
+    }
+
+    #[test]
+    fn open_sessions_leave_the_workers_free_up_to_a_cap() {
+        let (addr, shutdown, closed, server) = start_with(|server| server.max_websockets(3));
+
+        // As many sessions as workers, and then some: none holds a worker.
+        let mut clients: Vec<Client> = (0..3).map(|_| Client::connect(addr)).collect();
+        let mut plain = TcpStream::connect(addr).unwrap();
+        plain
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        plain
+            .write_all(b"GET /plain HTTP/1.1\r\nHost: t\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut answer = String::new();
+        plain.read_to_string(&mut answer).unwrap();
+        assert!(answer.ends_with("\r\n\r\nplain"), "{answer}");
+
+        // The cap is reached, so the next upgrade is turned away...
+        let mut refused = TcpStream::connect(addr).unwrap();
+        refused
+            .write_all(
+                b"GET /echo HTTP/1.1\r\nHost: t\r\nUpgrade: websocket\r\n\
+                  Connection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+            )
+            .unwrap();
+        let mut answer = String::new();
+        refused.read_to_string(&mut answer).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 503 "), "{answer}");
+
+        // ...until one of the sessions ends.
+        let mut leaving = clients.pop().unwrap();
+        leaving.send(&Frame::close(CloseCode::NORMAL, ""));
+        assert_eq!(leaving.recv(), Some(Frame::close(CloseCode::NORMAL, "")));
+        assert_eq!(leaving.recv(), None);
+        clients.push(Client::connect(addr));
+        for client in &mut clients {
+            client.send(&Frame::new(Opcode::Text, "still here"));
+            assert_eq!(client.recv(), Some(Frame::new(Opcode::Text, "still here")));
+        }
+
+        shutdown.request();
+        server.join().unwrap();
+        assert_eq!(closed.lock().unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,74 @@
+//! What the integration tests share: a server running on its own thread
+//! and a scratch directory.
+//! Each test file is its own crate and uses only some of this.
+#![allow(dead_code)]
+
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+
+use custom_server::server::{Server, Shutdown};
+
+/// A server started on ephemeral ports, stopped by `stop`.
+pub struct Running {
+    pub addr: SocketAddr,
+    /// The HTTPS listener's address, if the server has one.
+    pub https: Option<SocketAddr>,
+    shutdown: Shutdown,
+    server: thread::JoinHandle<io::Result<()>>,
+}
+
+impl Running {
+    pub fn start(server: Server) -> Running {
+        Running {
+            addr: server.local_addr().unwrap(),
+            https: server.https_addr().transpose().unwrap(),
+            shutdown: server.shutdown_handle(),
+            server: thread::spawn(move || server.run()),
+        }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+
+    /// Shut the server down and check its listeners are gone.
+    pub fn stop(self) {
+        self.shutdown.request();
+        self.server.join().unwrap().unwrap();
+        assert!(TcpStream::connect(self.addr).is_err());
+        if let Some(https) = self.https {
+            assert!(TcpStream::connect(https).is_err());
+        }
+    }
+}
+
+/// A scratch directory under the system temp dir, removed on drop. `name`
+/// keeps tests that run at the same time out of each other's way.
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let root =
+            std::env::temp_dir().join(format!("custom_server-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        TempDir(root)
+    }
+
+    /// Write `contents` to `relative`, creating directories on the way.
+    pub fn file(&self, relative: &str, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(relative);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
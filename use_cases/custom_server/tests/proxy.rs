@@ -0,0 +1,228 @@
+//! The reverse proxy in front of real upstreams: each test starts a few
+//! servers on ephemeral ports and a proxy server over them.
+
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use custom_server::client::Client;
+use custom_server::http::{Request, Response, StatusCode};
+use custom_server::proxy::{Balance, HealthCheck, Proxy, ProxyConfig};
+use custom_server::router::Router;
+use custom_server::server::Server;
+
+mod common;
+
+use common::Running;
+
+/// `router` behind a server of its own.
+fn serve(router: Router) -> Running {
+    Running::start(Server::bind("127.0.0.1:0", router).unwrap().workers(4))
+}
+
+/// An upstream that answers `/who` with `name` and what it was told
+/// about the client, `/sleep/:ms` with `name` after a pause, and
+/// `/health` with `200` while `healthy` is set.
+fn upstream(name: &'static str, healthy: Arc<AtomicBool>) -> Running {
+    serve(
+        Router::new()
+            .get("/who", move |request: &Request| {
+                let header = |h| request.headers.get(h).unwrap_or("-");
+                Response::text(
+                    StatusCode::OK,
+                    format!(
+                        "{name} host={} forwarded-host={} forwarded-for={} hop={}",
+                        header("Host"),
+                        header("X-Forwarded-Host"),
+                        header("X-Forwarded-For"),
+                        header("X-Hop"),
+                    ),
+                )
+                .with_header("Connection", "close")
+            })
+            .get("/sleep/:ms", move |request: &Request| {
+                let ms = request.param("ms").unwrap().parse().unwrap();
+                thread::sleep(Duration::from_millis(ms));
+                Response::text(StatusCode::OK, name)
+            })
+            .get("/health", move |_| {
+                if healthy.load(Ordering::SeqCst) {
+                    Response::text(StatusCode::OK, "ok")
+                } else {
+                    Response::error(StatusCode::SERVICE_UNAVAILABLE)
+                }
+            }),
+    )
+}
+
+fn proxy_to(upstreams: &[&Running], config: ProxyConfig) -> (Proxy, Running) {
+    let names: Vec<String> = upstreams.iter().map(|u| u.addr.to_string()).collect();
+    let proxy = Proxy::new(&names, config).unwrap();
+    let running = serve(Router::new().wrap(proxy.clone()));
+    (proxy, running)
+}
+
+/// `GET path` through the proxy at `addr`, with `headers`. One client
+/// for every test, so connections to each proxy are reused.
+fn get(addr: SocketAddr, path: &str, headers: &[(&str, &str)]) -> Response {
+    static CLIENT: LazyLock<Client> =
+        LazyLock::new(|| Client::new().timeout(Duration::from_secs(10)));
+    let mut request = CLIENT
+        .get(&format!("http://{addr}{path}"))
+        .header("Host", "proxy.test");
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().unwrap()
+}
+
+fn body(response: &Response) -> String {
+    String::from_utf8(response.body.clone()).unwrap()
+}
+
+fn healthy() -> Arc<AtomicBool> {
+    Arc::new(AtomicBool::new(true))
+}
+
+#[test]
+fn round_robin_alternates_and_tells_upstreams_about_the_client() {
+    let a = upstream("a", healthy());
+    let b = upstream("b", healthy());
+    let (_, proxy) = proxy_to(&[&a, &b], ProxyConfig::default());
+
+    let names: Vec<String> = (0..4)
+        .map(|_| body(&get(proxy.addr, "/sleep/0", &[]))[..1].to_string())
+        .collect();
+    assert_eq!(names, ["a", "b", "a", "b"]);
+
+    let response = get(
+        proxy.addr,
+        "/who",
+        &[
+            ("X-Forwarded-For", "203.0.113.7"),
+            ("Connection", "X-Hop"),
+            ("X-Hop", "1"),
+        ],
+    );
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        body(&response),
+        format!(
+            "a host={} forwarded-host=proxy.test forwarded-for=203.0.113.7, 127.0.0.1 hop=-",
+            a.addr
+        )
+    );
+    // The upstream's `Connection: close` was about its own connection.
+    assert!(!response.headers.contains("Connection"));
+
+    proxy.stop();
+    a.stop();
+    b.stop();
+}
+
+#[test]
+fn least_connections_steers_around_a_busy_upstream() {
+    let a = upstream("a", healthy());
+    let b = upstream("b", healthy());
+    let config = ProxyConfig {
+        balance: Balance::LeastConnections,
+        ..ProxyConfig::default()
+    };
+    let (_, proxy) = proxy_to(&[&a, &b], config);
+
+    let addr = proxy.addr;
+    let slow = thread::spawn(move || body(&get(addr, "/sleep/800", &[])));
+    thread::sleep(Duration::from_millis(200));
+    let quick: Vec<String> = (0..4)
+        .map(|_| body(&get(proxy.addr, "/sleep/0", &[])))
+        .collect();
+    let busy = slow.join().unwrap();
+    assert!(quick.iter().all(|name| *name != busy), "{busy} {quick:?}");
+
+    proxy.stop();
+    a.stop();
+    b.stop();
+}
+
+#[test]
+fn failing_upstreams_are_ejected_until_their_health_check_passes() {
+    let a = upstream("a", healthy());
+    let b_healthy = healthy();
+    let b = upstream("b", Arc::clone(&b_healthy));
+    let c = upstream("c", healthy());
+    let config = ProxyConfig {
+        health_check: Some(HealthCheck {
+            path: "/health".to_string(),
+            interval: Duration::from_millis(50),
+        }),
+        eject_for: Duration::from_secs(60),
+        ..ProxyConfig::default()
+    };
+    let (upstreams, proxy) = proxy_to(&[&a, &b, &c], config);
+    let wait_for = |expected: &[&Running]| {
+        let expected: Vec<String> = expected.iter().map(|u| u.addr.to_string()).collect();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while upstreams.available() != expected {
+            assert!(Instant::now() < deadline, "{:?}", upstreams.available());
+            thread::sleep(Duration::from_millis(20));
+        }
+    };
+
+    // `b` fails its checks: out it goes, though its other routes work.
+    b_healthy.store(false, Ordering::SeqCst);
+    wait_for(&[&a, &c]);
+    for _ in 0..4 {
+        assert_ne!(body(&get(proxy.addr, "/sleep/0", &[])), "b");
+    }
+    b_healthy.store(true, Ordering::SeqCst);
+    wait_for(&[&a, &b, &c]);
+
+    // `c` goes away entirely. Requests that find it gone move on to the
+    // next upstream, so clients never notice.
+    c.stop();
+    for _ in 0..6 {
+        let response = get(proxy.addr, "/sleep/0", &[]);
+        assert_eq!(response.status, StatusCode::OK);
+        assert_ne!(body(&response), "c");
+    }
+    wait_for(&[&a, &b]);
+
+    proxy.stop();
+    a.stop();
+    b.stop();
+}
+
+#[test]
+fn slow_and_missing_upstreams_become_gateway_errors() {
+    let a = upstream("a", healthy());
+    let config = ProxyConfig {
+        timeout: Duration::from_millis(200),
+        max_fails: 1,
+        ..ProxyConfig::default()
+    };
+    let (upstreams, proxy) = proxy_to(&[&a], config.clone());
+    assert_eq!(
+        get(proxy.addr, "/sleep/1000", &[]).status,
+        StatusCode::GATEWAY_TIMEOUT
+    );
+    // That one failure was enough to eject the only upstream.
+    assert!(upstreams.available().is_empty());
+    assert_eq!(
+        get(proxy.addr, "/sleep/0", &[]).status,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    proxy.stop();
+    a.stop();
+
+    // Nothing listening at all.
+    let closed = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let proxy = Proxy::new([closed.to_string()], config).unwrap();
+    let proxy = serve(Router::new().wrap(proxy));
+    assert_eq!(get(proxy.addr, "/who", &[]).status, StatusCode::BAD_GATEWAY);
+    proxy.stop();
+}
@@ -0,0 +1,127 @@
+//! The server from the outside, through `client::Client`: each test starts
+//! a server on an ephemeral port and talks HTTP to it as any client would.
+
+use std::thread;
+use std::time::Duration;
+
+use custom_server::client::{Client, Error};
+use custom_server::http::{Method, Request, Response, StatusCode};
+use custom_server::router::Router;
+use custom_server::server::Server;
+use custom_server::static_files::StaticFiles;
+
+mod common;
+
+use common::{Running, TempDir};
+
+fn router() -> Router {
+    Router::new()
+        .get("/users/:id", |request: &Request| {
+            Response::text(
+                StatusCode::OK,
+                format!("user {}", request.param("id").unwrap()),
+            )
+        })
+        .route(Method::Post, "/echo", |request: &Request| {
+            Response::new(StatusCode::OK).with_body(request.body.clone())
+        })
+        .get("/sleep/:ms", |request: &Request| {
+            let ms = request.param("ms").unwrap().parse().unwrap();
+            thread::sleep(Duration::from_millis(ms));
+            Response::text(StatusCode::OK, "awake")
+        })
+}
+
+#[test]
+fn requests_share_a_connection_until_the_server_closes_it() {
+    let server = Server::bind("127.0.0.1:0", router())
+        .unwrap()
+        .workers(2)
+        .max_requests(3);
+    let running = Running::start(server);
+    let client = Client::new();
+
+    for id in 1..=3 {
+        let response = client
+            .get(&running.url(&format!("/users/{id}")))
+            .send()
+            .unwrap();
+        assert_eq!(response.body, format!("user {id}").as_bytes());
+        // The third answer says `Connection: close`, so it isn't kept.
+        assert_eq!(client.idle_connections(), usize::from(id < 3));
+    }
+    let response = client
+        .post(&running.url("/echo"))
+        .body("ping")
+        .send()
+        .unwrap();
+    assert_eq!(response.body, b"ping");
+    assert_eq!(client.idle_connections(), 1);
+
+    // A HEAD response has the GET's headers and no body to wait for.
+    let response = client.head(&running.url("/users/7")).send().unwrap();
+    assert_eq!(response.headers.get("Content-Length"), Some("6"));
+    assert!(response.body.is_empty());
+    assert_eq!(
+        client.get(&running.url("/nope")).send().unwrap().status,
+        StatusCode::NOT_FOUND
+    );
+    running.stop();
+}
+
+#[test]
+fn a_directory_without_its_slash_is_redirected_and_followed() {
+    let dir = TempDir::new("client");
+    dir.file("docs/index.html", "<h1>docs</h1>");
+    let files = StaticFiles::new(&dir.0).unwrap();
+    let router = Router::new().get("/*path", move |request: &Request| {
+        files.serve(request, request.param("path").unwrap_or_default())
+    });
+    let running = Running::start(Server::bind("127.0.0.1:0", router).unwrap());
+
+    let response = Client::new().get(&running.url("/docs?v=1")).send().unwrap();
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, b"<h1>docs</h1>");
+
+    let response = Client::new()
+        .max_redirects(0)
+        .get(&running.url("/docs"))
+        .send()
+        .unwrap();
+    assert_eq!(response.status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers.get("Location"), Some("/docs/"));
+    running.stop();
+}
+
+#[test]
+fn a_slow_handler_runs_into_the_client_timeout() {
+    let running = Running::start(Server::bind("127.0.0.1:0", router()).unwrap().workers(2));
+    let client = Client::new().timeout(Duration::from_millis(100));
+
+    let err = client.get(&running.url("/sleep/500")).send().unwrap_err();
+    assert!(matches!(err, Error::Timeout), "{err}");
+    let response = client
+        .get(&running.url("/sleep/0"))
+        .timeout(Duration::from_secs(5))
+        .send()
+        .unwrap();
+    assert_eq!(response.body, b"awake");
+    running.stop();
+}
+
+#[test]
+fn a_panicking_handler_gets_a_500_and_the_server_carries_on() {
+    let router = router().get("/panic", |_: &Request| -> Response {
+        panic!("handler bug")
+    });
+    let running = Running::start(Server::bind("127.0.0.1:0", router).unwrap().workers(1));
+    let client = Client::new();
+
+    let response = client.get(&running.url("/panic")).send().unwrap();
+    assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(response.headers.get("Connection"), Some("close"));
+    assert_eq!(client.idle_connections(), 0);
+    let response = client.get(&running.url("/users/1")).send().unwrap();
+    assert_eq!(response.body, b"user 1");
+    running.stop();
+}
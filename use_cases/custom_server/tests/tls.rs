@@ -0,0 +1,175 @@
+//! The HTTPS listener, from the outside: a rustls client that trusts only
+//! a certificate generated for the test.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use custom_server::client::Client;
+use custom_server::http::{Request, Response, StatusCode};
+use custom_server::router::Router;
+use custom_server::server::Server;
+use custom_server::tls::{SelfSigned, TlsConfig};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, ProtocolVersion, RootCertStore, StreamOwned};
+
+mod common;
+
+use common::{Running, TempDir};
+
+/// A server answering `/n/:n` over HTTP, and over HTTPS with `tls`.
+fn start(tls: TlsConfig) -> Running {
+    let router = Router::new().get("/n/:n", |request: &Request| {
+        Response::text(StatusCode::OK, format!("n={}", request.param("n").unwrap()))
+    });
+    let server = Server::bind("127.0.0.1:0", router)
+        .unwrap()
+        .workers(2)
+        .https("127.0.0.1:0", tls)
+        .unwrap();
+    Running::start(server)
+}
+
+/// A client for `version` that trusts only the certificate in `cert_pem`.
+fn client(cert_pem: &str, version: &'static rustls::SupportedProtocolVersion) -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_slice_iter(cert_pem.as_bytes()) {
+        roots.add(cert.unwrap()).unwrap();
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(&[version])
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+/// Send `raw` over TLS to `addr`, calling it `name`, and read until the
+/// server closes. Returns the negotiated version with the response.
+fn exchange(
+    config: ClientConfig,
+    name: &str,
+    addr: SocketAddr,
+    raw: &str,
+) -> io::Result<(ProtocolVersion, String)> {
+    let name = ServerName::try_from(name.to_string()).unwrap();
+    let connection = ClientConnection::new(Arc::new(config), name).unwrap();
+    let socket = TcpStream::connect(addr)?;
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut stream = StreamOwned::new(connection, socket);
+    stream.write_all(raw.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let version = stream.conn.protocol_version().unwrap();
+    Ok((version, response))
+}
+
+fn plain(addr: SocketAddr, raw: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(raw.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+const GET_CLOSE: &str = "GET /n/1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+#[test]
+fn both_ports_serve_the_same_routes_over_tls_1_2_and_1_3() {
+    let generated = SelfSigned::generate(&["localhost"]).unwrap();
+    let running = start(generated.config().unwrap());
+
+    let expected = plain(running.addr, GET_CLOSE);
+    assert!(expected.ends_with("\r\n\r\nn=1"), "{expected}");
+    for (version, negotiated) in [
+        (&rustls::version::TLS12, ProtocolVersion::TLSv1_2),
+        (&rustls::version::TLS13, ProtocolVersion::TLSv1_3),
+    ] {
+        let config = client(&generated.cert_pem, version);
+        let (version, response) =
+            exchange(config, "localhost", running.https.unwrap(), GET_CLOSE).unwrap();
+        assert_eq!(version, negotiated);
+        assert_eq!(response, expected);
+    }
+    running.stop();
+}
+
+#[test]
+fn keep_alive_and_pipelining_work_inside_one_session() {
+    let generated = SelfSigned::generate(&["localhost"]).unwrap();
+    let running = start(generated.config().unwrap());
+
+    let config = client(&generated.cert_pem, &rustls::version::TLS13);
+    let raw = "GET /n/1 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+               GET /n/2 HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        .to_string()
+        + GET_CLOSE.replace("/n/1", "/n/3").as_str();
+    let (_, responses) = exchange(config, "localhost", running.https.unwrap(), &raw).unwrap();
+    let bodies: Vec<&str> = responses
+        .split("HTTP/1.1 200 OK\r\n")
+        .skip(1)
+        .map(|r| r.rsplit("\r\n\r\n").next().unwrap())
+        .collect();
+    assert_eq!(bodies, ["n=1", "n=2", "n=3"]);
+    running.stop();
+}
+
+#[test]
+fn a_certificate_from_pem_files_is_checked_by_the_client() {
+    let generated = SelfSigned::generate(&["127.0.0.1"]).unwrap();
+    let dir = TempDir::new("tls");
+    let cert = dir.file("cert.pem", &generated.cert_pem);
+    let key = dir.file("key.pem", &generated.key_pem);
+    let tls = TlsConfig::load(cert, key);
+    let running = start(tls.unwrap());
+
+    let trusting = || client(&generated.cert_pem, &rustls::version::TLS13);
+    let (_, response) =
+        exchange(trusting(), "127.0.0.1", running.https.unwrap(), GET_CLOSE).unwrap();
+    assert!(response.ends_with("\r\n\r\nn=1"));
+
+    // The wrong name, or a certificate the client has never seen.
+    let err = exchange(trusting(), "example.com", running.https.unwrap(), GET_CLOSE).unwrap_err();
+    assert!(err.to_string().contains("certificate"), "{err}");
+    let stranger = SelfSigned::generate(&["127.0.0.1"]).unwrap();
+    let config = client(&stranger.cert_pem, &rustls::version::TLS13);
+    assert!(exchange(config, "127.0.0.1", running.https.unwrap(), GET_CLOSE).is_err());
+    running.stop();
+}
+
+#[test]
+fn plain_http_on_the_tls_port_is_dropped_without_harm() {
+    let generated = SelfSigned::generate(&["localhost"]).unwrap();
+    let running = start(generated.config().unwrap());
+
+    // The server answers with a TLS alert record and hangs up.
+    let mut stream = TcpStream::connect(running.https.unwrap()).unwrap();
+    stream.write_all(GET_CLOSE.as_bytes()).unwrap();
+    let mut answer = Vec::new();
+    stream.read_to_end(&mut answer).unwrap();
+    assert_eq!(answer.first(), Some(&0x15));
+    let config = client(&generated.cert_pem, &rustls::version::TLS12);
+    let (_, response) = exchange(config, "localhost", running.https.unwrap(), GET_CLOSE).unwrap();
+    assert!(response.ends_with("\r\n\r\nn=1"));
+    running.stop();
+}
+
+#[test]
+fn the_client_reuses_one_session_for_several_requests() {
+    let generated = SelfSigned::generate(&["localhost"]).unwrap();
+    let running = start(generated.config().unwrap());
+
+    let https = Client::new().tls(client(&generated.cert_pem, &rustls::version::TLS13));
+    for n in 1..=3 {
+        let url = format!("https://localhost:{}/n/{n}", running.https.unwrap().port());
+        let response = https.get(&url).send().unwrap();
+        assert_eq!(response.body, format!("n={n}").as_bytes());
+        assert_eq!(https.idle_connections(), 1);
+    }
+    // Without a TLS config the client won't guess which servers to trust.
+    let url = format!("https://localhost:{}/n/1", running.https.unwrap().port());
+    assert!(Client::new().get(&url).send().is_err());
+    running.stop();
+}
@@ -0,0 +1,136 @@
+//! Literal search benchmark: the naive line-by-line `search::search` against
+//! the whole-buffer `search::search_literal` (see `src/literal.rs`).
+//!
+//! ```sh
+//! cargo bench                          # 64 MiB generated log
+//! RGREP_BENCH_MB=256 cargo bench       # a bigger generated log
+//! cargo bench -- /var/log/syslog big.txt   # real files instead
+//! ```
+//!
+//! Each case runs a few times and reports the best throughput, after
+//! checking that both searches found the same lines. Plain `std::time`, no
+//! benchmarking crate: the numbers are for comparing the two approaches on
+//! one machine, not for publishing.
+
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rgrep::literal::Finder;
+use rgrep::search;
+
+const RUNS: usize = 5;
+
+/// Needles with different shapes: fairly common, rare, absent, one byte that
+/// hits every line (the `Z` in each timestamp), and one too short for
+/// Horspool to skip much.
+const NEEDLES: &[&str] = &[
+    "ERROR",
+    "pool exhausted",
+    "no-such-text-anywhere",
+    "Z",
+    "db",
+];
+
+fn main() {
+    let paths: Vec<String> = env::args()
+        .skip(1)
+        .filter(|a| !a.starts_with("--"))
+        .collect();
+    let corpora: Vec<(String, String)> = if paths.is_empty() {
+        let mb = env::var("RGREP_BENCH_MB")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(64);
+        vec![(format!("generated log, {mb} MiB"), generate_log(mb << 20))]
+    } else {
+        paths
+            .into_iter()
+            .map(|path| {
+                let bytes = fs::read(&path).unwrap_or_else(|err| panic!("{path}: {err}"));
+                let text = String::from_utf8_lossy(&bytes).into_owned();
+                (path, text)
+            })
+            .collect()
+    };
+
+    for (name, corpus) in &corpora {
+        println!("{name}");
+        println!(
+            "  {:<24} {:>8} {:>12} {:>12} {:>8}",
+            "needle", "lines", "naive MB/s", "literal MB/s", "speedup"
+        );
+        for needle in NEEDLES {
+            let finder = Finder::new(needle.as_bytes());
+            let (naive_time, naive) = best_of(|| search::search(*needle, corpus).len());
+            let (fast_time, fast) = best_of(|| search::search_literal(&finder, corpus).len());
+            assert_eq!(naive, fast, "{needle:?}: the two searches disagree");
+            println!(
+                "  {:<24} {:>8} {:>12.0} {:>12.0} {:>7.1}x",
+                format!("{needle:?}"),
+                fast,
+                throughput(corpus.len(), naive_time),
+                throughput(corpus.len(), fast_time),
+                naive_time.as_secs_f64() / fast_time.as_secs_f64()
+            );
+        }
+    }
+}
+
+/// The fastest of `RUNS` runs of `f`, and its result.
+fn best_of(mut f: impl FnMut() -> usize) -> (Duration, usize) {
+    let mut best = Duration::MAX;
+    let mut result = 0;
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        result = black_box(f());
+        best = best.min(started.elapsed());
+    }
+    (best, result)
+}
+
+fn throughput(bytes: usize, time: Duration) -> f64 {
+    bytes as f64 / (1 << 20) as f64 / time.as_secs_f64()
+}
+
+/// About `size` bytes of log lines shaped like `sample_logs/`, mostly INFO
+/// with the occasional WARN/ERROR. Deterministic, so runs are comparable.
+fn generate_log(size: usize) -> String {
+    const MESSAGES: &[&str] = &[
+        "INFO  request served in 12ms",
+        "INFO  cache hit for key user:1042",
+        "INFO  connected to database",
+        "DEBUG scheduling job 77 on worker 3",
+        "WARN  retrying cache connection",
+        "INFO  health check ok",
+    ];
+    const RARE: &[&str] = &[
+        "ERROR failed to connect to cache: timeout after 5s",
+        "ERROR [db] pool exhausted, 32 waiters",
+        "WARN  Zone transfer slow",
+    ];
+    let mut out = String::with_capacity(size + 128);
+    // A small linear congruential generator is plenty for picking lines.
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut second = 0u64;
+    while out.len() < size {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        let roll = (state >> 33) as usize;
+        let message = if roll.is_multiple_of(100) {
+            RARE[roll / 100 % RARE.len()]
+        } else {
+            MESSAGES[roll % MESSAGES.len()]
+        };
+        second += 1;
+        out.push_str(&format!(
+            "2026-08-08T{:02}:{:02}:{:02}Z {message}\n",
+            second / 3600 % 24,
+            second / 60 % 60,
+            second % 60
+        ));
+    }
+    out
+}
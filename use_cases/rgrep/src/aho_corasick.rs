@@ -0,0 +1,202 @@
+//! Searching for many plain strings at once (`-e` several times, `-f
+//! FILE`) in a single pass over the text.
+//!
+//! Aho-Corasick builds a trie of all the patterns and adds a "failure" link
+//! to every node: where to continue when the next byte doesn't extend the
+//! current match, namely the longest suffix of what was read that is still
+//! a prefix of some pattern. Following those links ahead of time turns the
+//! trie into a DFA, so scanning costs one table lookup per byte no matter
+//! how many patterns there are — searching for five hundred IDs is as
+//! linear as searching for one.
+//!
+//! The table has a column per *byte class* rather than per byte: every
+//! byte that appears in no pattern behaves the same, so they share one
+//! column, which keeps the table small.
+//!
+//! Matches are leftmost-longest, as in `grep`: of the patterns found, the
+//! one starting earliest wins, and the longest of those.
+//!
+//! Concepts: tries, breadth-first search with `VecDeque`, DFAs as flat
+//! `Vec<u32>` tables.
+
+use std::collections::VecDeque;
+
+const ROOT: u32 = 0;
+
+/// A set of literal patterns compiled for searching.
+#[derive(Debug, Clone)]
+pub struct AhoCorasick {
+    /// Byte -> column in `delta`.
+    classes: [u8; 256],
+    stride: usize,
+    /// `delta[state * stride + class]`: the next state.
+    delta: Vec<u32>,
+    /// Patterns ending at each state (including via failure links).
+    outputs: Vec<Vec<usize>>,
+    lens: Vec<usize>,
+    max_len: usize,
+    /// A pattern that's the empty string, which matches everywhere.
+    empty: Option<usize>,
+}
+
+impl AhoCorasick {
+    pub fn new<P: AsRef<[u8]>>(patterns: &[P]) -> AhoCorasick {
+        let mut classes = [0u8; 256];
+        let mut used = [false; 256];
+        for pattern in patterns {
+            for &b in pattern.as_ref() {
+                used[usize::from(b)] = true;
+            }
+        }
+        // Class 0 is "in no pattern"; the others get one column each.
+        let mut stride = 1;
+        for b in 0..256 {
+            if used[b] {
+                classes[b] = stride as u8;
+                stride += 1;
+            }
+        }
+        // Only 255 classes fit in a `u8`; past that, give up on sharing.
+        if stride > 256 {
+            stride = 256;
+            for (b, class) in classes.iter_mut().enumerate() {
+                *class = b as u8;
+            }
+        }
+
+        // The trie, with 0 meaning "no edge" (the root is never a target).
+        let mut delta = vec![0u32; stride];
+        let mut outputs = vec![Vec::new()];
+        for (id, pattern) in patterns.iter().enumerate() {
+            let mut state = ROOT as usize;
+            for &b in pattern.as_ref() {
+                let slot = state * stride + usize::from(classes[usize::from(b)]);
+                if delta[slot] == ROOT {
+                    delta[slot] = outputs.len() as u32;
+                    delta.extend(std::iter::repeat_n(0, stride));
+                    outputs.push(Vec::new());
+                }
+                state = delta[slot] as usize;
+            }
+            outputs[state].push(id);
+        }
+
+        // Breadth-first, so a node's failure target is finished before the
+        // node itself: fill in missing edges from the failure state and
+        // inherit its outputs.
+        let mut fail = vec![ROOT; outputs.len()];
+        let mut queue: VecDeque<usize> = (0..stride)
+            .map(|class| delta[class] as usize)
+            .filter(|&child| child != ROOT as usize)
+            .collect();
+        while let Some(state) = queue.pop_front() {
+            for class in 0..stride {
+                let slot = state * stride + class;
+                let child = delta[slot] as usize;
+                let via_fail = delta[fail[state] as usize * stride + class];
+                if child == ROOT as usize {
+                    delta[slot] = via_fail;
+                } else {
+                    fail[child] = via_fail;
+                    let inherited = outputs[via_fail as usize].clone();
+                    outputs[child].extend(inherited);
+                    queue.push_back(child);
+                }
+            }
+        }
+
+        let lens: Vec<usize> = patterns.iter().map(|p| p.as_ref().len()).collect();
+        AhoCorasick {
+            classes,
+            stride,
+            delta,
+            outputs,
+            max_len: lens.iter().copied().max().unwrap_or(0),
+            empty: lens.iter().position(|&len| len == 0),
+            lens,
+        }
+    }
+
+    /// Number of patterns.
+    pub fn len(&self) -> usize {
+        self.lens.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lens.is_empty()
+    }
+
+    /// The leftmost-longest match starting at or after `start`, as
+    /// `(start, end, pattern index)`.
+    pub fn find_at(&self, haystack: &[u8], start: usize) -> Option<(usize, usize, usize)> {
+        let mut best = self.empty.map(|id| (start, start, id));
+        let mut state = ROOT as usize;
+        for (i, &b) in haystack.iter().enumerate().skip(start) {
+            // Nothing found later can start at or before the best start.
+            if best.is_some_and(|(from, ..)| i >= from + self.max_len) {
+                break;
+            }
+            state = self.delta[state * self.stride + usize::from(self.classes[usize::from(b)])]
+                as usize;
+            for &id in &self.outputs[state] {
+                let (from, to) = (i + 1 - self.lens[id], i + 1);
+                let better = match best {
+                    None => true,
+                    Some((best_from, best_to, _)) => {
+                        from < best_from || (from == best_from && to > best_to)
+                    }
+                };
+                if better {
+                    best = Some((from, to, id));
+                }
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Leftmost-longest by brute force.
+    fn naive(patterns: &[&str], haystack: &str, start: usize) -> Option<(usize, usize, usize)> {
+        (start..=haystack.len()).find_map(|from| {
+            patterns
+                .iter()
+                .enumerate()
+                .filter(|(_, p)| haystack.as_bytes()[from..].starts_with(p.as_bytes()))
+                .max_by_key(|(id, p)| (p.len(), std::cmp::Reverse(*id)))
+                .map(|(id, p)| (from, from + p.len(), id))
+        })
+    }
+
+    #[test]
+    fn agrees_with_brute_force() {
+        let patterns = ["he", "she", "his", "hers", "abcd", "bc", "c"];
+        let ac = AhoCorasick::new(&patterns);
+        for haystack in ["ushers", "abcd", "xbcx", "hishe", "nothing", "ahishers c"] {
+            for start in 0..=haystack.len() {
+                assert_eq!(
+                    ac.find_at(haystack.as_bytes(), start),
+                    naive(&patterns, haystack, start),
+                    "{haystack:?} from {start}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn many_ids_and_the_empty_pattern() {
+        let ids: Vec<String> = (0..500).map(|n| format!("ID-{n:04}")).collect();
+        let ac = AhoCorasick::new(&ids);
+        assert_eq!(ac.len(), 500);
+        assert_eq!(ac.find_at(b"user ID-0417 logged in", 0), Some((5, 12, 417)));
+        assert_eq!(ac.find_at(b"user ID-9999", 0), None);
+
+        let with_empty = AhoCorasick::new(&["", "ab"]);
+        assert_eq!(with_empty.find_at(b"xab", 0), Some((0, 0, 0)));
+        assert_eq!(with_empty.find_at(b"xab", 1), Some((1, 3, 1)));
+        assert_eq!(AhoCorasick::new::<&str>(&[]).find_at(b"anything", 0), None);
+    }
+}
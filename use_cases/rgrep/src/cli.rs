@@ -1,44 +1,811 @@
+use std::fmt::Write;
+use std::fs;
+use std::io;
+
 use crate::error::RgrepError;
+use crate::glob::Glob;
+use crate::index::Query;
+use crate::printer::{ColorChoice, OutputMode, OutputOptions};
+use crate::regex::RegexOptions;
+use crate::replace::Template;
+use crate::search::Pattern;
+use crate::walk::WalkOptions;
 
 /// Parsed command-line arguments for a run of `rgrep`.
 ///
 /// Called like: `rgrep "error" app.log` or `rgrep "error" *.log`
 /// (the shell expands `*.log` into multiple arguments before your program
 /// ever sees them — that's why `paths` is a `Vec`, not a single `String`.)
+/// Options follow `grep`: `-E` treats the pattern as a regular expression,
+/// `-i`/`-w`/`-x` change how it matches, `-v` inverts the selection, and
+/// `-c`/`-l`/`-L`/`-q` replace the matching lines with a summary. Short
+/// options combine (`-inr`), `--` ends the options, and the full list —
+/// generated from `OPTIONS` — is in `rgrep --help`. With no paths (or a
+/// path of `-`) the input comes from stdin: `cat app.log | rgrep error`.
+/// `-e PATTERN` (repeatable) and `-f FILE` (one pattern per line) search
+/// for several patterns at once; then every positional argument is a path.
 ///
 /// Concepts: structs, `String` vs `&str` (why owned `String` here and not
 /// borrowed `&str` — think about how long `args` lives vs how long `Config`
 /// needs to live), `Vec<T>`.
 pub struct Config {
-    pub pattern: String,
+    /// The pattern (or set of patterns), already compiled — a bad regex is a usage error caught
+    /// here, not halfway through searching the first file.
+    pub pattern: Pattern,
     pub paths: Vec<String>,
-    // Stretch goal (do this after the base version works): add a field like
-    // `pub case_insensitive: bool` and parse a `-i` flag out of `args` below.
+    /// `-r`: descend into directories given in `paths`.
+    pub recursive: bool,
+    /// `--hidden` / `--no-ignore`: what `-r` skips while walking.
+    pub walk: WalkOptions,
+    /// `-j N`: worker threads for searching files concurrently. Defaults to
+    /// the number of CPUs; `1` searches strictly one file at a time.
+    pub threads: usize,
+    /// `-n`, `-A/-B/-C` context, `-o` and the `-c/-l/-L/-q` modes.
+    /// `output.color` is filled in by `run` from `color`, since "auto"
+    /// depends on where stdout goes.
+    pub output: OutputOptions,
+    /// `--color=auto|always|never`.
+    pub color: ColorChoice,
+    /// `-v`: select the lines that *don't* match.
+    pub invert: bool,
+    /// `-m NUM`: stop reading a file after this many selected lines.
+    pub max_count: Option<u64>,
+    /// `--include GLOB`: if any are given, only search files matching one.
+    pub include: Vec<Glob>,
+    /// `--exclude GLOB`: skip files matching any of these.
+    pub exclude: Vec<Glob>,
+    /// `-s`: don't report files that can't be read.
+    pub no_messages: bool,
+    /// `--fail-fast`: stop at the first file that can't be read, instead
+    /// of reporting it and searching the rest.
+    pub fail_fast: bool,
+    /// `-z`: decompress gzip files and search inside tar archives.
+    pub search_zip: bool,
+    /// `--replace TEXT`: rewrite matches in the files instead of printing
+    /// them (see `replace`).
+    pub replace: Option<Template>,
+    /// `--dry-run`: with `--replace`, print a diff and leave files alone.
+    pub dry_run: bool,
+    /// `--follow`: after reaching the end of each file, keep reading lines
+    /// appended to it, like `tail -F`.
+    pub follow: bool,
+    /// `--index`: skip files a directory's trigram index rules out (see
+    /// `index`). `None` without the flag.
+    pub index: Option<Query>,
+}
+
+/// Every option `rgrep` understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flag {
+    IgnoreCase,
+    Invert,
+    WordRegexp,
+    LineRegexp,
+    Count,
+    FilesWithMatches,
+    FilesWithoutMatch,
+    MaxCount,
+    OnlyMatching,
+    Quiet,
+    NoMessages,
+    FailFast,
+    FixedStrings,
+    Regex,
+    Regexp,
+    File,
+    Recursive,
+    Follow,
+    Index,
+    Include,
+    Exclude,
+    Hidden,
+    NoIgnore,
+    LineNumber,
+    AfterContext,
+    BeforeContext,
+    Context,
+    Threads,
+    Color,
+    Json,
+    SearchZip,
+    Replace,
+    DryRun,
+    Help,
+}
+
+/// Whether an option takes a value, and what `--help` calls it.
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    None,
+    /// Attached (`-m5`, `--max-count=5`) or the next argument (`-m 5`).
+    Required(&'static str),
+    /// Only ever attached with `=` (`--color=never`).
+    Optional(&'static str),
+}
+
+/// One row of the option table that drives both parsing and `--help`.
+struct Opt {
+    flag: Flag,
+    short: Option<char>,
+    long: &'static str,
+    value: Value,
+    help: &'static str,
+}
+
+const fn opt(
+    flag: Flag,
+    short: Option<char>,
+    long: &'static str,
+    value: Value,
+    help: &'static str,
+) -> Opt {
+    Opt {
+        flag,
+        short,
+        long,
+        value,
+        help,
+    }
+}
+
+const OPTIONS: &[Opt] = &[
+    opt(
+        Flag::IgnoreCase,
+        Some('i'),
+        "ignore-case",
+        Value::None,
+        "match letters in either case",
+    ),
+    opt(
+        Flag::Invert,
+        Some('v'),
+        "invert-match",
+        Value::None,
+        "select non-matching lines",
+    ),
+    opt(
+        Flag::WordRegexp,
+        Some('w'),
+        "word-regexp",
+        Value::None,
+        "match only whole words",
+    ),
+    opt(
+        Flag::LineRegexp,
+        Some('x'),
+        "line-regexp",
+        Value::None,
+        "match only whole lines",
+    ),
+    opt(
+        Flag::FixedStrings,
+        Some('F'),
+        "fixed-strings",
+        Value::None,
+        "PATTERN is a plain string (the default)",
+    ),
+    opt(
+        Flag::Regex,
+        Some('E'),
+        "regex",
+        Value::None,
+        "PATTERN is a regular expression",
+    ),
+    opt(
+        Flag::Regexp,
+        Some('e'),
+        "regexp",
+        Value::Required("PATTERN"),
+        "search for PATTERN too (repeatable; no positional PATTERN)",
+    ),
+    opt(
+        Flag::File,
+        Some('f'),
+        "file",
+        Value::Required("FILE"),
+        "search for each line of FILE as a pattern (- for stdin)",
+    ),
+    opt(
+        Flag::Count,
+        Some('c'),
+        "count",
+        Value::None,
+        "print only a count of selected lines per file",
+    ),
+    opt(
+        Flag::FilesWithMatches,
+        Some('l'),
+        "files-with-matches",
+        Value::None,
+        "print only names of files with selected lines",
+    ),
+    opt(
+        Flag::FilesWithoutMatch,
+        Some('L'),
+        "files-without-match",
+        Value::None,
+        "print only names of files with no selected lines",
+    ),
+    opt(
+        Flag::MaxCount,
+        Some('m'),
+        "max-count",
+        Value::Required("NUM"),
+        "stop after NUM selected lines per file",
+    ),
+    opt(
+        Flag::OnlyMatching,
+        Some('o'),
+        "only-matching",
+        Value::None,
+        "print only the matched parts of lines",
+    ),
+    opt(
+        Flag::Quiet,
+        Some('q'),
+        "quiet",
+        Value::None,
+        "print nothing; exit 0 on the first match",
+    ),
+    opt(
+        Flag::NoMessages,
+        Some('s'),
+        "no-messages",
+        Value::None,
+        "suppress errors about unreadable files",
+    ),
+    opt(
+        Flag::FailFast,
+        None,
+        "fail-fast",
+        Value::None,
+        "stop at the first unreadable file instead of skipping it",
+    ),
+    opt(
+        Flag::LineNumber,
+        Some('n'),
+        "line-number",
+        Value::None,
+        "prefix lines with their line number",
+    ),
+    opt(
+        Flag::AfterContext,
+        Some('A'),
+        "after-context",
+        Value::Required("NUM"),
+        "print NUM lines after each match",
+    ),
+    opt(
+        Flag::BeforeContext,
+        Some('B'),
+        "before-context",
+        Value::Required("NUM"),
+        "print NUM lines before each match",
+    ),
+    opt(
+        Flag::Context,
+        Some('C'),
+        "context",
+        Value::Required("NUM"),
+        "print NUM lines around each match",
+    ),
+    opt(
+        Flag::Color,
+        None,
+        "color",
+        Value::Optional("WHEN"),
+        "highlight matches: auto, always or never",
+    ),
+    opt(
+        Flag::Json,
+        None,
+        "json",
+        Value::None,
+        "print JSON Lines records instead of text",
+    ),
+    opt(
+        Flag::SearchZip,
+        Some('z'),
+        "search-zip",
+        Value::None,
+        "search inside gzip files and tar archives",
+    ),
+    opt(
+        Flag::Replace,
+        None,
+        "replace",
+        Value::Required("TEXT"),
+        "rewrite matches in place with TEXT ($1 is group 1 with -E)",
+    ),
+    opt(
+        Flag::DryRun,
+        None,
+        "dry-run",
+        Value::None,
+        "with --replace, print a diff instead of writing files",
+    ),
+    opt(
+        Flag::Recursive,
+        Some('r'),
+        "recursive",
+        Value::None,
+        "search directories recursively",
+    ),
+    opt(
+        Flag::Follow,
+        None,
+        "follow",
+        Value::None,
+        "keep searching lines appended to files, like tail -F (no -f: that is --file)",
+    ),
+    opt(
+        Flag::Index,
+        None,
+        "index",
+        Value::None,
+        "like -r, but use a directory's index to skip files",
+    ),
+    opt(
+        Flag::Include,
+        None,
+        "include",
+        Value::Required("GLOB"),
+        "search only files matching GLOB",
+    ),
+    opt(
+        Flag::Exclude,
+        None,
+        "exclude",
+        Value::Required("GLOB"),
+        "skip files matching GLOB",
+    ),
+    opt(
+        Flag::Hidden,
+        None,
+        "hidden",
+        Value::None,
+        "with -r, search hidden files and directories",
+    ),
+    opt(
+        Flag::NoIgnore,
+        None,
+        "no-ignore",
+        Value::None,
+        "with -r, don't honor .gitignore / .ignore",
+    ),
+    opt(
+        Flag::Threads,
+        Some('j'),
+        "threads",
+        Value::Required("NUM"),
+        "search NUM files at once",
+    ),
+    opt(
+        Flag::Help,
+        Some('h'),
+        "help",
+        Value::None,
+        "print this help and exit",
+    ),
+];
+
+/// Arguments split into options (which one, as spelled, and its value) and
+/// positional arguments, before any meaning is attached to them.
+struct Parsed {
+    options: Vec<(Flag, String, Option<String>)>,
+    positional: Vec<String>,
+}
+
+/// Split `args` (after the program name) using `OPTIONS`. Handles clusters
+/// of short options (`-inr`, `-m5`, `-nA 3`), `--long=value` and
+/// `--long value`, and `--`, after which everything is positional.
+fn parse_args(args: &[String]) -> Result<Parsed, RgrepError> {
+    let mut parsed = Parsed {
+        options: Vec::new(),
+        positional: Vec::new(),
+    };
+    let mut args_iter = args.iter().skip(1);
+    while let Some(arg) = args_iter.next() {
+        if arg == "--" {
+            parsed.positional.extend(args_iter.cloned());
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, attached) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let spelled = format!("--{name}");
+            let Some(opt) = OPTIONS.iter().find(|o| o.long == name) else {
+                return Err(RgrepError::UnknownOption(spelled));
+            };
+            let value = match opt.value {
+                Value::None if attached.is_some() => {
+                    return Err(RgrepError::InvalidValue {
+                        flag: spelled,
+                        value: attached,
+                    });
+                }
+                Value::Required(_) => attached.or_else(|| args_iter.next().cloned()),
+                _ => attached,
+            };
+            parsed.options.push((opt.flag, spelled, value));
+        } else if let Some(cluster) = arg.strip_prefix('-').filter(|c| !c.is_empty()) {
+            for (i, c) in cluster.char_indices() {
+                let spelled = format!("-{c}");
+                let Some(opt) = OPTIONS.iter().find(|o| o.short == Some(c)) else {
+                    return Err(RgrepError::UnknownOption(spelled));
+                };
+                if let Value::Required(_) = opt.value {
+                    // The rest of the cluster is the value: `-m5`, `-nA3`.
+                    let value = match &cluster[i + c.len_utf8()..] {
+                        "" => args_iter.next().cloned(),
+                        rest => Some(rest.to_string()),
+                    };
+                    parsed.options.push((opt.flag, spelled, value));
+                    break;
+                }
+                parsed.options.push((opt.flag, spelled, None));
+            }
+        } else {
+            parsed.positional.push(arg.clone());
+        }
+    }
+    Ok(parsed)
+}
+
+/// The directories to index when `args` are `rgrep index build [DIR...]`
+/// (the current directory if none are given), `None` for a search. To
+/// search for the word "index" in a file named "build", write
+/// `rgrep -- index build`.
+pub fn index_build_dirs(args: &[String]) -> Option<Vec<String>> {
+    match args {
+        [_, command, action, dirs @ ..] if command == "index" && action == "build" => {
+            Some(if dirs.is_empty() {
+                vec![".".to_string()]
+            } else {
+                dirs.to_vec()
+            })
+        }
+        _ => None,
+    }
+}
+
+/// `true` if `-h`/`--help` is among `args`. `main` checks this before
+/// `Config::build`, so `rgrep --help` works without a pattern.
+pub fn wants_help(args: &[String]) -> bool {
+    parse_args(args).is_ok_and(|parsed| parsed.options.iter().any(|(flag, ..)| *flag == Flag::Help))
+}
+
+/// The `--help` text, generated from `OPTIONS` so it can't drift from what
+/// the parser accepts.
+pub fn help() -> String {
+    let rows: Vec<(String, &str)> = OPTIONS
+        .iter()
+        .map(|o| {
+            let short = o.short.map_or("    ".to_string(), |c| format!("-{c}, "));
+            let value = match o.value {
+                Value::None => String::new(),
+                Value::Required(name) => format!("={name}"),
+                Value::Optional(name) => format!("[={name}]"),
+            };
+            (format!("{short}--{}{value}", o.long), o.help)
+        })
+        .collect();
+    let width = rows
+        .iter()
+        .map(|(spelling, _)| spelling.len())
+        .max()
+        .unwrap_or(0);
+
+    let mut out = String::from(
+        "Usage: rgrep [OPTION]... PATTERN [PATH]...\n\
+         Search for PATTERN in each PATH, or standard input if there are none.\n\n\
+         Options:\n",
+    );
+    for (spelling, help) in rows {
+        let _ = writeln!(out, "  {spelling:width$}  {help}");
+    }
+    out.push_str(
+        "\nExit status is 0 if any line was selected, 1 if none was, 2 if an error\n\
+         occurred, and 3 if the search ran but some files couldn't be read (with\n\
+         -q, a selected line still exits 0).\n",
+    );
+    out
 }
 
 impl Config {
     /// Build a `Config` from raw process args (including `args[0]`, the
-    /// program name — you'll need to skip it).
+    /// program name, which is skipped).
     ///
-    /// Concepts: slices (`&[String]`), ownership and borrowing (do you need
-    /// to `.clone()` here, or can you take ownership with `.to_string()` /
-    /// indexing + `into()`? think about what `args` looks like to the
-    /// caller after this returns), `Result<T, E>`, `match` / `if let`.
+    /// Concepts: slices (`&[String]`), ownership and borrowing, `Result<T, E>`,
+    /// `match` / `if let`.
     pub fn build(args: &[String]) -> Result<Config, RgrepError> {
-        // TODO:
-        //   1. args[0] is the program name — ignore it.
-        //   2. args[1], if present, is the pattern. If it's missing,
-        //      return an appropriate RgrepError.
-        //   3. args[2..], if non-empty, are the file paths to search.
-        //      If there are none, return an appropriate RgrepError.
-        //   4. Build and return `Ok(Config { pattern, paths })`.
-        todo!("parse `args` into a Config, or a RgrepError if usage is wrong")
+        let parsed = parse_args(args)?;
+        let mut regex = false;
+        let mut regex_options = RegexOptions::default();
+        let mut recursive = false;
+        let mut walk = WalkOptions::default();
+        let mut threads = None;
+        let mut output = OutputOptions::default();
+        let mut color = ColorChoice::default();
+        let mut invert = false;
+        let mut max_count = None;
+        let mut include = Vec::new();
+        let mut exclude = Vec::new();
+        let mut no_messages = false;
+        let mut fail_fast = false;
+        let mut search_zip = false;
+        let mut replace = None;
+        let mut dry_run = None;
+        let mut index = false;
+        let mut follow = false;
+        let mut quiet = None;
+        // Patterns from -e and -f, in the order given.
+        let mut sources: Vec<String> = Vec::new();
+        let mut explicit_patterns = false;
+        // The option that picked `output.mode`, for conflict messages.
+        let mut mode_option = None;
+
+        for (flag, name, value) in &parsed.options {
+            let value = value.as_deref();
+            match flag {
+                Flag::IgnoreCase => regex_options.case_insensitive = true,
+                Flag::Invert => invert = true,
+                Flag::WordRegexp => regex_options.whole_word = true,
+                Flag::LineRegexp => regex_options.whole_line = true,
+                Flag::FixedStrings => regex = false,
+                Flag::Regex => regex = true,
+                Flag::Regexp => {
+                    // Like grep, a newline separates patterns.
+                    let value = value.ok_or_else(|| invalid_value(name, value))?;
+                    sources.extend(value.split('\n').map(str::to_string));
+                    explicit_patterns = true;
+                }
+                Flag::File => {
+                    let path = value.ok_or_else(|| invalid_value(name, value))?;
+                    sources.extend(read_patterns(path)?);
+                    explicit_patterns = true;
+                }
+                Flag::Count | Flag::FilesWithMatches | Flag::FilesWithoutMatch => {
+                    output.mode = match flag {
+                        Flag::Count => OutputMode::Count,
+                        Flag::FilesWithMatches => OutputMode::FilesWithMatches,
+                        _ => OutputMode::FilesWithoutMatch,
+                    };
+                    mode_option = Some(name);
+                }
+                Flag::MaxCount => max_count = Some(parse_count(name, value)? as u64),
+                Flag::OnlyMatching => output.only_matching = true,
+                Flag::Quiet => quiet = Some(name),
+                Flag::NoMessages => no_messages = true,
+                Flag::FailFast => fail_fast = true,
+                Flag::LineNumber => output.line_numbers = true,
+                Flag::AfterContext => output.after_context = parse_count(name, value)?,
+                Flag::BeforeContext => output.before_context = parse_count(name, value)?,
+                Flag::Context => {
+                    let n = parse_count(name, value)?;
+                    output.before_context = n;
+                    output.after_context = n;
+                }
+                Flag::Color => {
+                    color = match value {
+                        None | Some("always") => ColorChoice::Always,
+                        Some("auto") => ColorChoice::Auto,
+                        Some("never") => ColorChoice::Never,
+                        Some(_) => return Err(invalid_value(name, value)),
+                    }
+                }
+                Flag::Json => output.json = true,
+                Flag::SearchZip => {
+                    search_zip = true;
+                    walk.search_zip = true;
+                }
+                Flag::Replace => replace = Some((name, value.unwrap_or_default())),
+                Flag::DryRun => dry_run = Some(name),
+                Flag::Recursive => recursive = true,
+                Flag::Follow => follow = true,
+                Flag::Index => {
+                    index = true;
+                    recursive = true;
+                }
+                Flag::Include => {
+                    include.push(Glob::new(value.ok_or_else(|| invalid_value(name, value))?))
+                }
+                Flag::Exclude => {
+                    exclude.push(Glob::new(value.ok_or_else(|| invalid_value(name, value))?))
+                }
+                Flag::Hidden => walk.hidden = true,
+                Flag::NoIgnore => walk.respect_ignore = false,
+                Flag::Threads => threads = Some(nonzero(name, parse_count(name, value)?)?),
+                // Handled by `wants_help` before `build` is called.
+                Flag::Help => {}
+            }
+        }
+        if let Some(name) = quiet {
+            // `-q` beats `-c`/`-l`/`-L`, as in grep.
+            output.mode = OutputMode::Quiet;
+            mode_option = Some(name);
+        }
+        if output.json
+            && let Some(mode_option) = mode_option
+        {
+            return Err(RgrepError::Conflict {
+                first: "--json".to_string(),
+                second: mode_option.clone(),
+            });
+        }
+        if replace.is_some() {
+            // Rewriting files has no use for another output format.
+            let clash = parsed.options.iter().find(|(flag, ..)| {
+                matches!(
+                    flag,
+                    Flag::Invert
+                        | Flag::OnlyMatching
+                        | Flag::Json
+                        | Flag::SearchZip
+                        | Flag::Count
+                        | Flag::FilesWithMatches
+                        | Flag::FilesWithoutMatch
+                        | Flag::Quiet
+                )
+            });
+            if let Some((_, name, _)) = clash {
+                return Err(RgrepError::Conflict {
+                    first: "--replace".to_string(),
+                    second: name.clone(),
+                });
+            }
+        } else if let Some(name) = dry_run {
+            return Err(RgrepError::Requires {
+                option: name.clone(),
+                required: "--replace".to_string(),
+            });
+        }
+        if follow {
+            // Per-file summaries and rewrites need the end of the file;
+            // an index only knows the files as they were.
+            let clash = parsed.options.iter().find(|(flag, ..)| {
+                matches!(
+                    flag,
+                    Flag::Count
+                        | Flag::FilesWithMatches
+                        | Flag::FilesWithoutMatch
+                        | Flag::Quiet
+                        | Flag::Replace
+                        | Flag::SearchZip
+                        | Flag::Index
+                )
+            });
+            if let Some((_, name, _)) = clash {
+                return Err(RgrepError::Conflict {
+                    first: "--follow".to_string(),
+                    second: name.clone(),
+                });
+            }
+        }
+        if output.only_matching || output.mode != OutputMode::Lines {
+            // Nothing to put context around.
+            output.before_context = 0;
+            output.after_context = 0;
+        }
+
+        let mut positional = parsed.positional.into_iter();
+        if !explicit_patterns {
+            sources.push(positional.next().ok_or(RgrepError::MissingPattern)?);
+        }
+        let mut paths: Vec<String> = positional.collect();
+        if paths.is_empty() {
+            // Like grep: search the current directory with `-r`, else stdin.
+            paths.push(if recursive { "." } else { "-" }.to_string());
+        }
+
+        let pattern = Pattern::set(&sources, regex, &regex_options).map_err(|(i, source)| {
+            RgrepError::InvalidPattern {
+                pattern: sources[i].clone(),
+                source,
+            }
+        })?;
+        let index = index.then(|| {
+            // Files without a match still show up in -v, -c and -L output.
+            if invert
+                || matches!(
+                    output.mode,
+                    OutputMode::Count | OutputMode::FilesWithoutMatch
+                )
+            {
+                Query::everything()
+            } else {
+                Query::any(&sources, regex, &regex_options)
+            }
+        });
+        let replace = match replace {
+            // Without -E, `$` in the replacement is just a dollar sign.
+            Some((name, text)) => {
+                let template = if regex {
+                    Template::parse(text)
+                } else {
+                    Some(Template::literal(text))
+                };
+                match template {
+                    Some(template) if template.max_group() < pattern.captures_len() => {
+                        Some(template)
+                    }
+                    _ => return Err(invalid_value(name, Some(text))),
+                }
+            }
+            None => None,
+        };
+
+        Ok(Config {
+            pattern,
+            paths,
+            recursive,
+            walk,
+            threads: threads.unwrap_or_else(default_threads),
+            output,
+            color,
+            invert,
+            max_count,
+            include,
+            exclude,
+            no_messages,
+            fail_fast,
+            search_zip,
+            replace,
+            dry_run: dry_run.is_some(),
+            follow,
+            index,
+        })
+    }
+}
+
+/// The patterns in a `-f` file, one per line; `-` reads them from stdin.
+/// An empty file gives no patterns, so nothing matches.
+fn read_patterns(path: &str) -> Result<Vec<String>, RgrepError> {
+    let text = if path == "-" {
+        io::read_to_string(io::stdin())
+    } else {
+        fs::read_to_string(path)
+    };
+    let text = text.map_err(|source| RgrepError::Io {
+        path: path.to_string(),
+        source,
+    })?;
+    Ok(text.lines().map(str::to_string).collect())
+}
+
+fn invalid_value(flag: &str, value: Option<&str>) -> RgrepError {
+    RgrepError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.map(str::to_string),
+    }
+}
+
+fn parse_count(flag: &str, value: Option<&str>) -> Result<usize, RgrepError> {
+    value
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| invalid_value(flag, value))
+}
+
+fn nonzero(flag: &str, n: usize) -> Result<usize, RgrepError> {
+    if n == 0 {
+        return Err(RgrepError::InvalidValue {
+            flag: flag.to_string(),
+            value: Some(n.to_string()),
+        });
     }
+    Ok(n)
+}
+
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::TempDir;
 
     #[test]
     fn builds_config_from_valid_args() {
@@ -48,7 +815,7 @@ mod tests {
             "app.log".to_string(),
         ];
         let config = Config::build(&args).unwrap();
-        assert_eq!(config.pattern, "error");
+        assert_eq!(config.pattern.as_str(), "error");
         assert_eq!(config.paths, vec!["app.log".to_string()]);
     }
 
@@ -71,8 +838,198 @@ mod tests {
     }
 
     #[test]
-    fn errors_when_paths_missing() {
+    fn reads_stdin_when_paths_missing() {
         let args = vec!["rgrep".to_string(), "error".to_string()];
-        assert!(Config::build(&args).is_err());
+        assert_eq!(Config::build(&args).unwrap().paths, vec!["-".to_string()]);
+
+        let args = vec!["rgrep".to_string(), "-r".to_string(), "error".to_string()];
+        assert_eq!(Config::build(&args).unwrap().paths, vec![".".to_string()]);
+    }
+
+    #[test]
+    fn regex_flag_compiles_pattern() {
+        let args = vec![
+            "rgrep".to_string(),
+            "-E".to_string(),
+            "^ERROR".to_string(),
+            "app.log".to_string(),
+        ];
+        let config = Config::build(&args).unwrap();
+        assert!(matches!(config.pattern, Pattern::Regex(_)));
+        assert_eq!(config.paths, vec!["app.log".to_string()]);
+    }
+
+    #[test]
+    fn errors_on_invalid_regex() {
+        let args = vec![
+            "rgrep".to_string(),
+            "--regex".to_string(),
+            "(unclosed".to_string(),
+            "app.log".to_string(),
+        ];
+        assert!(matches!(
+            Config::build(&args),
+            Err(RgrepError::InvalidPattern { .. })
+        ));
+    }
+
+    #[test]
+    fn parses_recursive_walk_flags() {
+        let args = vec![
+            "rgrep".to_string(),
+            "-r".to_string(),
+            "--hidden".to_string(),
+            "TODO".to_string(),
+            "src/".to_string(),
+        ];
+        let config = Config::build(&args).unwrap();
+        assert!(config.recursive);
+        assert!(config.walk.hidden);
+        assert!(config.walk.respect_ignore);
+        assert_eq!(config.paths, vec!["src/".to_string()]);
+    }
+
+    #[test]
+    fn parses_thread_count() {
+        let args: Vec<String> = ["rgrep", "-j", "8", "error", "a.log", "b.log"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let config = Config::build(&args).unwrap();
+        assert_eq!(config.threads, 8);
+        assert_eq!(config.paths.len(), 2);
+
+        let args: Vec<String> = ["rgrep", "-j2", "error", "a.log"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(Config::build(&args).unwrap().threads, 2);
+    }
+
+    #[test]
+    fn rejects_bad_thread_counts() {
+        for bad in [
+            &["rgrep", "-j", "0", "e", "a"][..],
+            &["rgrep", "e", "a", "-j"],
+        ] {
+            let args: Vec<String> = bad.iter().map(|s| s.to_string()).collect();
+            assert!(matches!(
+                Config::build(&args),
+                Err(RgrepError::InvalidValue { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn parses_context_and_line_number_flags() {
+        let args: Vec<String> = ["rgrep", "-n", "-C", "2", "-A5", "--color=never", "e", "a"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let config = Config::build(&args).unwrap();
+        assert!(config.output.line_numbers);
+        assert_eq!(config.output.before_context, 2);
+        assert_eq!(config.output.after_context, 5);
+        assert_eq!(config.color, ColorChoice::Never);
+        assert!(!config.output.json);
+    }
+
+    fn build(args: &[&str]) -> Result<Config, RgrepError> {
+        let args: Vec<String> = args.iter().map(|s| s.to_string()).collect();
+        Config::build(&args)
+    }
+
+    #[test]
+    fn parses_combined_short_flags_and_attached_values() {
+        let config = build(&["rgrep", "-inr", "-vm5", "--include=*.rs", "e"]).unwrap();
+        assert!(config.recursive && config.invert && config.output.line_numbers);
+        assert_eq!(config.max_count, Some(5));
+        assert_eq!(config.include.len(), 1);
+        assert!(config.pattern.as_str().contains("e"));
+
+        let config = build(&["rgrep", "--max-count", "2", "-nA3", "e", "a"]).unwrap();
+        assert_eq!(config.max_count, Some(2));
+        assert_eq!(config.output.after_context, 3);
+    }
+
+    #[test]
+    fn double_dash_ends_options() {
+        let config = build(&["rgrep", "-n", "--", "-v", "--json"]).unwrap();
+        assert_eq!(config.pattern.as_str(), "-v");
+        assert_eq!(config.paths, vec!["--json".to_string()]);
+        assert!(!config.invert && !config.output.json);
+    }
+
+    #[test]
+    fn e_and_f_collect_patterns_and_leave_positionals_as_paths() {
+        let dir = TempDir::new("patterns");
+        let file = dir.file("patterns", "ID-0417\r\nID-0090\n");
+        let file_arg = file.to_string_lossy().into_owned();
+        let config = build(&["rgrep", "-e", "warn", "-f", &file_arg, "a.log", "b.log"]).unwrap();
+        match &config.pattern {
+            Pattern::Literals { sources, .. } => {
+                assert_eq!(sources, &["warn", "ID-0417", "ID-0090"])
+            }
+            other => panic!("expected literals, got {other:?}"),
+        }
+        assert_eq!(config.paths, vec!["a.log".to_string(), "b.log".to_string()]);
+
+        let config = build(&["rgrep", "-Ee", "err(or)?", "-eWARN", "-i"]).unwrap();
+        assert!(matches!(config.pattern, Pattern::Any(_)));
+        assert_eq!(config.paths, vec!["-".to_string()]);
+
+        assert!(matches!(
+            build(&["rgrep", "-E", "-e", "ok", "-e", "(bad"]),
+            Err(RgrepError::InvalidPattern { pattern, .. }) if pattern == "(bad"
+        ));
+        assert!(matches!(
+            build(&["rgrep", "-f", "/nonexistent/patterns"]),
+            Err(RgrepError::Io { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_options_and_conflicts() {
+        assert!(matches!(
+            build(&["rgrep", "-nZ", "e"]),
+            Err(RgrepError::UnknownOption(option)) if option == "-Z"
+        ));
+        assert!(matches!(
+            build(&["rgrep", "--frobnicate", "e"]),
+            Err(RgrepError::UnknownOption(_))
+        ));
+        assert!(matches!(
+            build(&["rgrep", "--json=yes", "e"]),
+            Err(RgrepError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            build(&["rgrep", "--json", "-l", "e"]),
+            Err(RgrepError::Conflict { .. })
+        ));
+    }
+
+    #[test]
+    fn summary_modes_drop_context_and_quiet_wins() {
+        let config = build(&["rgrep", "-C2", "-c", "e"]).unwrap();
+        assert_eq!(config.output.mode, OutputMode::Count);
+        assert!(!config.output.has_context());
+
+        let config = build(&["rgrep", "-q", "-l", "e"]).unwrap();
+        assert_eq!(config.output.mode, OutputMode::Quiet);
+    }
+
+    #[test]
+    fn help_lists_every_option() {
+        let args: Vec<String> = ["rgrep", "-nh"].iter().map(|s| s.to_string()).collect();
+        assert!(wants_help(&args));
+        let text = help();
+        for opt in OPTIONS {
+            assert!(text.contains(&format!("--{}", opt.long)), "{}", opt.long);
+        }
+        assert!(text.contains("-m, --max-count=NUM"));
+        // Every status `main` can exit with, partial searches included.
+        for status in ["0 if", "1 if", "2 if", "3 if"] {
+            assert!(text.contains(status), "{status}");
+        }
     }
 }
@@ -0,0 +1,741 @@
+//! Transparent decompression for `-z`.
+//!
+//! `open` sniffs the first bytes of an input and, for gzip, wraps it in a
+//! `GzDecoder` that inflates it on the fly — so the rest of `rgrep` (the
+//! `LineBuffer`, the printer) reads decompressed text through a plain
+//! `Read` and never knows the difference. Memory stays bounded: DEFLATE
+//! only ever refers back 32 KiB, so that's all the history the decoder
+//! keeps.
+//!
+//! The DEFLATE decoder (RFC 1951) and the gzip framing around it (RFC 1952)
+//! are written out here rather than pulled in from a crate. zstd and bzip2
+//! files are recognised by their magic numbers but not decoded; `open`
+//! reports them as unsupported instead of searching compressed bytes.
+//!
+//! Decoding errors travel through `Read` as `io::Error`s wrapping an
+//! `Error`, which `Error::from_io` recovers so callers can report them as
+//! corrupt data rather than as I/O failures.
+//!
+//! Concepts: bit-level parsing, canonical Huffman codes, implementing
+//! `Read`, ring buffers, `io::Error::new` with a custom payload.
+
+use std::fmt;
+use std::io::{self, Cursor, Read};
+
+/// What an input turned out to contain, judging by its first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Plain,
+    Gzip,
+    /// An uncompressed tar archive (see `tar`).
+    Tar,
+    Zstd,
+    Bzip2,
+}
+
+/// How many leading bytes `Format::sniff` needs to recognise everything:
+/// tar's magic sits at offset 257.
+pub const SNIFF_LEN: usize = 512;
+
+impl Format {
+    pub fn sniff(head: &[u8]) -> Format {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Format::Gzip
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Format::Zstd
+        } else if head.starts_with(b"BZh") {
+            Format::Bzip2
+        } else if head.get(257..262) == Some(b"ustar") {
+            Format::Tar
+        } else {
+            Format::Plain
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Plain => "plain",
+            Format::Gzip => "gzip",
+            Format::Tar => "tar",
+            Format::Zstd => "zstd",
+            Format::Bzip2 => "bzip2",
+        }
+    }
+}
+
+/// Why compressed data couldn't be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A compression format `rgrep` recognises but can't decode.
+    Unsupported(Format),
+    /// The gzip header is malformed or uses an unknown method.
+    BadHeader(&'static str),
+    /// The DEFLATE stream itself is malformed.
+    Corrupt(&'static str),
+    /// The input ended in the middle of the compressed data.
+    Truncated,
+    /// Everything decoded, but the CRC-32 or length in the gzip trailer
+    /// doesn't match what came out.
+    ChecksumMismatch,
+    /// A tar archive is malformed (see `tar`).
+    BadTar(&'static str),
+}
+
+impl Error {
+    /// Wrap `self` for returning through `Read`.
+    pub fn into_io(self) -> io::Error {
+        let kind = if self == Error::Truncated {
+            io::ErrorKind::UnexpectedEof
+        } else {
+            io::ErrorKind::InvalidData
+        };
+        io::Error::new(kind, self)
+    }
+
+    /// The `Error` inside an `io::Error` made by `into_io`, or the original
+    /// `io::Error` if it's a real I/O failure.
+    pub fn from_io(err: io::Error) -> Result<Error, io::Error> {
+        if err.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = err.into_inner().expect("checked above");
+            Ok(*inner.downcast::<Error>().expect("checked above"))
+        } else {
+            Err(err)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Unsupported(format) => {
+                write!(f, "{} compression is not supported", format.name())
+            }
+            Error::BadHeader(why) => write!(f, "bad gzip header: {why}"),
+            Error::Corrupt(why) => write!(f, "corrupt deflate data: {why}"),
+            Error::Truncated => write!(f, "compressed data ends unexpectedly"),
+            Error::ChecksumMismatch => write!(f, "gzip checksum mismatch"),
+            Error::BadTar(why) => write!(f, "bad tar archive: {why}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Read the first `SNIFF_LEN` bytes of `reader` (fewer if it's shorter),
+/// returning them along with a reader that still yields the whole input.
+pub fn peek<R: Read>(mut reader: R) -> io::Result<(Vec<u8>, impl Read)> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    (&mut reader)
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    let rest = Cursor::new(head.clone()).chain(reader);
+    Ok((head, rest))
+}
+
+/// `reader` decompressed if it holds gzip data, or as-is if it holds
+/// anything `rgrep` doesn't need to decode (plain text, tar). Also returns
+/// the format of what comes out, so the caller can spot a `.tar.gz`.
+pub fn open<'r, R: Read + 'r>(reader: R) -> io::Result<(Format, Box<dyn Read + 'r>)> {
+    let (head, reader) = peek(reader)?;
+    match Format::sniff(&head) {
+        Format::Gzip => {
+            let (head, reader) = peek(GzDecoder::new(reader))?;
+            // Only a tar inside is interesting; don't decompress twice.
+            let format = match Format::sniff(&head) {
+                Format::Tar => Format::Tar,
+                _ => Format::Plain,
+            };
+            Ok((format, Box::new(reader)))
+        }
+        format @ (Format::Zstd | Format::Bzip2) => Err(Error::Unsupported(format).into_io()),
+        format => Ok((format, Box::new(reader))),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// gzip
+// ---------------------------------------------------------------------------
+
+/// Decompresses a gzip stream, including files made of several gzip
+/// members back to back (as `cat a.gz b.gz` produces).
+pub struct GzDecoder<R> {
+    inflater: Inflater<R>,
+    state: GzState,
+    crc: u32,
+    size: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GzState {
+    Header,
+    Body,
+    Done,
+}
+
+impl<R: Read> GzDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        GzDecoder {
+            inflater: Inflater::new(reader),
+            state: GzState::Header,
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    fn read_header(&mut self) -> Result<(), Error> {
+        const FHCRC: u8 = 0x02;
+        const FEXTRA: u8 = 0x04;
+        const FNAME: u8 = 0x08;
+        const FCOMMENT: u8 = 0x10;
+        let input = &mut self.inflater.input;
+        if input.byte()? != 0x1f || input.byte()? != 0x8b {
+            return Err(Error::BadHeader("not gzip data"));
+        }
+        if input.byte()? != 8 {
+            return Err(Error::BadHeader("unknown compression method"));
+        }
+        let flags = input.byte()?;
+        if flags & 0xe0 != 0 {
+            return Err(Error::BadHeader("reserved flags set"));
+        }
+        // Modification time, extra flags, OS.
+        for _ in 0..6 {
+            input.byte()?;
+        }
+        if flags & FEXTRA != 0 {
+            let len = u16::from_le_bytes([input.byte()?, input.byte()?]);
+            for _ in 0..len {
+                input.byte()?;
+            }
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                while input.byte()? != 0 {}
+            }
+        }
+        if flags & FHCRC != 0 {
+            input.byte()?;
+            input.byte()?;
+        }
+        Ok(())
+    }
+
+    fn read_trailer(&mut self) -> Result<(), Error> {
+        let input = &mut self.inflater.input;
+        input.align();
+        let mut trailer = [0; 8];
+        for b in &mut trailer {
+            *b = input.byte()?;
+        }
+        let crc = u32::from_le_bytes(trailer[..4].try_into().expect("4 bytes"));
+        let size = u32::from_le_bytes(trailer[4..].try_into().expect("4 bytes"));
+        if crc != self.crc || size != self.size {
+            return Err(Error::ChecksumMismatch);
+        }
+        Ok(())
+    }
+
+    fn read_inner(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        loop {
+            match self.state {
+                GzState::Header => {
+                    self.read_header()?;
+                    self.inflater.reset();
+                    self.crc = 0;
+                    self.size = 0;
+                    self.state = GzState::Body;
+                }
+                GzState::Body => {
+                    let n = self.inflater.read(buf)?;
+                    if n > 0 || buf.is_empty() {
+                        self.crc = crc32_update(self.crc, &buf[..n]);
+                        self.size = self.size.wrapping_add(n as u32);
+                        return Ok(n);
+                    }
+                    self.read_trailer()?;
+                    self.state = if self.inflater.input.at_end()? {
+                        GzState::Done
+                    } else {
+                        GzState::Header
+                    };
+                }
+                GzState::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_inner(buf).map_err(Error::into_io)
+    }
+}
+
+/// CRC-32 (IEEE), the checksum in the gzip trailer.
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut c = i as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+                k += 1;
+            }
+            table[i] = c;
+            i += 1;
+        }
+        table
+    };
+    let mut crc = !crc;
+    for &b in bytes {
+        crc = TABLE[((crc ^ u32::from(b)) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+// ---------------------------------------------------------------------------
+// DEFLATE
+// ---------------------------------------------------------------------------
+
+/// Reads `R` least-significant bit first, as DEFLATE packs its codes.
+struct BitReader<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    start: usize,
+    end: usize,
+    /// Bits read from `buf` but not consumed yet; the next bit is bit 0.
+    bits: u64,
+    count: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(inner: R) -> Self {
+        BitReader {
+            inner,
+            buf: vec![0; 16 * 1024].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            bits: 0,
+            count: 0,
+        }
+    }
+
+    /// Refill `buf` once it's empty. `Ok(false)` at the end of the input.
+    fn fill(&mut self) -> Result<bool, Error> {
+        if self.start < self.end {
+            return Ok(true);
+        }
+        loop {
+            match self.inner.read(&mut self.buf) {
+                Ok(n) => {
+                    self.start = 0;
+                    self.end = n;
+                    return Ok(n > 0);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                // A real read failure can't be told apart from bad data at
+                // this level, so surface it as truncated input.
+                Err(_) => return Err(Error::Truncated),
+            }
+        }
+    }
+
+    /// Make at least `n` bits available, if the input has them.
+    fn refill(&mut self, n: u32) -> Result<(), Error> {
+        while self.count < n {
+            if !self.fill()? {
+                return Ok(());
+            }
+            self.bits |= u64::from(self.buf[self.start]) << self.count;
+            self.start += 1;
+            self.count += 8;
+        }
+        Ok(())
+    }
+
+    /// The next `n` bits without consuming them; missing bits past the end
+    /// of the input read as zero.
+    fn peek(&mut self, n: u32) -> Result<u32, Error> {
+        self.refill(n)?;
+        Ok((self.bits & ((1 << n) - 1)) as u32)
+    }
+
+    fn consume(&mut self, n: u32) -> Result<(), Error> {
+        if n > self.count {
+            return Err(Error::Truncated);
+        }
+        self.bits >>= n;
+        self.count -= n;
+        Ok(())
+    }
+
+    fn read_bits(&mut self, n: u32) -> Result<u32, Error> {
+        let value = self.peek(n)?;
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    /// Skip to the next byte boundary.
+    fn align(&mut self) {
+        let extra = self.count % 8;
+        self.bits >>= extra;
+        self.count -= extra;
+    }
+
+    /// One byte; only valid on a byte boundary.
+    fn byte(&mut self) -> Result<u8, Error> {
+        self.read_bits(8).map(|b| b as u8)
+    }
+
+    /// `true` if nothing but padding bits is left.
+    fn at_end(&mut self) -> Result<bool, Error> {
+        Ok(self.count < 8 && !self.fill()?)
+    }
+}
+
+/// A canonical Huffman code as a lookup table indexed by the next
+/// `max_len` input bits. Each entry is `symbol << 4 | code length`; a
+/// length of 0 marks bit patterns no code uses.
+struct Huffman {
+    table: Vec<u16>,
+    max_len: u32,
+}
+
+impl Huffman {
+    /// Build the code from each symbol's code length (0 = unused).
+    fn new(lengths: &[u8]) -> Result<Huffman, Error> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+        let max_len = (1..16).rev().find(|&l| counts[l] > 0).unwrap_or(0);
+
+        // Each extra bit of length doubles the codes available; a set of
+        // lengths that asks for more than exist can't be decoded.
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(Error::Corrupt("over-subscribed Huffman code"));
+            }
+        }
+
+        // Canonical codes: shorter codes first, then by symbol order.
+        let mut next_code = [0u32; 16];
+        let mut code = 0u32;
+        for len in 1..16 {
+            code = (code + u32::from(counts[len - 1])) << 1;
+            next_code[len] = code;
+        }
+
+        let mut table = vec![0u16; 1 << max_len];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len == 0 {
+                continue;
+            }
+            let len = usize::from(len);
+            let code = next_code[len];
+            next_code[len] += 1;
+            // Codes are stored most-significant bit first but read from the
+            // stream least-significant first, so index by the reversed code.
+            let reversed = code.reverse_bits() >> (32 - len);
+            let entry = (symbol as u16) << 4 | len as u16;
+            let mut index = reversed as usize;
+            while index < table.len() {
+                table[index] = entry;
+                index += 1 << len;
+            }
+        }
+        Ok(Huffman {
+            table,
+            max_len: max_len as u32,
+        })
+    }
+
+    fn decode<R: Read>(&self, input: &mut BitReader<R>) -> Result<u16, Error> {
+        let entry = self.table[input.peek(self.max_len)? as usize];
+        let len = u32::from(entry & 0xf);
+        if len == 0 {
+            return Err(if input.count < self.max_len {
+                Error::Truncated
+            } else {
+                Error::Corrupt("invalid Huffman code")
+            });
+        }
+        input.consume(len)?;
+        Ok(entry >> 4)
+    }
+}
+
+const WINDOW: usize = 32 * 1024;
+const END_OF_BLOCK: u16 = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code-length code lengths are listed in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+enum BlockState {
+    /// Between blocks: the next thing in the stream is a block header.
+    Header,
+    /// Inside an uncompressed block with this many bytes left.
+    Stored(usize),
+    /// Inside a Huffman-coded block.
+    Codes {
+        literals: Huffman,
+        distances: Huffman,
+    },
+    /// The final block has ended.
+    Done,
+}
+
+/// Streaming DEFLATE decoder.
+struct Inflater<R> {
+    input: BitReader<R>,
+    state: BlockState,
+    last_block: bool,
+    /// The last `WINDOW` bytes of output, for back-references.
+    window: Box<[u8]>,
+    /// Total bytes written so far; `written % WINDOW` is the next slot.
+    written: usize,
+    /// A back-reference not fully copied out yet: (distance, bytes left).
+    copy: Option<(usize, usize)>,
+}
+
+impl<R: Read> Inflater<R> {
+    fn new(reader: R) -> Self {
+        Inflater {
+            input: BitReader::new(reader),
+            state: BlockState::Header,
+            last_block: false,
+            window: vec![0; WINDOW].into_boxed_slice(),
+            written: 0,
+            copy: None,
+        }
+    }
+
+    /// Get ready for a new DEFLATE stream (the next gzip member).
+    fn reset(&mut self) {
+        self.state = BlockState::Header;
+        self.last_block = false;
+        self.written = 0;
+        self.copy = None;
+    }
+
+    fn emit(&mut self, out: &mut [u8], n: &mut usize, byte: u8) {
+        out[*n] = byte;
+        *n += 1;
+        self.window[self.written % WINDOW] = byte;
+        self.written += 1;
+    }
+
+    /// Decode into `out`; `Ok(0)` once the stream's final block is done.
+    fn read(&mut self, out: &mut [u8]) -> Result<usize, Error> {
+        let mut n = 0;
+        while n < out.len() {
+            if let Some((distance, left)) = self.copy {
+                let take = left.min(out.len() - n);
+                for _ in 0..take {
+                    let byte = self.window[(self.written - distance) % WINDOW];
+                    self.emit(out, &mut n, byte);
+                }
+                self.copy = (left > take).then_some((distance, left - take));
+                continue;
+            }
+            match &mut self.state {
+                BlockState::Done => break,
+                BlockState::Header if self.last_block => self.state = BlockState::Done,
+                BlockState::Header => self.state = self.block_header()?,
+                BlockState::Stored(0) => self.state = BlockState::Header,
+                BlockState::Stored(left) => {
+                    *left -= 1;
+                    let byte = self.input.byte()?;
+                    self.emit(out, &mut n, byte);
+                }
+                BlockState::Codes {
+                    literals,
+                    distances,
+                } => {
+                    let symbol = literals.decode(&mut self.input)?;
+                    if symbol < END_OF_BLOCK {
+                        self.emit(out, &mut n, symbol as u8);
+                        continue;
+                    }
+                    if symbol == END_OF_BLOCK {
+                        self.state = BlockState::Header;
+                        continue;
+                    }
+                    let i = usize::from(symbol - 257);
+                    if i >= LENGTH_BASE.len() {
+                        return Err(Error::Corrupt("invalid length symbol"));
+                    }
+                    let extra = self.input.read_bits(u32::from(LENGTH_EXTRA[i]))?;
+                    let length = usize::from(LENGTH_BASE[i]) + extra as usize;
+                    let d = usize::from(distances.decode(&mut self.input)?);
+                    if d >= DISTANCE_BASE.len() {
+                        return Err(Error::Corrupt("invalid distance symbol"));
+                    }
+                    let extra = self.input.read_bits(u32::from(DISTANCE_EXTRA[d]))?;
+                    let distance = usize::from(DISTANCE_BASE[d]) + extra as usize;
+                    if distance > self.written.min(WINDOW) {
+                        return Err(Error::Corrupt("distance too far back"));
+                    }
+                    self.copy = Some((distance, length));
+                }
+            }
+        }
+        Ok(n)
+    }
+
+    fn block_header(&mut self) -> Result<BlockState, Error> {
+        self.last_block = self.input.read_bits(1)? == 1;
+        match self.input.read_bits(2)? {
+            0 => {
+                self.input.align();
+                let len = u16::from_le_bytes([self.input.byte()?, self.input.byte()?]);
+                let nlen = u16::from_le_bytes([self.input.byte()?, self.input.byte()?]);
+                if len != !nlen {
+                    return Err(Error::Corrupt("stored block length check failed"));
+                }
+                Ok(BlockState::Stored(usize::from(len)))
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                Ok(BlockState::Codes {
+                    literals: Huffman::new(&lengths)?,
+                    distances: Huffman::new(&[5; 30])?,
+                })
+            }
+            2 => self.dynamic_codes(),
+            _ => Err(Error::Corrupt("invalid block type")),
+        }
+    }
+
+    fn dynamic_codes(&mut self) -> Result<BlockState, Error> {
+        let literal_count = self.input.read_bits(5)? as usize + 257;
+        let distance_count = self.input.read_bits(5)? as usize + 1;
+        let code_length_count = self.input.read_bits(4)? as usize + 4;
+        if literal_count > 286 || distance_count > 30 {
+            return Err(Error::Corrupt("too many length codes"));
+        }
+        let mut code_lengths = [0u8; 19];
+        for &i in &CODE_LENGTH_ORDER[..code_length_count] {
+            code_lengths[i] = self.input.read_bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0u8; literal_count + distance_count];
+        let mut i = 0;
+        while i < lengths.len() {
+            let (value, repeat) = match code_length_code.decode(&mut self.input)? {
+                len @ 0..=15 => (len as u8, 1),
+                16 => {
+                    let Some(&previous) = i.checked_sub(1).map(|p| &lengths[p]) else {
+                        return Err(Error::Corrupt("repeat with no previous length"));
+                    };
+                    (previous, 3 + self.input.read_bits(2)? as usize)
+                }
+                17 => (0, 3 + self.input.read_bits(3)? as usize),
+                _ => (0, 11 + self.input.read_bits(7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(Error::Corrupt("too many code lengths"));
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+        if lengths[usize::from(END_OF_BLOCK)] == 0 {
+            return Err(Error::Corrupt("no end-of-block code"));
+        }
+        Ok(BlockState::Codes {
+            literals: Huffman::new(&lengths[..literal_count])?,
+            distances: Huffman::new(&lengths[literal_count..])?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `printf 'hello hello hello\n' | gzip -9n`: one fixed-Huffman block
+    /// with a back-reference.
+    const HELLO_GZ: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0x57, 0xc8, 0x40, 0x90, 0x5c, 0x00, 0x3b, 0x7c, 0x8a, 0xdf, 0x12, 0x00, 0x00, 0x00,
+    ];
+
+    fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+        GzDecoder::new(bytes)
+            .read_to_end(&mut out)
+            .map_err(|err| Error::from_io(err).unwrap())?;
+        Ok(out)
+    }
+
+    #[test]
+    fn decodes_fixed_huffman_gzip() {
+        assert_eq!(gunzip(HELLO_GZ).unwrap(), b"hello hello hello\n");
+    }
+
+    #[test]
+    fn decodes_stored_blocks_and_concatenated_members() {
+        // A stored block by hand: BFINAL=1, BTYPE=00, LEN=3, NLEN=!3, "abc".
+        let mut member = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+        member.extend([0x01, 0x03, 0x00, 0xfc, 0xff]);
+        member.extend(b"abc");
+        member.extend(crc32_update(0, b"abc").to_le_bytes());
+        member.extend(3u32.to_le_bytes());
+        let mut both = member.clone();
+        both.extend(HELLO_GZ);
+        assert_eq!(gunzip(&both).unwrap(), b"abchello hello hello\n");
+    }
+
+    #[test]
+    fn reports_corrupt_and_truncated_input() {
+        let mut bad_crc = HELLO_GZ.to_vec();
+        bad_crc[HELLO_GZ.len() - 8] ^= 1;
+        assert_eq!(gunzip(&bad_crc), Err(Error::ChecksumMismatch));
+        assert_eq!(gunzip(&HELLO_GZ[..15]), Err(Error::Truncated));
+        let mut bad_type = HELLO_GZ.to_vec();
+        bad_type[10] |= 0b110; // BTYPE = 11
+        assert_eq!(gunzip(&bad_type), Err(Error::Corrupt("invalid block type")));
+    }
+
+    #[test]
+    fn sniffs_formats_by_magic_number() {
+        assert_eq!(Format::sniff(HELLO_GZ), Format::Gzip);
+        assert_eq!(Format::sniff(&[0x28, 0xb5, 0x2f, 0xfd, 0]), Format::Zstd);
+        assert_eq!(Format::sniff(b"BZh91AY&SY"), Format::Bzip2);
+        let mut tar = vec![0u8; 512];
+        tar[257..263].copy_from_slice(b"ustar\0");
+        assert_eq!(Format::sniff(&tar), Format::Tar);
+        assert_eq!(Format::sniff(b"plain text"), Format::Plain);
+    }
+}
@@ -1,21 +1,68 @@
 use std::fmt;
 
+use crate::decompress;
+use crate::regex;
+
 /// All the ways `rgrep` can fail, collected into one type so `main` only
 /// has to handle one `Result` error type end to end.
 ///
-/// Concepts: enums, `Result<T, E>`, the `std::error::Error` trait, `match`.
+/// Variants that wrap a lower-level error (`io::Error`, a regex or
+/// decompression error) keep it as their `source()` rather than pasting it
+/// into their own message; `report` joins the whole chain for printing.
+///
+/// Concepts: enums, `Result<T, E>`, the `std::error::Error` trait and its
+/// `source()` chain, `match`.
 #[derive(Debug)]
 pub enum RgrepError {
-    // TODO: what does a usage error need to say? e.g. no pattern given.
-    // MissingPattern,
+    /// No pattern argument was given.
+    MissingPattern,
+
+    /// An argument that looks like an option (`-z`, `--frobnicate`) but
+    /// isn't one `rgrep` knows.
+    UnknownOption(String),
+
+    /// Two options that can't be used together, like `--json` and `-c`.
+    Conflict { first: String, second: String },
+
+    /// An option that only makes sense alongside another one, like
+    /// `--dry-run` without `--replace`.
+    Requires { option: String, required: String },
+
+    /// A flag that takes a value (like `-j N`) got a missing or malformed
+    /// one. `value` is `None` when the value was missing entirely.
+    InvalidValue { flag: String, value: Option<String> },
+
+    /// `-E` was given and the pattern isn't a valid regular expression.
+    InvalidPattern {
+        pattern: String,
+        source: regex::Error,
+    },
 
-    // TODO: what does a usage error need to say? e.g. no file paths given.
-    // MissingPaths,
+    /// Reading a file or directory failed (missing file, permissions, ...).
+    /// Carries the path so the message can say *which* file broke.
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
 
-    // TODO: reading a file can fail (missing file, permissions, ...).
-    // Hint: wrap the path that failed AND the underlying `std::io::Error`,
-    // so the message can say *which* file broke.
-    // Io { path: String, source: std::io::Error },
+    /// `--replace` couldn't write a file's new contents. The original is
+    /// left as it was.
+    Write {
+        path: String,
+        source: std::io::Error,
+    },
+
+    /// Writing results to standard output failed — usually a closed pipe,
+    /// as in `rgrep ... | head`. Unlike `Io`, there's no point carrying on.
+    Output(std::io::Error),
+
+    /// With `-z`, a file's compressed data is corrupt or truncated, or uses
+    /// a format `rgrep` can't decode. `path` is the file (and for a tar
+    /// member, `archive:member`).
+    Decompress {
+        path: String,
+        source: decompress::Error,
+    },
 }
 
 // `Display` controls what users see when this error is printed with `{}`
@@ -24,13 +71,111 @@ pub enum RgrepError {
 // the message wording is up to you.
 impl fmt::Display for RgrepError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        // TODO: match on `self` and `write!(f, "...")` a human-readable
-        // message for each variant you defined above.
-        todo!("format each RgrepError variant as a human-readable message")
+        match self {
+            RgrepError::MissingPattern => write!(f, "no search pattern given"),
+            RgrepError::UnknownOption(option) => write!(f, "unknown option '{option}'"),
+            RgrepError::Conflict { first, second } => {
+                write!(f, "options '{first}' and '{second}' can't be used together")
+            }
+            RgrepError::Requires { option, required } => {
+                write!(f, "option '{option}' only works with '{required}'")
+            }
+            RgrepError::InvalidValue { flag, value: None } => {
+                write!(f, "option '{flag}' requires a value")
+            }
+            RgrepError::InvalidValue {
+                flag,
+                value: Some(value),
+            } => write!(f, "invalid value '{value}' for option '{flag}'"),
+            RgrepError::InvalidPattern { pattern, .. } => write!(f, "invalid regex '{pattern}'"),
+            RgrepError::Io { path, .. } => write!(f, "cannot read {path}"),
+            RgrepError::Write { path, .. } => write!(f, "cannot write {path}"),
+            RgrepError::Output(_) => write!(f, "cannot write to standard output"),
+            RgrepError::Decompress { path, .. } => write!(f, "cannot decompress {path}"),
+        }
+    }
+}
+
+// Implementing this is what lets `RgrepError` be used anywhere a
+// `Box<dyn std::error::Error>` or `?` chain expects a "real" error type.
+// `source()` hands out the wrapped error, so callers can walk down to the
+// root cause (see `report`) instead of parsing message strings.
+impl std::error::Error for RgrepError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RgrepError::InvalidPattern { source, .. } => Some(source),
+            RgrepError::Io { source, .. }
+            | RgrepError::Write { source, .. }
+            | RgrepError::Output(source) => Some(source),
+            RgrepError::Decompress { source, .. } => Some(source),
+            RgrepError::MissingPattern
+            | RgrepError::UnknownOption(_)
+            | RgrepError::Conflict { .. }
+            | RgrepError::Requires { .. }
+            | RgrepError::InvalidValue { .. } => None,
+        }
     }
 }
 
-// Implementing this (empty is fine) is what lets `RgrepError` be used
-// anywhere a `Box<dyn std::error::Error>` or `?` chain expects a "real"
-// error type, and is what makes `{e}` work in `eprintln!("{e}")` via Display.
-impl std::error::Error for RgrepError {}
+impl RgrepError {
+    /// `true` for failures confined to one input file or directory, which
+    /// `run` reports and then moves past (unless `--fail-fast`).
+    pub fn is_per_file(&self) -> bool {
+        matches!(
+            self,
+            RgrepError::Io { .. } | RgrepError::Write { .. } | RgrepError::Decompress { .. }
+        )
+    }
+
+    /// The message followed by every `source()` under it, joined with
+    /// `": "` — e.g. `cannot read app.log: Permission denied (os error 13)`.
+    pub fn report(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = std::error::Error::source(self);
+        while let Some(err) = source {
+            message.push_str(": ");
+            message.push_str(&err.to_string());
+            source = err.source();
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn wrapped_errors_are_sources_not_part_of_the_message() {
+        let err = RgrepError::Io {
+            path: "app.log".to_string(),
+            source: io::Error::new(io::ErrorKind::NotFound, "No such file"),
+        };
+        assert_eq!(err.to_string(), "cannot read app.log");
+        assert_eq!(err.source().unwrap().to_string(), "No such file");
+        assert_eq!(err.report(), "cannot read app.log: No such file");
+        assert!(err.is_per_file());
+
+        let err = RgrepError::Decompress {
+            path: "a.tar.gz:b.log".to_string(),
+            source: decompress::Error::Truncated,
+        };
+        assert!(err.source().is_some());
+        assert!(err.is_per_file());
+    }
+
+    #[test]
+    fn usage_and_output_errors_are_not_per_file() {
+        let missing = RgrepError::MissingPattern;
+        assert!(missing.source().is_none());
+        assert_eq!(missing.report(), "no search pattern given");
+        assert!(!missing.is_per_file());
+
+        let pipe = RgrepError::Output(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert!(pipe.source().is_some());
+        assert!(!pipe.is_per_file());
+    }
+}
@@ -0,0 +1,324 @@
+//! `--follow`: reading files as they grow, like `tail -F`.
+//!
+//! A `Tail` remembers how far into its file it has read. Each `poll` reads
+//! whatever was appended since, and hands back only *complete* lines — a
+//! line still being written (no `\n` yet) waits for the next poll. Between
+//! reads it checks what the path now points at:
+//!
+//! - a file smaller than what's been read was truncated (`> app.log`), so
+//!   reading starts over from its beginning;
+//! - a different file (another inode) means the log was rotated: the old
+//!   file is read to its end first — a logger may still have been writing
+//!   to it — and then the new one from the start;
+//! - no file at all is reported once, and the path is retried until one
+//!   appears.
+//!
+//! A poll reads at most `MAX_READ` bytes, through a `LineBuffer`, so
+//! following a huge file (or one growing faster than it's polled) takes
+//! several polls rather than unbounded memory; `is_behind` says when there
+//! is more to read right away. The checks above wait until the end of the
+//! file has been reached.
+//!
+//! Concepts: `Seek`, file identity via `MetadataExt` (`dev` + `ino`),
+//! `#[cfg(unix)]`, state machines.
+
+use std::fmt;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use crate::reader::{LineBuffer, MAX_LINE_LEN};
+
+/// Most bytes one `poll` reads from a file.
+pub const MAX_READ: u64 = 1024 * 1024;
+
+/// What a `poll` saw, in the order it happened.
+#[derive(Debug)]
+pub enum Event {
+    /// Complete lines, `\n` included (except for the last line of a file
+    /// that was rotated away without one), starting at byte `offset` of
+    /// the file currently being read.
+    Lines { offset: u64, bytes: Vec<u8> },
+    /// The file shrank; what follows is read from its beginning.
+    Truncated,
+    /// The path now names a different file, which is read from the start.
+    Replaced,
+    /// A file appeared at a path that was missing.
+    Appeared,
+    /// The path can't be opened. Reported once; polling keeps retrying.
+    Missing(io::Error),
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Lines { bytes, .. } => write!(f, "{} new bytes", bytes.len()),
+            Event::Truncated => write!(f, "file truncated"),
+            Event::Replaced => write!(f, "file has been replaced; following new file"),
+            Event::Appeared => write!(f, "file has appeared; following new file"),
+            Event::Missing(err) => write!(f, "{err}; waiting for it to appear"),
+        }
+    }
+}
+
+/// Identifies the file behind a path across renames.
+#[cfg(unix)]
+type FileId = (u64, u64);
+#[cfg(not(unix))]
+type FileId = ();
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> FileId {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.dev(), metadata.ino())
+}
+
+// Elsewhere there's no portable inode, so rotation goes unnoticed unless
+// it also truncates.
+#[cfg(not(unix))]
+fn file_id(_: &Metadata) -> FileId {}
+
+/// One followed path.
+pub struct Tail {
+    path: PathBuf,
+    file: Option<(File, FileId)>,
+    /// Bytes read from `file` so far.
+    offset: u64,
+    /// The start of a line whose `\n` hasn't arrived yet.
+    partial: Vec<u8>,
+    /// Whether `Missing` was already reported for the current absence.
+    missing: bool,
+    /// Whether any file was ever opened, to tell `Appeared` from the
+    /// first open.
+    opened: bool,
+    /// Whether the last read stopped at `MAX_READ` rather than the end of
+    /// the file.
+    behind: bool,
+}
+
+impl Tail {
+    /// Follow `path` from the beginning of the file. Nothing is opened
+    /// until the first `poll`.
+    pub fn new(path: impl Into<PathBuf>) -> Tail {
+        Tail {
+            path: path.into(),
+            file: None,
+            offset: 0,
+            partial: Vec::new(),
+            missing: false,
+            opened: false,
+            behind: false,
+        }
+    }
+
+    /// Whether the last `poll` left unread bytes in the file, so the next
+    /// one shouldn't wait.
+    pub fn is_behind(&self) -> bool {
+        self.behind
+    }
+
+    /// Everything that happened since the last call. `Err` only for a
+    /// failed read of an open file; a missing path is an `Event`.
+    pub fn poll(&mut self) -> io::Result<Vec<Event>> {
+        let mut events = Vec::new();
+        self.drain(&mut events)?;
+        if self.behind {
+            // Truncation and rotation are looked for at the end.
+            return Ok(events);
+        }
+        match fs::metadata(&self.path) {
+            Err(err) => {
+                if !self.missing {
+                    self.missing = true;
+                    events.push(Event::Missing(err));
+                }
+            }
+            Ok(metadata) => {
+                let current = self.file.as_ref().map(|(_, id)| *id);
+                if current != Some(file_id(&metadata)) {
+                    self.reopen(&mut events)?;
+                } else if metadata.len() < self.offset {
+                    let (file, _) = self.file.as_mut().expect("checked above");
+                    file.seek(SeekFrom::Start(0))?;
+                    self.offset = 0;
+                    self.partial.clear();
+                    events.push(Event::Truncated);
+                    self.drain(&mut events)?;
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    /// Switch to whatever file the path names now, after finishing the
+    /// old one.
+    fn reopen(&mut self, events: &mut Vec<Event>) -> io::Result<()> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            // Gone again between the `metadata` call and now.
+            Err(err) => {
+                if !self.missing {
+                    self.missing = true;
+                    events.push(Event::Missing(err));
+                }
+                return Ok(());
+            }
+        };
+        let id = file_id(&file.metadata()?);
+        if self.file.is_some() {
+            // Lines written to the old file right before the rename.
+            self.drain(events)?;
+            if self.behind {
+                // More of the old file to go; switch once it's read.
+                return Ok(());
+            }
+            if !self.partial.is_empty() {
+                events.push(Event::Lines {
+                    offset: self.offset - self.partial.len() as u64,
+                    bytes: std::mem::take(&mut self.partial),
+                });
+            }
+            events.push(Event::Replaced);
+        } else if self.opened {
+            events.push(Event::Appeared);
+        }
+        self.file = Some((file, id));
+        self.offset = 0;
+        self.partial.clear();
+        self.missing = false;
+        self.opened = true;
+        self.drain(events)
+    }
+
+    /// Read the open file up to its current end, or `MAX_READ` bytes of
+    /// it, passing on complete lines.
+    fn drain(&mut self, events: &mut Vec<Event>) -> io::Result<()> {
+        let Some((file, _)) = self.file.as_mut() else {
+            return Ok(());
+        };
+        let start = self.offset - self.partial.len() as u64;
+        let partial = std::mem::take(&mut self.partial);
+        let mut reader = LineBuffer::new(partial.as_slice().chain(file.by_ref().take(MAX_READ)));
+        let mut bytes = Vec::new();
+        while let Some(block) = reader.next_block()? {
+            // Only the last block can lack its `\n`: the line still being
+            // written, unless it's a piece of one too long to keep whole.
+            if block.ends_with(b"\n") || block.len() >= MAX_LINE_LEN {
+                bytes.extend_from_slice(block);
+            } else {
+                self.partial = block.to_vec();
+            }
+        }
+        let end = start + (bytes.len() + self.partial.len()) as u64;
+        self.behind = end - self.offset == MAX_READ;
+        self.offset = end;
+        if !bytes.is_empty() {
+            events.push(Event::Lines {
+                offset: start,
+                bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// The events of one poll, with lines as text for easy comparison.
+    fn poll(tail: &mut Tail) -> Vec<String> {
+        tail.poll()
+            .unwrap()
+            .into_iter()
+            .map(|event| match event {
+                Event::Lines { offset, bytes } => {
+                    format!("{offset}:{}", String::from_utf8(bytes).unwrap())
+                }
+                Event::Missing(_) => "missing".to_string(),
+                other => other.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hands_out_complete_lines_as_they_are_appended() {
+        let dir = TempDir::new("follow-append");
+        let log = dir.0.join("app.log");
+        dir.append("app.log", "one\ntw");
+        let mut tail = Tail::new(&log);
+        assert_eq!(poll(&mut tail), vec!["0:one\n"]);
+        assert!(poll(&mut tail).is_empty());
+        dir.append("app.log", "o\nthree\n");
+        assert_eq!(poll(&mut tail), vec!["4:two\nthree\n"]);
+    }
+
+    #[test]
+    fn a_big_file_is_read_a_bounded_amount_per_poll() {
+        let dir = TempDir::new("follow-big");
+        let log = dir.0.join("app.log");
+        let line = "x".repeat(999) + "\n";
+        dir.append("app.log", &line.repeat(1500));
+        let mut tail = Tail::new(&log);
+        let mut read = Vec::new();
+        let mut polls = 0;
+        loop {
+            for event in tail.poll().unwrap() {
+                let Event::Lines { offset, bytes } = event else {
+                    panic!("{event}");
+                };
+                assert_eq!(offset, read.len() as u64);
+                assert!(bytes.len() as u64 <= MAX_READ);
+                read.extend_from_slice(&bytes);
+            }
+            polls += 1;
+            if !tail.is_behind() {
+                break;
+            }
+        }
+        assert_eq!(polls, 2);
+        assert_eq!(read, line.repeat(1500).into_bytes());
+    }
+
+    #[test]
+    fn truncation_starts_over_from_the_beginning() {
+        let dir = TempDir::new("follow-truncate");
+        let log = dir.0.join("app.log");
+        dir.append("app.log", "a long first line\n");
+        let mut tail = Tail::new(&log);
+        poll(&mut tail);
+        fs::write(&log, "new\n").unwrap();
+        assert_eq!(poll(&mut tail), vec!["file truncated", "0:new\n"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rotation_finishes_the_old_file_before_the_new_one() {
+        let dir = TempDir::new("follow-rotate");
+        let log = dir.0.join("app.log");
+        dir.append("app.log", "before\n");
+        let mut tail = Tail::new(&log);
+        assert_eq!(poll(&mut tail), vec!["0:before\n"]);
+
+        // The logger still writes to the old file after the rename.
+        let rotated = log.with_extension("1");
+        fs::rename(&log, &rotated).unwrap();
+        let mut old = OpenOptions::new().append(true).open(&rotated).unwrap();
+        old.write_all(b"late\nno newline").unwrap();
+        assert_eq!(poll(&mut tail), vec!["7:late\n", "missing"]);
+
+        dir.append("app.log", "fresh\n");
+        assert_eq!(
+            poll(&mut tail),
+            vec![
+                "12:no newline",
+                "file has been replaced; following new file",
+                "0:fresh\n"
+            ]
+        );
+    }
+}
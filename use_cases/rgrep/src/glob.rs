@@ -0,0 +1,231 @@
+//! Shell-style glob matching, as used by `.gitignore` files.
+//!
+//! | Syntax | Meaning |
+//! |---|---|
+//! | `*` | any run of characters except `/` |
+//! | `?` | any one character except `/` |
+//! | `[abc]`, `[a-z]`, `[!x]` | one character from (or not from) a set |
+//! | `**` | any run of characters, including `/` |
+//! | `**/` | zero or more whole directories |
+//! | `\x` | a literal `x` |
+//!
+//! Matching uses a small dynamic-programming table over (pattern position,
+//! text position), so a pattern full of `*`s can't make it go exponential.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Literal(char),
+    AnyChar,
+    Star,
+    DoubleStar,
+    /// `**/`: the empty string, or anything ending in `/`.
+    DirStar,
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+/// A compiled glob pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    tokens: Vec<Token>,
+}
+
+impl Glob {
+    /// Compile `pattern`. Never fails: an unterminated `[` is just a literal
+    /// `[`, the way shells treat it.
+    pub fn new(pattern: &str) -> Glob {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    if chars.get(i + 2) == Some(&'/') {
+                        tokens.push(Token::DirStar);
+                        i += 3;
+                    } else {
+                        tokens.push(Token::DoubleStar);
+                        i += 2;
+                    }
+                }
+                '*' => {
+                    tokens.push(Token::Star);
+                    i += 1;
+                }
+                '?' => {
+                    tokens.push(Token::AnyChar);
+                    i += 1;
+                }
+                '[' => match parse_class(&chars, i) {
+                    Some((token, next)) => {
+                        tokens.push(token);
+                        i = next;
+                    }
+                    None => {
+                        tokens.push(Token::Literal('['));
+                        i += 1;
+                    }
+                },
+                '\\' if i + 1 < chars.len() => {
+                    tokens.push(Token::Literal(chars[i + 1]));
+                    i += 2;
+                }
+                c => {
+                    tokens.push(Token::Literal(c));
+                    i += 1;
+                }
+            }
+        }
+        Glob { tokens }
+    }
+
+    /// Match against the file name at the end of `path`, or against the
+    /// whole path if the pattern itself contains a `/` — how `--include` and
+    /// `--exclude` decide which files to search.
+    pub fn matches_path(&self, path: &str) -> bool {
+        let has_slash = self
+            .tokens
+            .iter()
+            .any(|t| matches!(t, Token::Literal('/') | Token::DirStar));
+        if has_slash {
+            self.matches(path.strip_prefix("./").unwrap_or(path))
+        } else {
+            self.matches(path.rsplit('/').next().unwrap_or(path))
+        }
+    }
+
+    /// `true` if the whole of `text` matches the pattern.
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        // reachable[j]: the tokens consumed so far can match exactly text[..j].
+        let mut reachable = vec![false; text.len() + 1];
+        reachable[0] = true;
+        for token in &self.tokens {
+            let mut next = vec![false; text.len() + 1];
+            for j in 0..=text.len() {
+                if !reachable[j] {
+                    continue;
+                }
+                match token {
+                    Token::Star => {
+                        next[j] = true;
+                        for k in j..text.len() {
+                            if text[k] == '/' {
+                                break;
+                            }
+                            next[k + 1] = true;
+                        }
+                    }
+                    Token::DoubleStar => next[j..].fill(true),
+                    Token::DirStar => {
+                        next[j] = true;
+                        for k in j..text.len() {
+                            if text[k] == '/' {
+                                next[k + 1] = true;
+                            }
+                        }
+                    }
+                    _ => {
+                        if j < text.len() && single_matches(token, text[j]) {
+                            next[j + 1] = true;
+                        }
+                    }
+                }
+            }
+            reachable = next;
+        }
+        reachable[text.len()]
+    }
+}
+
+fn single_matches(token: &Token, c: char) -> bool {
+    match token {
+        Token::Literal(want) => *want == c,
+        Token::AnyChar => c != '/',
+        Token::Class { ranges, negated } => {
+            c != '/' && ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+        }
+        Token::Star | Token::DoubleStar | Token::DirStar => false,
+    }
+}
+
+/// Parse a `[...]` class starting at `chars[start] == '['`. Returns the token
+/// and the index just past the closing `]`, or `None` if it's unterminated.
+fn parse_class(chars: &[char], start: usize) -> Option<(Token, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let mut c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((Token::Class { ranges, negated }, i + 1));
+        }
+        first = false;
+        if c == '\\' {
+            i += 1;
+            c = *chars.get(i)?;
+        }
+        i += 1;
+        if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|&n| n != ']') {
+            let mut hi = chars[i + 1];
+            i += 2;
+            if hi == '\\' {
+                hi = *chars.get(i)?;
+                i += 1;
+            }
+            ranges.push((c, hi));
+        } else {
+            ranges.push((c, c));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_stays_within_a_segment() {
+        let glob = Glob::new("*.log");
+        assert!(glob.matches("app.log"));
+        assert!(glob.matches(".log"));
+        assert!(!glob.matches("logs/app.log"));
+        assert!(!glob.matches("app.log.1"));
+    }
+
+    #[test]
+    fn double_star_crosses_directories() {
+        let glob = Glob::new("**/target/**");
+        assert!(glob.matches("target/debug"));
+        assert!(glob.matches("a/b/target/x/y"));
+        assert!(!glob.matches("a/targets/x"));
+
+        let middle = Glob::new("a/**/b");
+        assert!(middle.matches("a/b"));
+        assert!(middle.matches("a/x/y/b"));
+        assert!(!middle.matches("a/xb"));
+    }
+
+    #[test]
+    fn classes_question_marks_and_escapes() {
+        assert!(Glob::new("file[0-9].?s").matches("file7.rs"));
+        assert!(!Glob::new("file[!0-9]").matches("file7"));
+        assert!(Glob::new(r"\*.txt").matches("*.txt"));
+        assert!(!Glob::new(r"\*.txt").matches("a.txt"));
+        assert!(Glob::new("[unterminated").matches("[unterminated"));
+    }
+
+    #[test]
+    fn matches_path_uses_the_file_name_unless_the_glob_has_a_slash() {
+        assert!(Glob::new("*.rs").matches_path("./src/cli/mod.rs"));
+        assert!(!Glob::new("*.rs").matches_path("src.rs/notes.md"));
+        assert!(Glob::new("src/*.rs").matches_path("./src/lib.rs"));
+        assert!(!Glob::new("src/*.rs").matches_path("tests/src/lib.rs"));
+    }
+}
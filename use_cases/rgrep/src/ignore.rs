@@ -0,0 +1,151 @@
+//! `.gitignore` / `.ignore` rule parsing and matching.
+//!
+//! Follows the gitignore rules that matter in practice:
+//!
+//! - blank lines and `#` comments are skipped (`\#` is a literal `#`);
+//! - `!pattern` re-includes something an earlier rule ignored;
+//! - a trailing `/` makes a rule match directories only;
+//! - a pattern with a `/` anywhere else is anchored to the directory holding
+//!   the ignore file, otherwise it matches the file name at any depth;
+//! - within one file the *last* matching rule wins.
+
+use std::path::{Path, PathBuf};
+
+use crate::glob::Glob;
+
+/// The ignore files read in each directory, in increasing precedence.
+pub const IGNORE_FILE_NAMES: [&str; 2] = [".gitignore", ".ignore"];
+
+#[derive(Debug, Clone)]
+struct Rule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+/// The rules from one ignore file, plus the directory they're relative to.
+#[derive(Debug, Clone)]
+pub struct IgnoreFile {
+    base: PathBuf,
+    rules: Vec<Rule>,
+}
+
+/// What an ignore file has to say about a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Ignore,
+    /// Explicitly re-included by a `!` rule.
+    Include,
+}
+
+impl IgnoreFile {
+    /// Parse the text of an ignore file living in directory `base`.
+    pub fn parse(base: &Path, contents: &str) -> IgnoreFile {
+        let rules = contents.lines().filter_map(parse_rule).collect();
+        IgnoreFile {
+            base: base.to_path_buf(),
+            rules,
+        }
+    }
+
+    /// The verdict of the last rule matching `path`, or `None` if no rule
+    /// mentions it (or `path` isn't under this file's directory).
+    pub fn verdict(&self, path: &Path, is_dir: bool) -> Option<Verdict> {
+        let relative = path.strip_prefix(&self.base).ok()?;
+        let relative = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let name = relative.rsplit('/').next().unwrap_or(&relative);
+
+        self.rules.iter().rev().find_map(|rule| {
+            if rule.dir_only && !is_dir {
+                return None;
+            }
+            let subject = if rule.anchored {
+                relative.as_str()
+            } else {
+                name
+            };
+            rule.glob.matches(subject).then_some(if rule.negated {
+                Verdict::Include
+            } else {
+                Verdict::Ignore
+            })
+        })
+    }
+}
+
+fn parse_rule(line: &str) -> Option<Rule> {
+    let mut line = line.trim_end_matches('\r');
+    // Trailing spaces are ignored unless escaped.
+    if !line.ends_with("\\ ") {
+        line = line.trim_end_matches(' ');
+    }
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, mut line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (
+            false,
+            line.strip_prefix('\\')
+                .filter(|r| r.starts_with(['#', '!']))
+                .unwrap_or(line),
+        ),
+    };
+    let dir_only = line.ends_with('/');
+    line = line.trim_end_matches('/');
+    let anchored = line.contains('/');
+    line = line.trim_start_matches('/');
+    if line.is_empty() {
+        return None;
+    }
+    Some(Rule {
+        glob: Glob::new(line),
+        negated,
+        dir_only,
+        anchored,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verdict(contents: &str, path: &str, is_dir: bool) -> Option<Verdict> {
+        IgnoreFile::parse(Path::new("/repo"), contents)
+            .verdict(&Path::new("/repo").join(path), is_dir)
+    }
+
+    #[test]
+    fn unanchored_patterns_match_names_at_any_depth() {
+        let rules = "# build output\n*.log\n";
+        assert_eq!(verdict(rules, "app.log", false), Some(Verdict::Ignore));
+        assert_eq!(
+            verdict(rules, "deep/nested/app.log", false),
+            Some(Verdict::Ignore)
+        );
+        assert_eq!(verdict(rules, "app.txt", false), None);
+    }
+
+    #[test]
+    fn anchored_and_directory_only_patterns() {
+        let rules = "/target\nbuild/\ndocs/*.tmp\n";
+        assert_eq!(verdict(rules, "target", true), Some(Verdict::Ignore));
+        assert_eq!(verdict(rules, "sub/target", true), None);
+        assert_eq!(verdict(rules, "build", false), None);
+        assert_eq!(verdict(rules, "x/build", true), Some(Verdict::Ignore));
+        assert_eq!(verdict(rules, "docs/a.tmp", false), Some(Verdict::Ignore));
+        assert_eq!(verdict(rules, "other/docs/a.tmp", false), None);
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let rules = "*.log\n!keep.log\n";
+        assert_eq!(verdict(rules, "keep.log", false), Some(Verdict::Include));
+        assert_eq!(verdict(rules, "drop.log", false), Some(Verdict::Ignore));
+    }
+}
@@ -0,0 +1,762 @@
+//! A trigram index, so repeated searches over a big directory only read the
+//! files that can possibly match.
+//!
+//! `rgrep index build DIR` records, for every file under `DIR`, which
+//! trigrams (runs of three bytes, ASCII-lowercased) occur in it, and writes
+//! that to `DIR/.rgrep-index`. A search with `--index` turns its pattern
+//! into the trigrams every match must contain (`Query`) and skips the files
+//! missing any of them. This only ever narrows the set of files: the ones
+//! that remain are searched as usual, so results are exactly those of a
+//! search without the index.
+//!
+//! The index is a cache, never the source of truth. A file whose size or
+//! modification time differs from what was indexed, or that isn't in the
+//! index at all, is simply searched. Rebuilding re-reads only those files
+//! and carries the rest over from the old index.
+//!
+//! On disk, after a magic number: the file table (relative path, mtime,
+//! size, kind), then a directory of trigrams sorted by value, each pointing
+//! at its posting list — the ids of the files containing it, as
+//! delta-encoded varints. A search reads the table and directory, then
+//! seeks to just the posting lists its trigrams need.
+//!
+//! Concepts: `Seek` + `SeekFrom`, fixed-width `to_le_bytes` fields, LEB128
+//! varints, bitsets, intersecting sorted lists, `BTreeMap`.
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::decompress::{self, Format};
+use crate::error::RgrepError;
+use crate::parallel;
+use crate::regex::{self, RegexOptions};
+use crate::walk::{self, WalkOptions};
+
+/// Name of the index file inside the indexed directory. It's hidden, so
+/// walks (including the one that builds it) pass it by.
+pub const INDEX_FILE: &str = ".rgrep-index";
+
+const MAGIC: &[u8; 8] = b"RGREPIX1";
+
+/// Every possible trigram, for the per-file "seen" bitset.
+const TRIGRAMS: usize = 1 << 24;
+
+/// Letters whose Unicode case folding reaches outside ASCII (`k` ~ KELVIN
+/// SIGN, `s` ~ LONG S), so an ASCII-lowercased trigram containing them
+/// can't stand in for every spelling `-i` accepts.
+const NON_ASCII_FOLDS: &[u8] = b"ks";
+
+/// How a file's trigrams were taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// From its bytes as they are on disk.
+    Plain = 0,
+    /// From its decompressed contents (gzip, tar); only valid for `-z`.
+    Decoded = 1,
+    /// Not indexed (e.g. a compression format `rgrep` can't decode); it
+    /// is always searched.
+    Unindexed = 2,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileEntry {
+    /// Relative to the indexed directory, with `/` separators.
+    path: String,
+    mtime: (u64, u32),
+    size: u64,
+    kind: Kind,
+}
+
+/// What `build` did, for its one-line report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildSummary {
+    /// Files in the new index.
+    pub files: usize,
+    /// Of those, how many had to be read (new or changed since last time).
+    pub read: usize,
+    /// Files in the old index that are gone now.
+    pub removed: usize,
+    /// Distinct trigrams across all files.
+    pub trigrams: usize,
+}
+
+/// Build or update the index for `dir`, reading files on up to `threads`
+/// threads.
+pub fn build(dir: &Path, threads: usize) -> Result<BuildSummary, RgrepError> {
+    let index_path = dir.join(INDEX_FILE);
+    let index_error = |source| RgrepError::Io {
+        path: index_path.display().to_string(),
+        source,
+    };
+    let (old_files, old_postings) = match Index::open(dir).map_err(index_error)? {
+        Some(mut index) => {
+            let postings = index.load_postings().map_err(index_error)?;
+            (index.files, postings)
+        }
+        None => (Vec::new(), BTreeMap::new()),
+    };
+    let old_ids: HashMap<&str, u32> = old_files
+        .iter()
+        .enumerate()
+        .map(|(id, entry)| (entry.path.as_str(), id as u32))
+        .collect();
+
+    // Gzip files and tar archives are indexed by their contents, for `-z`.
+    let options = WalkOptions {
+        search_zip: true,
+        ..WalkOptions::default()
+    };
+    let found = walk::walk(dir, &options);
+    if let Some(err) = found.errors.into_iter().next() {
+        return Err(err);
+    }
+
+    let mut files = Vec::new();
+    // Old id -> new id, for the files carried over unchanged.
+    let mut carried = HashMap::new();
+    let mut still_there = 0;
+    let mut to_read = Vec::new();
+    for path in &found.files {
+        let entry = stat(dir, path).map_err(|source| RgrepError::Io {
+            path: path.display().to_string(),
+            source,
+        })?;
+        let new_id = files.len() as u32;
+        if let Some(&old_id) = old_ids.get(entry.path.as_str()) {
+            still_there += 1;
+            let before = &old_files[old_id as usize];
+            if before.mtime == entry.mtime && before.size == entry.size {
+                carried.insert(old_id, new_id);
+                files.push(before.clone());
+                continue;
+            }
+        }
+        to_read.push((new_id, path.clone()));
+        files.push(entry);
+    }
+
+    let mut postings: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+    for (trigram, ids) in old_postings {
+        let ids: Vec<u32> = ids
+            .iter()
+            .filter_map(|id| carried.get(id).copied())
+            .collect();
+        if !ids.is_empty() {
+            postings.insert(trigram, ids);
+        }
+    }
+    parallel::for_each_ordered(
+        &to_read,
+        threads,
+        |(id, path)| (*id, path.clone(), trigrams_of(path)),
+        |(id, path, result)| {
+            let (kind, trigrams) = result.map_err(|source| RgrepError::Io {
+                path: path.display().to_string(),
+                source,
+            })?;
+            files[id as usize].kind = kind;
+            for trigram in trigrams {
+                postings.entry(trigram).or_default().push(id);
+            }
+            Ok::<(), RgrepError>(())
+        },
+    )?;
+    // Carried-over ids were all pushed first; new ones can fall between.
+    for ids in postings.values_mut() {
+        ids.sort_unstable();
+    }
+
+    write_index(&index_path, &files, &postings).map_err(|source| RgrepError::Write {
+        path: index_path.display().to_string(),
+        source,
+    })?;
+    Ok(BuildSummary {
+        files: files.len(),
+        read: to_read.len(),
+        removed: old_files.len() - still_there,
+        trigrams: postings.len(),
+    })
+}
+
+/// The trigrams a search needs a candidate file to contain.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    /// One set of trigrams per pattern, each sorted and deduplicated: a
+    /// file is a candidate if it has every trigram of any one set. Empty
+    /// means "can't tell": every file is a candidate.
+    alternatives: Vec<Vec<u32>>,
+}
+
+impl Query {
+    /// The query for a search pattern. Only plain strings give trigrams
+    /// (a regex without metacharacters counts as one); `-i` drops those
+    /// whose case folding isn't pure ASCII.
+    pub fn new(source: &str, is_regex: bool, options: &RegexOptions) -> Query {
+        // U+FFFD in the pattern can match any invalid byte sequence.
+        if (is_regex && regex::escape(source) != source) || source.contains('\u{FFFD}') {
+            return Query::default();
+        }
+        let bytes = source.as_bytes();
+        let mut trigrams: Vec<u32> = bytes
+            .windows(3)
+            .filter(|w| {
+                !options.case_insensitive
+                    || w.iter()
+                        .all(|b| b.is_ascii() && !NON_ASCII_FOLDS.contains(&b.to_ascii_lowercase()))
+            })
+            .map(trigram)
+            .collect();
+        if trigrams.is_empty() {
+            return Query::default();
+        }
+        trigrams.sort_unstable();
+        trigrams.dedup();
+        Query {
+            alternatives: vec![trigrams],
+        }
+    }
+
+    /// The query for several patterns (`-e`, `-f`), any of which may match.
+    /// One pattern that gives no trigrams rules nothing out.
+    pub fn any(sources: &[String], is_regex: bool, options: &RegexOptions) -> Query {
+        let mut alternatives = Vec::with_capacity(sources.len());
+        for source in sources {
+            match Query::new(source, is_regex, options).alternatives.pop() {
+                Some(trigrams) => alternatives.push(trigrams),
+                None => return Query::default(),
+            }
+        }
+        alternatives.sort();
+        alternatives.dedup();
+        Query { alternatives }
+    }
+
+    /// A query that rules nothing out, for searches where a file without
+    /// the pattern still produces output (`-v`, `-c`, `-L`).
+    pub fn everything() -> Query {
+        Query::default()
+    }
+}
+
+/// An index opened for searching: the file table and trigram directory in
+/// memory, posting lists read on demand.
+pub struct Index {
+    dir: PathBuf,
+    reader: BufReader<File>,
+    files: Vec<FileEntry>,
+    /// `(trigram, offset, length)`, sorted by trigram; offsets are from
+    /// `postings_start`.
+    directory: Vec<(u32, u64, u32)>,
+    postings_start: u64,
+}
+
+impl Index {
+    /// Open the index in `dir`, or `Ok(None)` if it has none.
+    pub fn open(dir: &Path) -> io::Result<Option<Index>> {
+        let file = match File::open(dir.join(INDEX_FILE)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(|_| corrupt())?;
+        if &magic != MAGIC {
+            return Err(corrupt());
+        }
+
+        let count = read_u32(&mut reader)?;
+        let mut files = Vec::with_capacity(count.min(1 << 20) as usize);
+        for _ in 0..count {
+            let len = read_u32(&mut reader)? as usize;
+            let mut path = vec![0u8; len];
+            reader.read_exact(&mut path)?;
+            let path = String::from_utf8(path).map_err(|_| corrupt())?;
+            let mtime = (read_u64(&mut reader)?, read_u32(&mut reader)?);
+            let size = read_u64(&mut reader)?;
+            let mut kind = [0u8];
+            reader.read_exact(&mut kind)?;
+            let kind = match kind[0] {
+                0 => Kind::Plain,
+                1 => Kind::Decoded,
+                2 => Kind::Unindexed,
+                _ => return Err(corrupt()),
+            };
+            files.push(FileEntry {
+                path,
+                mtime,
+                size,
+                kind,
+            });
+        }
+
+        let count = read_u32(&mut reader)?;
+        let mut directory = Vec::with_capacity(count.min(TRIGRAMS as u32) as usize);
+        for _ in 0..count {
+            directory.push((
+                read_u32(&mut reader)?,
+                read_u64(&mut reader)?,
+                read_u32(&mut reader)?,
+            ));
+        }
+        let postings_start = reader.stream_position()?;
+        Ok(Some(Index {
+            dir: dir.to_path_buf(),
+            reader,
+            files,
+            directory,
+            postings_start,
+        }))
+    }
+
+    /// The files among `paths` (found by walking this index's directory)
+    /// that may contain a match for `query`. Files that changed since the
+    /// index was built, or aren't in it, are always kept.
+    pub fn filter(
+        &mut self,
+        paths: Vec<PathBuf>,
+        query: &Query,
+        search_zip: bool,
+    ) -> io::Result<Vec<PathBuf>> {
+        if query.alternatives.is_empty() {
+            return Ok(paths);
+        }
+        // Files containing every trigram of some pattern, intersecting the
+        // rarest first. Patterns often share trigrams, so each posting list
+        // is read once.
+        let mut postings: HashMap<u32, Vec<u32>> = HashMap::new();
+        for &trigram in query.alternatives.iter().flatten() {
+            if let Entry::Vacant(slot) = postings.entry(trigram) {
+                slot.insert(self.postings(trigram)?);
+            }
+        }
+        let mut matching = Vec::new();
+        for trigrams in &query.alternatives {
+            let mut lists: Vec<&Vec<u32>> = trigrams.iter().map(|t| &postings[t]).collect();
+            lists.sort_by_key(|list| list.len());
+            let mut found = lists[0].clone();
+            for list in &lists[1..] {
+                found = intersect(&found, list);
+            }
+            matching = union(&matching, &found);
+        }
+
+        let by_path: HashMap<&str, usize> = self
+            .files
+            .iter()
+            .enumerate()
+            .map(|(id, entry)| (entry.path.as_str(), id))
+            .collect();
+        let mut kept = Vec::new();
+        for path in paths {
+            let keep = match stat(&self.dir, &path) {
+                Ok(now) => match by_path.get(now.path.as_str()) {
+                    Some(&id) => {
+                        let entry = &self.files[id];
+                        let trusted = entry.mtime == now.mtime
+                            && entry.size == now.size
+                            && (entry.kind == Kind::Plain
+                                || (entry.kind == Kind::Decoded && search_zip));
+                        !trusted || matching.binary_search(&(id as u32)).is_ok()
+                    }
+                    None => true,
+                },
+                // Let the search itself report it.
+                Err(_) => true,
+            };
+            if keep {
+                kept.push(path);
+            }
+        }
+        Ok(kept)
+    }
+
+    /// The ids of the files containing `trigram`.
+    fn postings(&mut self, trigram: u32) -> io::Result<Vec<u32>> {
+        let Ok(i) = self.directory.binary_search_by_key(&trigram, |&(t, ..)| t) else {
+            return Ok(Vec::new());
+        };
+        let (_, offset, len) = self.directory[i];
+        self.reader
+            .seek(SeekFrom::Start(self.postings_start + offset))?;
+        let mut bytes = vec![0u8; len as usize];
+        self.reader.read_exact(&mut bytes)?;
+        decode_ids(&bytes)
+    }
+
+    /// Every posting list, for carrying unchanged files over in `build`.
+    fn load_postings(&mut self) -> io::Result<BTreeMap<u32, Vec<u32>>> {
+        let trigrams: Vec<u32> = self.directory.iter().map(|&(t, ..)| t).collect();
+        let mut postings = BTreeMap::new();
+        for trigram in trigrams {
+            postings.insert(trigram, self.postings(trigram)?);
+        }
+        Ok(postings)
+    }
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "not an rgrep index (or a damaged one)",
+    )
+}
+
+/// A file's table entry, minus the kind (filled in once it's read).
+fn stat(dir: &Path, path: &Path) -> io::Result<FileEntry> {
+    let metadata = fs::metadata(path)?;
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_or((0, 0), |d| (d.as_secs(), d.subsec_nanos()));
+    let relative = path.strip_prefix(dir).unwrap_or(path);
+    Ok(FileEntry {
+        path: relative.to_string_lossy().replace('\\', "/"),
+        mtime,
+        size: metadata.len(),
+        kind: Kind::Unindexed,
+    })
+}
+
+fn trigram(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |acc, b| acc << 8 | u32::from(b.to_ascii_lowercase()))
+}
+
+/// Every trigram in the (decompressed) contents of `path`, skipping any
+/// that span a line break — a match never does.
+fn trigrams_of(path: &Path) -> io::Result<(Kind, Vec<u32>)> {
+    let (format, mut input) = match decompress::open(File::open(path)?) {
+        Ok(opened) => opened,
+        Err(err) => return unindexed(err),
+    };
+    let kind = if format == Format::Plain {
+        Kind::Plain
+    } else {
+        Kind::Decoded
+    };
+    let mut seen = vec![0u64; TRIGRAMS / 64];
+    let mut found = Vec::new();
+    let mut window = 0u32;
+    // How many bytes since the last line break are in `window`.
+    let mut run = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return unindexed(err),
+        };
+        for &b in &buf[..n] {
+            if b == b'\n' {
+                run = 0;
+                continue;
+            }
+            window = (window << 8 | u32::from(b.to_ascii_lowercase())) & 0xff_ffff;
+            run += 1;
+            if run >= 3 {
+                let (word, bit) = (window as usize / 64, window % 64);
+                if seen[word] & 1 << bit == 0 {
+                    seen[word] |= 1 << bit;
+                    found.push(window);
+                }
+            }
+        }
+    }
+    Ok((kind, found))
+}
+
+/// A format `rgrep` can't decode, or damaged compressed data, leaves the
+/// file out of the index — it's searched every time instead. Other I/O
+/// errors are real errors.
+fn unindexed(err: io::Error) -> io::Result<(Kind, Vec<u32>)> {
+    decompress::Error::from_io(err).map(|_| (Kind::Unindexed, Vec::new()))
+}
+
+/// Write the whole index to a temporary file, then rename it into place.
+fn write_index(
+    path: &Path,
+    files: &[FileEntry],
+    postings: &BTreeMap<u32, Vec<u32>>,
+) -> io::Result<()> {
+    let temp = path.with_extension(format!("tmp-{}", std::process::id()));
+    let result = (|| {
+        let mut out = BufWriter::new(File::create(&temp)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&(files.len() as u32).to_le_bytes())?;
+        for entry in files {
+            out.write_all(&(entry.path.len() as u32).to_le_bytes())?;
+            out.write_all(entry.path.as_bytes())?;
+            out.write_all(&entry.mtime.0.to_le_bytes())?;
+            out.write_all(&entry.mtime.1.to_le_bytes())?;
+            out.write_all(&entry.size.to_le_bytes())?;
+            out.write_all(&[entry.kind as u8])?;
+        }
+
+        let encoded: Vec<(u32, Vec<u8>)> = postings
+            .iter()
+            .map(|(&trigram, ids)| (trigram, encode_ids(ids)))
+            .collect();
+        out.write_all(&(encoded.len() as u32).to_le_bytes())?;
+        let mut offset = 0u64;
+        for (trigram, bytes) in &encoded {
+            out.write_all(&trigram.to_le_bytes())?;
+            out.write_all(&offset.to_le_bytes())?;
+            out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+            offset += bytes.len() as u64;
+        }
+        for (_, bytes) in &encoded {
+            out.write_all(bytes)?;
+        }
+        out.into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Sorted ids as LEB128 varints of the gaps between them.
+fn encode_ids(ids: &[u32]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut previous = 0;
+    for &id in ids {
+        let mut gap = id - previous;
+        previous = id;
+        while gap >= 0x80 {
+            out.push(gap as u8 | 0x80);
+            gap >>= 7;
+        }
+        out.push(gap as u8);
+    }
+    out
+}
+
+fn decode_ids(bytes: &[u8]) -> io::Result<Vec<u32>> {
+    let mut ids = Vec::new();
+    let mut previous = 0u32;
+    let (mut gap, mut shift) = (0u32, 0);
+    for &b in bytes {
+        if shift > 28 {
+            return Err(corrupt());
+        }
+        gap |= u32::from(b & 0x7f) << shift;
+        shift += 7;
+        if b & 0x80 == 0 {
+            previous = previous.checked_add(gap).ok_or_else(corrupt)?;
+            ids.push(previous);
+            (gap, shift) = (0, 0);
+        }
+    }
+    Ok(ids)
+}
+
+/// Elements in both sorted lists.
+fn intersect(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                out.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Every id in either of two sorted lists, once.
+fn union(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => {
+                out.push(a[i]);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                out.push(b[j]);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                out.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out.extend_from_slice(&a[i..]);
+    out.extend_from_slice(&b[j..]);
+    out
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::unicode;
+
+    /// The names of the files under `dir` its index keeps for `pattern`.
+    fn candidates(dir: &TempDir, pattern: &str, options: &RegexOptions) -> Vec<String> {
+        let mut index = Index::open(&dir.0).unwrap().unwrap();
+        let files = walk::walk(&dir.0, &WalkOptions::default()).files;
+        let query = Query::new(pattern, false, options);
+        let kept = index.filter(files, &query, false).unwrap();
+        kept.iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn queries_take_trigrams_only_from_plain_strings() {
+        let plain = RegexOptions::default();
+        assert_eq!(Query::new("Error", false, &plain).alternatives[0].len(), 3);
+        assert_eq!(Query::new("ab", false, &plain), Query::everything());
+        assert_eq!(Query::new("err(or)?", true, &plain), Query::everything());
+        assert_eq!(Query::new("error", true, &plain).alternatives[0].len(), 3);
+
+        // Under -i, "task" could be spelled with a LONG S or KELVIN SIGN.
+        let folded = RegexOptions {
+            case_insensitive: true,
+            ..RegexOptions::default()
+        };
+        assert_eq!(
+            Query::new("ERROR", false, &folded),
+            Query::new("error", false, &plain)
+        );
+        assert_eq!(Query::new("task", false, &folded), Query::everything());
+    }
+
+    #[test]
+    fn non_ascii_folds_lists_every_ascii_letter_with_a_non_ascii_variant() {
+        for letter in b'a'..=b'z' {
+            let non_ascii = unicode::case_variants(char::from(letter)).any(|v| !v.is_ascii());
+            assert_eq!(
+                non_ascii,
+                NON_ASCII_FOLDS.contains(&letter),
+                "{}",
+                char::from(letter)
+            );
+        }
+    }
+
+    #[test]
+    fn filter_keeps_only_files_with_every_trigram() {
+        let dir = TempDir::new("index-filter");
+        dir.file("a.log", "connection RESET by peer\n");
+        dir.file("b.log", "reset\nconnection\n");
+        dir.file("sub/c.log", "nothing here\n");
+        build(&dir.0, 2).unwrap();
+
+        assert_eq!(
+            candidates(&dir, "connection reset", &RegexOptions::default()),
+            vec!["a.log"]
+        );
+        assert_eq!(
+            candidates(&dir, "reset", &RegexOptions::default()),
+            vec!["a.log", "b.log"]
+        );
+        assert!(candidates(&dir, "absent", &RegexOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn several_patterns_keep_files_with_any_of_them() {
+        let dir = TempDir::new("index-any");
+        dir.file("a.log", "user ID-0417 logged in\n");
+        dir.file("b.log", "user ID-0090 logged out\n");
+        dir.file("c.log", "user ID-7777 idle\n");
+        build(&dir.0, 2).unwrap();
+
+        let mut index = Index::open(&dir.0).unwrap().unwrap();
+        let files = walk::walk(&dir.0, &WalkOptions::default()).files;
+        let ids = ["ID-0417".to_string(), "ID-0090".to_string()];
+        let query = Query::any(&ids, false, &RegexOptions::default());
+        let mut kept: Vec<_> = index.filter(files.clone(), &query, false).unwrap();
+        kept.sort();
+        assert_eq!(kept, vec![dir.0.join("a.log"), dir.0.join("b.log")]);
+
+        // One pattern too short for trigrams could match anywhere.
+        let query = Query::any(
+            &[ids[0].clone(), "ID".to_string()],
+            false,
+            &RegexOptions::default(),
+        );
+        assert_eq!(query, Query::everything());
+    }
+
+    #[test]
+    fn rebuild_reads_only_new_or_changed_files_and_stale_entries_are_searched() {
+        let dir = TempDir::new("index-update");
+        dir.file("a.log", "alpha\n");
+        dir.file("b.log", "beta\n");
+        dir.file("c.log", "gamma\n");
+        let first = build(&dir.0, 1).unwrap();
+        assert_eq!((first.files, first.read, first.removed), (3, 3, 0));
+
+        // Changed or new since indexing: can't be ruled out until the next
+        // build.
+        dir.file("a.log", "beta beta\n");
+        fs::remove_file(dir.0.join("c.log")).unwrap();
+        dir.file("d.log", "delta\n");
+        assert_eq!(
+            candidates(&dir, "beta", &RegexOptions::default()),
+            vec!["a.log", "b.log", "d.log"]
+        );
+        assert_eq!(
+            candidates(&dir, "delta", &RegexOptions::default()),
+            vec!["a.log", "d.log"]
+        );
+
+        let second = build(&dir.0, 1).unwrap();
+        assert_eq!((second.files, second.read, second.removed), (3, 2, 1));
+        assert_eq!(
+            candidates(&dir, "alpha", &RegexOptions::default()),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            candidates(&dir, "delta", &RegexOptions::default()),
+            vec!["d.log"]
+        );
+        assert_eq!(
+            candidates(&dir, "beta", &RegexOptions::default()),
+            vec!["a.log", "b.log"]
+        );
+    }
+
+    #[test]
+    fn ids_round_trip_and_damaged_indexes_are_rejected() {
+        let ids = [0, 1, 5, 200, 70_000, u32::MAX];
+        assert_eq!(decode_ids(&encode_ids(&ids)).unwrap(), ids);
+
+        let dir = TempDir::new("index-corrupt");
+        dir.file(INDEX_FILE, "not an index");
+        let err = Index::open(&dir.0).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
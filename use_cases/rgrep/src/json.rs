@@ -0,0 +1,137 @@
+//! JSON Lines records for `--json` output.
+//!
+//! Every record is one JSON object on its own line, with a `type` and a
+//! `data` field, loosely following ripgrep's format:
+//!
+//! ```text
+//! {"type":"begin","data":{"path":"app.log"}}
+//! {"type":"match","data":{"path":"app.log","line_number":3,"absolute_offset":120,
+//!   "lines":"... ERROR ...","submatches":[{"match":"ERROR","start":21,"end":26,"pattern":0}]}}
+//! {"type":"context","data":{"path":"app.log","line_number":4,"absolute_offset":190,"lines":"..."}}
+//! {"type":"end","data":{"path":"app.log","binary":false,"stats":{...}}}
+//! {"type":"summary","data":{"elapsed_secs":0.002,"stats":{...}}}
+//! ```
+//!
+//! `begin`/`end` only appear for files with at least one match; `summary`
+//! comes once, last. Offsets are in bytes: `absolute_offset` is where the line
+//! starts in the file, `start`/`end` are relative to the line. `pattern`
+//! says which of several `-e`/`-f` patterns made the match, counting from 0
+//! in the order they were given.
+//!
+//! There's no `serde` here — the records are small and fixed, so they're
+//! built by hand with `escape` doing the one tricky part.
+
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::printer::Stats;
+use crate::search::LineMatch;
+
+/// `s` as a quoted JSON string literal.
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+pub fn begin(path: &str) -> String {
+    format!(r#"{{"type":"begin","data":{{"path":{}}}}}"#, escape(path))
+}
+
+pub fn matched(path: &str, m: &LineMatch<'_>) -> String {
+    let submatches: Vec<String> = m
+        .spans
+        .iter()
+        .zip(&m.patterns)
+        .map(|(&(start, end), pattern)| {
+            format!(
+                r#"{{"match":{},"start":{start},"end":{end},"pattern":{pattern}}}"#,
+                escape(&m.line[start..end])
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"type":"match","data":{{"path":{},"line_number":{},"absolute_offset":{},"lines":{},"submatches":[{}]}}}}"#,
+        escape(path),
+        m.line_number,
+        m.byte_offset,
+        escape(m.line),
+        submatches.join(",")
+    )
+}
+
+pub fn context(path: &str, line_number: usize, byte_offset: usize, line: &str) -> String {
+    format!(
+        r#"{{"type":"context","data":{{"path":{},"line_number":{line_number},"absolute_offset":{byte_offset},"lines":{}}}}}"#,
+        escape(path),
+        escape(line)
+    )
+}
+
+pub fn end(path: &str, binary: bool, stats: &Stats) -> String {
+    format!(
+        r#"{{"type":"end","data":{{"path":{},"binary":{binary},"stats":{}}}}}"#,
+        escape(path),
+        stats_object(stats)
+    )
+}
+
+pub fn summary(elapsed: Duration, stats: &Stats) -> String {
+    format!(
+        r#"{{"type":"summary","data":{{"elapsed_secs":{},"stats":{}}}}}"#,
+        elapsed.as_secs_f64(),
+        stats_object(stats)
+    )
+}
+
+fn stats_object(stats: &Stats) -> String {
+    format!(
+        r#"{{"searches":{},"searches_with_match":{},"matched_lines":{},"matches":{},"bytes_searched":{}}}"#,
+        stats.searches,
+        stats.searches_with_match,
+        stats.matched_lines,
+        stats.matches,
+        stats.bytes_searched
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_quotes_backslashes_and_control_characters() {
+        assert_eq!(escape(r#"say "hi" \ bye"#), r#""say \"hi\" \\ bye""#);
+        assert_eq!(escape("tab\there\u{1}"), r#""tab\there\u0001""#);
+        assert_eq!(escape("नमस्ते"), "\"नमस्ते\"");
+    }
+
+    #[test]
+    fn match_record_has_offsets_and_submatches() {
+        let m = LineMatch {
+            line_number: 3,
+            byte_offset: 120,
+            line: "a \"ERROR\" b",
+            spans: vec![(3, 8)],
+            patterns: vec![2],
+        };
+        assert_eq!(
+            matched("app.log", &m),
+            r#"{"type":"match","data":{"path":"app.log","line_number":3,"absolute_offset":120,"lines":"a \"ERROR\" b","submatches":[{"match":"ERROR","start":3,"end":8,"pattern":2}]}}"#
+        );
+    }
+}
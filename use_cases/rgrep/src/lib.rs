@@ -1,29 +1,765 @@
+pub mod aho_corasick;
 pub mod cli;
+pub mod decompress;
 pub mod error;
+pub mod follow;
+pub mod glob;
+pub mod ignore;
+pub mod index;
+pub mod json;
+pub mod literal;
+pub mod parallel;
+pub mod printer;
+pub mod reader;
+pub mod regex;
+pub mod replace;
 pub mod search;
+pub mod tar;
+pub mod unicode;
+pub mod walk;
+
+#[cfg(test)]
+mod test_util;
+
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Write};
+use std::mem;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use cli::Config;
+use decompress::Format;
 use error::RgrepError;
+use index::Query;
+use literal::Finder;
+use parallel::Spill;
+use printer::{ColorChoice, OutputMode, OutputOptions, Printer, Stats};
+use reader::LineBuffer;
+use replace::Template;
+use search::{LineMatch, Pattern};
+
+/// How often `--follow` checks its files for new lines.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How standard input (a `-` path, or no path at all) is named in output.
+const STDIN_NAME: &str = "(standard input)";
 
 /// Run one full `rgrep` invocation: read every file in `config.paths`,
-/// search it for `config.pattern`, and print matching lines.
-///
-/// Concepts: File I/O (`std::fs::read_to_string`), `Result<T, E>` and the
-/// `?` operator for propagating errors up to `main`, `match`, and — once
-/// you're searching more than one file — deciding whether to prefix each
-/// printed line with its filename (real `grep` does this automatically
-/// when given multiple files; try matching that behavior).
-pub fn run(config: Config) -> Result<(), RgrepError> {
-    // TODO, roughly:
-    //   for path in &config.paths {
-    //       - read the file into a String
-    //         (std::fs::read_to_string returns Result<String, io::Error> —
-    //          turn a failure into your RgrepError::Io variant, with `path`
-    //          attached, instead of just using `?` directly)
-    //       - call search::search(&config.pattern, &contents)
-    //       - print each matching line (println!)
-    //         - if config.paths.len() > 1, prefix each line with
-    //           "path: " the way real grep does
-    //   }
-    todo!("read each path in config.paths, search it, print matches")
+/// search it for `config.pattern`, and print matching lines. A path of `-`
+/// means standard input.
+///
+/// With `config.recursive`, directories in `config.paths` are expanded into
+/// the files under them (see `walk::walk`). Like real `grep`, each line is
+/// prefixed with `path:` when more than one file is, or may be, searched.
+///
+/// Files are searched on `config.threads` workers (see `parallel`), but
+/// output always comes out grouped per file, in the order the files were
+/// listed. `printer::Printer` handles `-n`, context lines and highlighting,
+/// or JSON Lines records with `--json`, which also end with a summary.
+///
+/// A file or directory that can't be read doesn't stop the search: it's
+/// reported on stderr (unless `-s`) and counted in `Outcome::failed`, and
+/// the remaining paths are still searched. `--fail-fast` turns that back
+/// into an `Err` at the first such file. Errors writing to stdout always
+/// stop the run.
+///
+/// Concepts: streaming File I/O (see `reader`), `Result<T, E>` and the `?`
+/// operator for propagating errors up to `main`, `match`.
+pub fn run(config: Config) -> Result<Outcome, RgrepError> {
+    let started = Instant::now();
+    let mut failed = 0;
+    let files = collect_files(&config, &mut failed)?;
+    if let Some(template) = &config.replace {
+        return replace_files(&config, template, &files, failed);
+    }
+    let show_path = files.len() > 1 || config.recursive;
+    let stdout = io::stdout();
+    let mut output = config.output.clone();
+    output.color = !output.json
+        && match config.color {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => stdout.is_terminal(),
+        };
+    if config.follow {
+        return follow_files(&config, &output, &files, show_path, failed);
+    }
+    let mut stdout = stdout.lock();
+    let mut totals = Stats::default();
+
+    let quiet = output.mode == OutputMode::Quiet;
+    if config.threads <= 1 || quiet {
+        // No need to buffer anything: write straight through. `-q` goes
+        // this way too, so it can stop at the first file that matches.
+        for path in &files {
+            if quiet && totals.searches_with_match > 0 {
+                break;
+            }
+            let separate_first = totals.searches_with_match > 0;
+            match search_file(
+                &config,
+                &output,
+                path,
+                show_path,
+                separate_first,
+                &mut stdout,
+            ) {
+                Ok(stats) => totals.add(&stats),
+                Err(err) => skip_failure(&config, err, &mut failed)?,
+            }
+        }
+    } else {
+        parallel::for_each_ordered(
+            &files,
+            config.threads,
+            |path| {
+                // Held back until every earlier file has been printed.
+                let mut out = Spill::default();
+                let result = search_file(&config, &output, path, show_path, false, &mut out);
+                (out, result)
+            },
+            |(out, result)| {
+                let stats = match result {
+                    Ok(stats) => stats,
+                    Err(err) => {
+                        // Whatever was printed before the failure still
+                        // goes out, as it would searching sequentially.
+                        out.copy_to(&mut stdout).map_err(RgrepError::Output)?;
+                        return skip_failure(&config, err, &mut failed);
+                    }
+                };
+                if stats.searches_with_match > 0
+                    && totals.searches_with_match > 0
+                    && output.separates_groups()
+                {
+                    stdout
+                        .write_all(output.group_separator().as_bytes())
+                        .map_err(RgrepError::Output)?;
+                }
+                totals.add(&stats);
+                out.copy_to(&mut stdout).map_err(RgrepError::Output)
+            },
+        )?;
+    }
+
+    if output.json {
+        writeln!(stdout, "{}", json::summary(started.elapsed(), &totals))
+            .map_err(RgrepError::Output)?;
+    }
+    let selected = if output.mode == OutputMode::FilesWithoutMatch {
+        totals.searches_with_match < totals.searches
+    } else {
+        totals.searches_with_match > 0
+    };
+    Ok(Outcome { selected, failed })
+}
+
+/// How a `run` that got to the end went; `main` turns it into grep's exit
+/// status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    /// Whether anything was selected — a matching line, or with `-L` a
+    /// file without one.
+    pub selected: bool,
+    /// How many files or directories couldn't be searched and were skipped.
+    pub failed: usize,
+}
+
+/// `--follow`: search each file to its end, then keep checking for lines
+/// appended to it (see `follow`) until every file has reached its `-m`
+/// limit — without `-m`, until the process is interrupted. Standard input
+/// is only searched to its end.
+fn follow_files(
+    config: &Config,
+    output: &OutputOptions,
+    files: &[String],
+    show_path: bool,
+    mut failed: usize,
+) -> Result<Outcome, RgrepError> {
+    let finder = literal_finder(config);
+    let mut selected = false;
+    // Each file keeps its `FileSearch` from one poll to the next, so line
+    // numbers, context and `-m` carry on where they left off.
+    let mut followed = Vec::new();
+    for path in files {
+        if path != "-" {
+            followed.push((path.as_str(), follow::Tail::new(path), None));
+            continue;
+        }
+        match search_file(config, output, path, show_path, false, &mut io::stdout()) {
+            Ok(stats) => selected |= stats.searches_with_match > 0,
+            Err(err) => skip_failure(config, err, &mut failed)?,
+        }
+    }
+
+    while !followed.is_empty() {
+        let mut behind = false;
+        let mut i = 0;
+        while i < followed.len() {
+            let (name, tail, search) = &mut followed[i];
+            let name: &str = name;
+            let events = match tail.poll() {
+                Ok(events) => events,
+                Err(err) => {
+                    skip_failure(config, read_error(name, err), &mut failed)?;
+                    followed.remove(i);
+                    continue;
+                }
+            };
+            behind |= tail.is_behind();
+            for event in events {
+                let follow::Event::Lines { offset, bytes } = event else {
+                    if !config.no_messages {
+                        eprintln!("rgrep: {name}: {event}");
+                    }
+                    if !matches!(event, follow::Event::Missing(_)) {
+                        // A new file: line numbers and `-m` start over.
+                        *search = None;
+                    }
+                    continue;
+                };
+                let search = search.get_or_insert_with(|| {
+                    FileSearch::new(config, output, io::stdout(), name, show_path, false)
+                });
+                let done = search.finished()
+                    || search
+                        .feed(finder.as_ref(), offset as usize, &bytes)?
+                        .is_break();
+                selected |= search.selected > 0;
+                if done {
+                    break;
+                }
+            }
+            if search.as_ref().is_some_and(|search| search.finished()) {
+                followed.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+        io::stdout().flush().map_err(RgrepError::Output)?;
+        // A file with more to read is polled again straight away.
+        if !followed.is_empty() && !behind {
+            thread::sleep(FOLLOW_INTERVAL);
+        }
+    }
+    Ok(Outcome { selected, failed })
+}
+
+/// `--replace`: rewrite each file, or with `--dry-run` print a diff of what
+/// would change. Standard input is rewritten to standard output.
+fn replace_files(
+    config: &Config,
+    template: &Template,
+    files: &[String],
+    mut failed: usize,
+) -> Result<Outcome, RgrepError> {
+    let mut stdout = io::stdout().lock();
+    let mut selected = false;
+    for path in files {
+        match replace_file(config, template, path, &mut stdout) {
+            Ok(changed) => selected |= changed,
+            Err(err) => skip_failure(config, err, &mut failed)?,
+        }
+    }
+    Ok(Outcome { selected, failed })
+}
+
+/// Rewrite one file; `Ok(true)` if anything in it changed.
+fn replace_file(
+    config: &Config,
+    template: &Template,
+    path: &str,
+    out: &mut impl Write,
+) -> Result<bool, RgrepError> {
+    let stdin = path == "-";
+    let name = if stdin { STDIN_NAME } else { path };
+    let mut bytes = Vec::new();
+    let read = if stdin {
+        io::stdin().lock().read_to_end(&mut bytes).map(drop)
+    } else {
+        fs::read(path).map(|contents| bytes = contents)
+    };
+    read.map_err(|err| read_error(name, err))?;
+
+    // Binary files are left alone, the way a search skips their lines.
+    let rewrite = if walk::looks_binary(&bytes) {
+        None
+    } else {
+        let contents = std::str::from_utf8(&bytes)
+            .map_err(|err| read_error(name, io::Error::new(io::ErrorKind::InvalidData, err)))?;
+        Some(replace::rewrite(
+            &config.pattern,
+            template,
+            contents,
+            config.max_count,
+        ))
+        .filter(|rewrite| !rewrite.edits.is_empty())
+    };
+
+    if config.dry_run {
+        if let Some(rewrite) = &rewrite {
+            let original = std::str::from_utf8(&bytes).expect("checked above");
+            out.write_all(replace::unified_diff(name, original, &rewrite.edits).as_bytes())
+                .map_err(RgrepError::Output)?;
+        }
+    } else if stdin {
+        let contents = rewrite
+            .as_ref()
+            .map_or(&bytes[..], |r| r.contents.as_bytes());
+        out.write_all(contents).map_err(RgrepError::Output)?;
+    } else if let Some(rewrite) = &rewrite {
+        replace::write_atomic(Path::new(path), rewrite.contents.as_bytes()).map_err(|source| {
+            RgrepError::Write {
+                path: path.to_string(),
+                source,
+            }
+        })?;
+        let n = rewrite.edits.len();
+        writeln!(
+            out,
+            "{path}: {n} {} changed",
+            if n == 1 { "line" } else { "lines" }
+        )
+        .map_err(RgrepError::Output)?;
+    }
+    Ok(rewrite.is_some())
+}
+
+/// Report a per-file failure and count it, so the caller can move on to
+/// the next path; anything else (or any failure with `--fail-fast`) is
+/// handed back to stop the run.
+fn skip_failure(config: &Config, err: RgrepError, failed: &mut usize) -> Result<(), RgrepError> {
+    if !err.is_per_file() || config.fail_fast {
+        return Err(err);
+    }
+    if !config.no_messages {
+        eprintln!("rgrep: {}", err.report());
+    }
+    *failed += 1;
+    Ok(())
+}
+
+/// Search one file and write its matching lines (plus any context) to
+/// `out`, returning the file's `Stats`.
+///
+/// With `-z`, gzip input is decompressed on the fly (see `decompress`), and
+/// each file in a tar archive (compressed or not) is searched on its own,
+/// named `archive:member` in the output.
+fn search_file(
+    config: &Config,
+    output: &OutputOptions,
+    path: &str,
+    show_path: bool,
+    separate_first: bool,
+    out: &mut impl Write,
+) -> Result<Stats, RgrepError> {
+    let name = if path == "-" { STDIN_NAME } else { path };
+    let input: Box<dyn Read> = if path == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(path).map_err(|err| read_error(name, err))?)
+    };
+    if !config.search_zip {
+        return search_reader(config, output, name, input, show_path, separate_first, out);
+    }
+
+    let (format, input) = decompress::open(input).map_err(|err| read_error(name, err))?;
+    if format != Format::Tar {
+        return search_reader(config, output, name, input, show_path, separate_first, out);
+    }
+    let mut archive = tar::Archive::new(input);
+    let mut totals = Stats::default();
+    while let Some(entry) = archive.next_file().map_err(|err| read_error(name, err))? {
+        if output.mode == OutputMode::Quiet && totals.searches_with_match > 0 {
+            break;
+        }
+        let member = format!("{name}:{}", entry.path);
+        let separate = separate_first || totals.searches_with_match > 0;
+        let stats = search_reader(
+            config,
+            output,
+            &member,
+            archive.contents(),
+            true,
+            separate,
+            &mut *out,
+        )?;
+        totals.add(&stats);
+    }
+    Ok(totals)
+}
+
+/// Search `input`, displayed as `name`, and write its matching lines (plus
+/// any context) to `out`, returning its `Stats`.
+///
+/// The input is streamed through a `LineBuffer`, so memory stays bounded no
+/// matter how large it is. Lines that aren't valid UTF-8 are decoded lossily
+/// for matching and display. Input that looks binary gets a single
+/// "Binary file ... matches" line instead of its contents, like `grep`.
+///
+/// With `-v` the lines that *don't* match are the selected ones. Reading
+/// stops once `-m NUM` lines were selected (after any trailing context), or
+/// after the first one for `-l`/`-L`/`-q`, where one is all it takes.
+///
+/// A plain literal pattern takes a fast path: each block is scanned whole
+/// with a `literal::Finder`, and only the lines around a hit (or owed as
+/// context) are decoded and handed to the printer.
+fn search_reader(
+    config: &Config,
+    output: &OutputOptions,
+    name: &str,
+    input: impl Read,
+    show_path: bool,
+    separate_first: bool,
+    out: &mut impl Write,
+) -> Result<Stats, RgrepError> {
+    let finder = literal_finder(config);
+    let mut reader = LineBuffer::new(input);
+    let mut search = FileSearch::new(config, output, out, name, show_path, separate_first);
+    let mut block_offset = 0;
+    while let Some(block) = reader.next_block().map_err(|err| read_error(name, err))? {
+        let flow = search.feed(finder.as_ref(), block_offset, block)?;
+        block_offset += block.len();
+        if flow.is_break() {
+            break;
+        }
+    }
+    search
+        .printer
+        .finish(block_offset as u64)
+        .map_err(RgrepError::Output)
+}
+
+/// A `Finder` for the whole-buffer fast path, when the pattern allows one.
+fn literal_finder(config: &Config) -> Option<Finder> {
+    match &config.pattern {
+        // Lines never contain `\n`, and `U+FFFD` may only appear after lossy
+        // decoding, so those needles go the line-by-line way.
+        Pattern::Literal(s)
+            if !config.invert && !s.is_empty() && !s.contains(['\n', '\u{FFFD}']) =>
+        {
+            Some(Finder::new(s.as_bytes()))
+        }
+        _ => None,
+    }
+}
+
+/// One file's search in progress. Every method takes lines in file order
+/// and returns `ControlFlow::Break` once nothing more needs to be read.
+struct FileSearch<'p, W: Write> {
+    config: &'p Config,
+    output: &'p OutputOptions,
+    printer: Printer<'p, W>,
+    /// Stop selecting after this many lines.
+    limit: Option<u64>,
+    selected: u64,
+    line_number: usize,
+    /// The next line fed continues the last one: a line longer than
+    /// `MAX_LINE_LEN` arrives in pieces, which share its number and are
+    /// selected (and counted) as one.
+    continues: bool,
+    /// Whether the line at `line_number` has been selected.
+    line_selected: bool,
+    /// Whether the file looks binary, decided from its first block.
+    binary: Option<bool>,
+}
+
+impl<'p, W: Write> FileSearch<'p, W> {
+    fn new(
+        config: &'p Config,
+        output: &'p OutputOptions,
+        out: W,
+        name: &'p str,
+        show_path: bool,
+        separate_first: bool,
+    ) -> Self {
+        FileSearch {
+            config,
+            output,
+            printer: Printer::new(out, name, show_path, output, separate_first),
+            limit: match output.mode {
+                OutputMode::Lines | OutputMode::Count => config.max_count,
+                _ => Some(1),
+            },
+            selected: 0,
+            line_number: 0,
+            continues: false,
+            line_selected: false,
+            binary: None,
+        }
+    }
+
+    /// The next `block` of whole lines, starting at `offset` in the file.
+    fn feed(
+        &mut self,
+        finder: Option<&Finder>,
+        offset: usize,
+        block: &[u8],
+    ) -> Result<ControlFlow<()>, RgrepError> {
+        self.binary.get_or_insert_with(|| walk::looks_binary(block));
+        let flow = match finder {
+            Some(finder) => self.literal_block(finder, offset, block)?,
+            None => self.block(offset, block)?,
+        };
+        self.continues = !block.ends_with(b"\n");
+        if self.continues && self.line_selected {
+            // The rest of a selected line still goes out, even past `-m`.
+            return Ok(ControlFlow::Continue(()));
+        }
+        Ok(flow)
+    }
+
+    /// Start on the next line, unless the one coming continues the last.
+    fn advance(&mut self) {
+        if !mem::take(&mut self.continues) {
+            self.line_number += 1;
+            self.line_selected = false;
+        }
+    }
+
+    /// Every line of `block`, which starts at `offset` in the file.
+    fn block(&mut self, offset: usize, block: &[u8]) -> Result<ControlFlow<()>, RgrepError> {
+        for (start, raw) in reader::lines(block) {
+            self.line(offset + start, raw)?;
+            if self.finished() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Like `block`, but only lines containing a hit for `finder` are
+    /// matched; the ones between hits are known not to match and go to
+    /// `skip`.
+    fn literal_block(
+        &mut self,
+        finder: &Finder,
+        offset: usize,
+        block: &[u8],
+    ) -> Result<ControlFlow<()>, RgrepError> {
+        let mut pos = 0;
+        while pos < block.len() {
+            let Some(hit) = finder.find_at(block, pos) else {
+                return self.skip(offset + pos, &block[pos..]);
+            };
+            let start = block[pos..hit]
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(pos, |i| pos + i + 1);
+            if self.skip(offset + pos, &block[pos..start])?.is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+            let end = reader::line_end(block, hit);
+            self.line(offset + start, reader::trim_terminator(&block[start..end]))?;
+            if self.finished() {
+                return Ok(ControlFlow::Break(()));
+            }
+            pos = end;
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Whole lines in `region` that are known not to match. Only the ones
+    /// the printer could still show — trailing context for an earlier
+    /// match, or the last `-B N` lines — are decoded; the rest are counted.
+    fn skip(&mut self, offset: usize, region: &[u8]) -> Result<ControlFlow<()>, RgrepError> {
+        let mut pos = 0;
+        while pos < region.len() && self.printer.in_after_context() {
+            let end = reader::line_end(region, pos);
+            self.context(offset + pos, reader::trim_terminator(&region[pos..end]))?;
+            pos = end;
+        }
+        if self.finished() {
+            return Ok(ControlFlow::Break(()));
+        }
+        let rest = &region[pos..];
+        let mut keep = rest.len();
+        for _ in 0..self.output.before_context {
+            if keep == 0 {
+                break;
+            }
+            keep = rest[..keep - 1]
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |i| i + 1);
+        }
+        let skipped = reader::count_lines(&rest[..keep]);
+        if skipped > 0 {
+            self.advance();
+            self.line_number += skipped - 1;
+            self.line_selected &= skipped == 1;
+        }
+        for (start, raw) in reader::lines(&rest[keep..]) {
+            self.context(offset + pos + keep + start, raw)?;
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// One line (without its terminator) starting at `offset` in the file.
+    fn line(&mut self, offset: usize, raw: &[u8]) -> Result<(), RgrepError> {
+        if self.limit_reached() && !(self.continues && self.line_selected) {
+            // Done selecting; only trailing context is still owed.
+            return self.context(offset, raw);
+        }
+        self.advance();
+        let line_number = self.line_number;
+        let line = String::from_utf8_lossy(raw);
+        let found = search::match_line(&self.config.pattern, line_number, offset, &line);
+        let found = match (found, self.config.invert) {
+            (found, false) => found,
+            (Some(_), true) => None,
+            (None, true) => Some(LineMatch {
+                line_number,
+                byte_offset: offset,
+                line: &line,
+                spans: Vec::new(),
+                patterns: Vec::new(),
+            }),
+        };
+        let Some(m) = found else {
+            return self
+                .printer
+                .context(line_number, offset, &line)
+                .map_err(RgrepError::Output);
+        };
+        if mem::replace(&mut self.line_selected, true) {
+            // Another piece of a line that's already been selected.
+            return self.printer.matched_piece(&m).map_err(RgrepError::Output);
+        }
+        self.selected += 1;
+        if self.binary == Some(true) && self.output.mode == OutputMode::Lines {
+            self.printer
+                .binary_file_matches()
+                .map_err(RgrepError::Output)?;
+            // Nothing else gets printed for a binary file.
+            self.limit = Some(0);
+            return Ok(());
+        }
+        self.printer.matched(&m).map_err(RgrepError::Output)
+    }
+
+    fn context(&mut self, offset: usize, raw: &[u8]) -> Result<(), RgrepError> {
+        self.advance();
+        let line = String::from_utf8_lossy(raw);
+        self.printer
+            .context(self.line_number, offset, &line)
+            .map_err(RgrepError::Output)
+    }
+
+    fn limit_reached(&self) -> bool {
+        self.limit.is_some_and(|limit| self.selected >= limit)
+    }
+
+    /// `true` once no later line can change the output.
+    fn finished(&self) -> bool {
+        self.limit_reached()
+            && !self.printer.in_after_context()
+            && !(self.continues && self.line_selected)
+    }
+}
+
+/// An error reading `path`: corrupt compressed data (see `decompress`) or
+/// a plain I/O failure.
+fn read_error(path: &str, err: io::Error) -> RgrepError {
+    match decompress::Error::from_io(err) {
+        Ok(source) => RgrepError::Decompress {
+            path: path.to_string(),
+            source,
+        },
+        Err(source) => RgrepError::Io {
+            path: path.to_string(),
+            source,
+        },
+    }
+}
+
+/// Expand `config.paths` into the list of files to search, in order, keeping
+/// only those that pass `--include` / `--exclude`.
+fn collect_files(config: &Config, failed: &mut usize) -> Result<Vec<String>, RgrepError> {
+    let mut files = Vec::new();
+    for path in &config.paths {
+        if config.recursive && Path::new(path).is_dir() {
+            let found = walk::walk(Path::new(path), &config.walk);
+            for err in found.errors {
+                skip_failure(config, err, failed)?;
+            }
+            let mut found = found.files;
+            if let Some(query) = &config.index {
+                found = prune_with_index(config, Path::new(path), found, query);
+            }
+            files.extend(found.iter().map(|p| p.display().to_string()));
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files.retain(|path| path == "-" || wanted(config, path));
+    Ok(files)
+}
+
+/// `--index`: drop the files under `dir` that its index rules out. A
+/// directory without an index is searched in full; a broken index is
+/// reported, then ignored.
+fn prune_with_index(
+    config: &Config,
+    dir: &Path,
+    files: Vec<PathBuf>,
+    query: &Query,
+) -> Vec<PathBuf> {
+    let pruned = index::Index::open(dir).and_then(|index| match index {
+        Some(mut index) => index.filter(files.clone(), query, config.search_zip),
+        None => Ok(files.clone()),
+    });
+    pruned.unwrap_or_else(|source| {
+        if !config.no_messages {
+            let err = RgrepError::Io {
+                path: dir.join(index::INDEX_FILE).display().to_string(),
+                source,
+            };
+            eprintln!("rgrep: {}; searching without it", err.report());
+        }
+        files
+    })
+}
+
+fn wanted(config: &Config, path: &str) -> bool {
+    (config.include.is_empty() || config.include.iter().any(|g| g.matches_path(path)))
+        && !config.exclude.iter().any(|g| g.matches_path(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::BLOCK_SIZE;
+
+    /// What `rgrep ARGS` prints for `input`.
+    fn search(args: &[&str], input: &[u8]) -> String {
+        let args: Vec<String> = ["rgrep"]
+            .iter()
+            .chain(args)
+            .map(|s| s.to_string())
+            .collect();
+        let config = Config::build(&args).unwrap();
+        let mut out = Vec::new();
+        search_reader(&config, &config.output, "-", input, false, false, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn the_pieces_of_a_split_line_are_numbered_and_counted_as_one() {
+        // Past the buffer, so `LineBuffer` hands it out in pieces.
+        let long = "a".repeat(BLOCK_SIZE + 100);
+        let input = format!("{long}\nneedle\n");
+        assert_eq!(search(&["-n", "needle"], input.as_bytes()), "2:needle\n");
+        assert_eq!(search(&["-c", "a"], input.as_bytes()), "1\n");
+        assert_eq!(search(&["-c", "-v", "needle"], input.as_bytes()), "1\n");
+        let first = search(&["-n", "-m", "1", "a"], input.as_bytes());
+        assert_eq!(first.len(), "1:".len() * 2 + long.len() + 2);
+        assert!(first.lines().all(|piece| piece.starts_with("1:")));
+        assert_eq!(
+            search(&["-n", "-m", "1", "-E", "a+"], input.as_bytes()),
+            first
+        );
+    }
 }
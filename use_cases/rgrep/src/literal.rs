@@ -0,0 +1,180 @@
+//! Fast substring search for plain (non-regex) patterns.
+//!
+//! Instead of checking a file line by line, a literal search scans the whole
+//! buffer for the needle and only looks for line boundaries around the hits
+//! — most lines of a typical file never match, so most bytes only ever pass
+//! through the tight loops here.
+//!
+//! Two techniques do the scanning:
+//!
+//! - `memchr` finds a single byte a whole machine word at a time (SWAR —
+//!   "SIMD within a register"): XOR-ing a word with the byte repeated in
+//!   every lane turns matching lanes into zero bytes, and a couple of
+//!   arithmetic tricks tell whether any lane is zero without looking at
+//!   them one by one. Short needles use it to jump between candidate
+//!   positions for their first byte.
+//! - Longer needles use Boyer-Moore-Horspool: compare the window's *last*
+//!   byte first, and on a mismatch slide the window as far as that byte
+//!   allows — up to the whole needle length — so most of the haystack is
+//!   never even read.
+//!
+//! Concepts: `[usize; 256]` lookup tables, `chunks_exact`, bit tricks on
+//! `usize`, `usize::from_ne_bytes`.
+
+use std::mem::size_of;
+
+/// Needles shorter than this are found with `memchr` on their first byte
+/// plus a comparison; Horspool's skips are too short to pay off below it.
+const HORSPOOL_MIN_LEN: usize = 4;
+
+const WORD: usize = size_of::<usize>();
+/// `0x0101...01`: one in the low bit of every byte lane.
+const LO: usize = usize::from_ne_bytes([0x01; WORD]);
+/// `0x8080...80`: one in the high bit of every byte lane.
+const HI: usize = usize::from_ne_bytes([0x80; WORD]);
+
+/// `true` if any byte lane of `word` is zero. Subtracting one from every
+/// lane borrows into a lane's high bit only where that lane was zero (or
+/// already had its high bit set, which `!word` filters out).
+fn has_zero_byte(word: usize) -> bool {
+    word.wrapping_sub(LO) & !word & HI != 0
+}
+
+/// Index of the first `byte` in `haystack`.
+pub fn memchr(byte: u8, haystack: &[u8]) -> Option<usize> {
+    let repeated = LO * usize::from(byte);
+    let mut chunks = haystack.chunks_exact(WORD);
+    for (i, chunk) in chunks.by_ref().enumerate() {
+        let word = usize::from_ne_bytes(chunk.try_into().expect("chunk is one word"));
+        if has_zero_byte(word ^ repeated) {
+            let lane = chunk.iter().position(|&b| b == byte);
+            return lane.map(|lane| i * WORD + lane);
+        }
+    }
+    let done = haystack.len() - chunks.remainder().len();
+    chunks
+        .remainder()
+        .iter()
+        .position(|&b| b == byte)
+        .map(|i| done + i)
+}
+
+/// A needle prepared for repeated searching.
+#[derive(Debug, Clone)]
+pub struct Finder {
+    needle: Vec<u8>,
+    /// Horspool's bad-character table: how far the window may slide when
+    /// the given byte sits under its last position.
+    shift: Box<[usize; 256]>,
+}
+
+impl Finder {
+    pub fn new(needle: &[u8]) -> Finder {
+        let mut shift = Box::new([needle.len(); 256]);
+        if let Some(last) = needle.len().checked_sub(1) {
+            for (i, &b) in needle[..last].iter().enumerate() {
+                shift[usize::from(b)] = last - i;
+            }
+        }
+        Finder {
+            needle: needle.to_vec(),
+            shift,
+        }
+    }
+
+    pub fn needle(&self) -> &[u8] {
+        &self.needle
+    }
+
+    /// Index of the first occurrence of the needle in `haystack`.
+    pub fn find(&self, haystack: &[u8]) -> Option<usize> {
+        match self.needle.len() {
+            0 => Some(0),
+            1 => memchr(self.needle[0], haystack),
+            n if n < HORSPOOL_MIN_LEN => self.find_by_first_byte(haystack),
+            _ => self.find_horspool(haystack),
+        }
+    }
+
+    /// Like `find`, but starting at byte offset `start`; the result is
+    /// still an index into the whole of `haystack`.
+    pub fn find_at(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        self.find(&haystack[start..]).map(|i| start + i)
+    }
+
+    fn find_by_first_byte(&self, haystack: &[u8]) -> Option<usize> {
+        let mut pos = 0;
+        while pos + self.needle.len() <= haystack.len() {
+            pos += memchr(self.needle[0], &haystack[pos..])?;
+            if haystack[pos..].starts_with(&self.needle) {
+                return Some(pos);
+            }
+            pos += 1;
+        }
+        None
+    }
+
+    fn find_horspool(&self, haystack: &[u8]) -> Option<usize> {
+        let n = self.needle.len();
+        let last = n - 1;
+        let mut pos = 0;
+        while pos + n <= haystack.len() {
+            let b = haystack[pos + last];
+            if b == self.needle[last] && haystack[pos..pos + last] == self.needle[..last] {
+                return Some(pos);
+            }
+            pos += self.shift[usize::from(b)];
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The obvious quadratic search, as the reference answer.
+    fn naive(needle: &[u8], haystack: &[u8]) -> Option<usize> {
+        (0..=haystack.len().checked_sub(needle.len())?).find(|&i| haystack[i..].starts_with(needle))
+    }
+
+    #[test]
+    fn memchr_finds_bytes_in_every_lane_and_the_tail() {
+        let haystack: Vec<u8> = (0..37).collect();
+        for (i, &b) in haystack.iter().enumerate() {
+            assert_eq!(memchr(b, &haystack), Some(i));
+        }
+        assert_eq!(memchr(200, &haystack), None);
+        assert_eq!(memchr(0x80, &[0x7f, 0x81, 0x80]), Some(2));
+        assert_eq!(memchr(b'a', b""), None);
+    }
+
+    #[test]
+    fn finder_agrees_with_naive_search() {
+        let haystack = b"abracadabra: an ERROR, then ERRORS and an ERR at the end ERR";
+        for needle in [
+            &b"a"[..],
+            b"ab",
+            b"ERR",
+            b"ERROR",
+            b"ERRORS and",
+            b"cadabra:",
+            b"end ERR",
+            b"missing",
+            b"abracadabra: an ERROR, then ERRORS and an ERR at the end ERR!",
+        ] {
+            let finder = Finder::new(needle);
+            assert_eq!(finder.find(haystack), naive(needle, haystack), "{needle:?}");
+        }
+    }
+
+    #[test]
+    fn find_at_reports_positions_in_the_whole_haystack() {
+        let finder = Finder::new(b"needle");
+        let haystack = b"needle hay needle";
+        assert_eq!(finder.find_at(haystack, 0), Some(0));
+        assert_eq!(finder.find_at(haystack, 1), Some(11));
+        assert_eq!(finder.find_at(haystack, 12), None);
+        assert_eq!(Finder::new(b"").find_at(haystack, 5), Some(5));
+    }
+}
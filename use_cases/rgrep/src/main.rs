@@ -1,18 +1,80 @@
 use std::env;
+use std::io;
+use std::path::Path;
 use std::process;
 
-use rgrep::cli::Config;
+use rgrep::cli::{self, Config};
+use rgrep::error::RgrepError;
+use rgrep::index;
+use rgrep::printer::OutputMode;
+
+// Exit statuses, as in grep — plus one for "searched, but some files
+// couldn't be read", so scripts can tell a partial result from a usage
+// error. Both are >= 2, so `[ $? -ge 2 ]` still means "something went wrong".
+const EXIT_MATCH: i32 = 0;
+const EXIT_NO_MATCH: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+const EXIT_PARTIAL: i32 = 3;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
+    if cli::wants_help(&args) {
+        print!("{}", cli::help());
+        return;
+    }
+
+    if let Some(dirs) = cli::index_build_dirs(&args) {
+        for dir in dirs {
+            match index::build(Path::new(&dir), cli::default_threads()) {
+                Ok(summary) => println!(
+                    "{dir}: indexed {} files ({} read, {} removed), {} trigrams",
+                    summary.files, summary.read, summary.removed, summary.trigrams
+                ),
+                Err(err) => {
+                    eprintln!("Application error: {}", err.report());
+                    process::exit(EXIT_ERROR);
+                }
+            }
+        }
+        return;
+    }
+
     let config = Config::build(&args).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
+        eprintln!("Problem parsing arguments: {}", err.report());
+        eprintln!("Try 'rgrep --help' for more information.");
+        process::exit(EXIT_ERROR);
     });
+    let no_messages = config.no_messages;
+    let quiet = config.output.mode == OutputMode::Quiet;
 
-    if let Err(err) = rgrep::run(config) {
-        eprintln!("Application error: {err}");
-        process::exit(1);
+    match rgrep::run(config) {
+        Ok(outcome) => {
+            if outcome.failed > 0 && !no_messages {
+                let noun = if outcome.failed == 1 { "path" } else { "paths" };
+                eprintln!("rgrep: {} {noun} could not be searched", outcome.failed);
+            }
+            // As in grep, `-q` succeeds on a match even if other files failed.
+            process::exit(if outcome.selected && quiet {
+                EXIT_MATCH
+            } else if outcome.failed > 0 {
+                EXIT_PARTIAL
+            } else if outcome.selected {
+                EXIT_MATCH
+            } else {
+                EXIT_NO_MATCH
+            });
+        }
+        Err(RgrepError::Output(err)) if err.kind() == io::ErrorKind::BrokenPipe => {
+            // The reader went away (`rgrep ... | head`); nothing to report.
+            process::exit(EXIT_ERROR);
+        }
+        Err(err) => {
+            // `-s` silences unreadable files, but not other failures.
+            if !(no_messages && err.is_per_file()) {
+                eprintln!("Application error: {}", err.report());
+            }
+            process::exit(EXIT_ERROR);
+        }
     }
 }
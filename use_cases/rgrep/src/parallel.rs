@@ -0,0 +1,293 @@
+//! A small worker pool for searching many files at once (`-j N`).
+//!
+//! Workers claim items by bumping a shared counter, do the expensive part
+//! (reading + searching a file) concurrently, and send `(index, result)`
+//! pairs back over a channel. The calling thread holds early arrivals in a
+//! reorder buffer and hands results to `emit` strictly in input order, so the
+//! output is identical to a sequential run no matter which file finishes
+//! first.
+//!
+//! One slow file mustn't let the others pile up behind it, so workers only
+//! claim items within `AHEAD_PER_THREAD * threads` of the next one to be
+//! emitted, and otherwise wait for it: the reorder buffer (and every result
+//! held in it) stays bounded however many files there are. A single
+//! result can still be big — every line of a huge log matching — so output
+//! waiting for its turn goes into a `Spill`, which moves to a temporary
+//! file past `SPILL_AT` bytes.
+//!
+//! Concepts: `std::thread::scope` (workers can borrow `items` and `work`
+//! because the scope guarantees they finish before it returns), `mpsc`
+//! channels, `Mutex` + `Condvar`, `BTreeMap` as a reorder buffer,
+//! implementing `Write`.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// How many items per worker may be started ahead of the next one due.
+const AHEAD_PER_THREAD: usize = 4;
+
+/// What the workers and the emitting thread share.
+struct Progress {
+    /// The next item to claim.
+    next: usize,
+    /// The next item to emit: everything before it has been.
+    wanted: usize,
+    /// `emit` failed, so no more items are started.
+    stopped: bool,
+}
+
+/// Run `work` over every item on up to `threads` worker threads, passing each
+/// result to `emit` in the same order as `items`.
+///
+/// If `emit` returns an error, no new items are started and that error is
+/// returned once in-flight work has wound down. With `threads <= 1` (or a
+/// single item) everything runs on the calling thread.
+pub fn for_each_ordered<T, R, E, W, F>(
+    items: &[T],
+    threads: usize,
+    work: W,
+    mut emit: F,
+) -> Result<(), E>
+where
+    T: Sync,
+    R: Send,
+    W: Fn(&T) -> R + Sync,
+    F: FnMut(R) -> Result<(), E>,
+{
+    if threads <= 1 || items.len() <= 1 {
+        for item in items {
+            emit(work(item))?;
+        }
+        return Ok(());
+    }
+
+    let window = threads * AHEAD_PER_THREAD;
+    let progress = Mutex::new(Progress {
+        next: 0,
+        wanted: 0,
+        stopped: false,
+    });
+    let advanced = Condvar::new();
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..threads.min(items.len()) {
+            let tx = tx.clone();
+            let (progress, advanced, work) = (&progress, &advanced, &work);
+            scope.spawn(move || {
+                loop {
+                    let i = {
+                        let progress = progress.lock().unwrap();
+                        let mut progress = advanced
+                            .wait_while(progress, |p| !p.stopped && p.next >= p.wanted + window)
+                            .unwrap();
+                        if progress.stopped || progress.next >= items.len() {
+                            break;
+                        }
+                        progress.next += 1;
+                        progress.next - 1
+                    };
+                    if tx.send((i, work(&items[i]))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // Only the workers hold senders now, so `rx` ends when they all do.
+        drop(tx);
+
+        let mut pending = BTreeMap::new();
+        let mut wanted = 0;
+        for (i, result) in rx {
+            pending.insert(i, result);
+            let before = wanted;
+            while let Some(result) = pending.remove(&wanted) {
+                wanted += 1;
+                if let Err(err) = emit(result) {
+                    progress.lock().unwrap().stopped = true;
+                    advanced.notify_all();
+                    return Err(err);
+                }
+            }
+            if wanted > before {
+                progress.lock().unwrap().wanted = wanted;
+                advanced.notify_all();
+            }
+        }
+        Ok(())
+    })
+}
+
+/// How much of one result's output a `Spill` keeps in memory.
+pub const SPILL_AT: usize = 1024 * 1024;
+
+/// Output held back until its turn comes: in memory while it's small, in
+/// a temporary file (removed on drop) once it passes `SPILL_AT`.
+#[derive(Default)]
+pub struct Spill {
+    memory: Vec<u8>,
+    file: Option<(BufWriter<File>, PathBuf)>,
+}
+
+impl Spill {
+    /// Write everything held so far to `out`.
+    pub fn copy_to(mut self, out: &mut impl Write) -> io::Result<()> {
+        let Some((file, _)) = &mut self.file else {
+            return out.write_all(&self.memory);
+        };
+        file.flush()?;
+        let file = file.get_mut();
+        file.seek(SeekFrom::Start(0))?;
+        io::copy(file, out)?;
+        Ok(())
+    }
+
+    /// Move what's in memory to a new temporary file.
+    fn spill(&mut self) -> io::Result<()> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let path = std::env::temp_dir().join(format!("rgrep-spill-{}-{n}", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let mut file = BufWriter::new(file);
+        file.write_all(&self.memory)?;
+        self.memory = Vec::new();
+        self.file = Some((file, path));
+        Ok(())
+    }
+}
+
+impl Write for Spill {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.file.is_none() && self.memory.len() + buf.len() > SPILL_AT {
+            self.spill()?;
+        }
+        match &mut self.file {
+            Some((file, _)) => file.write(buf),
+            None => {
+                self.memory.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some((file, _)) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        if let Some((_, path)) = &self.file {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn emits_in_input_order_even_when_work_finishes_out_of_order() {
+        let items: Vec<u64> = (0..16).collect();
+        let mut seen = Vec::new();
+        let result: Result<(), ()> = for_each_ordered(
+            &items,
+            4,
+            |&n| {
+                // Earlier items take longest, so they finish last.
+                thread::sleep(Duration::from_millis(16 - n));
+                n * 10
+            },
+            |r| {
+                seen.push(r);
+                Ok(())
+            },
+        );
+        assert!(result.is_ok());
+        assert_eq!(seen, (0..16).map(|n| n * 10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn stops_at_first_emit_error() {
+        let items: Vec<u32> = (0..100).collect();
+        let mut seen = Vec::new();
+        let result = for_each_ordered(
+            &items,
+            3,
+            |&n| n,
+            |n| {
+                if n == 5 {
+                    return Err(n);
+                }
+                seen.push(n);
+                Ok(())
+            },
+        );
+        assert_eq!(result, Err(5));
+        assert_eq!(seen, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn a_slow_item_holds_back_only_a_bounded_window() {
+        let items: Vec<usize> = (0..200).collect();
+        let emitted = AtomicUsize::new(0);
+        // The furthest item started while item 0 was still running.
+        let furthest = AtomicUsize::new(0);
+        let result: Result<(), ()> = for_each_ordered(
+            &items,
+            4,
+            |&n| {
+                if n == 0 {
+                    thread::sleep(Duration::from_millis(100));
+                } else if emitted.load(Ordering::SeqCst) == 0 {
+                    furthest.fetch_max(n, Ordering::SeqCst);
+                }
+                n
+            },
+            |_| {
+                emitted.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            },
+        );
+        assert!(result.is_ok());
+        assert_eq!(emitted.into_inner(), 200);
+        assert!(furthest.into_inner() < 4 * AHEAD_PER_THREAD);
+    }
+
+    #[test]
+    fn spill_keeps_big_output_out_of_memory() {
+        let line = b"every line of this log matches\n";
+        let mut spill = Spill::default();
+        for _ in 0..(3 * SPILL_AT / line.len()) {
+            spill.write_all(line).unwrap();
+            assert!(spill.memory.len() <= SPILL_AT);
+        }
+        let path = spill.file.as_ref().map(|(_, path)| path.clone()).unwrap();
+        let mut out = Vec::new();
+        spill.copy_to(&mut out).unwrap();
+        assert_eq!(out, line.repeat(3 * SPILL_AT / line.len()));
+        assert!(!path.exists());
+
+        let mut small = Spill::default();
+        small.write_all(line).unwrap();
+        assert!(small.file.is_none());
+        let mut out = Vec::new();
+        small.copy_to(&mut out).unwrap();
+        assert_eq!(out, line);
+    }
+}
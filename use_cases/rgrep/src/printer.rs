@@ -0,0 +1,464 @@
+//! Turning matches into `grep`-style output lines.
+//!
+//! A `Printer` is fed every line of one file in order — matching lines via
+//! `matched`, the rest via `context` — and decides what to write: it keeps the
+//! last `-B N` lines around in case a match follows, keeps printing for `-A N`
+//! lines after one, and writes a `--` separator between groups that aren't
+//! adjacent. Matching lines use `:` after the path/line number and context
+//! lines use `-`, like `grep`. With `--json` the same lines go out as JSON
+//! records instead (see `json`), and the printer also counts `Stats`. The
+//! summary modes (`-c`, `-l`, `-L`, `-q`) print nothing per line; their one
+//! line per file is written by `finish`.
+//!
+//! Concepts: `VecDeque` as a bounded ring buffer, generic `W: Write`, ANSI
+//! escape codes.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use crate::json;
+use crate::search::LineMatch;
+
+const COLOR_PATH: &str = "\x1b[35m";
+const COLOR_LINE_NUMBER: &str = "\x1b[32m";
+const COLOR_SEPARATOR: &str = "\x1b[36m";
+const COLOR_MATCH: &str = "\x1b[1;31m";
+const COLOR_RESET: &str = "\x1b[0m";
+
+/// `--color=WHEN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Color only when stdout is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// What gets printed for each file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Matching lines (plus context), the normal `grep` output.
+    #[default]
+    Lines,
+    /// `-c`: the number of matching lines.
+    Count,
+    /// `-l`: just the name, if the file matched.
+    FilesWithMatches,
+    /// `-L`: just the name, if the file did not match.
+    FilesWithoutMatch,
+    /// `-q`: nothing at all; only the exit status tells.
+    Quiet,
+}
+
+/// How matching lines are displayed.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OutputOptions {
+    /// `-n`: prefix each line with its line number.
+    pub line_numbers: bool,
+    /// `-B N`: lines of context to show before each match.
+    pub before_context: usize,
+    /// `-A N`: lines of context to show after each match.
+    pub after_context: usize,
+    /// Whether to highlight matches with ANSI colors.
+    pub color: bool,
+    /// `--json`: write JSON Lines records (see `json`) instead of text.
+    pub json: bool,
+    /// `-o`: print each match on its own line instead of the whole line.
+    pub only_matching: bool,
+    /// `-c` / `-l` / `-L` / `-q`.
+    pub mode: OutputMode,
+}
+
+impl OutputOptions {
+    /// `true` if `-A`/`-B`/`-C` asked for any context lines.
+    pub fn has_context(&self) -> bool {
+        self.before_context > 0 || self.after_context > 0
+    }
+
+    /// `true` if non-adjacent groups of lines get a `--` line between them.
+    pub fn separates_groups(&self) -> bool {
+        self.has_context() && !self.json
+    }
+
+    /// The `--` line written between non-adjacent groups of output.
+    pub fn group_separator(&self) -> String {
+        if self.color {
+            format!("{COLOR_SEPARATOR}--{COLOR_RESET}\n")
+        } else {
+            "--\n".to_string()
+        }
+    }
+}
+
+/// Counters for one file, or (added together) a whole run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Files searched.
+    pub searches: u64,
+    /// Files with at least one matching line.
+    pub searches_with_match: u64,
+    pub matched_lines: u64,
+    /// Individual matches; a line can hold several.
+    pub matches: u64,
+    pub bytes_searched: u64,
+}
+
+impl Stats {
+    pub fn add(&mut self, other: &Stats) {
+        self.searches += other.searches;
+        self.searches_with_match += other.searches_with_match;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+        self.bytes_searched += other.bytes_searched;
+    }
+}
+
+/// Writes one file's matches (and their context) to `out`.
+pub struct Printer<'p, W: Write> {
+    out: W,
+    /// Display name of the file, for prefixes and JSON records.
+    name: &'p str,
+    /// Whether text lines are prefixed with `name`.
+    show_path: bool,
+    options: &'p OutputOptions,
+    /// The last `before_context` non-matching lines (number, offset, text),
+    /// waiting in case a match follows them.
+    before: VecDeque<(usize, usize, String)>,
+    /// How many more lines to print as trailing context.
+    after_remaining: usize,
+    /// Line number of the last line written, to spot gaps between groups.
+    last_printed: Option<usize>,
+    /// Whether output from an earlier file already precedes ours, so our
+    /// first group needs a separator too.
+    separate_first: bool,
+    binary: bool,
+    stats: Stats,
+}
+
+impl<'p, W: Write> Printer<'p, W> {
+    /// `name` is the file's display name; text lines are prefixed with it
+    /// when `show_path` is set. `separate_first` says whether an earlier file
+    /// already printed a group.
+    pub fn new(
+        out: W,
+        name: &'p str,
+        show_path: bool,
+        options: &'p OutputOptions,
+        separate_first: bool,
+    ) -> Self {
+        Printer {
+            out,
+            name,
+            show_path,
+            options,
+            before: VecDeque::with_capacity(options.before_context),
+            after_remaining: 0,
+            last_printed: None,
+            separate_first,
+            binary: false,
+            stats: Stats {
+                searches: 1,
+                ..Stats::default()
+            },
+        }
+    }
+
+    /// Feed a matching line.
+    pub fn matched(&mut self, m: &LineMatch<'_>) -> io::Result<()> {
+        self.stats.matched_lines += 1;
+        self.matched_piece(m)
+    }
+
+    /// Like `matched`, for a later piece of a line too long to be read in
+    /// one (see `reader::MAX_LINE_LEN`) that was already counted.
+    pub fn matched_piece(&mut self, m: &LineMatch<'_>) -> io::Result<()> {
+        self.stats.matches += m.spans.len() as u64;
+        if self.options.mode != OutputMode::Lines {
+            return Ok(());
+        }
+        let pending: Vec<_> = self.before.drain(..).collect();
+        for (number, offset, line) in pending {
+            self.write_context(number, offset, &line)?;
+        }
+        self.start_line(m.line_number)?;
+        if self.options.json {
+            writeln!(self.out, "{}", json::matched(self.name, m))?;
+        } else if self.options.only_matching {
+            for &(start, end) in m.spans.iter().filter(|(start, end)| start < end) {
+                self.write_text(m.line_number, &m.line[start..end], &[(0, end - start)], ':')?;
+            }
+        } else {
+            self.write_text(m.line_number, m.line, &m.spans, ':')?;
+        }
+        self.after_remaining = self.options.after_context;
+        Ok(())
+    }
+
+    /// `true` while lines after a match are still owed as `-A` context, so
+    /// the caller knows whether to keep feeding lines after it stops looking
+    /// for matches (`-m NUM`).
+    pub fn in_after_context(&self) -> bool {
+        self.after_remaining > 0
+    }
+
+    /// Feed a non-matching line that starts at `byte_offset` in the file.
+    pub fn context(
+        &mut self,
+        line_number: usize,
+        byte_offset: usize,
+        line: &str,
+    ) -> io::Result<()> {
+        if self.after_remaining > 0 {
+            self.after_remaining -= 1;
+            return self.write_context(line_number, byte_offset, line);
+        }
+        if self.options.before_context > 0 {
+            if self.before.len() == self.options.before_context {
+                self.before.pop_front();
+            }
+            self.before
+                .push_back((line_number, byte_offset, line.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Report that a binary file matched, instead of printing its lines.
+    pub fn binary_file_matches(&mut self) -> io::Result<()> {
+        self.stats.matched_lines += 1;
+        self.stats.matches += 1;
+        self.binary = true;
+        if self.options.mode != OutputMode::Lines {
+            return Ok(());
+        }
+        self.start_line(0)?;
+        if self.options.json {
+            return Ok(());
+        }
+        writeln!(self.out, "Binary file {} matches", self.name)
+    }
+
+    /// Finish the file: write the JSON `end` record if anything matched, or
+    /// the file's line for `-c`/`-l`/`-L`, and return this file's counters.
+    pub fn finish(mut self, bytes_searched: u64) -> io::Result<Stats> {
+        self.stats.bytes_searched = bytes_searched;
+        let matched = self.stats.matched_lines > 0;
+        self.stats.searches_with_match = u64::from(matched);
+        match self.options.mode {
+            OutputMode::Lines => {
+                if self.options.json && self.last_printed.is_some() {
+                    writeln!(
+                        self.out,
+                        "{}",
+                        json::end(self.name, self.binary, &self.stats)
+                    )?;
+                }
+            }
+            OutputMode::Count => {
+                if self.show_path {
+                    self.paint(COLOR_PATH, self.name)?;
+                    self.paint(COLOR_SEPARATOR, ':')?;
+                }
+                writeln!(self.out, "{}", self.stats.matched_lines)?;
+            }
+            OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch => {
+                if matched == (self.options.mode == OutputMode::FilesWithMatches) {
+                    self.paint(COLOR_PATH, self.name)?;
+                    writeln!(self.out)?;
+                }
+            }
+            OutputMode::Quiet => {}
+        }
+        Ok(self.stats)
+    }
+
+    /// Bookkeeping before any line goes out: the JSON `begin` record for the
+    /// file's first line, or a `--` separator in front of a new group.
+    fn start_line(&mut self, line_number: usize) -> io::Result<()> {
+        if self.options.json {
+            if self.last_printed.is_none() {
+                writeln!(self.out, "{}", json::begin(self.name))?;
+            }
+        } else if self.options.separates_groups() {
+            let gap = match self.last_printed {
+                Some(last) => line_number > last + 1,
+                None => self.separate_first,
+            };
+            if gap {
+                self.out
+                    .write_all(self.options.group_separator().as_bytes())?;
+            }
+        }
+        self.last_printed = Some(line_number);
+        Ok(())
+    }
+
+    fn write_context(
+        &mut self,
+        line_number: usize,
+        byte_offset: usize,
+        line: &str,
+    ) -> io::Result<()> {
+        self.start_line(line_number)?;
+        if self.options.json {
+            let record = json::context(self.name, line_number, byte_offset, line);
+            return writeln!(self.out, "{record}");
+        }
+        self.write_text(line_number, line, &[], '-')
+    }
+
+    fn write_text(
+        &mut self,
+        line_number: usize,
+        line: &str,
+        spans: &[(usize, usize)],
+        separator: char,
+    ) -> io::Result<()> {
+        if self.show_path {
+            self.paint(COLOR_PATH, self.name)?;
+            self.paint(COLOR_SEPARATOR, separator)?;
+        }
+        if self.options.line_numbers {
+            self.paint(COLOR_LINE_NUMBER, line_number)?;
+            self.paint(COLOR_SEPARATOR, separator)?;
+        }
+        if !self.options.color || spans.is_empty() {
+            return writeln!(self.out, "{line}");
+        }
+        let mut written = 0;
+        for &(start, end) in spans {
+            if start == end {
+                continue;
+            }
+            write!(self.out, "{}", &line[written..start])?;
+            self.paint(COLOR_MATCH, &line[start..end])?;
+            written = end;
+        }
+        writeln!(self.out, "{}", &line[written..])
+    }
+
+    fn paint(&mut self, color: &str, text: impl std::fmt::Display) -> io::Result<()> {
+        if self.options.color {
+            write!(self.out, "{color}{text}{COLOR_RESET}")
+        } else {
+            write!(self.out, "{text}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{lines_with_offsets, match_line};
+
+    fn render(contents: &str, show_path: bool, options: &OutputOptions) -> String {
+        let mut out = Vec::new();
+        let mut printer = Printer::new(&mut out, "app.log", show_path, options, false);
+        for (i, (offset, line)) in lines_with_offsets(contents).enumerate() {
+            match match_line("ERROR", i + 1, offset, line) {
+                Some(m) => printer.matched(&m).unwrap(),
+                None => printer.context(i + 1, offset, line).unwrap(),
+            }
+        }
+        printer.finish(contents.len() as u64).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn prints_context_with_group_separators() {
+        let contents = "a\nb\nERROR 1\nc\nd\ne\nf\nERROR 2\ng";
+        let options = OutputOptions {
+            line_numbers: true,
+            before_context: 1,
+            after_context: 1,
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            render(contents, false, &options),
+            "2-b\n3:ERROR 1\n4-c\n--\n7-f\n8:ERROR 2\n9-g\n"
+        );
+    }
+
+    #[test]
+    fn overlapping_context_is_not_repeated() {
+        let contents = "ERROR 1\nx\nERROR 2\ny";
+        let options = OutputOptions {
+            before_context: 2,
+            after_context: 2,
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            render(contents, true, &options),
+            "app.log:ERROR 1\napp.log-x\napp.log:ERROR 2\napp.log-y\n"
+        );
+    }
+
+    #[test]
+    fn highlights_matched_spans() {
+        let options = OutputOptions {
+            color: true,
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            render("an ERROR here", false, &options),
+            "an \x1b[1;31mERROR\x1b[0m here\n"
+        );
+    }
+
+    #[test]
+    fn json_mode_writes_begin_match_context_and_end_records() {
+        let options = OutputOptions {
+            after_context: 1,
+            json: true,
+            ..OutputOptions::default()
+        };
+        let out = render("ok\nERROR x\nafter\nskipped", false, &options);
+        let types: Vec<&str> = out.lines().map(|l| l.split('"').nth(3).unwrap()).collect();
+        assert_eq!(types, vec!["begin", "match", "context", "end"]);
+        assert!(out.contains(r#""line_number":2,"absolute_offset":3"#));
+        assert!(out.contains(r#""matched_lines":1,"matches":1,"bytes_searched":24"#));
+    }
+
+    #[test]
+    fn summary_modes_write_one_line_per_file() {
+        let contents = "ERROR 1\nok\nERROR 2";
+        let with_mode = |mode| OutputOptions {
+            mode,
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            render(contents, true, &with_mode(OutputMode::Count)),
+            "app.log:2\n"
+        );
+        assert_eq!(
+            render(contents, false, &with_mode(OutputMode::Count)),
+            "2\n"
+        );
+        assert_eq!(
+            render(contents, true, &with_mode(OutputMode::FilesWithMatches)),
+            "app.log\n"
+        );
+        assert_eq!(
+            render(contents, true, &with_mode(OutputMode::FilesWithoutMatch)),
+            ""
+        );
+        assert_eq!(
+            render("ok", true, &with_mode(OutputMode::FilesWithoutMatch)),
+            "app.log\n"
+        );
+        assert_eq!(render(contents, true, &with_mode(OutputMode::Quiet)), "");
+    }
+
+    #[test]
+    fn only_matching_prints_each_match_on_its_own_line() {
+        let options = OutputOptions {
+            line_numbers: true,
+            only_matching: true,
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            render("ok\nan ERROR and an ERROR", false, &options),
+            "2:ERROR\n2:ERROR\n"
+        );
+    }
+}
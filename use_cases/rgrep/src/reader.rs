@@ -0,0 +1,180 @@
+//! Streaming, bounded-memory input for searching files of any size.
+//!
+//! `LineBuffer` reads from any `Read` (a file, stdin, ...) into a fixed-size
+//! buffer and hands out *blocks*: runs of whole lines, each ending in `\n`
+//! (except possibly the very last line of the input). Memory use stays at
+//! roughly `BLOCK_SIZE` no matter how big the input is — only a single line
+//! longer than the buffer makes it grow, up to `MAX_LINE_LEN`; past that the
+//! line is handed out in pieces.
+//!
+//! Blocks are raw bytes, so input doesn't have to be valid UTF-8; `lines`
+//! splits a block into lines and `String::from_utf8_lossy` is used to display
+//! them (invalid sequences show up as `U+FFFD`).
+//!
+//! Concepts: `Read`, buffer management with `Vec<u8>`, `copy_within`,
+//! returning borrowed slices from `&mut self`.
+
+use std::io::{self, Read};
+
+use crate::literal::memchr;
+
+/// How much input to read at a time.
+pub const BLOCK_SIZE: usize = 64 * 1024;
+
+/// The longest single line kept in one piece. Longer lines are split, so a
+/// match straddling a split point can be missed — a trade-off for never
+/// holding a multi-gigabyte "line" in memory. A block that doesn't end in
+/// `\n` (except at the end of the input) is such a piece, and the next
+/// block's first line continues it: the searcher numbers and counts the
+/// pieces as one line.
+#[cfg(not(test))]
+pub const MAX_LINE_LEN: usize = 16 * 1024 * 1024;
+/// Small enough for tests to split lines (once they outgrow `BLOCK_SIZE`).
+#[cfg(test)]
+pub const MAX_LINE_LEN: usize = 1024;
+
+/// Reads `R` in blocks of whole lines.
+pub struct LineBuffer<R> {
+    reader: R,
+    buf: Vec<u8>,
+    /// `buf[start..end]` holds bytes read but not yet handed out.
+    start: usize,
+    end: usize,
+    eof: bool,
+}
+
+impl<R: Read> LineBuffer<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_capacity(reader, BLOCK_SIZE)
+    }
+
+    /// Like `new`, with an explicit starting buffer size (handy for tests).
+    pub fn with_capacity(reader: R, capacity: usize) -> Self {
+        LineBuffer {
+            reader,
+            buf: vec![0; capacity.max(1)],
+            start: 0,
+            end: 0,
+            eof: false,
+        }
+    }
+
+    /// The next block of complete lines, or `None` once the input is used up.
+    pub fn next_block(&mut self) -> io::Result<Option<&[u8]>> {
+        // Slide whatever is left of the previous fill to the front.
+        self.buf.copy_within(self.start..self.end, 0);
+        self.end -= self.start;
+        self.start = 0;
+
+        // Bytes before `scanned` are known not to contain a newline.
+        let mut scanned = 0;
+        loop {
+            if let Some(i) = self.buf[scanned..self.end]
+                .iter()
+                .rposition(|&b| b == b'\n')
+            {
+                let cut = scanned + i + 1;
+                self.start = cut;
+                return Ok(Some(&self.buf[..cut]));
+            }
+            scanned = self.end;
+
+            if self.eof {
+                if self.end == 0 {
+                    return Ok(None);
+                }
+                self.start = self.end;
+                return Ok(Some(&self.buf[..self.end]));
+            }
+
+            if self.end == self.buf.len() {
+                if self.buf.len() >= MAX_LINE_LEN {
+                    // One enormous line: hand it out in pieces.
+                    self.start = self.end;
+                    return Ok(Some(&self.buf[..self.end]));
+                }
+                let grown = (self.buf.len() * 2).min(MAX_LINE_LEN);
+                self.buf.resize(grown, 0);
+            }
+
+            match self.reader.read(&mut self.buf[self.end..]) {
+                Ok(0) => self.eof = true,
+                Ok(n) => self.end += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Split a block into `(offset within block, line)` pairs. Lines don't
+/// include their `\n` / `\r\n` terminator.
+pub fn lines(block: &[u8]) -> impl Iterator<Item = (usize, &[u8])> {
+    block
+        .split_inclusive(|&b| b == b'\n')
+        .scan(0, |offset, raw| {
+            let start = *offset;
+            *offset += raw.len();
+            Some((start, trim_terminator(raw)))
+        })
+}
+
+/// `raw` without its `\n` / `\r\n` terminator, if it has one.
+pub fn trim_terminator(raw: &[u8]) -> &[u8] {
+    let line = raw.strip_suffix(b"\n").unwrap_or(raw);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+/// Index just past the line that `pos` is in: after its `\n`, or the end
+/// of `block` for a last line without one.
+pub fn line_end(block: &[u8], pos: usize) -> usize {
+    memchr(b'\n', &block[pos..]).map_or(block.len(), |i| pos + i + 1)
+}
+
+/// Number of lines in `region`, which holds whole lines; the last one may
+/// lack its `\n`.
+pub fn count_lines(region: &[u8]) -> usize {
+    let newlines = region.iter().filter(|&&b| b == b'\n').count();
+    newlines + usize::from(!region.is_empty() && !region.ends_with(b"\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(input: &[u8], capacity: usize) -> Vec<Vec<u8>> {
+        let mut reader = LineBuffer::with_capacity(input, capacity);
+        let mut out = Vec::new();
+        while let Some(block) = reader.next_block().unwrap() {
+            out.push(block.to_vec());
+        }
+        out
+    }
+
+    #[test]
+    fn blocks_always_end_on_line_boundaries() {
+        let input = b"one\ntwo\nthree\nfour";
+        let got = blocks(input, 6);
+        assert!(got[..got.len() - 1].iter().all(|b| b.ends_with(b"\n")));
+        assert_eq!(got.concat(), input.to_vec());
+    }
+
+    #[test]
+    fn long_lines_grow_the_buffer_instead_of_splitting() {
+        let input = b"short\na-much-longer-line-than-the-buffer\nend\n";
+        let got = blocks(input, 4);
+        let all_lines: Vec<&[u8]> = got.iter().flat_map(|b| lines(b).map(|(_, l)| l)).collect();
+        assert_eq!(
+            all_lines,
+            vec![&b"short"[..], b"a-much-longer-line-than-the-buffer", b"end"]
+        );
+    }
+
+    #[test]
+    fn lines_handle_crlf_and_invalid_utf8() {
+        let block = b"ok\r\nbad \xff byte\n";
+        let got: Vec<_> = lines(block).collect();
+        assert_eq!(got, vec![(0, &b"ok"[..]), (4, &b"bad \xff byte"[..])]);
+        assert_eq!(String::from_utf8_lossy(got[1].1), "bad \u{FFFD} byte");
+    }
+}
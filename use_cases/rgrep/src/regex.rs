@@ -0,0 +1,1111 @@
+//! A small regular-expression engine for `-E` mode.
+//!
+//! A pattern goes through three stages: it is parsed into a syntax tree
+//! (`Node`), compiled into a flat list of NFA instructions (`Inst`), and then
+//! run by a Pike VM. The VM advances every live thread through the NFA in
+//! lock step, one input character at a time, so it never backtracks: matching
+//! is `O(pattern length × input length)` no matter how the pattern is written.
+//!
+//! Supported syntax:
+//!
+//! | Syntax | Meaning |
+//! |---|---|
+//! | `a`, `\.`, `\n`, `\t` | literal characters |
+//! | `.` | any character except newline |
+//! | `[abc]`, `[a-z]`, `[^0-9]` | character classes (may contain `\d \w \s`) |
+//! | `\d \D \w \W \s \S` | digit / word / whitespace classes and their negations |
+//! | `^ $` | start / end of line |
+//! | `\b \B` | word boundary / not a word boundary |
+//! | `a\|b` | alternation |
+//! | `(...)`, `(?:...)` | capturing / non-capturing groups |
+//! | `* + ? {n} {n,} {n,m}` | repetition, greedy; add a trailing `?` for lazy |
+//!
+//! `\d`, `\w` and `\s` are ASCII-only, but `\b` and `\B` know the word
+//! characters of every script (see `unicode`).
+//!
+//! `RegexOptions` adds the matching modes behind `grep`'s `-i`, `-w` and
+//! `-x`; they're applied while parsing, so the VM itself doesn't know about
+//! them. `-i` uses Unicode simple case folding.
+//!
+//! Concepts: enums as syntax trees, recursive descent parsing, `Box<T>` for
+//! recursive types, slices of `Option<usize>` as capture slots.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::unicode::{case_variants, is_word_char};
+
+/// Upper bound on `{n,m}` counts, so `a{1000000}` can't blow up the program.
+const MAX_REPEAT: u32 = 1000;
+
+/// Upper bound on compiled program size, in instructions.
+const MAX_PROGRAM_LEN: usize = 100_000;
+
+/// Why a pattern failed to compile, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    message: String,
+    position: usize,
+}
+
+impl Error {
+    fn new(message: impl Into<String>, position: usize) -> Self {
+        Error {
+            message: message.into(),
+            position,
+        }
+    }
+
+    /// Character offset into the pattern where the problem was found.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Matching modes applied on top of the pattern syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RegexOptions {
+    /// `-i`: letters match in any case (Unicode simple case folding).
+    pub case_insensitive: bool,
+    /// `-w`: a match may not touch a word character, in any script, on
+    /// either side.
+    pub whole_word: bool,
+    /// `-x`: a match must span a whole line.
+    pub whole_line: bool,
+}
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    source: String,
+    program: Program,
+    caches: Caches,
+}
+
+/// Capture group positions from one match. Group 0 is the whole match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures {
+    slots: Vec<Option<usize>>,
+}
+
+impl Captures {
+    /// Byte range of group `index`, or `None` if that group didn't take part
+    /// in the match.
+    pub fn get(&self, index: usize) -> Option<(usize, usize)> {
+        match (self.slots.get(index * 2)?, self.slots.get(index * 2 + 1)?) {
+            (Some(start), Some(end)) => Some((*start, *end)),
+            _ => None,
+        }
+    }
+
+    /// Number of groups, including group 0.
+    pub fn len(&self) -> usize {
+        self.slots.len() / 2
+    }
+
+    /// Always `false`: group 0 is present in every successful match.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+}
+
+impl Regex {
+    /// Parse and compile `pattern`.
+    pub fn new(pattern: &str) -> Result<Regex, Error> {
+        Regex::with_options(pattern, &RegexOptions::default())
+    }
+
+    /// Parse and compile `pattern` with the given matching modes.
+    pub fn with_options(pattern: &str, options: &RegexOptions) -> Result<Regex, Error> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            groups: 0,
+            case_insensitive: options.case_insensitive,
+        };
+        let mut node = parser.parse()?;
+        if options.whole_word {
+            node = Node::Concat(vec![
+                Node::Look(Look::NotAfterWord),
+                group(node),
+                Node::Look(Look::NotBeforeWord),
+            ]);
+        }
+        if options.whole_line {
+            node = Node::Concat(vec![
+                Node::Look(Look::LineStart),
+                group(node),
+                Node::Look(Look::LineEnd),
+            ]);
+        }
+        let program = Compiler::compile(&node, parser.groups)?;
+        Ok(Regex {
+            source: pattern.to_string(),
+            program,
+            caches: Caches::default(),
+        })
+    }
+
+    /// The pattern this regex was compiled from.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Number of capture groups, including the implicit group 0.
+    pub fn captures_len(&self) -> usize {
+        self.program.slots / 2
+    }
+
+    /// `true` if the regex matches anywhere in `haystack`.
+    pub fn is_match(&self, haystack: &str) -> bool {
+        self.find_at(haystack, 0).is_some()
+    }
+
+    /// Byte range of the leftmost match starting at or after `start`.
+    pub fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        self.captures_at(haystack, start)?.get(0)
+    }
+
+    /// Like `find_at`, but also reports where each capture group matched.
+    pub fn captures_at(&self, haystack: &str, start: usize) -> Option<Captures> {
+        let mut cache = self.caches.take(&self.program);
+        let slots = self.program.exec(&mut cache, haystack, start);
+        self.caches.put(cache);
+        slots.map(|slots| Captures { slots })
+    }
+}
+
+/// `literal` with every regex metacharacter backslash-escaped, so that it
+/// compiles to a pattern matching exactly that text.
+pub fn escape(literal: &str) -> String {
+    let mut out = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if r"\.+*?()|[]{}^$".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Syntax tree + parser
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Look {
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+    /// No word character just before (the start of a `-w` match).
+    NotAfterWord,
+    /// No word character just after (the end of a `-w` match).
+    NotBeforeWord,
+}
+
+/// A set of characters, stored as sorted inclusive ranges.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CharClass {
+    ranges: Vec<(char, char)>,
+}
+
+impl CharClass {
+    fn new(mut ranges: Vec<(char, char)>) -> Self {
+        ranges.sort_unstable();
+        let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+        for (lo, hi) in ranges {
+            if let Some(last) = merged.last_mut()
+                && (lo as u32) <= (last.1 as u32).saturating_add(1)
+            {
+                last.1 = last.1.max(hi);
+                continue;
+            }
+            merged.push((lo, hi));
+        }
+        CharClass { ranges: merged }
+    }
+
+    fn digit() -> Self {
+        CharClass::new(vec![('0', '9')])
+    }
+
+    fn word() -> Self {
+        CharClass::new(vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')])
+    }
+
+    fn space() -> Self {
+        CharClass::new(vec![('\t', '\r'), (' ', ' ')])
+    }
+
+    /// Every character *not* in `self`.
+    fn negate(&self) -> Self {
+        let mut out = Vec::new();
+        let mut next = 0u32;
+        for &(lo, hi) in &self.ranges {
+            if (lo as u32) > next {
+                push_scalar_range(&mut out, next, lo as u32 - 1);
+            }
+            next = hi as u32 + 1;
+        }
+        if next <= char::MAX as u32 {
+            push_scalar_range(&mut out, next, char::MAX as u32);
+        }
+        CharClass { ranges: out }
+    }
+
+    /// `self` plus every character that case-folds together with one in it
+    /// (see `unicode`). Huge ranges are left alone: they already cover most
+    /// of what folding would add.
+    fn case_fold(&self) -> Self {
+        const MAX_FOLD_RANGE: u32 = 10_000;
+        let mut ranges = self.ranges.clone();
+        for &(lo, hi) in &self.ranges {
+            if hi as u32 - lo as u32 > MAX_FOLD_RANGE {
+                continue;
+            }
+            for c in lo..=hi {
+                ranges.extend(case_variants(c).map(|v| (v, v)));
+            }
+        }
+        CharClass::new(ranges)
+    }
+
+    fn matches(&self, c: char) -> bool {
+        self.ranges
+            .binary_search_by(|&(lo, hi)| {
+                if hi < c {
+                    std::cmp::Ordering::Less
+                } else if lo > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+}
+
+/// Push `lo..=hi` as char ranges, stepping around the surrogate gap
+/// (`U+D800..=U+DFFF`), which isn't made of valid `char`s.
+fn push_scalar_range(out: &mut Vec<(char, char)>, lo: u32, hi: u32) {
+    const GAP: (u32, u32) = (0xD800, 0xDFFF);
+    let pieces = [(lo, hi.min(GAP.0 - 1)), (lo.max(GAP.1 + 1), hi)];
+    for (a, b) in pieces {
+        if a <= b
+            && let (Some(a), Some(b)) = (char::from_u32(a), char::from_u32(b))
+        {
+            out.push((a, b));
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Literal(char),
+    AnyExceptNewline,
+    Class(CharClass),
+    Look(Look),
+    Group {
+        index: Option<usize>,
+        node: Box<Node>,
+    },
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+}
+
+/// Wrap `node` in a non-capturing group.
+fn group(node: Node) -> Node {
+    Node::Group {
+        index: None,
+        node: Box::new(node),
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    groups: usize,
+    /// `-i`: turn every letter into a class of both its cases.
+    case_insensitive: bool,
+}
+
+impl Parser {
+    fn parse(&mut self) -> Result<Node, Error> {
+        let node = self.parse_alternation()?;
+        if self.pos < self.chars.len() {
+            // The only thing that stops `parse_alternation` early is a `)`.
+            return Err(Error::new("unmatched ')'", self.pos));
+        }
+        Ok(node)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alternation(&mut self) -> Result<Node, Error> {
+        let mut branches = vec![self.parse_concat()?];
+        while self.eat('|') {
+            branches.push(self.parse_concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alternate(branches)
+        })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, Error> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            items.push(self.parse_repeat()?);
+        }
+        Ok(match items.len() {
+            0 => Node::Empty,
+            1 => items.pop().unwrap(),
+            _ => Node::Concat(items),
+        })
+    }
+
+    fn parse_repeat(&mut self) -> Result<Node, Error> {
+        let atom = self.parse_atom()?;
+        let start = self.pos;
+        let (min, max) = match self.peek() {
+            Some('*') => {
+                self.pos += 1;
+                (0, None)
+            }
+            Some('+') => {
+                self.pos += 1;
+                (1, None)
+            }
+            Some('?') => {
+                self.pos += 1;
+                (0, Some(1))
+            }
+            Some('{') => match self.parse_counted()? {
+                Some(bounds) => bounds,
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        let greedy = !self.eat('?');
+        if matches!(self.peek(), Some('*' | '+' | '?')) {
+            return Err(Error::new("nested repetition", self.pos));
+        }
+        if matches!(atom, Node::Look(_)) {
+            return Err(Error::new("nothing to repeat", start));
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy,
+        })
+    }
+
+    /// Parse `{n}`, `{n,}` or `{n,m}` at the cursor. Anything else leaves the
+    /// cursor alone and returns `None`, so the `{` is read as a literal.
+    fn parse_counted(&mut self) -> Result<Option<(u32, Option<u32>)>, Error> {
+        let start = self.pos;
+        self.pos += 1; // '{'
+        let min = self.parse_number();
+        let max = if self.eat(',') {
+            if self.peek() == Some('}') {
+                None
+            } else {
+                match self.parse_number() {
+                    Some(n) => Some(n),
+                    None => {
+                        self.pos = start;
+                        return Ok(None);
+                    }
+                }
+            }
+        } else {
+            min
+        };
+        let Some(min) = min else {
+            self.pos = start;
+            return Ok(None);
+        };
+        if !self.eat('}') {
+            self.pos = start;
+            return Ok(None);
+        }
+        if min > MAX_REPEAT || max.is_some_and(|m| m > MAX_REPEAT) {
+            return Err(Error::new("repetition count too large", start));
+        }
+        if max.is_some_and(|m| m < min) {
+            return Err(Error::new("repetition range out of order", start));
+        }
+        Ok(Some((min, max)))
+    }
+
+    fn parse_number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return None;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        // Overflowing u32 is certainly over MAX_REPEAT; saturate so the range
+        // check reports it.
+        Some(digits.parse().unwrap_or(u32::MAX))
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, Error> {
+        let start = self.pos;
+        let c = self.peek().expect("parse_concat checked for end of input");
+        self.pos += 1;
+        match c {
+            '(' => {
+                let index = if self.eat('?') {
+                    if !self.eat(':') {
+                        return Err(Error::new("unsupported group flag", self.pos));
+                    }
+                    None
+                } else {
+                    self.groups += 1;
+                    Some(self.groups)
+                };
+                let node = self.parse_alternation()?;
+                if !self.eat(')') {
+                    return Err(Error::new("unclosed group", start));
+                }
+                Ok(Node::Group {
+                    index,
+                    node: Box::new(node),
+                })
+            }
+            '[' => self.parse_class(start),
+            '.' => Ok(Node::AnyExceptNewline),
+            '^' => Ok(Node::Look(Look::LineStart)),
+            '$' => Ok(Node::Look(Look::LineEnd)),
+            '*' | '+' | '?' => Err(Error::new("nothing to repeat", start)),
+            '\\' => self.parse_escape(start),
+            c => Ok(self.literal(c)),
+        }
+    }
+
+    /// A literal character, widened to a class of its cases under `-i`.
+    fn literal(&self, c: char) -> Node {
+        if !self.case_insensitive {
+            return Node::Literal(c);
+        }
+        let mut ranges = vec![(c, c)];
+        ranges.extend(case_variants(c).map(|v| (v, v)));
+        if ranges.len() == 1 {
+            Node::Literal(c)
+        } else {
+            Node::Class(CharClass::new(ranges))
+        }
+    }
+
+    fn parse_escape(&mut self, start: usize) -> Result<Node, Error> {
+        let Some(c) = self.peek() else {
+            return Err(Error::new("trailing backslash", start));
+        };
+        self.pos += 1;
+        Ok(match c {
+            'b' => Node::Look(Look::WordBoundary),
+            'B' => Node::Look(Look::NotWordBoundary),
+            _ => match escape_class(c) {
+                Some(class) => Node::Class(class),
+                None => self.literal(escape_literal(c, start)?),
+            },
+        })
+    }
+
+    fn parse_class(&mut self, start: usize) -> Result<Node, Error> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(Error::new("unclosed character class", start));
+            };
+            self.pos += 1;
+            // A `]` right after `[` or `[^` is a literal, as in POSIX.
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let lo = if c == '\\' {
+                let Some(e) = self.peek() else {
+                    return Err(Error::new("unclosed character class", start));
+                };
+                self.pos += 1;
+                if let Some(class) = escape_class(e) {
+                    ranges.extend(class.ranges);
+                    continue;
+                }
+                escape_literal(e, self.pos - 2)?
+            } else {
+                c
+            };
+
+            let is_range =
+                self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']');
+            if !is_range {
+                ranges.push((lo, lo));
+                continue;
+            }
+            self.pos += 1; // '-'
+            let hi_pos = self.pos;
+            let mut hi = self.chars[self.pos];
+            self.pos += 1;
+            if hi == '\\' {
+                let Some(e) = self.peek() else {
+                    return Err(Error::new("unclosed character class", start));
+                };
+                self.pos += 1;
+                hi = escape_literal(e, hi_pos)?;
+            }
+            if hi < lo {
+                return Err(Error::new("character range out of order", hi_pos));
+            }
+            ranges.push((lo, hi));
+        }
+        let mut class = CharClass::new(ranges);
+        if self.case_insensitive {
+            class = class.case_fold();
+        }
+        Ok(Node::Class(if negated {
+            // Like `.`, a negated class never matches a line break.
+            CharClass::new(class.ranges.into_iter().chain([('\n', '\n')]).collect()).negate()
+        } else {
+            class
+        }))
+    }
+}
+
+fn escape_class(c: char) -> Option<CharClass> {
+    Some(match c {
+        'd' => CharClass::digit(),
+        'D' => CharClass::digit().negate(),
+        'w' => CharClass::word(),
+        'W' => CharClass::word().negate(),
+        's' => CharClass::space(),
+        'S' => CharClass::space().negate(),
+        _ => return None,
+    })
+}
+
+fn escape_literal(c: char, position: usize) -> Result<char, Error> {
+    match c {
+        'n' => Ok('\n'),
+        't' => Ok('\t'),
+        'r' => Ok('\r'),
+        c if c.is_ascii_alphanumeric() => {
+            Err(Error::new(format!("unknown escape '\\{c}'"), position))
+        }
+        c => Ok(c),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Compiler
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(char),
+    Class(CharClass),
+    AnyExceptNewline,
+    Look(Look),
+    /// Record the current position in capture slot `n`.
+    Save(usize),
+    /// Try both targets; the first has priority.
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+#[derive(Debug, Clone)]
+struct Program {
+    insts: Vec<Inst>,
+    slots: usize,
+}
+
+struct Compiler {
+    insts: Vec<Inst>,
+}
+
+impl Compiler {
+    fn compile(node: &Node, groups: usize) -> Result<Program, Error> {
+        let mut c = Compiler { insts: Vec::new() };
+        c.emit(Inst::Save(0));
+        c.node(node)?;
+        c.emit(Inst::Save(1));
+        c.emit(Inst::Match);
+        Ok(Program {
+            insts: c.insts,
+            slots: (groups + 1) * 2,
+        })
+    }
+
+    fn emit(&mut self, inst: Inst) -> usize {
+        self.insts.push(inst);
+        self.insts.len() - 1
+    }
+
+    fn patch_split(&mut self, at: usize, first: usize, second: usize) {
+        self.insts[at] = Inst::Split(first, second);
+    }
+
+    fn node(&mut self, node: &Node) -> Result<(), Error> {
+        if self.insts.len() > MAX_PROGRAM_LEN {
+            return Err(Error::new("pattern too large", 0));
+        }
+        match node {
+            Node::Empty => {}
+            Node::Literal(c) => {
+                self.emit(Inst::Char(*c));
+            }
+            Node::AnyExceptNewline => {
+                self.emit(Inst::AnyExceptNewline);
+            }
+            Node::Class(class) => {
+                self.emit(Inst::Class(class.clone()));
+            }
+            Node::Look(look) => {
+                self.emit(Inst::Look(*look));
+            }
+            Node::Group { index, node } => match index {
+                Some(i) => {
+                    self.emit(Inst::Save(i * 2));
+                    self.node(node)?;
+                    self.emit(Inst::Save(i * 2 + 1));
+                }
+                None => self.node(node)?,
+            },
+            Node::Concat(nodes) => {
+                for n in nodes {
+                    self.node(n)?;
+                }
+            }
+            Node::Alternate(branches) => {
+                let mut jumps = Vec::new();
+                let (last, rest) = branches.split_last().expect("alternation has branches");
+                for branch in rest {
+                    let split = self.emit(Inst::Split(0, 0));
+                    self.node(branch)?;
+                    jumps.push(self.emit(Inst::Jump(0)));
+                    let next = self.insts.len();
+                    self.patch_split(split, split + 1, next);
+                }
+                self.node(last)?;
+                let end = self.insts.len();
+                for j in jumps {
+                    self.insts[j] = Inst::Jump(end);
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => self.repeat(node, *min, *max, *greedy)?,
+        }
+        Ok(())
+    }
+
+    fn repeat(
+        &mut self,
+        node: &Node,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    ) -> Result<(), Error> {
+        for _ in 0..min {
+            self.node(node)?;
+        }
+        match max {
+            None => {
+                // L: split(body, out); body; jump L; out:
+                let split = self.emit(Inst::Split(0, 0));
+                self.node(node)?;
+                self.emit(Inst::Jump(split));
+                let out = self.insts.len();
+                self.order_split(split, split + 1, out, greedy);
+            }
+            Some(max) => {
+                // Each optional copy: split(body, out); body; ... out:
+                let mut splits = Vec::new();
+                for _ in min..max {
+                    splits.push(self.emit(Inst::Split(0, 0)));
+                    self.node(node)?;
+                }
+                let out = self.insts.len();
+                for split in splits {
+                    self.order_split(split, split + 1, out, greedy);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn order_split(&mut self, at: usize, body: usize, out: usize, greedy: bool) {
+        if greedy {
+            self.patch_split(at, body, out);
+        } else {
+            self.patch_split(at, out, body);
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Pike VM
+// ---------------------------------------------------------------------------
+
+/// The set of threads alive at one input position, in priority order, with
+/// each thread's capture slots stored side by side in `slots`.
+struct Threads {
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+    slots: Vec<Option<usize>>,
+}
+
+impl Threads {
+    fn new(insts: usize, slots: usize) -> Self {
+        Threads {
+            dense: Vec::with_capacity(insts),
+            sparse: vec![0; insts],
+            slots: vec![None; insts * slots],
+        }
+    }
+
+    fn contains(&self, pc: usize) -> bool {
+        let i = self.sparse[pc];
+        i < self.dense.len() && self.dense[i] == pc
+    }
+
+    fn insert(&mut self, pc: usize) {
+        self.sparse[pc] = self.dense.len();
+        self.dense.push(pc);
+    }
+
+    fn clear(&mut self) {
+        self.dense.clear();
+    }
+}
+
+enum Frame {
+    Explore(usize),
+    RestoreSlot(usize, Option<usize>),
+}
+
+/// Everything one run of the VM works in, sized for one program. It's
+/// cleared rather than reallocated between runs, since a search runs the
+/// VM on every line.
+struct Cache {
+    current: Threads,
+    next: Threads,
+    stack: Vec<Frame>,
+    scratch: Vec<Option<usize>>,
+}
+
+impl Cache {
+    fn new(program: &Program) -> Self {
+        let (insts, slots) = (program.insts.len(), program.slots);
+        Cache {
+            current: Threads::new(insts, slots),
+            next: Threads::new(insts, slots),
+            stack: Vec::new(),
+            scratch: vec![None; slots],
+        }
+    }
+}
+
+/// The caches not in use right now: one per thread searching with the
+/// regex at the same time, at most. A clone starts with none.
+#[derive(Default)]
+struct Caches(Mutex<Vec<Cache>>);
+
+impl Caches {
+    fn take(&self, program: &Program) -> Cache {
+        let cache = self.0.lock().unwrap().pop();
+        cache.unwrap_or_else(|| Cache::new(program))
+    }
+
+    fn put(&self, cache: Cache) {
+        self.0.lock().unwrap().push(cache);
+    }
+}
+
+impl Clone for Caches {
+    fn clone(&self) -> Self {
+        Caches::default()
+    }
+}
+
+impl fmt::Debug for Caches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Caches")
+    }
+}
+
+impl Program {
+    fn exec(&self, cache: &mut Cache, haystack: &str, start: usize) -> Option<Vec<Option<usize>>> {
+        let n = self.slots;
+        let Cache {
+            current,
+            next,
+            stack,
+            scratch,
+        } = cache;
+        current.clear();
+        next.clear();
+        let mut matched: Option<Vec<Option<usize>>> = None;
+        let mut pos = start;
+
+        loop {
+            // Leftmost semantics: only start new attempts until something has
+            // matched; after that we're just letting higher-priority threads
+            // finish.
+            if matched.is_none() {
+                scratch.fill(None);
+                self.add_thread(current, stack, 0, haystack, pos, scratch);
+            }
+            if current.dense.is_empty() {
+                break;
+            }
+
+            let c = haystack[pos..].chars().next();
+            let next_pos = pos + c.map_or(0, char::len_utf8);
+            for i in 0..current.dense.len() {
+                let pc = current.dense[i];
+                let thread_slots = &current.slots[pc * n..(pc + 1) * n];
+                let advance = match (&self.insts[pc], c) {
+                    (Inst::Char(want), Some(c)) => *want == c,
+                    (Inst::Class(class), Some(c)) => class.matches(c),
+                    (Inst::AnyExceptNewline, Some(c)) => c != '\n',
+                    (Inst::Match, _) => {
+                        matched = Some(thread_slots.to_vec());
+                        // Everything after this thread has lower priority.
+                        break;
+                    }
+                    _ => false,
+                };
+                if advance {
+                    scratch.copy_from_slice(thread_slots);
+                    self.add_thread(next, stack, pc + 1, haystack, next_pos, scratch);
+                }
+            }
+
+            if c.is_none() {
+                break;
+            }
+            pos = next_pos;
+            std::mem::swap(current, next);
+            next.clear();
+        }
+        matched
+    }
+
+    /// Follow every non-consuming instruction reachable from `pc`, adding the
+    /// consuming ones (and `Match`) to `list` in priority order.
+    fn add_thread(
+        &self,
+        list: &mut Threads,
+        stack: &mut Vec<Frame>,
+        pc: usize,
+        haystack: &str,
+        pos: usize,
+        slots: &mut [Option<usize>],
+    ) {
+        let n = self.slots;
+        stack.push(Frame::Explore(pc));
+        while let Some(frame) = stack.pop() {
+            let pc = match frame {
+                Frame::RestoreSlot(slot, old) => {
+                    slots[slot] = old;
+                    continue;
+                }
+                Frame::Explore(pc) => pc,
+            };
+            if list.contains(pc) {
+                continue;
+            }
+            list.insert(pc);
+            match &self.insts[pc] {
+                Inst::Jump(target) => stack.push(Frame::Explore(*target)),
+                Inst::Split(first, second) => {
+                    stack.push(Frame::Explore(*second));
+                    stack.push(Frame::Explore(*first));
+                }
+                Inst::Save(slot) => {
+                    stack.push(Frame::RestoreSlot(*slot, slots[*slot]));
+                    slots[*slot] = Some(pos);
+                    stack.push(Frame::Explore(pc + 1));
+                }
+                Inst::Look(look) => {
+                    if look_matches(*look, haystack, pos) {
+                        stack.push(Frame::Explore(pc + 1));
+                    }
+                }
+                Inst::Char(_) | Inst::Class(_) | Inst::AnyExceptNewline | Inst::Match => {
+                    list.slots[pc * n..(pc + 1) * n].copy_from_slice(slots);
+                }
+            }
+        }
+    }
+}
+
+fn look_matches(look: Look, haystack: &str, pos: usize) -> bool {
+    let before = haystack[..pos].chars().next_back();
+    let after = haystack[pos..].chars().next();
+    match look {
+        Look::LineStart => before.is_none_or(|c| c == '\n'),
+        Look::LineEnd => after.is_none_or(|c| c == '\n'),
+        Look::WordBoundary => before.is_some_and(is_word_char) != after.is_some_and(is_word_char),
+        Look::NotWordBoundary => {
+            before.is_some_and(is_word_char) == after.is_some_and(is_word_char)
+        }
+        Look::NotAfterWord => !before.is_some_and(is_word_char),
+        Look::NotBeforeWord => !after.is_some_and(is_word_char),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, haystack: &str) -> Option<(usize, usize)> {
+        Regex::new(pattern).unwrap().find_at(haystack, 0)
+    }
+
+    #[test]
+    fn matches_anchored_alternation_with_groups() {
+        let re = Regex::new(r"^ERROR \[(db|net)\]").unwrap();
+        let caps = re.captures_at("ERROR [net] connection reset", 0).unwrap();
+        assert_eq!(caps.get(0), Some((0, 11)));
+        assert_eq!(caps.get(1), Some((7, 10)));
+        assert!(!re.is_match("ERROR [disk] full"));
+        assert!(!re.is_match("  ERROR [db] late"));
+    }
+
+    #[test]
+    fn classes_and_repetition() {
+        assert_eq!(find(r"job \d+", "processed job 1042"), Some((10, 18)));
+        assert_eq!(find(r"[^ ]+$", "retrying cache connection"), Some((15, 25)));
+        assert_eq!(find(r"a{2,3}", "caaaat"), Some((1, 4)));
+        assert_eq!(find(r"x{2}", "x-xx"), Some((2, 4)));
+        assert_eq!(find(r"[a-c-]+", "zz-abcq"), Some((2, 6)));
+    }
+
+    #[test]
+    fn leftmost_first_and_lazy_repetition() {
+        assert_eq!(find("a|ab", "ab"), Some((0, 1)));
+        assert_eq!(find("<.+>", "<a><b>"), Some((0, 6)));
+        assert_eq!(find("<.+?>", "<a><b>"), Some((0, 3)));
+    }
+
+    #[test]
+    fn word_boundaries_and_unmatched_groups() {
+        assert_eq!(find(r"\berr\b", "stderr err"), Some((7, 10)));
+        let re = Regex::new("(a)|(b)").unwrap();
+        let caps = re.captures_at("b", 0).unwrap();
+        assert_eq!(caps.len(), 3);
+        assert_eq!(caps.get(1), None);
+        assert_eq!(caps.get(2), Some((0, 1)));
+    }
+
+    #[test]
+    fn runs_share_one_cache_and_leave_nothing_behind_in_it() {
+        let re = Regex::new(r"(\w+)@(\w+)|x+").unwrap();
+        // The first run stops with threads still live; the next must not
+        // see them.
+        assert_eq!(re.find_at("xxxx a@b", 0), Some((0, 4)));
+        let caps = re.captures_at("mail ann@example", 0).unwrap();
+        assert_eq!(caps.get(1), Some((5, 8)));
+        assert_eq!(caps.get(2), Some((9, 16)));
+        assert!(!re.is_match("none here"));
+        assert_eq!(re.caches.0.lock().unwrap().len(), 1);
+        assert!(re.clone().caches.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn pathological_pattern_runs_in_linear_time() {
+        let haystack = "a".repeat(5_000);
+        assert!(!Regex::new("(a*)*b").unwrap().is_match(&haystack));
+    }
+
+    #[test]
+    fn options_fold_case_and_anchor_words_and_lines() {
+        let with = |pattern: &str, options: RegexOptions, haystack: &str| {
+            Regex::with_options(pattern, &options)
+                .unwrap()
+                .find_at(haystack, 0)
+        };
+        let icase = RegexOptions {
+            case_insensitive: true,
+            ..RegexOptions::default()
+        };
+        assert_eq!(with("error", icase, "An ErRoR"), Some((3, 8)));
+        assert_eq!(with("[a-c]+", icase, "xAbC"), Some((1, 4)));
+        assert_eq!(with("[^a]", icase, "Ab"), Some((1, 2)));
+
+        let word = RegexOptions {
+            whole_word: true,
+            ..RegexOptions::default()
+        };
+        assert_eq!(with("err", word, "stderr err"), Some((7, 10)));
+        assert_eq!(with("a|ab", word, "ab"), Some((0, 2)));
+        assert_eq!(with(r"\.", word, "a . b"), Some((2, 3)));
+
+        let line = RegexOptions {
+            whole_line: true,
+            ..RegexOptions::default()
+        };
+        assert_eq!(with("a|b", line, "b"), Some((0, 1)));
+        assert_eq!(with("a|b", line, "ab"), None);
+    }
+
+    #[test]
+    fn escape_makes_literals_safe_to_compile() {
+        let literal = r"a.b*(c)[d]{2}\e|^$";
+        let re = Regex::new(&escape(literal)).unwrap();
+        assert_eq!(re.find_at(literal, 0), Some((0, literal.len())));
+        assert!(!re.is_match("aXb"));
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        for bad in ["(abc", "abc)", "[abc", "*a", r"\q", "a{5,2}", r"x\"] {
+            assert!(Regex::new(bad).is_err(), "{bad:?} should not compile");
+        }
+    }
+}
@@ -0,0 +1,417 @@
+//! `--replace`: rewriting every match in place, or with `--dry-run`,
+//! showing what would change as a unified diff.
+//!
+//! Matching works line by line, exactly as in a search, so `-i`, `-w`, `-x`
+//! and `-m` mean the same thing here. With `-E` the replacement text is a
+//! `Template`: `$1` or `${1}` inserts what capture group 1 matched, `$0`
+//! the whole match, and `$$` a literal `$`. A plain (`-F`) pattern's
+//! replacement is used verbatim.
+//!
+//! Files are never rewritten in place: the new contents go to a temporary
+//! file next to the original, which is then renamed over it. A rename
+//! within one directory is atomic, so a crash or a full disk leaves either
+//! the old file or the new one — never half of each.
+//!
+//! Concepts: `split_inclusive`, building `String`s with `push_str`,
+//! `fs::rename`, `Permissions`, closures that return `io::Result`.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::search::{Matcher, Pattern, lines_with_offsets};
+
+/// Lines of unchanged context around each change in a diff, as in
+/// `diff -u`.
+const DIFF_CONTEXT: usize = 3;
+
+/// Replacement text, split into literal runs and capture-group references.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Group(usize),
+}
+
+impl Template {
+    /// Replacement text with no substitutions at all.
+    pub fn literal(text: &str) -> Template {
+        Template {
+            parts: vec![Part::Text(text.to_string())],
+        }
+    }
+
+    /// Parse `$N`, `${N}` and `$$`. A `$` followed by anything else is
+    /// kept as is; `None` for an unterminated or non-numeric `${...}`.
+    pub fn parse(text: &str) -> Option<Template> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(dollar) = rest.find('$') {
+            literal.push_str(&rest[..dollar]);
+            let after = &rest[dollar + 1..];
+            let (group, consumed) = if let Some(braced) = after.strip_prefix('{') {
+                let close = braced.find('}')?;
+                (Some(braced[..close].parse().ok()?), close + 2)
+            } else if after.starts_with('$') {
+                literal.push('$');
+                (None, 1)
+            } else {
+                let digits =
+                    after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                match after[..digits].parse() {
+                    Ok(n) => (Some(n), digits),
+                    Err(_) => {
+                        literal.push('$');
+                        (None, 0)
+                    }
+                }
+            };
+            if let Some(group) = group {
+                if !literal.is_empty() {
+                    parts.push(Part::Text(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Group(group));
+            }
+            rest = &after[consumed..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        Some(Template { parts })
+    }
+
+    /// The highest group number referenced, so the caller can check the
+    /// pattern actually has that many.
+    pub fn max_group(&self) -> usize {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Group(n) => *n,
+                Part::Text(_) => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Append the replacement for one match to `out`. `group` gives each
+    /// group's byte range in `line`; a group that didn't take part in the
+    /// match expands to nothing.
+    fn expand(
+        &self,
+        line: &str,
+        group: impl Fn(usize) -> Option<(usize, usize)>,
+        out: &mut String,
+    ) {
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Group(n) => {
+                    if let Some((start, end)) = group(*n) {
+                        out.push_str(&line[start..end]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `line` with every non-overlapping match of `pattern` replaced, or `None`
+/// if nothing matched. Like `search::find_iter`, an empty match never
+/// repeats at the same position.
+pub fn replace_line(pattern: &Pattern, template: &Template, line: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut copied = 0;
+    let mut start = 0;
+    let mut matched = false;
+    while start <= line.len() {
+        let Some((from, to, id)) = pattern.find_pattern_at(line, start) else {
+            break;
+        };
+        out.push_str(&line[copied..from]);
+        match pattern.member(id) {
+            // Search again from the match for its groups.
+            Some(Pattern::Regex(re)) => {
+                let captures = re.captures_at(line, from).expect("the regex matched here");
+                template.expand(line, |n| captures.get(n), &mut out);
+            }
+            _ => template.expand(line, |n| (n == 0).then_some((from, to)), &mut out),
+        }
+        matched = true;
+        copied = to;
+        start = if to > from {
+            to
+        } else {
+            let step = line[to..].chars().next().map_or(1, char::len_utf8);
+            // The character we step over is kept, not replaced.
+            out.push_str(&line[to..(to + step).min(line.len())]);
+            copied = (to + step).min(line.len());
+            to + step
+        };
+    }
+    matched.then(|| {
+        out.push_str(&line[copied..]);
+        out
+    })
+}
+
+/// One rewritten line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// 1-based line number in the original text.
+    pub line_number: usize,
+    /// The new line, without a terminator. May contain `\n` if the
+    /// replacement text does.
+    pub new: String,
+}
+
+/// The result of rewriting a whole file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rewrite {
+    /// The new file contents. Line terminators are kept as they were.
+    pub contents: String,
+    /// Every line that changed, in order.
+    pub edits: Vec<Edit>,
+}
+
+/// Replace matches line by line, changing at most `max_lines` lines (`-m`).
+pub fn rewrite(
+    pattern: &Pattern,
+    template: &Template,
+    contents: &str,
+    max_lines: Option<u64>,
+) -> Rewrite {
+    let mut out = String::with_capacity(contents.len());
+    let mut edits = Vec::new();
+    for (i, raw) in contents.split_inclusive('\n').enumerate() {
+        if max_lines.is_some_and(|max| edits.len() as u64 >= max) {
+            out.push_str(raw);
+            continue;
+        }
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match replace_line(pattern, template, line) {
+            Some(new) => {
+                out.push_str(&new);
+                out.push_str(&raw[line.len()..]);
+                edits.push(Edit {
+                    line_number: i + 1,
+                    new,
+                });
+            }
+            None => out.push_str(raw),
+        }
+    }
+    Rewrite {
+        contents: out,
+        edits,
+    }
+}
+
+/// A unified diff (`diff -u` style, readable by `patch -p0`) turning
+/// `original` into the result of `edits`. `name` labels both sides.
+pub fn unified_diff(name: &str, original: &str, edits: &[Edit]) -> String {
+    let lines: Vec<&str> = lines_with_offsets(original).map(|(_, line)| line).collect();
+    let missing_newline = !original.is_empty() && !original.ends_with('\n');
+    let mut out = format!("--- {name}\n+++ {name}\n");
+    // Added minus removed lines in hunks so far, to place the next hunk's
+    // new-side start.
+    let mut shift: isize = 0;
+    let mut rest = edits;
+    while let Some(first) = rest.first() {
+        // A hunk takes edits until the gap to the next one is too wide for
+        // their context to touch.
+        let mut taken = 1;
+        while taken < rest.len()
+            && rest[taken].line_number - rest[taken - 1].line_number <= 2 * DIFF_CONTEXT + 1
+        {
+            taken += 1;
+        }
+        let (hunk, later) = rest.split_at(taken);
+        rest = later;
+
+        let start = first.line_number.saturating_sub(DIFF_CONTEXT).max(1);
+        let end = (hunk[taken - 1].line_number + DIFF_CONTEXT).min(lines.len());
+        let old_count = end - start + 1;
+        let added: usize = hunk.iter().map(|e| e.new.split('\n').count()).sum();
+        let new_count = old_count - hunk.len() + added;
+        let new_start = start as isize + shift;
+        out.push_str(&format!(
+            "@@ -{start},{old_count} +{new_start},{new_count} @@\n"
+        ));
+        shift += new_count as isize - old_count as isize;
+
+        let no_newline = |number: usize, out: &mut String| {
+            if missing_newline && number == lines.len() {
+                out.push_str("\\ No newline at end of file\n");
+            }
+        };
+        let mut number = start;
+        let mut edits = hunk.iter().peekable();
+        while number <= end {
+            // A run of consecutive edited lines: all old, then all new.
+            let mut run = Vec::new();
+            while let Some(edit) = edits.next_if(|e| e.line_number == number + run.len()) {
+                run.push(edit);
+            }
+            if run.is_empty() {
+                out.push_str(&format!(" {}\n", lines[number - 1]));
+                no_newline(number, &mut out);
+                number += 1;
+                continue;
+            }
+            for edit in &run {
+                out.push_str(&format!("-{}\n", lines[edit.line_number - 1]));
+                no_newline(edit.line_number, &mut out);
+            }
+            for edit in &run {
+                for line in edit.new.split('\n') {
+                    out.push_str(&format!("+{line}\n"));
+                }
+                no_newline(edit.line_number, &mut out);
+            }
+            number += run.len();
+        }
+    }
+    out
+}
+
+/// Replace the file at `path` with `contents`, keeping its permissions:
+/// write a temporary file in the same directory, then rename it over the
+/// original. A symlink is followed, so its target is what gets rewritten.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let path = fs::canonicalize(path)?;
+    let permissions = fs::metadata(&path)?.permissions();
+    let temp = temp_path(&path);
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp)?;
+        file.write_all(contents)?;
+        file.set_permissions(permissions)?;
+        file.sync_all()?;
+        fs::rename(&temp, &path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// `dir/.name.rgrep-PID.tmp` beside `path`: hidden, and unique per process.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.rgrep-{}.tmp", process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regex::RegexOptions;
+    use crate::test_util::TempDir;
+
+    fn regex(source: &str) -> Pattern {
+        Pattern::new(source, true, &RegexOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn templates_substitute_capture_groups() {
+        let pattern = regex("(\\w+)@(\\w+)");
+        let template = Template::parse("$2 at ${1}s, $$5 $x").unwrap();
+        assert_eq!(template.max_group(), 2);
+        assert_eq!(
+            replace_line(&pattern, &template, "mail bob@example and amy@test").as_deref(),
+            Some("mail example at bobs, $5 $x and test at amys, $5 $x")
+        );
+        assert_eq!(replace_line(&pattern, &template, "no address"), None);
+        assert_eq!(Template::parse("${1"), None);
+        assert_eq!(Template::parse("${x}"), None);
+    }
+
+    #[test]
+    fn literal_patterns_replace_verbatim_and_empty_matches_step_forward() {
+        let pattern = Pattern::Literal("ERROR".to_string());
+        let template = Template::literal("$1");
+        assert_eq!(
+            replace_line(&pattern, &template, "ERROR: ERROR").as_deref(),
+            Some("$1: $1")
+        );
+        let every = regex("x*");
+        assert_eq!(
+            replace_line(&every, &Template::literal("-"), "aé").as_deref(),
+            Some("-a-é-")
+        );
+    }
+
+    #[test]
+    fn rewrite_keeps_terminators_and_honors_max_lines() {
+        let pattern = Pattern::Literal("old".to_string());
+        let contents = "old one\r\nkeep\nold two\nold three";
+        let result = rewrite(&pattern, &Template::literal("new"), contents, None);
+        assert_eq!(result.contents, "new one\r\nkeep\nnew two\nnew three");
+        assert_eq!(result.edits.len(), 3);
+        assert_eq!(result.edits[1].line_number, 3);
+
+        let limited = rewrite(&pattern, &Template::literal("new"), contents, Some(1));
+        assert_eq!(limited.contents, "new one\r\nkeep\nold two\nold three");
+    }
+
+    #[test]
+    fn unified_diff_groups_nearby_changes_into_hunks() {
+        let original: String = (1..=20).map(|n| format!("line {n}\n")).collect();
+        let pattern = regex("^line (2|4|17)$");
+        let result = rewrite(
+            &pattern,
+            &Template::parse("LINE $1").unwrap(),
+            &original,
+            None,
+        );
+        let diff = unified_diff("f.txt", &original, &result.edits);
+        assert_eq!(
+            diff,
+            "--- f.txt\n+++ f.txt\n\
+             @@ -1,7 +1,7 @@\n line 1\n-line 2\n+LINE 2\n line 3\n-line 4\n+LINE 4\n\
+             \x20line 5\n line 6\n line 7\n\
+             @@ -14,7 +14,7 @@\n line 14\n line 15\n line 16\n-line 17\n+LINE 17\n\
+             \x20line 18\n line 19\n line 20\n"
+        );
+
+        let edits = [Edit {
+            line_number: 2,
+            new: "B\nC".to_string(),
+        }];
+        assert_eq!(
+            unified_diff("g", "a\nb", &edits),
+            "--- g\n+++ g\n@@ -1,2 +1,3 @@\n a\n-b\n\\ No newline at end of file\n+B\n+C\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn write_atomic_replaces_contents_and_keeps_permissions() {
+        let dir = TempDir::new("replace");
+        let path = dir.file("config.sh", "old");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o751)).unwrap();
+        }
+
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o751);
+        }
+        // Only the file itself is left: the temporary was renamed away.
+        assert_eq!(fs::read_dir(&dir.0).unwrap().count(), 1);
+    }
+}
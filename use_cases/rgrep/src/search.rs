@@ -1,14 +1,323 @@
-/// Return every line in `contents` that contains `pattern`, in order.
+use crate::aho_corasick::AhoCorasick;
+use crate::literal::Finder;
+use crate::reader;
+use crate::regex::{self, Regex, RegexOptions};
+
+/// Anything that can locate a pattern inside a line of text.
+///
+/// `search` is generic over this trait so the same line-filtering loop works
+/// for a plain `&str` (literal search, the default) and for a compiled
+/// `Regex` (`-E` mode).
+///
+/// Concepts: traits, `?Sized` (so `str` itself — not just `&str` — can
+/// implement it), default methods.
+pub trait Matcher {
+    /// Byte range of the leftmost match in `haystack` starting at or after
+    /// byte offset `start`.
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)>;
+
+    /// Like `find_at`, but also says which pattern matched, for matchers
+    /// holding several (`-e`/`-f`). A lone pattern is number 0.
+    fn find_pattern_at(&self, haystack: &str, start: usize) -> Option<(usize, usize, usize)> {
+        self.find_at(haystack, start)
+            .map(|(from, to)| (from, to, 0))
+    }
+
+    /// `true` if the pattern occurs anywhere in `haystack`.
+    fn is_match(&self, haystack: &str) -> bool {
+        self.find_at(haystack, 0).is_some()
+    }
+}
+
+impl Matcher for str {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        haystack[start..]
+            .find(self)
+            .map(|i| (start + i, start + i + self.len()))
+    }
+}
+
+impl Matcher for Regex {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        Regex::find_at(self, haystack, start)
+    }
+}
+
+/// The pattern a `Config` searches for, compiled once up front.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Plain substring search (the default).
+    Literal(String),
+    /// Regular-expression search (`-E` / `--regex`).
+    Regex(Regex),
+    /// Several plain strings (`-e` more than once, `-f FILE`), found in one
+    /// pass however many there are (see `aho_corasick`).
+    Literals {
+        sources: Vec<String>,
+        matcher: Box<AhoCorasick>,
+    },
+    /// Several patterns that need the regex engine, each a `Literal` or
+    /// `Regex`. The leftmost match wins, then the longest, then the one
+    /// given first.
+    Any(Vec<Pattern>),
+}
+
+impl Pattern {
+    /// Compile `source` as a regular expression.
+    pub fn regex(source: &str) -> Result<Pattern, regex::Error> {
+        Regex::new(source).map(Pattern::Regex)
+    }
+
+    /// Build the pattern for a search. `source` is a regex when `is_regex`
+    /// is set and a literal otherwise; `-i`/`-w`/`-x` (in `options`) turn
+    /// even a literal into a regex, since plain substring search can't
+    /// express them.
+    pub fn new(
+        source: &str,
+        is_regex: bool,
+        options: &RegexOptions,
+    ) -> Result<Pattern, regex::Error> {
+        if is_regex {
+            Regex::with_options(source, options).map(Pattern::Regex)
+        } else if *options == RegexOptions::default() {
+            Ok(Pattern::Literal(source.to_string()))
+        } else {
+            Regex::with_options(&regex::escape(source), options).map(Pattern::Regex)
+        }
+    }
+
+    /// Build the pattern for a search with any number of `sources` (`-e`,
+    /// `-f`), which a line matches if any of them does. Plain strings all
+    /// go into one Aho-Corasick automaton; anything else is compiled one by
+    /// one. On error, also returns the index of the source at fault.
+    pub fn set(
+        sources: &[String],
+        is_regex: bool,
+        options: &RegexOptions,
+    ) -> Result<Pattern, (usize, regex::Error)> {
+        if let [source] = sources {
+            return Pattern::new(source, is_regex, options).map_err(|err| (0, err));
+        }
+        // A regex without metacharacters is a plain string too.
+        let literal = |source: &String| !is_regex || regex::escape(source) == *source;
+        if *options == RegexOptions::default() && sources.iter().all(literal) {
+            return Ok(Pattern::Literals {
+                matcher: Box::new(AhoCorasick::new(sources)),
+                sources: sources.to_vec(),
+            });
+        }
+        sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| Pattern::new(source, is_regex, options).map_err(|err| (i, err)))
+            .collect::<Result<_, _>>()
+            .map(Pattern::Any)
+    }
+
+    /// Number of capture groups a match reports, including group 0 (the
+    /// whole match); a literal has only that one. For a set of patterns,
+    /// the most any of them has.
+    pub fn captures_len(&self) -> usize {
+        match self {
+            Pattern::Literal(_) | Pattern::Literals { .. } => 1,
+            Pattern::Regex(re) => re.captures_len(),
+            Pattern::Any(patterns) => patterns
+                .iter()
+                .map(Pattern::captures_len)
+                .max()
+                .unwrap_or(1),
+        }
+    }
+
+    /// Pattern number `id` of a set on its own, if it was compiled that way
+    /// (always for `Any`, never for `Literals`). A lone pattern is its own
+    /// number 0.
+    pub fn member(&self, id: usize) -> Option<&Pattern> {
+        match self {
+            Pattern::Literal(_) | Pattern::Regex(_) => (id == 0).then_some(self),
+            Pattern::Literals { .. } => None,
+            Pattern::Any(patterns) => patterns.get(id),
+        }
+    }
+
+    /// The pattern text as the user typed it; for a set, the first one.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Pattern::Literal(s) => s,
+            Pattern::Regex(re) => re.as_str(),
+            Pattern::Literals { sources, .. } => sources.first().map_or("", String::as_str),
+            Pattern::Any(patterns) => patterns.first().map_or("", Pattern::as_str),
+        }
+    }
+}
+
+impl Matcher for Pattern {
+    fn find_at(&self, haystack: &str, start: usize) -> Option<(usize, usize)> {
+        match self {
+            Pattern::Literal(s) => s.as_str().find_at(haystack, start),
+            Pattern::Regex(re) => re.find_at(haystack, start),
+            _ => self
+                .find_pattern_at(haystack, start)
+                .map(|(from, to, _)| (from, to)),
+        }
+    }
+
+    fn find_pattern_at(&self, haystack: &str, start: usize) -> Option<(usize, usize, usize)> {
+        match self {
+            Pattern::Literal(_) | Pattern::Regex(_) => self
+                .find_at(haystack, start)
+                .map(|(from, to)| (from, to, 0)),
+            // Patterns are valid UTF-8, so every match starts and ends on a
+            // char boundary.
+            Pattern::Literals { matcher, .. } => matcher.find_at(haystack.as_bytes(), start),
+            Pattern::Any(patterns) => {
+                let mut best: Option<(usize, usize, usize)> = None;
+                for (id, pattern) in patterns.iter().enumerate() {
+                    let Some((from, to)) = pattern.find_at(haystack, start) else {
+                        continue;
+                    };
+                    if best.is_none_or(|(best_from, best_to, _)| {
+                        from < best_from || (from == best_from && to > best_to)
+                    }) {
+                        best = Some((from, to, id));
+                    }
+                }
+                best
+            }
+        }
+    }
+}
+
+/// One matching line, with enough position information to print line
+/// numbers, highlight matches, or point an editor at it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMatch<'a> {
+    /// 1-based line number.
+    pub line_number: usize,
+    /// Byte offset of the start of the line within the searched text.
+    pub byte_offset: usize,
+    /// The line itself, without its line terminator.
+    pub line: &'a str,
+    /// Byte range of every non-overlapping match, relative to `line`.
+    pub spans: Vec<(usize, usize)>,
+    /// Which pattern made each of `spans` (see `Matcher::find_pattern_at`).
+    pub patterns: Vec<usize>,
+}
+
+/// Every line of `contents` paired with the byte offset it starts at. Like
+/// `str::lines()`, a trailing `\n` or `\r\n` is not part of the line.
+pub fn lines_with_offsets(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+    contents.split_inclusive('\n').scan(0, |offset, raw| {
+        let start = *offset;
+        *offset += raw.len();
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        Some((start, line.strip_suffix('\r').unwrap_or(line)))
+    })
+}
+
+/// Byte ranges of every non-overlapping match of `matcher` in `line`, left to
+/// right. An empty match never repeats at the same position.
+pub fn find_iter<M: Matcher + ?Sized>(matcher: &M, line: &str) -> Vec<(usize, usize)> {
+    find_iter_patterns(matcher, line)
+        .into_iter()
+        .map(|(from, to, _)| (from, to))
+        .collect()
+}
+
+/// Like `find_iter`, with the number of the pattern behind each match.
+fn find_iter_patterns<M: Matcher + ?Sized>(matcher: &M, line: &str) -> Vec<(usize, usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while start <= line.len() {
+        let Some((from, to, id)) = matcher.find_pattern_at(line, start) else {
+            break;
+        };
+        spans.push((from, to, id));
+        start = if to > from {
+            to
+        } else {
+            // Step over one whole character so we stay on a char boundary.
+            to + line[to..].chars().next().map_or(1, char::len_utf8)
+        };
+    }
+    spans
+}
+
+/// Match a single line, returning its `LineMatch` if `matcher` hits it.
+pub fn match_line<'a, M: Matcher + ?Sized>(
+    matcher: &M,
+    line_number: usize,
+    byte_offset: usize,
+    line: &'a str,
+) -> Option<LineMatch<'a>> {
+    let (spans, patterns) = find_iter_patterns(matcher, line)
+        .into_iter()
+        .map(|(from, to, id)| ((from, to), id))
+        .unzip::<_, _, Vec<_>, Vec<_>>();
+    if spans.is_empty() {
+        return None;
+    }
+    Some(LineMatch {
+        line_number,
+        byte_offset,
+        line,
+        spans,
+        patterns,
+    })
+}
+
+/// Every line in `contents` that `matcher` matches, as `LineMatch` records.
+pub fn search_lines<'a, M: Matcher + ?Sized>(matcher: &M, contents: &'a str) -> Vec<LineMatch<'a>> {
+    lines_with_offsets(contents)
+        .enumerate()
+        .filter_map(|(i, (offset, line))| match_line(matcher, i + 1, offset, line))
+        .collect()
+}
+
+/// Return every line in `contents` that `matcher` matches, in order.
 ///
-/// Concepts: iterators (`str::lines()`, `.filter()`/`.collect()` or a plain
-/// `for` loop — try both, see which reads better to you), `&str` slices,
-/// lifetimes (notice `contents` and the return type share the `'a` lifetime
-/// — the lines you return borrow directly from `contents`; nothing gets
-/// copied).
-pub fn search<'a>(pattern: &str, contents: &'a str) -> Vec<&'a str> {
-    // TODO: iterate over contents.lines(), keep only the lines that
-    // contain `pattern` (see `str::contains`), and collect them into a Vec.
-    todo!("find every line in `contents` containing `pattern`")
+/// Concepts: iterators (`str::lines()`, `.filter()`/`.collect()`), `&str`
+/// slices, lifetimes (notice `contents` and the return type share the `'a`
+/// lifetime — the lines you return borrow directly from `contents`; nothing
+/// gets copied), generics with trait bounds.
+pub fn search<'a, M: Matcher + ?Sized>(matcher: &M, contents: &'a str) -> Vec<&'a str> {
+    contents
+        .lines()
+        .filter(|line| matcher.is_match(line))
+        .collect()
+}
+
+/// Like `search` with a literal pattern, but scanning the whole of
+/// `contents` with `finder` and only looking for line boundaries around each
+/// hit, instead of testing every line on its own (see `literal`).
+pub fn search_literal<'a>(finder: &Finder, contents: &'a str) -> Vec<&'a str> {
+    let bytes = contents.as_bytes();
+    let mut lines = Vec::new();
+    if finder.needle().contains(&b'\n') {
+        // Lines never contain a `\n`, so no line can match.
+        return lines;
+    }
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let Some(hit) = finder.find_at(bytes, pos) else {
+            break;
+        };
+        // `pos` is always at the start of a line.
+        let start = bytes[pos..hit]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(pos, |i| pos + i + 1);
+        let end = reader::line_end(bytes, hit);
+        // Both ends are next to an ASCII `\n` (or the ends of `contents`),
+        // so they're char boundaries.
+        let line = &contents[start..end];
+        let line = line
+            .strip_suffix('\n')
+            .map_or(line, |l| l.strip_suffix('\r').unwrap_or(l));
+        lines.push(line);
+        pos = end;
+    }
+    lines
 }
 
 #[cfg(test)]
@@ -42,4 +351,137 @@ fourth line: error again";
         let contents = "ERROR in caps\nerror in lowercase";
         assert_eq!(search("error", contents), vec!["error in lowercase"]);
     }
+
+    #[test]
+    fn ignore_case_uses_unicode_simple_folding() {
+        let icase = RegexOptions {
+            case_insensitive: true,
+            ..RegexOptions::default()
+        };
+        let lines = |source: &str, contents| {
+            search(&Pattern::new(source, false, &icase).unwrap(), contents)
+        };
+        // Greek: all three sigmas fold together.
+        assert_eq!(
+            lines("ΟΔΥΣΣΕΥΣ", "οδυσσευς\nΟδυσσευσ\nοδυσευς"),
+            vec!["οδυσσευς", "Οδυσσευσ"]
+        );
+        // German: ß matches capital ẞ, but not the two letters SS.
+        assert_eq!(
+            lines("straße", "STRAẞE\nSTRASSE\nStraße"),
+            vec!["STRAẞE", "Straße"]
+        );
+        // Turkish: without Turkish rules, İ and ı are letters of their own.
+        assert_eq!(
+            lines("istanbul", "İSTANBUL\nISTANBUL\nıstanbul"),
+            vec!["ISTANBUL"]
+        );
+        assert_eq!(lines("İSTANBUL", "İstanbul\nistanbul"), vec!["İstanbul"]);
+        // Devanagari has no case, so -i changes nothing.
+        assert_eq!(lines("नमस्ते", "नमस्ते दुनिया\nनमस्कार"), vec!["नमस्ते दुनिया"]);
+    }
+
+    #[test]
+    fn whole_words_are_found_in_every_script() {
+        let word = RegexOptions {
+            whole_word: true,
+            ..RegexOptions::default()
+        };
+        let lines = |source: &str, options: &RegexOptions, contents| {
+            search(&Pattern::new(source, false, options).unwrap(), contents)
+        };
+        // A following vowel sign or virama continues a Devanagari word.
+        assert_eq!(
+            lines("राम", &word, "राम आए।\nरामायण\nश्रीराम\nरामु"),
+            vec!["राम आए।"]
+        );
+        assert!(lines("नमस", &word, "नमस्ते").is_empty());
+        assert_eq!(lines("Maß", &word, "Maßstab\nein Maß."), vec!["ein Maß."]);
+        let both = RegexOptions {
+            case_insensitive: true,
+            ..word
+        };
+        assert_eq!(
+            lines("σοφία", &both, "ΦΙΛΟΣΟΦΊΑ\nΗ ΣΟΦΊΑ."),
+            vec!["Η ΣΟΦΊΑ."]
+        );
+    }
+
+    #[test]
+    fn regex_pattern_filters_lines() {
+        let contents = "\
+ERROR [db] pool exhausted
+ERROR [disk] full
+WARN [net] slow
+ERROR [net] reset";
+        let pattern = Pattern::regex(r"^ERROR \[(db|net)\]").unwrap();
+        assert_eq!(
+            search(&pattern, contents),
+            vec!["ERROR [db] pool exhausted", "ERROR [net] reset"]
+        );
+    }
+
+    #[test]
+    fn search_lines_reports_positions_of_every_match() {
+        let contents = "ok\r\nerror: error twice\nok\n";
+        let matches = search_lines("error", contents);
+        assert_eq!(
+            matches,
+            vec![LineMatch {
+                line_number: 2,
+                byte_offset: 4,
+                line: "error: error twice",
+                spans: vec![(0, 5), (7, 12)],
+                patterns: vec![0, 0],
+            }]
+        );
+    }
+
+    #[test]
+    fn find_iter_steps_past_empty_matches() {
+        let pattern = Pattern::regex("x*").unwrap();
+        assert_eq!(
+            find_iter(&pattern, "axé"),
+            vec![(0, 0), (1, 2), (2, 2), (4, 4)]
+        );
+    }
+
+    #[test]
+    fn pattern_sets_report_which_pattern_matched() {
+        let sources = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let plain = RegexOptions::default();
+        let line = "user ID-0417 paid ID-0090";
+
+        let literals = Pattern::set(&sources(&["ID-0090", "ID-0417"]), false, &plain).unwrap();
+        assert!(matches!(literals, Pattern::Literals { .. }));
+        let m = match_line(&literals, 1, 0, line).unwrap();
+        assert_eq!(m.spans, vec![(5, 12), (18, 25)]);
+        assert_eq!(m.patterns, vec![1, 0]);
+
+        // A regex in the set: leftmost wins, then longest.
+        let mixed = Pattern::set(&sources(&["ID", r"ID-\d+", "paid"]), true, &plain).unwrap();
+        assert!(matches!(mixed, Pattern::Any(_)));
+        let m = match_line(&mixed, 1, 0, line).unwrap();
+        assert_eq!(m.spans, vec![(5, 12), (13, 17), (18, 25)]);
+        assert_eq!(m.patterns, vec![1, 2, 1]);
+
+        assert!(matches!(
+            Pattern::set(&sources(&["ok", "(bad"]), true, &plain),
+            Err((1, _))
+        ));
+        let nothing = Pattern::set(&[], false, &plain).unwrap();
+        assert!(match_line(&nothing, 1, 0, line).is_none());
+    }
+
+    #[test]
+    fn search_literal_matches_naive_search() {
+        let contents = "ERROR one\r\nok\nan ERROR\n\nERRORERROR\ntrailing ERROR";
+        for needle in ["ERROR", "R", "\n", "ok", "missing", ""] {
+            assert_eq!(
+                search_literal(&Finder::new(needle.as_bytes()), contents),
+                search(needle, contents),
+                "{needle:?}"
+            );
+        }
+    }
 }
@@ -0,0 +1,293 @@
+//! Reading the members of a tar archive, for `-z`.
+//!
+//! A tar file is a sequence of 512-byte header blocks, each followed by the
+//! member's data padded to a multiple of 512 bytes, and ends with blocks of
+//! zeros. `Archive` walks those headers and hands out each regular file's
+//! contents as a `Read`, so members are searched as they stream past —
+//! nothing is extracted to disk or held in memory whole.
+//!
+//! Names come from the classic header (plus the ustar `prefix` field for
+//! long paths), GNU `L` long-name records, or a pax `path=` record; other
+//! member types (directories, links, devices) are skipped.
+//!
+//! Concepts: fixed-layout binary headers, octal fields, `Read::take`,
+//! `io::copy` into `io::sink()` to skip data.
+
+use std::io::{self, Read};
+
+use crate::decompress::Error;
+
+const BLOCK: usize = 512;
+
+/// One regular file in the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: String,
+    pub size: u64,
+}
+
+/// Iterates over the regular files in a tar stream.
+pub struct Archive<R> {
+    inner: R,
+    /// Bytes of the current member's data not read yet.
+    remaining: u64,
+    /// Padding after the current member's data.
+    padding: u64,
+}
+
+impl<R: Read> Archive<R> {
+    pub fn new(inner: R) -> Self {
+        Archive {
+            inner,
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    /// The next regular file, skipping over whatever the caller didn't read
+    /// of the previous one. `None` at the end of the archive.
+    pub fn next_file(&mut self) -> io::Result<Option<Entry>> {
+        self.skip(self.remaining + self.padding)?;
+        self.remaining = 0;
+        self.padding = 0;
+        // Set by a GNU long-name or pax record for the member after it.
+        let mut long_name = None;
+        loop {
+            let mut header = [0u8; BLOCK];
+            if !self.read_block(&mut header)? || header.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+            if !checksum_ok(&header) {
+                return Err(Error::BadTar("header checksum mismatch").into_io());
+            }
+            let size = parse_size(&header[124..136])?;
+            let padding = size.next_multiple_of(BLOCK as u64) - size;
+            match header[156] {
+                b'L' | b'x' => {
+                    let data = self.read_data(size)?;
+                    self.skip(padding)?;
+                    long_name = if header[156] == b'L' {
+                        Some(String::from_utf8_lossy(field(&data)).into_owned())
+                    } else {
+                        pax_path(&data).or(long_name)
+                    };
+                }
+                b'0' | b'\0' | b'7' => {
+                    self.remaining = size;
+                    self.padding = padding;
+                    let path = long_name.take().unwrap_or_else(|| header_path(&header));
+                    return Ok(Some(Entry { path, size }));
+                }
+                _ => {
+                    self.skip(size + padding)?;
+                    long_name = None;
+                }
+            }
+        }
+    }
+
+    /// The contents of the entry `next_file` last returned.
+    pub fn contents(&mut self) -> Contents<'_, R> {
+        Contents { archive: self }
+    }
+
+    /// Read one header block; `Ok(false)` at a clean end of input.
+    fn read_block(&mut self, block: &mut [u8; BLOCK]) -> io::Result<bool> {
+        let mut filled = 0;
+        while filled < BLOCK {
+            match self.inner.read(&mut block[filled..]) {
+                Ok(0) if filled == 0 => return Ok(false),
+                Ok(0) => return Err(Error::BadTar("truncated header").into_io()),
+                Ok(n) => filled += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+
+    fn read_data(&mut self, size: u64) -> io::Result<Vec<u8>> {
+        // Metadata records are small; refuse absurd ones rather than
+        // allocate whatever a corrupt header claims.
+        if size > 1 << 20 {
+            return Err(Error::BadTar("oversized metadata record").into_io());
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        (&mut self.inner).take(size).read_to_end(&mut data)?;
+        if data.len() as u64 != size {
+            return Err(Error::BadTar("truncated member").into_io());
+        }
+        Ok(data)
+    }
+
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        let skipped = io::copy(&mut (&mut self.inner).take(n), &mut io::sink())?;
+        if skipped != n {
+            return Err(Error::BadTar("truncated member").into_io());
+        }
+        Ok(())
+    }
+}
+
+/// `Read` over one member's data.
+pub struct Contents<'a, R> {
+    archive: &'a mut Archive<R>,
+}
+
+impl<R: Read> Read for Contents<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let archive = &mut *self.archive;
+        if archive.remaining == 0 {
+            return Ok(0);
+        }
+        let max = buf
+            .len()
+            .min(archive.remaining.try_into().unwrap_or(usize::MAX));
+        let n = archive.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(Error::BadTar("truncated member").into_io());
+        }
+        archive.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// A NUL-terminated (or full-width) header field.
+fn field(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    &bytes[..end]
+}
+
+fn header_path(header: &[u8; BLOCK]) -> String {
+    let name = String::from_utf8_lossy(field(&header[..100]));
+    // Only POSIX ustar has a prefix field; GNU tar keeps timestamps there.
+    let prefix = if &header[257..263] == b"ustar\0" {
+        field(&header[345..500])
+    } else {
+        &[]
+    };
+    if prefix.is_empty() {
+        name.into_owned()
+    } else {
+        format!("{}/{name}", String::from_utf8_lossy(prefix))
+    }
+}
+
+/// The header checksum is the sum of all header bytes, with the checksum
+/// field itself counted as spaces.
+fn checksum_ok(header: &[u8; BLOCK]) -> bool {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            if (148..156).contains(&i) {
+                32
+            } else {
+                u64::from(b)
+            }
+        })
+        .sum();
+    parse_octal(&header[148..156]) == Some(sum)
+}
+
+/// The size field: octal text, or big-endian binary (GNU) when the top bit
+/// of the first byte is set, for members over 8 GiB.
+fn parse_size(bytes: &[u8]) -> io::Result<u64> {
+    if bytes[0] & 0x80 != 0 {
+        let value = bytes[1..]
+            .iter()
+            .fold(u64::from(bytes[0] & 0x7f), |acc, &b| {
+                acc << 8 | u64::from(b)
+            });
+        return Ok(value);
+    }
+    parse_octal(bytes).ok_or_else(|| Error::BadTar("invalid size field").into_io())
+}
+
+fn parse_octal(bytes: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(field(bytes)).ok()?;
+    let text = text.trim_matches(' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// The `path` from pax extended-header records (`"<len> key=value\n"`).
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines().find_map(|record| {
+        let (_, keyvalue) = record.split_once(' ')?;
+        keyvalue.strip_prefix("path=").map(str::to_string)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A minimal ustar header for a member, with a valid checksum.
+    fn header(name: &str, size: usize, kind: u8) -> Vec<u8> {
+        let mut h = vec![0u8; BLOCK];
+        h[..name.len()].copy_from_slice(name.as_bytes());
+        h[124..135].copy_from_slice(format!("{size:011o}").as_bytes());
+        h[156] = kind;
+        h[257..263].copy_from_slice(b"ustar\0");
+        h[148..156].fill(b' ');
+        let sum: u32 = h.iter().map(|&b| u32::from(b)).sum();
+        h[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+        h
+    }
+
+    fn member(name: &str, data: &[u8], kind: u8) -> Vec<u8> {
+        let mut out = header(name, data.len(), kind);
+        out.extend(data);
+        out.resize(out.len().next_multiple_of(BLOCK), 0);
+        out
+    }
+
+    #[test]
+    fn lists_regular_files_and_reads_their_contents() {
+        let mut tar = member("logs/", b"", b'5');
+        tar.extend(member("logs/a.log", b"one\ntwo\n", b'0'));
+        tar.extend(member("logs/b.log", &[b'x'; 700], b'0'));
+        tar.extend([0u8; 2 * BLOCK]);
+
+        let mut archive = Archive::new(&tar[..]);
+        let a = archive.next_file().unwrap().unwrap();
+        assert_eq!(a.path, "logs/a.log");
+        let mut text = String::new();
+        archive.contents().read_to_string(&mut text).unwrap();
+        assert_eq!(text, "one\ntwo\n");
+
+        // b.log is skipped without being read.
+        assert_eq!(archive.next_file().unwrap().unwrap().size, 700);
+        assert_eq!(archive.next_file().unwrap(), None);
+    }
+
+    #[test]
+    fn long_names_come_from_gnu_and_pax_records() {
+        let long = format!("{}/deep.log", "d".repeat(120));
+        let mut tar = member("././@LongLink", format!("{long}\0").as_bytes(), b'L');
+        tar.extend(member("truncated", b"x", b'0'));
+        let record = format!("{} path=pax.log\n", "path=pax.log\n".len() + 3);
+        tar.extend(member("PaxHeaders/x", record.as_bytes(), b'x'));
+        tar.extend(member("short", b"y", b'0'));
+
+        let mut archive = Archive::new(&tar[..]);
+        assert_eq!(archive.next_file().unwrap().unwrap().path, long);
+        assert_eq!(archive.next_file().unwrap().unwrap().path, "pax.log");
+        assert_eq!(archive.next_file().unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_corrupt_headers() {
+        let mut tar = member("a.log", b"data", b'0');
+        tar[0] = b'b'; // breaks the checksum
+        let err = Archive::new(&tar[..]).next_file().unwrap_err();
+        assert_eq!(
+            Error::from_io(err).unwrap(),
+            Error::BadTar("header checksum mismatch")
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Recursive directory traversal for `-r`.
+//!
+//! Walks a directory tree in sorted order and returns the files worth
+//! searching: hidden entries (names starting with `.`) are skipped unless
+//! asked for, `.gitignore` / `.ignore` files are honored at every level, and
+//! files that look binary are left out.
+//!
+//! Concepts: `std::fs::read_dir`, recursion, `Path` / `PathBuf`.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::decompress::Format;
+use crate::error::RgrepError;
+use crate::ignore::{IGNORE_FILE_NAMES, IgnoreFile, Verdict};
+
+/// How many leading bytes to inspect when deciding whether a file is binary.
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// Knobs for `walk`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WalkOptions {
+    /// Descend into / return hidden files and directories too (`--hidden`).
+    pub hidden: bool,
+    /// Honor `.gitignore` / `.ignore` files (turned off by `--no-ignore`).
+    pub respect_ignore: bool,
+    /// Keep gzip files and tar archives despite them looking binary (`-z`).
+    pub search_zip: bool,
+}
+
+impl Default for WalkOptions {
+    fn default() -> Self {
+        WalkOptions {
+            hidden: false,
+            respect_ignore: true,
+            search_zip: false,
+        }
+    }
+}
+
+/// What `walk` found under one root.
+#[derive(Debug, Default)]
+pub struct Walk {
+    /// Every searchable file, in sorted (depth-first) order.
+    pub files: Vec<PathBuf>,
+    /// Directories (or entries) that couldn't be read. The walk carries on
+    /// past them, so one unreadable subdirectory doesn't hide the rest.
+    pub errors: Vec<RgrepError>,
+}
+
+/// Every searchable file under `root`, plus whatever couldn't be read.
+pub fn walk(root: &Path, options: &WalkOptions) -> Walk {
+    let mut found = Walk::default();
+    let mut ignores = Vec::new();
+    visit(root, options, &mut ignores, &mut found);
+    found
+}
+
+/// `true` if `bytes` look like binary data rather than text: the same NUL
+/// byte heuristic `grep` and `git` use.
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_SNIFF_LEN)].contains(&0)
+}
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> RgrepError + '_ {
+    move |source| RgrepError::Io {
+        path: path.display().to_string(),
+        source,
+    }
+}
+
+fn visit(dir: &Path, options: &WalkOptions, ignores: &mut Vec<IgnoreFile>, found: &mut Walk) {
+    let pushed_before = ignores.len();
+    if options.respect_ignore {
+        for name in IGNORE_FILE_NAMES {
+            if let Ok(contents) = fs::read_to_string(dir.join(name)) {
+                ignores.push(IgnoreFile::parse(dir, &contents));
+            }
+        }
+    }
+
+    let mut entries =
+        match fs::read_dir(dir).and_then(|entries| entries.collect::<Result<Vec<_>, _>>()) {
+            Ok(entries) => entries,
+            Err(err) => {
+                found.errors.push(io_error(dir)(err));
+                ignores.truncate(pushed_before);
+                return;
+            }
+        };
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if !options.hidden && entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        // Symlinked directories are not followed, which also rules out
+        // cycles; a symlink to a file is searched like the file itself.
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(err) => {
+                found.errors.push(io_error(&path)(err));
+                continue;
+            }
+        };
+        let is_dir = file_type.is_dir();
+        if file_type.is_symlink() && path.is_dir() {
+            continue;
+        }
+        if is_ignored(ignores, &path, is_dir) {
+            continue;
+        }
+        if is_dir {
+            visit(&path, options, ignores, found);
+        } else if !is_binary_file(&path, options) {
+            found.files.push(path);
+        }
+    }
+
+    ignores.truncate(pushed_before);
+}
+
+/// Deeper ignore files override shallower ones, and `.ignore` overrides
+/// `.gitignore` in the same directory — i.e. the last verdict wins.
+fn is_ignored(ignores: &[IgnoreFile], path: &Path, is_dir: bool) -> bool {
+    ignores
+        .iter()
+        .rev()
+        .find_map(|file| file.verdict(path, is_dir))
+        == Some(Verdict::Ignore)
+}
+
+/// Unreadable files aren't treated as binary — `run` will report the real
+/// error when it tries to search them. With `-z`, archives `rgrep` can
+/// decode don't count as binary either.
+fn is_binary_file(path: &Path, options: &WalkOptions) -> bool {
+    let mut head = Vec::with_capacity(BINARY_SNIFF_LEN);
+    match File::open(path) {
+        Ok(file) => match file.take(BINARY_SNIFF_LEN as u64).read_to_end(&mut head) {
+            Ok(_) => {
+                looks_binary(&head)
+                    && !(options.search_zip
+                        && matches!(Format::sniff(&head), Format::Gzip | Format::Tar))
+            }
+            Err(_) => false,
+        },
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// The paths in `files`, relative to `tree`.
+    fn relative(tree: &TempDir, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|p| {
+                p.strip_prefix(&tree.0)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn honors_ignore_files_hidden_entries_and_binaries() {
+        let tree = TempDir::new("walk");
+        tree.file(".gitignore", b"target/\n*.log\n");
+        tree.file("src/.ignore", b"generated.rs\n");
+        tree.file("src/main.rs", b"fn main() {}");
+        tree.file("src/generated.rs", b"// TODO");
+        tree.file("target/debug/out.txt", b"TODO");
+        tree.file("app.log", b"TODO");
+        tree.file(".hidden/notes.txt", b"TODO");
+        tree.file("image.bin", b"\x89PNG\0\0\0");
+        tree.file("README.md", b"TODO");
+
+        let files = walk(&tree.0, &WalkOptions::default()).files;
+        assert_eq!(relative(&tree, files), vec!["README.md", "src/main.rs"]);
+    }
+
+    #[test]
+    fn options_can_include_hidden_and_ignored_files() {
+        let tree = TempDir::new("walk-opts");
+        tree.file(".gitignore", b"*.log\n");
+        tree.file(".env", b"TODO");
+        tree.file("app.log", b"TODO");
+
+        let options = WalkOptions {
+            hidden: true,
+            respect_ignore: false,
+            ..WalkOptions::default()
+        };
+        let files = walk(&tree.0, &options).files;
+        assert_eq!(
+            relative(&tree, files),
+            vec![".env", ".gitignore", "app.log"]
+        );
+    }
+
+    #[test]
+    fn search_zip_keeps_gzip_files_but_not_other_binaries() {
+        let tree = TempDir::new("walk-zip");
+        tree.file("app.log.gz", [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0]);
+        tree.file("image.bin", b"\x89PNG\0\0");
+        tree.file("notes.txt", b"TODO");
+
+        let files = walk(&tree.0, &WalkOptions::default()).files;
+        assert_eq!(relative(&tree, files), vec!["notes.txt"]);
+
+        let options = WalkOptions {
+            search_zip: true,
+            ..WalkOptions::default()
+        };
+        let files = walk(&tree.0, &options).files;
+        assert_eq!(relative(&tree, files), vec!["app.log.gz", "notes.txt"]);
+    }
+
+    #[test]
+    fn unreadable_directories_are_reported_and_skipped() {
+        let tree = TempDir::new("walk-missing");
+        let found = walk(&tree.0.join("no-such-dir"), &WalkOptions::default());
+        assert!(found.files.is_empty());
+        assert_eq!(found.errors.len(), 1);
+        assert!(found.errors[0].is_per_file());
+    }
+}